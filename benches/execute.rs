@@ -0,0 +1,68 @@
+//! 在 release 下运行 benches/loop.s, 比较逐条执行和按基本块执行的速度.
+//! cargo bench --bench execute [-- <iterations>]
+
+use std::time::Instant;
+
+use rvemu_hitsz::rvemu::*;
+
+const KERNEL_BASE: u32 = 0x1c09_0000;
+const DRAM_SIZE: u32 = 0x1_0000;
+
+fn cpu(iterations: Option<u32>) -> CPU {
+    let mut source = include_str!("loop.s").to_string();
+    if let Some(iterations) = iterations {
+        source = source.replace("li s0, 20000000", &format!("li s0, {}", iterations));
+    }
+    let user = assemble(&source, 0).unwrap();
+    let kernel = assemble("ebreak", KERNEL_BASE).unwrap();
+    CPU::new(
+        &user.image(),
+        0,
+        &kernel.text,
+        KERNEL_BASE,
+        0,
+        DRAM_SIZE,
+        Config::default(),
+    )
+}
+
+fn report(name: &str, steps: u64, start: Instant, cpu: &CPU) {
+    let seconds = start.elapsed().as_secs_f64();
+    println!(
+        "{:<8} {} instructions, {:.2} s, {:.2} Minst/s, a0 = {:#010x}",
+        name,
+        steps,
+        seconds,
+        steps as f64 / seconds / 1e6,
+        cpu.reg(10)
+    );
+}
+
+fn main() {
+    // cargo bench 会传入 --bench
+    let iterations = std::env::args()
+        .skip(1)
+        .find(|arg| !arg.starts_with('-'))
+        .map(|arg| arg.parse().expect("iterations"));
+
+    let mut step = cpu(iterations);
+    let start = Instant::now();
+    let mut steps = 0;
+    loop {
+        let result = step.fetch().and_then(|inst| {
+            step.pc_step();
+            step.execute(inst)
+        });
+        if result.is_err() {
+            break;
+        }
+        steps += 1;
+    }
+    report("execute", steps, start, &step);
+
+    let mut run = cpu(iterations);
+    let start = Instant::now();
+    let (steps, _) = run.run(u64::MAX);
+    report("run", steps, start, &run);
+    assert_eq!(step.reg(10), run.reg(10));
+}
@@ -0,0 +1,34 @@
+# ALU/load/store/branch loop used by benches/execute.rs.
+# Every value stored is folded into a0, which is checked at the end.
+    li s0, 20000000
+    la s1, buf
+    li a0, 0x12345678
+loop:
+    andi t0, s0, 15
+    slli t0, t0, 2
+    add t1, s1, t0
+    lw t2, 0(t1)
+    xor t2, t2, a0
+    slli t3, t2, 5
+    srli t4, t2, 27
+    or t2, t3, t4
+    add a0, a0, t2
+    sw t2, 0(t1)
+    lbu t5, 1(t1)
+    sltu t6, t5, a0
+    add a0, a0, t6
+    andi t5, a0, 1
+    beqz t5, even
+    addi a0, a0, 7
+    j next
+even:
+    sub a0, a0, s0
+next:
+    addi s0, s0, -1
+    bnez s0, loop
+    ebreak
+
+    .data
+buf:
+    .word 0, 0, 0, 0, 0, 0, 0, 0
+    .word 0, 0, 0, 0, 0, 0, 0, 0
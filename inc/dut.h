@@ -64,6 +64,8 @@ public:
         ret.wb_rd = dut->io_dbg_wb_reg;
         ret.wb_val = dut->io_dbg_wb_value;
         ret.inst_valid = dut->io_dbg_inst_valid;
+        ret.wb_fp = 0;
+        ret.wb_trap = 0;
         return ret;
     }
 
@@ -11,10 +11,65 @@ typedef struct WBInfo
     uint32_t wb_val;
     uint32_t wb_ena;
     uint32_t inst_valid;
+    uint32_t wb_fp;
+    // 这一步进入了异常处理, 响应中断时 wb_have_inst 和 inst_valid 为 0
+    uint32_t wb_trap;
 } WBInfo;
 
+typedef struct TlbStats
+{
+    uint64_t hits;
+    uint64_t misses;
+} TlbStats;
+
+typedef struct Config
+{
+    bool rv32e;
+    bool zba;
+    bool zbb;
+    bool zbs;
+    bool f;
+    uint32_t cycles_per_inst;
+    bool trap_illegal;
+    bool trap_misaligned;
+    bool trap_access_fault;
+    bool halt_on_eret;
+    bool uret_as_mret;
+    bool clint;
+    bool plic;
+    bool user_mode;
+    uint32_t pmp_entries;
+    uint32_t tlb_entries;
+    uint32_t decode_cache_entries;
+    bool jit;
+    bool jit_check;
+} Config;
+
+// rvemu_last_error 的错误码
+enum RvemuError
+{
+    RVEMU_ERROR_NONE = 0,
+    RVEMU_HALT_EBREAK = 0x1,
+    RVEMU_HALT_ERET = 0x2,
+    RVEMU_DECODE_UNIMPLEMENTED = 0x10,
+    RVEMU_DECODE_COMPRESSED = 0x11,
+    RVEMU_DECODE_RV32E = 0x12,
+    RVEMU_DECODE_DISABLED = 0x13,
+    RVEMU_CSR_UNIMPLEMENTED = 0x20,
+    RVEMU_CSR_READ_ONLY = 0x21,
+    RVEMU_CSR_PRIVILEGE = 0x22,
+    RVEMU_CSR_FS_OFF = 0x23,
+    RVEMU_CSR_COUNTER_DISABLED = 0x24,
+    RVEMU_MEM_OUT_OF_RANGE = 0x30,
+    RVEMU_MEM_INVALID_SIZE = 0x31,
+    // 没有进入异常处理的异常, 加上 mcause
+    RVEMU_EXCEPTION = 0x100,
+    RVEMU_ERROR_OTHER = 0xffffffff,
+};
+
 extern "C"
 {
+    extern Config rvemu_config_default(void);
     extern uint64_t rvemu_new(
         uint8_t user_ptr[],
         uint32_t user_base,
@@ -23,14 +78,20 @@ extern "C"
         uint32_t kernel_base,
         uint32_t kernel_len,
         uint32_t dram_base,
-        uint32_t dram_size);
+        uint32_t dram_size,
+        Config config);
 
     extern void rvemu_free(uint64_t emu);
     extern WBInfo rvemu_execute(uint64_t emu, uint32_t inst);
+    extern uint64_t rvemu_run(uint64_t emu, uint64_t max_steps);
+    extern uint32_t rvemu_last_error(void);
     extern uint32_t rvemu_fetch(uint64_t emu);
+    extern TlbStats rvemu_tlb_stats(uint64_t emu);
     extern void rvemu_pc_step(uint64_t emu);
     extern void rvemu_dump(uint64_t emu);
     extern uint32_t rvemu_pc(uint64_t emu);
+    extern void rvemu_set_switch(uint64_t emu, uint32_t value);
+    extern bool rvemu_set_irq(uint64_t emu, uint32_t source, bool level);
     extern void disasm(uint32_t inst);
 }
 
@@ -35,7 +35,10 @@ int main(int argc, char const *argv[])
     std::vector<uint8_t> kernel_code((std::istreambuf_iterator<char>(kernel_file)), std::istreambuf_iterator<char>());
     auto kernel_ptr = kernel_code.data();
     auto kernel_len = (uint32_t)kernel_code.size();
-    uint64_t emu = rvemu_new(user_ptr, 0, user_len, kernel_ptr, 0x1c09'0000, kernel_len, 0x0000'0000, 0xffff'f000);
+    // trap_handle.bin 用 uret 返回
+    Config config = rvemu_config_default();
+    config.uret_as_mret = true;
+    uint64_t emu = rvemu_new(user_ptr, 0, user_len, kernel_ptr, 0x1c09'0000, kernel_len, 0x0000'0000, 0xffff'f000, config);
 
     while (true)
     {
@@ -52,7 +55,8 @@ int main(int argc, char const *argv[])
         WBInfo info_emu = rvemu_execute(emu, code);
         top->wb_dump();
         printf("PC=0x%8.8x, WBEn = %d, WReg = %d, WBValue = 0x%8.8x\n", info_emu.wb_pc, info_emu.wb_ena, info_emu.wb_rd, info_emu.wb_val);
-        if (!info_emu.inst_valid)
+        // 响应中断时没有执行指令, 但不是出错
+        if (!info_emu.inst_valid && !info_emu.wb_trap)
         {
             break;
         }
@@ -0,0 +1,46 @@
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Context, Result};
+use rvemu_hitsz::rvemu::*;
+
+const USAGE: &str = "usage: asm <input.s> [-o <output.bin>] [--base <addr>]";
+
+/// 把汇编源文件翻译成 CPU::new 使用的 .bin 镜像
+fn main() -> Result<()> {
+    let mut input = None;
+    let mut output = None;
+    let mut base = 0;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" => output = Some(PathBuf::from(args.next().context(USAGE)?)),
+            "--base" => {
+                let addr = args.next().context(USAGE)?;
+                base = match addr.strip_prefix("0x") {
+                    Some(hex) => u32::from_str_radix(hex, 16),
+                    None => addr.parse(),
+                }
+                .with_context(|| format!("invalid base address: {}", addr))?;
+            }
+            _ if input.is_none() && !arg.starts_with('-') => input = Some(PathBuf::from(arg)),
+            _ => return Err(anyhow!(USAGE)),
+        }
+    }
+    let input = input.context(USAGE)?;
+    let output = output.unwrap_or_else(|| input.with_extension("bin"));
+
+    let source = std::fs::read_to_string(&input)
+        .with_context(|| format!("cannot read {}", input.display()))?;
+    let program = assemble(&source, base).with_context(|| input.display().to_string())?;
+    std::fs::write(&output, program.image())
+        .with_context(|| format!("cannot write {}", output.display()))?;
+    println!(
+        "{}: text {} bytes at {:#x}, data {} bytes at {:#x}",
+        output.display(),
+        program.text.len(),
+        program.base,
+        program.data.len(),
+        program.data_base()
+    );
+    Ok(())
+}
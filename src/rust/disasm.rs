@@ -0,0 +1,565 @@
+use std::{
+    collections::BTreeMap,
+    io::{ErrorKind, Write},
+    path::Path,
+};
+
+use anyhow::{anyhow, Context, Result};
+use rvemu_hitsz::rvemu::*;
+
+const USAGE: &str = "usage: disasm <file> [--base <addr>] [--kernel <file>] [--split]\n\
+     \x20 <file>           flat .bin, ELF, .coe or .hex (one word per line, @addr in words)\n\
+     \x20 --base <addr>    load address of a flat/coe/hex image (default 0)\n\
+     \x20 --kernel <file>  also disassemble a kernel image at the kernel IROM base\n\
+     \x20 --split          group the output into user/kernel regions";
+
+/// IROM 的基址, 与 main.cxx 中 rvemu_new 的参数一致
+const USER_BASE: u32 = 0x0000_0000;
+const KERNEL_BASE: u32 = 0x1c09_0000;
+
+/// 一段连续的代码
+struct Region {
+    base: u32,
+    bytes: Vec<u8>,
+}
+
+/// 解码后的一行
+struct Line {
+    pc: u32,
+    len: usize,
+    inst: Option<Instr>,
+}
+
+fn parse_addr(addr: &str) -> Result<u32> {
+    match addr.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => addr.parse(),
+    }
+    .with_context(|| format!("invalid address: {}", addr))
+}
+
+fn main() -> Result<()> {
+    // 输出接到 head 之类的管道时, 对方关闭后正常退出
+    match run() {
+        Err(e)
+            if e.downcast_ref::<std::io::Error>()
+                .is_some_and(|e| e.kind() == ErrorKind::BrokenPipe) =>
+        {
+            Ok(())
+        }
+        result => result,
+    }
+}
+
+fn run() -> Result<()> {
+    let mut input = None;
+    let mut kernel = None;
+    let mut base = USER_BASE;
+    let mut split = false;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--base" => base = parse_addr(&args.next().context(USAGE)?)?,
+            "--kernel" => kernel = Some(args.next().context(USAGE)?),
+            "--split" => split = true,
+            _ if input.is_none() && !arg.starts_with('-') => input = Some(arg),
+            _ => return Err(anyhow!(USAGE)),
+        }
+    }
+    let input = input.context(USAGE)?;
+
+    let mut symbols = SymbolTable::new();
+    let mut regions = load(Path::new(&input), base, &mut symbols)?;
+    if let Some(kernel) = kernel {
+        regions.extend(load(Path::new(&kernel), KERNEL_BASE, &mut symbols)?);
+        split = true;
+    }
+    print(regions, symbols, split, &mut std::io::stdout().lock())
+}
+
+/// 反汇编所有的段. split 时按用户/内核分组
+fn print(
+    mut regions: Vec<Region>,
+    mut symbols: SymbolTable,
+    split: bool,
+    out: &mut impl Write,
+) -> Result<()> {
+    regions.sort_by_key(|region| region.base);
+    for region in &regions {
+        symbols.add_region(region.base, region.bytes.len() as u32);
+    }
+
+    let decoded: Vec<Vec<Line>> = regions.iter().map(decode).collect();
+    discover_labels(&decoded, &regions, &mut symbols);
+
+    let mut current = None;
+    for (region, lines) in regions.iter().zip(&decoded) {
+        if split {
+            let name = if region.base >= KERNEL_BASE {
+                "kernel"
+            } else {
+                "user"
+            };
+            if current != Some(name) {
+                writeln!(out, "Disassembly of {}:", name)?;
+                current = Some(name);
+            }
+        }
+        for line in lines {
+            if let Some(name) = symbols.get(&line.pc) {
+                writeln!(out, "\n{:08x} <{}>:", line.pc, name)?;
+            }
+            let offset = (line.pc - region.base) as usize;
+            let bytes: Vec<String> = region.bytes[offset..offset + line.len]
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect();
+            let text = match &line.inst {
+                Some(inst) => inst.disasm(line.pc, Some(&symbols)),
+                None => {
+                    let value = region.bytes[offset..offset + line.len]
+                        .iter()
+                        .rev()
+                        .fold(0u32, |value, byte| value << 8 | *byte as u32);
+                    match line.len {
+                        4 => format!(".word {:#010x}", value),
+                        2 => format!(".half {:#06x}", value),
+                        _ => format!(".byte {:#04x}", value),
+                    }
+                }
+            };
+            writeln!(out, "{:8x}:\t{:<12}\t{}", line.pc, bytes.join(" "), text)?;
+        }
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+/// 按 IROM 取指的方式切分: 先看低 16 位是否是压缩指令
+fn decode(region: &Region) -> Vec<Line> {
+    let mut lines = Vec::new();
+    let mut offset = 0;
+    while offset < region.bytes.len() {
+        let pc = region.base.wrapping_add(offset as u32);
+        let rest = &region.bytes[offset..];
+        let (len, code) = match rest {
+            [lo, hi, ..] if is_compressed(*lo as u32) => {
+                (2, Some(u16::from_le_bytes([*lo, *hi]) as u32))
+            }
+            [b0, b1, b2, b3, ..] => (4, Some(u32::from_le_bytes([*b0, *b1, *b2, *b3]))),
+            [_, _, ..] => (2, None),
+            _ => (1, None),
+        };
+        let inst = code.and_then(|code| Instr::try_from(code).ok());
+        lines.push(Line { pc, len, inst });
+        offset += len;
+    }
+    lines
+}
+
+/// 给没有符号的跳转目标起名字: jal ra 的目标是函数, 其余的是局部标号
+fn discover_labels(decoded: &[Vec<Line>], regions: &[Region], symbols: &mut SymbolTable) {
+    let inside = |addr: u32| {
+        regions
+            .iter()
+            .any(|region| region.base <= addr && addr - region.base < region.bytes.len() as u32)
+    };
+    let mut found = BTreeMap::new();
+    for line in decoded.iter().flatten() {
+        let inst = match &line.inst {
+            Some(Instr::C(_, expanded)) => expanded.as_ref(),
+            Some(inst) => inst,
+            None => continue,
+        };
+        let (offset, call) = match *inst {
+            Instr::JAL(rd, offset) => (offset, rd == 1),
+            Instr::BEQ(_, _, offset)
+            | Instr::BNE(_, _, offset)
+            | Instr::BLT(_, _, offset)
+            | Instr::BGE(_, _, offset)
+            | Instr::BLTU(_, _, offset)
+            | Instr::BGEU(_, _, offset) => (offset, false),
+            _ => continue,
+        };
+        let target = line.pc.wrapping_add(offset as u32);
+        if inside(target) && !symbols.contains_key(&target) {
+            *found.entry(target).or_insert(false) |= call;
+        }
+    }
+    for (addr, call) in found {
+        let prefix = if call { "func" } else { "label" };
+        symbols.insert(addr, format!("{}_{:08x}", prefix, addr));
+    }
+}
+
+/// 按扩展名和文件头选择格式
+fn load(path: &Path, base: u32, symbols: &mut SymbolTable) -> Result<Vec<Region>> {
+    let data = std::fs::read(path).with_context(|| format!("cannot read {}", path.display()))?;
+    let extension = path.extension().and_then(|ext| ext.to_str()).unwrap_or("");
+    let regions = if data.starts_with(b"\x7fELF") {
+        load_elf(&data, symbols)
+    } else if extension.eq_ignore_ascii_case("coe") {
+        load_coe(&String::from_utf8_lossy(&data), base)
+    } else if extension.eq_ignore_ascii_case("hex") {
+        load_hex(&String::from_utf8_lossy(&data), base)
+    } else {
+        Ok(vec![Region { base, bytes: data }])
+    };
+    regions.with_context(|| path.display().to_string())
+}
+
+/// Vivado 的 COE: memory_initialization_radix 和 memory_initialization_vector, 每项一个字
+fn load_coe(text: &str, base: u32) -> Result<Vec<Region>> {
+    let text: String = text
+        .lines()
+        .map(|line| line.split_once(';').map_or(line, |(code, _)| code))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let text = text.to_ascii_lowercase();
+    let field = |name: &str| {
+        let start = text.find(name).ok_or_else(|| anyhow!("missing {}", name))?;
+        let value = text[start + name.len()..].trim_start();
+        value
+            .strip_prefix('=')
+            .ok_or_else(|| anyhow!("expected '=' after {}", name))
+    };
+    let radix_text = field("memory_initialization_radix")?;
+    let radix: u32 = radix_text
+        .split(|c: char| !c.is_ascii_digit())
+        .find(|digits| !digits.is_empty())
+        .ok_or_else(|| anyhow!("invalid radix"))?
+        .parse()?;
+    let mut bytes = Vec::new();
+    for word in field("memory_initialization_vector")?
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|word| !word.is_empty())
+    {
+        let value =
+            u32::from_str_radix(word, radix).with_context(|| format!("invalid word: {}", word))?;
+        bytes.extend(value.to_le_bytes());
+    }
+    Ok(vec![Region { base, bytes }])
+}
+
+/// $readmemh 格式: 每行一个字, @addr 以字为单位设置地址
+fn load_hex(text: &str, base: u32) -> Result<Vec<Region>> {
+    let mut regions = vec![Region {
+        base,
+        bytes: Vec::new(),
+    }];
+    for line in text.lines() {
+        let line = line.split("//").next().unwrap_or_default();
+        for token in line.split_whitespace() {
+            if let Some(addr) = token.strip_prefix('@') {
+                let addr = u32::from_str_radix(addr, 16)
+                    .with_context(|| format!("invalid address: {}", token))?;
+                regions.push(Region {
+                    base: base.wrapping_add(addr << 2),
+                    bytes: Vec::new(),
+                });
+                continue;
+            }
+            let value = u32::from_str_radix(token, 16)
+                .with_context(|| format!("invalid word: {}", token))?;
+            let region = regions.last_mut().unwrap();
+            region.bytes.extend(value.to_le_bytes());
+        }
+    }
+    regions.retain(|region| !region.bytes.is_empty());
+    Ok(regions)
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Result<u32> {
+    data.get(offset..offset + 2)
+        .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]) as u32)
+        .ok_or_else(|| anyhow!("truncated ELF at {:#x}", offset))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32> {
+    data.get(offset..offset + 4)
+        .map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        .ok_or_else(|| anyhow!("truncated ELF at {:#x}", offset))
+}
+
+fn read_bytes(data: &[u8], offset: u32, size: u32) -> Result<&[u8]> {
+    data.get(offset as usize..(offset as usize).saturating_add(size as usize))
+        .ok_or_else(|| anyhow!("truncated ELF at {:#x}", offset))
+}
+
+fn read_str(data: &[u8], offset: usize) -> String {
+    let bytes = data.get(offset..).unwrap_or_default();
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).into_owned()
+}
+
+/// 32 位小端 RISC-V ELF: 可执行的节和符号表, 没有节头时用可执行的段
+fn load_elf(data: &[u8], symbols: &mut SymbolTable) -> Result<Vec<Region>> {
+    const EM_RISCV: u32 = 243;
+    const SHT_PROGBITS: u32 = 1;
+    const SHT_SYMTAB: u32 = 2;
+    const SHF_EXECINSTR: u32 = 0x4;
+    const PT_LOAD: u32 = 1;
+    const PF_X: u32 = 0x1;
+    const STT_FUNC: u32 = 2;
+
+    if data.get(4) != Some(&1) || data.get(5) != Some(&1) {
+        return Err(anyhow!("not a 32-bit little-endian ELF"));
+    }
+    if read_u16(data, 18)? != EM_RISCV {
+        return Err(anyhow!("not a RISC-V ELF"));
+    }
+    let phoff = read_u32(data, 28)? as usize;
+    let shoff = read_u32(data, 32)? as usize;
+    let phentsize = read_u16(data, 42)? as usize;
+    let phnum = read_u16(data, 44)? as usize;
+    let shentsize = read_u16(data, 46)? as usize;
+    let shnum = read_u16(data, 48)? as usize;
+
+    let mut regions = Vec::new();
+    if shoff == 0 || shnum == 0 {
+        for i in 0..phnum {
+            let header = phoff + i * phentsize;
+            let [kind, offset, vaddr, filesz] =
+                [0, 4, 8, 16].map(|field| read_u32(data, header + field));
+            if kind? == PT_LOAD && read_u32(data, header + 24)? & PF_X != 0 {
+                regions.push(Region {
+                    base: vaddr?,
+                    bytes: read_bytes(data, offset?, filesz?)?.to_vec(),
+                });
+            }
+        }
+        return Ok(regions);
+    }
+
+    let section = |i: usize, field: usize| read_u32(data, shoff + i * shentsize + field);
+    for i in 0..shnum {
+        let kind = section(i, 4)?;
+        if kind == SHT_PROGBITS && section(i, 8)? & SHF_EXECINSTR != 0 {
+            regions.push(Region {
+                base: section(i, 12)?,
+                bytes: read_bytes(data, section(i, 16)?, section(i, 20)?)?.to_vec(),
+            });
+        } else if kind == SHT_SYMTAB {
+            let strtab = section(section(i, 24)? as usize, 16)? as usize;
+            let (offset, size) = (section(i, 16)? as usize, section(i, 20)? as usize);
+            for symbol in (offset..offset + size).step_by(16).skip(1) {
+                let name = read_str(data, strtab + read_u32(data, symbol)? as usize);
+                let value = read_u32(data, symbol + 4)?;
+                let kind = *data.get(symbol + 12).unwrap_or(&0) as u32 & 0xf;
+                let shndx = read_u16(data, symbol + 14)?;
+                // $x/$d 是映射符号, 不是标号
+                if name.is_empty() || name.starts_with('$') || shndx == 0 || kind > STT_FUNC {
+                    continue;
+                }
+                if !symbols.contains_key(&value) {
+                    symbols.insert(value, name);
+                }
+            }
+        }
+    }
+    Ok(regions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn disassemble(regions: Vec<Region>, symbols: SymbolTable, split: bool) -> String {
+        let mut out = Vec::new();
+        print(regions, symbols, split, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    fn code(source: &str, base: u32) -> Region {
+        Region {
+            base,
+            bytes: assemble(source, base).unwrap().text,
+        }
+    }
+
+    fn words(region: &Region) -> Vec<u32> {
+        region
+            .bytes
+            .chunks(4)
+            .map(|word| u32::from_le_bytes(word.try_into().unwrap()))
+            .collect()
+    }
+
+    /// 只有 .text, .symtab 和 .strtab 的 ELF
+    fn elf(base: u32, text: &[u8], names: &[(&str, u32)]) -> Vec<u8> {
+        let mut strtab = vec![0u8];
+        let mut symtab = vec![0u8; 16];
+        for &(name, value) in names {
+            symtab.extend((strtab.len() as u32).to_le_bytes());
+            symtab.extend(value.to_le_bytes());
+            symtab.extend(0u32.to_le_bytes());
+            // STB_GLOBAL, STT_FUNC, 在 .text 里
+            symtab.extend([0x12, 0]);
+            symtab.extend(1u16.to_le_bytes());
+            strtab.extend(name.as_bytes());
+            strtab.push(0);
+        }
+        while strtab.len() % 4 != 0 {
+            strtab.push(0);
+        }
+        let text_offset = 52;
+        let symtab_offset = text_offset + text.len();
+        let strtab_offset = symtab_offset + symtab.len();
+        let shoff = strtab_offset + strtab.len();
+
+        let mut data = b"\x7fELF\x01\x01\x01".to_vec();
+        data.resize(16, 0);
+        data.extend(2u16.to_le_bytes());
+        data.extend(243u16.to_le_bytes());
+        data.extend(1u32.to_le_bytes());
+        data.extend(base.to_le_bytes());
+        data.extend(0u32.to_le_bytes());
+        data.extend((shoff as u32).to_le_bytes());
+        data.extend(0u32.to_le_bytes());
+        for half in [52u16, 32, 0, 40, 4, 0] {
+            data.extend(half.to_le_bytes());
+        }
+        data.extend(text);
+        data.extend(&symtab);
+        data.extend(&strtab);
+        let sections = [
+            [0; 10],
+            [
+                0,
+                1,
+                0x6,
+                base,
+                text_offset as u32,
+                text.len() as u32,
+                0,
+                0,
+                4,
+                0,
+            ],
+            [
+                0,
+                2,
+                0,
+                0,
+                symtab_offset as u32,
+                symtab.len() as u32,
+                3,
+                1,
+                4,
+                16,
+            ],
+            [
+                0,
+                3,
+                0,
+                0,
+                strtab_offset as u32,
+                strtab.len() as u32,
+                0,
+                0,
+                1,
+                0,
+            ],
+        ];
+        for field in sections.iter().flatten() {
+            data.extend(field.to_le_bytes());
+        }
+        data
+    }
+
+    #[test]
+    fn undecodable_words_fall_back_to_data() {
+        let mut region = code("nop", 0);
+        region
+            .bytes
+            .extend([0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]);
+        let out = disassemble(vec![region], SymbolTable::new(), false);
+        let lines: Vec<&str> = out.lines().filter(|line| !line.is_empty()).collect();
+        assert_eq!(
+            lines,
+            [
+                "       0:\t13 00 00 00 \tnop",
+                "       4:\tff ff ff ff \t.word 0xffffffff",
+                "       8:\tff ff       \t.half 0xffff",
+                "       a:\tff          \t.byte 0xff",
+            ]
+        );
+    }
+
+    #[test]
+    fn branch_targets_get_labels() {
+        let region = code("jal ra, 8\nbnez a0, -4\nret", 0x100);
+        let out = disassemble(vec![region], SymbolTable::new(), false);
+        assert!(out.contains("00000100 <label_00000100>:"), "{}", out);
+        assert!(out.contains("00000108 <func_00000108>:"), "{}", out);
+        assert!(out.contains("jal 0x108 <func_00000108>"), "{}", out);
+    }
+
+    #[test]
+    fn load_elf_sections_and_symbols() {
+        let text = assemble("start: j next\nnext: ret", 0x8000).unwrap().text;
+        let data = elf(0x8000, &text, &[("start", 0x8000), ("next", 0x8004)]);
+        let mut symbols = SymbolTable::new();
+        let regions = load_elf(&data, &mut symbols).unwrap();
+        assert_eq!(regions.len(), 1);
+        assert_eq!(regions[0].base, 0x8000);
+        assert_eq!(regions[0].bytes, text);
+        assert_eq!(symbols.get(&0x8000).map(String::as_str), Some("start"));
+
+        let out = disassemble(regions, symbols, false);
+        assert!(out.contains("00008000 <start>:"), "{}", out);
+        assert!(out.contains("j 0x8004 <next>"), "{}", out);
+    }
+
+    #[test]
+    fn load_elf_rejects_other_machines() {
+        let mut data = elf(0, &[0x13, 0, 0, 0], &[]);
+        data[18] = 0x3e;
+        data[19] = 0;
+        let error = load_elf(&data, &mut SymbolTable::new()).err().unwrap();
+        assert_eq!(error.to_string(), "not a RISC-V ELF");
+    }
+
+    #[test]
+    fn load_coe_vector() {
+        let text = "; comment\n\
+                    memory_initialization_radix = 16;\n\
+                    memory_initialization_vector =\n\
+                    00000013,\n\
+                    00100093;\n";
+        let regions = load_coe(text, 0x400).unwrap();
+        assert_eq!(regions.len(), 1);
+        assert_eq!(regions[0].base, 0x400);
+        assert_eq!(words(&regions[0]), [0x0000_0013, 0x0010_0093]);
+
+        let error = load_coe("memory_initialization_radix=16;", 0)
+            .err()
+            .unwrap();
+        assert_eq!(error.to_string(), "missing memory_initialization_vector");
+    }
+
+    #[test]
+    fn load_hex_addresses_are_in_words() {
+        let text = "00000013 // nop\n@4\n00100093\n00200113\n";
+        let regions = load_hex(text, 0x1000).unwrap();
+        assert_eq!(regions.len(), 2);
+        assert_eq!(regions[0].base, 0x1000);
+        assert_eq!(words(&regions[0]), [0x0000_0013]);
+        assert_eq!(regions[1].base, 0x1010);
+        assert_eq!(words(&regions[1]), [0x0010_0093, 0x0020_0113]);
+    }
+
+    #[test]
+    fn split_groups_user_and_kernel() {
+        let regions = vec![code("ret", KERNEL_BASE), code("nop", USER_BASE)];
+        let out = disassemble(regions, SymbolTable::new(), true);
+        let user = out.find("Disassembly of user:").unwrap();
+        let kernel = out.find("Disassembly of kernel:").unwrap();
+        let nop = out.find("nop").unwrap();
+        let ret = out.find("ret").unwrap();
+        assert!(user < nop && nop < kernel && kernel < ret, "{}", out);
+
+        let out = disassemble(vec![code("nop", USER_BASE)], SymbolTable::new(), false);
+        assert!(!out.contains("Disassembly of"), "{}", out);
+    }
+}
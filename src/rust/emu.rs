@@ -13,7 +13,19 @@ fn main() {
         .unwrap()
         .read_to_end(&mut kernel)
         .unwrap();
-    let mut cpu = CPU::new(&user, 0, &kernel, 0x1c09_0000, 0, (1 << 14) << 2);
+    let mut cpu = CPU::new(
+        &user,
+        0,
+        &kernel,
+        0x1c09_0000,
+        0,
+        (1 << 14) << 2,
+        Config {
+            // trap_handle.bin 用 uret 返回
+            uret_as_mret: true,
+            ..Default::default()
+        },
+    );
     let mut cycles = 0;
     while cycles < 1_000_000 {
         println!("==========  ==========");
@@ -23,7 +35,7 @@ fn main() {
         let code = cpu.fetch().unwrap();
         // 将要执行的动作
         println!("pc={:#x}", pc);
-        println!("{}", Instr::try_from(code).unwrap());
+        println!("{}", Instr::try_from(code).unwrap().disasm(pc, None));
         cpu.pc_step();
         // if let Err(e) = cpu.execute(code) {
         //     dbg!(e);
@@ -31,10 +43,12 @@ fn main() {
         // }
         match cpu.execute(code) {
             Ok(info) => {
-                println!(
-                    "wen={}, rd={}, val={:#x}",
-                    info.wb_ena, ABI[info.wb_rd as usize], info.wb_val
-                );
+                let rd = if info.wb_fp != 0 {
+                    FABI[info.wb_rd as usize]
+                } else {
+                    cpu.abi()[info.wb_rd as usize]
+                };
+                println!("wen={}, rd={}, val={:#x}", info.wb_ena, rd, info.wb_val);
             }
             Err(e) => {
                 dbg!(e);
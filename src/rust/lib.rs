@@ -2,6 +2,29 @@ pub mod rvemu;
 pub mod utils;
 
 use rvemu::*;
+use std::cell::Cell;
+
+thread_local! {
+    /// 这个线程上一次可能出错的调用的错误码
+    static LAST_ERROR: Cell<u32> = const { Cell::new(ERROR_NONE) };
+}
+
+/// 记下错误码, 出错时返回 None
+fn check<T>(result: anyhow::Result<T>) -> Option<T> {
+    LAST_ERROR.set(result.as_ref().err().map_or(ERROR_NONE, error_code));
+    result.ok()
+}
+
+/// 上一次 rvemu_execute, rvemu_run 或 rvemu_fetch 的错误码, 成功时是 0
+#[no_mangle]
+pub extern "C" fn rvemu_last_error() -> u32 {
+    LAST_ERROR.get()
+}
+
+#[no_mangle]
+pub extern "C" fn rvemu_config_default() -> Config {
+    Config::default()
+}
 
 /// # Safety
 #[no_mangle]
@@ -14,10 +37,19 @@ pub unsafe extern "C" fn rvemu_new(
     kernel_len: u32,
     dram_base: u32,
     dram_size: u32,
+    config: Config,
 ) -> *mut CPU {
     let user = std::slice::from_raw_parts(user_ptr, user_len as usize);
     let kernel = std::slice::from_raw_parts(kernel_ptr, kernel_len as usize);
-    let cpu = CPU::new(user, user_base, kernel, kernel_base, dram_base, dram_size);
+    let cpu = CPU::new(
+        user,
+        user_base,
+        kernel,
+        kernel_base,
+        dram_base,
+        dram_size,
+        config,
+    );
     Box::into_raw(Box::new(cpu))
 }
 
@@ -34,18 +66,34 @@ pub unsafe extern "C" fn rvemu_free(cpu: *mut CPU) {
 #[no_mangle]
 pub unsafe extern "C" fn rvemu_execute(cpu: *mut CPU, inst: u32) -> WBInfo {
     let cpu = &mut *cpu;
-    match cpu.execute(inst) {
-        Ok(wb_status) => wb_status,
-        Err(_) => WBInfo::default(),
-    }
+    check(cpu.execute(inst)).unwrap_or_default()
+}
+
+/// 按基本块运行最多 max_steps 步, 返回执行完的步数, 出错停下时小于 max_steps
+///
+/// # Safety
+#[no_mangle]
+pub unsafe extern "C" fn rvemu_run(cpu: *mut CPU, max_steps: u64) -> u64 {
+    let cpu = &mut *cpu;
+    let (steps, result) = cpu.run(max_steps);
+    check(result);
+    steps
 }
 
+/// 出错时返回 0, 错误码由 rvemu_last_error 得到
+///
 /// # Safety
 #[no_mangle]
-pub unsafe extern "C" fn rvemu_fetch(cpu: *const CPU) -> u32 {
+pub unsafe extern "C" fn rvemu_fetch(cpu: *mut CPU) -> u32 {
+    let cpu = &mut *cpu;
+    check(cpu.fetch()).unwrap_or(0)
+}
+
+/// # Safety
+#[no_mangle]
+pub unsafe extern "C" fn rvemu_tlb_stats(cpu: *const CPU) -> TlbStats {
     let cpu = &*cpu;
-    // 1.
-    cpu.fetch().unwrap()
+    cpu.tlb_stats()
 }
 
 /// # Safety
@@ -69,8 +117,27 @@ pub unsafe extern "C" fn rvemu_pc(cpu: *const CPU) -> u32 {
     cpu.pc()
 }
 
+/// # Safety
+#[no_mangle]
+pub unsafe extern "C" fn rvemu_set_switch(cpu: *mut CPU, value: u32) {
+    let cpu = &mut *cpu;
+    cpu.set_switch(value);
+}
+
+/// 中断源编号不合法时返回 false
+///
+/// # Safety
+#[no_mangle]
+pub unsafe extern "C" fn rvemu_set_irq(cpu: *mut CPU, source: u32, level: bool) -> bool {
+    let cpu = &mut *cpu;
+    cpu.set_irq(source, level).is_ok()
+}
+
 /// # Safety
 #[no_mangle]
 pub unsafe extern "C" fn disasm(inst: u32) {
-    println!("{}", Instr::try_from(inst).unwrap());
+    match Instr::try_from(inst) {
+        Ok(inst) => println!("{}", inst),
+        Err(_) => println!(".word {:#010x}", inst),
+    }
 }
@@ -0,0 +1,1119 @@
+use super::*;
+use std::collections::BTreeMap;
+
+/// 汇编的结果. .text 从 base 开始, .data 紧跟在 .text 之后 (4 字节对齐).
+pub struct Program {
+    pub base: u32,
+    pub text: Vec<u8>,
+    pub data: Vec<u8>,
+    /// 标号的地址
+    pub symbols: BTreeMap<String, u32>,
+}
+
+impl Program {
+    /// .data 的起始地址
+    pub fn data_base(&self) -> u32 {
+        self.base.wrapping_add(align_up(self.text.len() as u32, 4))
+    }
+
+    /// `CPU::new` 的 user 镜像: .text 和 .data 拼在一起.
+    /// 镜像同时装入 IROM 和 DRAM, 所以 dram_base 要和 user_base 相同.
+    pub fn image(&self) -> Vec<u8> {
+        let mut image = self.text.clone();
+        image.resize(align_up(self.text.len() as u32, 4) as usize, 0);
+        image.extend_from_slice(&self.data);
+        image
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Section {
+    Text,
+    Data,
+}
+
+enum Stmt {
+    /// 指令或伪指令, 操作数在第二遍解析
+    Inst(String, Vec<String>),
+    /// .word/.half/.byte: 每一项的字节数和表达式
+    Data(u32, Vec<String>),
+    /// .align 填充的 0
+    Fill(u32),
+}
+
+struct Item {
+    line: usize,
+    section: Section,
+    offset: u32,
+    size: u32,
+    stmt: Stmt,
+}
+
+fn align_up(value: u32, align: u32) -> u32 {
+    value.wrapping_add(align - 1) & !(align - 1)
+}
+
+/// 汇编一个源文件, .text 放在 base.
+///
+/// 分支和跳转的目标是标号时按 pc 相对计算; 是数字时就是偏移量, 与 `Instr` 的 `Display` 一致.
+pub fn assemble(source: &str, base: u32) -> Result<Program> {
+    // 第一遍: 确定每条语句的位置和标号
+    let mut items = Vec::new();
+    let mut labels: Vec<(String, Section, u32)> = Vec::new();
+    let mut section = Section::Text;
+    let mut offsets = [0u32; 2];
+    for (n, raw) in source.lines().enumerate() {
+        let line = n + 1;
+        let mut text = strip_comment(raw).trim();
+        while let Some((label, rest)) = split_label(text) {
+            if labels.iter().any(|(name, _, _)| name == label) {
+                return Err(anyhow!("line {}: duplicate label: {}", line, label))
+                    .with_context(|| context!());
+            }
+            labels.push((label.to_string(), section, offsets[section as usize]));
+            text = rest.trim();
+        }
+        if text.is_empty() {
+            continue;
+        }
+        let (head, rest) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+        let operands = split_operands(rest);
+        let offset = offsets[section as usize];
+        let stmt = match head {
+            ".text" => {
+                section = Section::Text;
+                continue;
+            }
+            ".data" => {
+                section = Section::Data;
+                continue;
+            }
+            ".globl" | ".global" => continue,
+            ".word" => Stmt::Data(4, operands),
+            ".half" => Stmt::Data(2, operands),
+            ".byte" => Stmt::Data(1, operands),
+            // riscv 的 .align n 对齐到 2^n
+            ".align" => {
+                let n = operands
+                    .first()
+                    .ok_or_else(|| anyhow!(".align needs an operand"))
+                    .and_then(|expr| eval(expr, &BTreeMap::new()))
+                    .with_context(|| format!("line {}: {}", line, raw.trim()))?;
+                if !(0..=12).contains(&n) {
+                    return Err(anyhow!("line {}: invalid alignment: {}", line, n))
+                        .with_context(|| context!());
+                }
+                Stmt::Fill(align_up(offset, 1 << n) - offset)
+            }
+            directive if directive.starts_with('.') => {
+                return Err(anyhow!("line {}: unknown directive: {}", line, directive))
+                    .with_context(|| context!());
+            }
+            mnemonic => Stmt::Inst(mnemonic.to_string(), operands),
+        };
+        let size = match &stmt {
+            Stmt::Inst(mnemonic, operands) => 4 * inst_count(mnemonic, operands),
+            Stmt::Data(size, exprs) => size * exprs.len() as u32,
+            Stmt::Fill(size) => *size,
+        };
+        offsets[section as usize] += size;
+        items.push(Item {
+            line,
+            section,
+            offset,
+            size,
+            stmt,
+        });
+    }
+
+    let data_base = base.wrapping_add(align_up(offsets[Section::Text as usize], 4));
+    let section_base = |section: Section| match section {
+        Section::Text => base,
+        Section::Data => data_base,
+    };
+    let symbols: BTreeMap<String, u32> = labels
+        .into_iter()
+        .map(|(name, section, offset)| (name, section_base(section).wrapping_add(offset)))
+        .collect();
+
+    // 第二遍: 编码
+    let mut text = Vec::new();
+    let mut data = Vec::new();
+    for item in items {
+        let pc = section_base(item.section).wrapping_add(item.offset);
+        let bytes = assemble_item(&item.stmt, pc, &symbols).with_context(|| {
+            let raw = source.lines().nth(item.line - 1).unwrap_or_default();
+            format!("line {}: {}", item.line, raw.trim())
+        })?;
+        assert_eq!(bytes.len() as u32, item.size);
+        match item.section {
+            Section::Text => text.extend(bytes),
+            Section::Data => data.extend(bytes),
+        }
+    }
+    Ok(Program {
+        base,
+        text,
+        data,
+        symbols,
+    })
+}
+
+fn assemble_item(stmt: &Stmt, pc: u32, symbols: &BTreeMap<String, u32>) -> Result<Vec<u8>> {
+    match stmt {
+        Stmt::Inst(mnemonic, operands) => {
+            let mut bytes = Vec::new();
+            for inst in expand(mnemonic, operands, pc, symbols)? {
+                bytes.extend(inst.encode()?.to_le_bytes());
+            }
+            Ok(bytes)
+        }
+        Stmt::Data(size, exprs) => {
+            let mut bytes = Vec::new();
+            for expr in exprs {
+                let value = eval(expr, symbols)?;
+                let bits = size * 8;
+                if value < -(1 << (bits - 1)) || value >= 1 << bits {
+                    return Err(anyhow!("{} does not fit in {} bytes", value, size))
+                        .with_context(|| context!());
+                }
+                bytes.extend(&value.to_le_bytes()[..*size as usize]);
+            }
+            Ok(bytes)
+        }
+        Stmt::Fill(size) => Ok(vec![0; *size as usize]),
+    }
+}
+
+fn strip_comment(line: &str) -> &str {
+    let end = [line.find('#'), line.find("//")]
+        .into_iter()
+        .flatten()
+        .min()
+        .unwrap_or(line.len());
+    &line[..end]
+}
+
+fn is_ident(s: &str) -> bool {
+    let mut chars = s.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_' || c == '.')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '$')
+}
+
+/// "label: rest" 拆成标号和剩下的部分
+fn split_label(text: &str) -> Option<(&str, &str)> {
+    let (label, rest) = text.split_once(':')?;
+    is_ident(label.trim()).then(|| (label.trim(), rest))
+}
+
+/// 按括号外的逗号分开操作数
+fn split_operands(text: &str) -> Vec<String> {
+    let mut operands = Vec::new();
+    let mut depth = 0;
+    let mut current = String::new();
+    for c in text.chars() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                operands.push(current.trim().to_string());
+                current.clear();
+                continue;
+            }
+            _ => {}
+        }
+        current.push(c);
+    }
+    if !current.trim().is_empty() || !operands.is_empty() {
+        operands.push(current.trim().to_string());
+    }
+    operands
+}
+
+/// 求值: 数字、标号、%hi()/%lo() 和它们的加减
+fn eval(expr: &str, symbols: &BTreeMap<String, u32>) -> Result<i64> {
+    let mut parser = Parser {
+        s: expr.trim(),
+        symbols,
+    };
+    let value = parser.expr()?;
+    if !parser.s.trim().is_empty() {
+        return Err(anyhow!(
+            "unexpected {:?} in expression {:?}",
+            parser.s,
+            expr
+        ))
+        .with_context(|| context!());
+    }
+    Ok(value)
+}
+
+/// 不引用标号的表达式在第一遍就能求值
+fn is_constant(expr: &str) -> bool {
+    eval(expr, &BTreeMap::new()).is_ok()
+}
+
+struct Parser<'a> {
+    s: &'a str,
+    symbols: &'a BTreeMap<String, u32>,
+}
+
+impl Parser<'_> {
+    fn eat(&mut self, token: &str) -> bool {
+        self.s = self.s.trim_start();
+        match self.s.strip_prefix(token) {
+            Some(rest) => {
+                self.s = rest;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn expr(&mut self) -> Result<i64> {
+        let mut value = if self.eat("-") {
+            -self.term()?
+        } else {
+            self.eat("+");
+            self.term()?
+        };
+        loop {
+            if self.eat("+") {
+                value += self.term()?;
+            } else if self.eat("-") {
+                value -= self.term()?;
+            } else {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn parenthesized(&mut self) -> Result<i64> {
+        if !self.eat("(") {
+            return Err(anyhow!("expected '('")).with_context(|| context!());
+        }
+        let value = self.expr()?;
+        if !self.eat(")") {
+            return Err(anyhow!("expected ')'")).with_context(|| context!());
+        }
+        Ok(value)
+    }
+
+    fn term(&mut self) -> Result<i64> {
+        if self.eat("%hi") {
+            let value = self.parenthesized()?;
+            return Ok((value.wrapping_add(0x800) >> 12) & 0xfffff);
+        }
+        if self.eat("%lo") {
+            let value = self.parenthesized()?;
+            return Ok(((value as i32) << 20 >> 20) as i64);
+        }
+        if self.s.starts_with('(') {
+            return self.parenthesized();
+        }
+        let end = self
+            .s
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '$'))
+            .unwrap_or(self.s.len());
+        let (token, rest) = self.s.split_at(end);
+        self.s = rest;
+        let number = if let Some(hex) = token.strip_prefix("0x") {
+            i64::from_str_radix(hex, 16).ok()
+        } else if let Some(bin) = token.strip_prefix("0b") {
+            i64::from_str_radix(bin, 2).ok()
+        } else {
+            token.parse::<i64>().ok()
+        };
+        match number {
+            Some(value) => Ok(value),
+            None if is_ident(token) => match self.symbols.get(token) {
+                Some(&addr) => Ok(addr as i64),
+                None => Err(anyhow!("undefined symbol: {}", token)).with_context(|| context!()),
+            },
+            None => Err(anyhow!("invalid number: {:?}", token)).with_context(|| context!()),
+        }
+    }
+}
+
+/// 32 位的值: 超过 i32 的十六进制数按补码解释
+fn to_i32(value: i64) -> Result<i32> {
+    if value < i32::MIN as i64 || value > u32::MAX as i64 {
+        return Err(anyhow!("{} does not fit in 32 bits", value)).with_context(|| context!());
+    }
+    Ok(value as u32 as i32)
+}
+
+/// 伪指令展开成几条指令
+fn inst_count(mnemonic: &str, operands: &[String]) -> u32 {
+    match mnemonic {
+        "la" | "call" => 2,
+        "li" => match operands.get(1) {
+            Some(expr) if is_constant(expr) => {
+                let value = eval(expr, &BTreeMap::new()).map_or(0, |v| v as i32);
+                li(0, value, true).len() as u32
+            }
+            _ => 2,
+        },
+        _ => 1,
+    }
+}
+
+/// li 的展开: 12 位以内用 addi, 否则 lui + addi.
+/// 引用标号时第一遍还不知道值, 总是用两条.
+fn li(rd: Reg, value: i32, constant: bool) -> Vec<Instr> {
+    if constant && (-2048..2048).contains(&value) {
+        return vec![Instr::ADDI(rd, 0, value)];
+    }
+    let hi = (value as u32).wrapping_add(0x800) & 0xffff_f000;
+    let lo = value.wrapping_sub(hi as i32);
+    if constant && lo == 0 {
+        return vec![Instr::LUI(rd, hi)];
+    }
+    vec![Instr::LUI(rd, hi), Instr::ADDI(rd, rd, lo)]
+}
+
+/// pc 相对的 auipc + 12 位偏移
+fn pcrel(offset: i32) -> (u32, i32) {
+    let hi = (offset as u32).wrapping_add(0x800) & 0xffff_f000;
+    (hi, offset.wrapping_sub(hi as i32))
+}
+
+fn reg_by_name(name: &str) -> Option<Reg> {
+    if let Some(i) = ABI.iter().position(|abi| abi.trim() == name) {
+        return Some(i as Reg);
+    }
+    if name == "fp" {
+        return Some(8);
+    }
+    let n = name.strip_prefix('x')?.parse::<Reg>().ok()?;
+    (n < 32).then_some(n)
+}
+
+fn freg_by_name(name: &str) -> Option<Reg> {
+    if let Some(i) = FABI.iter().position(|abi| abi.trim() == name) {
+        return Some(i as Reg);
+    }
+    let n = name.strip_prefix('f')?.parse::<Reg>().ok()?;
+    (n < 32).then_some(n)
+}
+
+/// 一条指令的操作数
+struct Operands<'a> {
+    ops: &'a [String],
+    pc: u32,
+    symbols: &'a BTreeMap<String, u32>,
+}
+
+impl Operands<'_> {
+    fn expect(&self, min: usize, max: usize) -> Result<()> {
+        if self.ops.len() < min || self.ops.len() > max {
+            return Err(anyhow!(
+                "expected {} operands, found {}",
+                if min == max {
+                    min.to_string()
+                } else {
+                    format!("{}-{}", min, max)
+                },
+                self.ops.len()
+            ))
+            .with_context(|| context!());
+        }
+        Ok(())
+    }
+
+    fn get(&self, i: usize) -> Result<&str> {
+        self.ops
+            .get(i)
+            .map(|op| op.as_str())
+            .ok_or_else(|| anyhow!("missing operand {}", i + 1))
+    }
+
+    fn reg(&self, i: usize) -> Result<Reg> {
+        let op = self.get(i)?;
+        reg_by_name(op)
+            .ok_or_else(|| anyhow!("invalid register: {:?}", op))
+            .with_context(|| context!())
+    }
+
+    fn freg(&self, i: usize) -> Result<Reg> {
+        let op = self.get(i)?;
+        freg_by_name(op)
+            .ok_or_else(|| anyhow!("invalid float register: {:?}", op))
+            .with_context(|| context!())
+    }
+
+    fn value(&self, i: usize) -> Result<i64> {
+        eval(self.get(i)?, self.symbols)
+    }
+
+    fn imm(&self, i: usize) -> Result<i32> {
+        to_i32(self.value(i)?)
+    }
+
+    /// 标号是绝对地址, 数字是相对 pc 的偏移
+    fn target(&self, i: usize) -> Result<i32> {
+        let value = to_i32(self.value(i)?)?;
+        if is_constant(self.get(i)?) {
+            Ok(value)
+        } else {
+            Ok(value.wrapping_sub(self.pc as i32))
+        }
+    }
+
+    /// 标号或数字都是绝对地址, 返回相对 pc 的偏移
+    fn address(&self, i: usize) -> Result<i32> {
+        Ok(to_i32(self.value(i)?)?.wrapping_sub(self.pc as i32))
+    }
+
+    /// offset(base), offset 可以省略
+    fn mem(&self, i: usize) -> Result<(i32, Reg)> {
+        let op = self.get(i)?;
+        let (offset, base) = op
+            .strip_suffix(')')
+            .and_then(|op| op.rsplit_once('('))
+            .ok_or_else(|| anyhow!("expected offset(base): {:?}", op))?;
+        let base = reg_by_name(base.trim())
+            .ok_or_else(|| anyhow!("invalid register: {:?}", base))
+            .with_context(|| context!())?;
+        let offset = if offset.trim().is_empty() {
+            0
+        } else {
+            to_i32(eval(offset, self.symbols)?)?
+        };
+        Ok((offset, base))
+    }
+
+    /// 原子指令的地址只能是 (rs1)
+    fn addr_reg(&self, i: usize) -> Result<Reg> {
+        match self.mem(i)? {
+            (0, base) => Ok(base),
+            _ => Err(anyhow!("atomic address must be (rs1)")).with_context(|| context!()),
+        }
+    }
+
+    fn csr(&self, i: usize) -> Result<CSR> {
+        let op = self.get(i)?;
+        if let Some(addr) = (0..4096).find(|addr| csr_abi(addr) == op) {
+            return Ok(addr);
+        }
+        self.imm(i).map(|addr| addr as CSR)
+    }
+
+    /// 可选的舍入模式, 默认是 dyn
+    fn rm(&self, i: usize) -> Result<u32> {
+        match self.ops.get(i).map(|op| op.as_str()) {
+            None | Some("dyn") => Ok(0b111),
+            Some("rne") => Ok(0b000),
+            Some("rtz") => Ok(0b001),
+            Some("rdn") => Ok(0b010),
+            Some("rup") => Ok(0b011),
+            Some("rmm") => Ok(0b100),
+            Some(op) => Err(anyhow!("invalid rounding mode: {:?}", op)).with_context(|| context!()),
+        }
+    }
+
+    /// fence 的 pred/succ, 省略时是 iorw
+    fn fence_set(&self, i: usize) -> Result<u32> {
+        let op = match self.ops.get(i) {
+            Some(op) => op,
+            None => return Ok(0xf),
+        };
+        op.chars().try_fold(0, |set, c| match c {
+            'i' => Ok(set | 0x8),
+            'o' => Ok(set | 0x4),
+            'r' => Ok(set | 0x2),
+            'w' => Ok(set | 0x1),
+            _ => Err(anyhow!("invalid fence set: {:?}", op)),
+        })
+    }
+}
+
+type RType = fn(Reg, Reg, Reg) -> Instr;
+type IType = fn(Reg, Reg, i32) -> Instr;
+type Unary = fn(Reg, Reg) -> Instr;
+type Mem = fn(Reg, i32, Reg) -> Instr;
+type Branch = fn(Reg, Reg, i32) -> Instr;
+type Amo = fn(Reg, Reg, Reg, u32) -> Instr;
+type Csr = fn(Reg, CSR, Reg) -> Instr;
+type FArith = fn(Reg, Reg, Reg, u32) -> Instr;
+type Fma = fn(Reg, Reg, Reg, Reg, u32) -> Instr;
+type FConvert = fn(Reg, Reg, u32) -> Instr;
+
+/// rd, rs1, rs2
+const R_TYPE: &[(&str, RType)] = &[
+    ("add", Instr::ADD),
+    ("sub", Instr::SUB),
+    ("sll", Instr::SLL),
+    ("slt", Instr::SLT),
+    ("sltu", Instr::SLTU),
+    ("xor", Instr::XOR),
+    ("srl", Instr::SRL),
+    ("sra", Instr::SRA),
+    ("or", Instr::OR),
+    ("and", Instr::AND),
+    ("mul", Instr::MUL),
+    ("mulh", Instr::MULH),
+    ("mulhsu", Instr::MULHSU),
+    ("mulhu", Instr::MULHU),
+    ("div", Instr::DIV),
+    ("divu", Instr::DIVU),
+    ("rem", Instr::REM),
+    ("remu", Instr::REMU),
+    ("sh1add", Instr::SH1ADD),
+    ("sh2add", Instr::SH2ADD),
+    ("sh3add", Instr::SH3ADD),
+    ("andn", Instr::ANDN),
+    ("orn", Instr::ORN),
+    ("xnor", Instr::XNOR),
+    ("max", Instr::MAX),
+    ("maxu", Instr::MAXU),
+    ("min", Instr::MIN),
+    ("minu", Instr::MINU),
+    ("rol", Instr::ROL),
+    ("ror", Instr::ROR),
+    ("bclr", Instr::BCLR),
+    ("bext", Instr::BEXT),
+    ("binv", Instr::BINV),
+    ("bset", Instr::BSET),
+];
+
+/// rd, rs1, imm
+const I_TYPE: &[(&str, IType)] = &[
+    ("addi", Instr::ADDI),
+    ("andi", Instr::ANDI),
+    ("ori", Instr::ORI),
+    ("xori", Instr::XORI),
+    ("slli", Instr::SLLI),
+    ("srli", Instr::SRLI),
+    ("srai", Instr::SRAI),
+    ("slti", Instr::SLTI),
+    ("sltiu", Instr::SLTIU),
+    ("rori", Instr::RORI),
+    ("bclri", Instr::BCLRI),
+    ("bexti", Instr::BEXTI),
+    ("binvi", Instr::BINVI),
+    ("bseti", Instr::BSETI),
+];
+
+/// rd, rs1
+const UNARY: &[(&str, Unary)] = &[
+    ("clz", Instr::CLZ),
+    ("ctz", Instr::CTZ),
+    ("cpop", Instr::CPOP),
+    ("sext.b", Instr::SEXTB),
+    ("sext.h", Instr::SEXTH),
+    ("zext.h", Instr::ZEXTH),
+    ("orc.b", Instr::ORCB),
+    ("rev8", Instr::REV8),
+];
+
+/// rd, offset(rs1)
+const LOAD: &[(&str, Mem)] = &[
+    ("lb", Instr::LB),
+    ("lh", Instr::LH),
+    ("lw", Instr::LW),
+    ("lbu", Instr::LBU),
+    ("lhu", Instr::LHU),
+];
+
+/// rs2, offset(rs1)
+const STORE: &[(&str, Mem)] = &[("sb", Instr::SB), ("sh", Instr::SH), ("sw", Instr::SW)];
+
+/// rs1, rs2, target
+const BRANCH: &[(&str, Branch)] = &[
+    ("beq", Instr::BEQ),
+    ("bne", Instr::BNE),
+    ("blt", Instr::BLT),
+    ("bge", Instr::BGE),
+    ("bltu", Instr::BLTU),
+    ("bgeu", Instr::BGEU),
+];
+
+/// rd, rs2, (rs1)
+const AMO: &[(&str, Amo)] = &[
+    ("sc.w", Instr::SCW),
+    ("amoswap.w", Instr::AMOSWAPW),
+    ("amoadd.w", Instr::AMOADDW),
+    ("amoxor.w", Instr::AMOXORW),
+    ("amoand.w", Instr::AMOANDW),
+    ("amoor.w", Instr::AMOORW),
+    ("amomin.w", Instr::AMOMINW),
+    ("amomax.w", Instr::AMOMAXW),
+    ("amominu.w", Instr::AMOMINUW),
+    ("amomaxu.w", Instr::AMOMAXUW),
+];
+
+/// rd, csr, rs1 (或 zimm)
+const CSR_OPS: &[(&str, Csr)] = &[
+    ("csrrw", Instr::CSRRW),
+    ("csrrs", Instr::CSRRS),
+    ("csrrc", Instr::CSRRC),
+    ("csrrwi", Instr::CSRRWI),
+    ("csrrsi", Instr::CSRRSI),
+    ("csrrci", Instr::CSRRCI),
+];
+
+/// fd, fs1, fs2 [, rm]
+const F_ARITH: &[(&str, FArith)] = &[
+    ("fadd.s", Instr::FADDS),
+    ("fsub.s", Instr::FSUBS),
+    ("fmul.s", Instr::FMULS),
+    ("fdiv.s", Instr::FDIVS),
+];
+
+/// fd, fs1, fs2, fs3 [, rm]
+const F_FMA: &[(&str, Fma)] = &[
+    ("fmadd.s", Instr::FMADDS),
+    ("fmsub.s", Instr::FMSUBS),
+    ("fnmsub.s", Instr::FNMSUBS),
+    ("fnmadd.s", Instr::FNMADDS),
+];
+
+/// fd, fs1, fs2
+const F_BINARY: &[(&str, RType)] = &[
+    ("fsgnj.s", Instr::FSGNJS),
+    ("fsgnjn.s", Instr::FSGNJNS),
+    ("fsgnjx.s", Instr::FSGNJXS),
+    ("fmin.s", Instr::FMINS),
+    ("fmax.s", Instr::FMAXS),
+];
+
+/// rd, fs1, fs2
+const F_COMPARE: &[(&str, RType)] = &[
+    ("feq.s", Instr::FEQS),
+    ("flt.s", Instr::FLTS),
+    ("fle.s", Instr::FLES),
+];
+
+fn lookup<T: Copy>(table: &[(&str, T)], mnemonic: &str) -> Option<T> {
+    table
+        .iter()
+        .find(|(name, _)| *name == mnemonic)
+        .map(|&(_, f)| f)
+}
+
+/// 原子指令的 .aq/.rl/.aqrl 后缀
+fn split_aqrl(mnemonic: &str) -> (&str, u32) {
+    for (suffix, aqrl) in [(".aqrl", 0b11), (".aq", 0b10), (".rl", 0b01)] {
+        if let Some(base) = mnemonic.strip_suffix(suffix) {
+            return (base, aqrl);
+        }
+    }
+    (mnemonic, 0)
+}
+
+/// 解析一条指令 (包括伪指令), pc 用于计算标号的偏移
+fn expand(
+    mnemonic: &str,
+    ops: &[String],
+    pc: u32,
+    symbols: &BTreeMap<String, u32>,
+) -> Result<Vec<Instr>> {
+    let o = Operands { ops, pc, symbols };
+    let (base, aqrl) = split_aqrl(mnemonic);
+    if let Some(f) = lookup(R_TYPE, mnemonic) {
+        o.expect(3, 3)?;
+        return Ok(vec![f(o.reg(0)?, o.reg(1)?, o.reg(2)?)]);
+    }
+    if let Some(f) = lookup(I_TYPE, mnemonic) {
+        o.expect(3, 3)?;
+        return Ok(vec![f(o.reg(0)?, o.reg(1)?, o.imm(2)?)]);
+    }
+    if let Some(f) = lookup(UNARY, mnemonic) {
+        o.expect(2, 2)?;
+        return Ok(vec![f(o.reg(0)?, o.reg(1)?)]);
+    }
+    if let Some(f) = lookup(LOAD, mnemonic).or_else(|| lookup(STORE, mnemonic)) {
+        o.expect(2, 2)?;
+        let (offset, rs1) = o.mem(1)?;
+        return Ok(vec![f(o.reg(0)?, offset, rs1)]);
+    }
+    if let Some(f) = lookup(BRANCH, mnemonic) {
+        o.expect(3, 3)?;
+        return Ok(vec![f(o.reg(0)?, o.reg(1)?, o.target(2)?)]);
+    }
+    if let Some(f) = lookup(AMO, base) {
+        o.expect(3, 3)?;
+        return Ok(vec![f(o.reg(0)?, o.reg(1)?, o.addr_reg(2)?, aqrl)]);
+    }
+    if let Some(f) = lookup(CSR_OPS, mnemonic) {
+        o.expect(3, 3)?;
+        let src = if mnemonic.ends_with('i') {
+            o.imm(2)? as u32
+        } else {
+            o.reg(2)?
+        };
+        return Ok(vec![f(o.reg(0)?, o.csr(1)?, src)]);
+    }
+    if let Some(f) = lookup(F_ARITH, mnemonic) {
+        o.expect(3, 4)?;
+        return Ok(vec![f(o.freg(0)?, o.freg(1)?, o.freg(2)?, o.rm(3)?)]);
+    }
+    if let Some(f) = lookup(F_FMA, mnemonic) {
+        o.expect(4, 5)?;
+        let [rd, rs1, rs2, rs3] = [o.freg(0)?, o.freg(1)?, o.freg(2)?, o.freg(3)?];
+        return Ok(vec![f(rd, rs1, rs2, rs3, o.rm(4)?)]);
+    }
+    if let Some(f) = lookup(F_BINARY, mnemonic) {
+        o.expect(3, 3)?;
+        return Ok(vec![f(o.freg(0)?, o.freg(1)?, o.freg(2)?)]);
+    }
+    if let Some(f) = lookup(F_COMPARE, mnemonic) {
+        o.expect(3, 3)?;
+        return Ok(vec![f(o.reg(0)?, o.freg(1)?, o.freg(2)?)]);
+    }
+    let inst = match mnemonic {
+        "lui" | "auipc" => {
+            o.expect(2, 2)?;
+            let imm = o.value(1)?;
+            if !(0..=0xfffff).contains(&imm) {
+                return Err(anyhow!("{} immediate {:#x} is not 20 bits", mnemonic, imm))
+                    .with_context(|| context!());
+            }
+            let imm = (imm as u32) << 12;
+            if mnemonic == "lui" {
+                Instr::LUI(o.reg(0)?, imm)
+            } else {
+                Instr::AUIPC(o.reg(0)?, imm)
+            }
+        }
+        "jal" => {
+            o.expect(1, 2)?;
+            if ops.len() == 1 {
+                Instr::JAL(1, o.target(0)?)
+            } else {
+                Instr::JAL(o.reg(0)?, o.target(1)?)
+            }
+        }
+        "jalr" => {
+            o.expect(1, 2)?;
+            if ops.len() == 1 {
+                Instr::JALR(1, 0, o.reg(0)?)
+            } else {
+                let (offset, rs1) = o.mem(1)?;
+                Instr::JALR(o.reg(0)?, offset, rs1)
+            }
+        }
+        "lr.w" | "lr.w.aq" | "lr.w.rl" | "lr.w.aqrl" => {
+            o.expect(2, 2)?;
+            Instr::LRW(o.reg(0)?, o.addr_reg(1)?, aqrl)
+        }
+        "flw" | "fsw" => {
+            o.expect(2, 2)?;
+            let (offset, rs1) = o.mem(1)?;
+            if mnemonic == "flw" {
+                Instr::FLW(o.freg(0)?, offset, rs1)
+            } else {
+                Instr::FSW(o.freg(0)?, offset, rs1)
+            }
+        }
+        "fsqrt.s" => {
+            o.expect(2, 3)?;
+            Instr::FSQRTS(o.freg(0)?, o.freg(1)?, o.rm(2)?)
+        }
+        "fcvt.w.s" | "fcvt.wu.s" => {
+            o.expect(2, 3)?;
+            let f: FConvert = if mnemonic == "fcvt.w.s" {
+                Instr::FCVTWS
+            } else {
+                Instr::FCVTWUS
+            };
+            f(o.reg(0)?, o.freg(1)?, o.rm(2)?)
+        }
+        "fcvt.s.w" | "fcvt.s.wu" => {
+            o.expect(2, 3)?;
+            let f: FConvert = if mnemonic == "fcvt.s.w" {
+                Instr::FCVTSW
+            } else {
+                Instr::FCVTSWU
+            };
+            f(o.freg(0)?, o.reg(1)?, o.rm(2)?)
+        }
+        "fmv.x.w" | "fclass.s" => {
+            o.expect(2, 2)?;
+            if mnemonic == "fmv.x.w" {
+                Instr::FMVXW(o.reg(0)?, o.freg(1)?)
+            } else {
+                Instr::FCLASSS(o.reg(0)?, o.freg(1)?)
+            }
+        }
+        "fmv.w.x" => {
+            o.expect(2, 2)?;
+            Instr::FMVWX(o.freg(0)?, o.reg(1)?)
+        }
+        "fence" => {
+            if !ops.is_empty() {
+                o.expect(2, 2)?;
+            }
+            Instr::FENCE(o.fence_set(0)?, o.fence_set(1)?)
+        }
+        "sfence.vma" => {
+            o.expect(0, 2)?;
+            let rs1 = if ops.is_empty() { 0 } else { o.reg(0)? };
+            let rs2 = if ops.len() < 2 { 0 } else { o.reg(1)? };
+            Instr::SFENCEVMA(rs1, rs2)
+        }
+        "fence.i" | "ecall" | "ebreak" | "wfi" | "uret" | "sret" | "mret" | "nop" | "ret" => {
+            o.expect(0, 0)?;
+            match mnemonic {
+                "fence.i" => Instr::FENCEI,
+                "ecall" => Instr::ECALL,
+                "ebreak" => Instr::EBREAK,
+                "wfi" => Instr::WFI,
+                "uret" => Instr::URET,
+                "sret" => Instr::SRET,
+                "mret" => Instr::MRET,
+                "nop" => Instr::ADDI(0, 0, 0),
+                _ => Instr::JALR(0, 0, 1),
+            }
+        }
+        // 伪指令
+        "li" => {
+            o.expect(2, 2)?;
+            return Ok(li(o.reg(0)?, o.imm(1)?, is_constant(o.get(1)?)));
+        }
+        "la" | "call" => {
+            o.expect(2 - (mnemonic == "call") as usize, 2)?;
+            if mnemonic == "la" {
+                let rd = o.reg(0)?;
+                let (hi, lo) = pcrel(o.address(1)?);
+                return Ok(vec![Instr::AUIPC(rd, hi), Instr::ADDI(rd, rd, lo)]);
+            }
+            let (hi, lo) = pcrel(o.address(0)?);
+            return Ok(vec![Instr::AUIPC(1, hi), Instr::JALR(1, lo, 1)]);
+        }
+        "mv" | "not" | "neg" => {
+            o.expect(2, 2)?;
+            let (rd, rs) = (o.reg(0)?, o.reg(1)?);
+            match mnemonic {
+                "mv" => Instr::ADDI(rd, rs, 0),
+                "not" => Instr::XORI(rd, rs, -1),
+                _ => Instr::SUB(rd, 0, rs),
+            }
+        }
+        "j" => {
+            o.expect(1, 1)?;
+            Instr::JAL(0, o.target(0)?)
+        }
+        "jr" => {
+            o.expect(1, 1)?;
+            Instr::JALR(0, 0, o.reg(0)?)
+        }
+        "beqz" | "bnez" => {
+            o.expect(2, 2)?;
+            let f: Branch = if mnemonic == "beqz" {
+                Instr::BEQ
+            } else {
+                Instr::BNE
+            };
+            f(o.reg(0)?, 0, o.target(1)?)
+        }
+        "csrr" => {
+            o.expect(2, 2)?;
+            Instr::CSRRS(o.reg(0)?, o.csr(1)?, 0)
+        }
+        "csrw" | "csrs" | "csrc" => {
+            o.expect(2, 2)?;
+            let f: Csr = match mnemonic {
+                "csrw" => Instr::CSRRW,
+                "csrs" => Instr::CSRRS,
+                _ => Instr::CSRRC,
+            };
+            f(0, o.csr(0)?, o.reg(1)?)
+        }
+        _ => {
+            return Err(anyhow!("unknown instruction: {}", mnemonic)).with_context(|| context!());
+        }
+    };
+    Ok(vec![inst])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn insts(program: &Program) -> Vec<Instr> {
+        program
+            .text
+            .chunks(4)
+            .map(|word| Instr::try_from(u32::from_le_bytes(word.try_into().unwrap())).unwrap())
+            .collect()
+    }
+
+    fn li_of(value: &str) -> Vec<Instr> {
+        insts(&assemble(&format!("li a0, {}", value), 0).unwrap())
+    }
+
+    /// 错误链的完整信息
+    fn error(source: &str) -> String {
+        format!("{:#}", assemble(source, 0).err().unwrap())
+    }
+
+    #[test]
+    fn labels_and_forward_references() {
+        let program = assemble(
+            "
+            start: j end
+                   nop
+            end:   beq a0, a1, start
+                   jal func
+            func:  ret
+            ",
+            0x100,
+        )
+        .unwrap();
+        assert_eq!(program.symbols["start"], 0x100);
+        assert_eq!(program.symbols["end"], 0x108);
+        assert_eq!(program.symbols["func"], 0x110);
+        assert_eq!(
+            insts(&program),
+            [
+                Instr::JAL(0, 8),
+                Instr::ADDI(0, 0, 0),
+                Instr::BEQ(10, 11, -8),
+                Instr::JAL(1, 4),
+                Instr::JALR(0, 0, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn hi_lo_with_negative_lo() {
+        let program = assemble(
+            "
+            lui a0, %hi(0x12345fff)
+            addi a0, a0, %lo(0x12345fff)
+            lui a1, %hi(0x12345800)
+            lw a1, %lo(0x12345800)(a1)
+            ",
+            0,
+        )
+        .unwrap();
+        assert_eq!(
+            insts(&program),
+            [
+                Instr::LUI(10, 0x12346000),
+                Instr::ADDI(10, 10, -1),
+                Instr::LUI(11, 0x12346000),
+                Instr::LW(11, -2048, 11),
+            ]
+        );
+    }
+
+    #[test]
+    fn hi_lo_of_a_data_label() {
+        let program = assemble(
+            "
+            lui a0, %hi(var)
+            lw a0, %lo(var)(a0)
+            .data
+            .word 0
+            var: .word 42
+            ",
+            0x7ff8,
+        )
+        .unwrap();
+        // .data 在 0x8000, var 在 0x8004
+        assert_eq!(program.symbols["var"], 0x8004);
+        assert_eq!(
+            insts(&program),
+            [Instr::LUI(10, 0x8000), Instr::LW(10, 4, 10)]
+        );
+    }
+
+    #[test]
+    fn li_splits_at_12_bits() {
+        assert_eq!(li_of("2047"), [Instr::ADDI(10, 0, 2047)]);
+        assert_eq!(li_of("-2048"), [Instr::ADDI(10, 0, -2048)]);
+        assert_eq!(
+            li_of("2048"),
+            [Instr::LUI(10, 0x1000), Instr::ADDI(10, 10, -2048)]
+        );
+        assert_eq!(
+            li_of("-2049"),
+            [Instr::LUI(10, 0xffff_f000), Instr::ADDI(10, 10, 2047)]
+        );
+        assert_eq!(li_of("0x1000"), [Instr::LUI(10, 0x1000)]);
+        // 高 20 位进位到 0x80000
+        assert_eq!(
+            li_of("0x7ffff800"),
+            [Instr::LUI(10, 0x8000_0000), Instr::ADDI(10, 10, -2048)]
+        );
+        assert_eq!(li_of("0xffffffff"), [Instr::ADDI(10, 0, -1)]);
+    }
+
+    #[test]
+    fn li_of_a_label_is_always_two_instructions() {
+        let program = assemble("li a0, here\nhere: nop", 0).unwrap();
+        assert_eq!(
+            insts(&program),
+            [
+                Instr::LUI(10, 0),
+                Instr::ADDI(10, 10, 8),
+                Instr::ADDI(0, 0, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn la_and_call_are_pc_relative() {
+        let program = assemble(
+            "
+            la a0, 0x2ff0
+            call 0x2ff0
+            la a1, var
+            .data
+            var: .word 0
+            ",
+            0x1000,
+        )
+        .unwrap();
+        assert_eq!(program.symbols["var"], 0x1018);
+        // auipc 在 0x1000, 0x1008 和 0x1010, 低 12 位是负数时高 20 位进位
+        assert_eq!(
+            insts(&program),
+            [
+                Instr::AUIPC(10, 0x2000),
+                Instr::ADDI(10, 10, -16),
+                Instr::AUIPC(1, 0x2000),
+                Instr::JALR(1, -24, 1),
+                Instr::AUIPC(11, 0),
+                Instr::ADDI(11, 11, 8),
+            ]
+        );
+    }
+
+    #[test]
+    fn align_pads_after_bytes() {
+        let program = assemble(
+            "
+            .data
+            a: .byte 1, 2
+            .align 2
+            b: .word 0x05060708
+            .byte 9
+            .align 1
+            c: .half 10
+            ",
+            0,
+        )
+        .unwrap();
+        assert_eq!(program.data, [1, 2, 0, 0, 8, 7, 6, 5, 9, 0, 10, 0]);
+        assert_eq!(program.symbols["a"], 0);
+        assert_eq!(program.symbols["b"], 4);
+        assert_eq!(program.symbols["c"], 10);
+    }
+
+    #[test]
+    fn duplicate_label_reports_its_line() {
+        let e = error("a: nop\nb: nop\na: nop");
+        assert!(e.contains("line 3: duplicate label: a"), "{}", e);
+    }
+
+    #[test]
+    fn undefined_label_reports_its_line() {
+        let e = error("nop\nnop\nj nowhere");
+        assert!(e.contains("line 3: j nowhere"), "{}", e);
+        assert!(e.contains("undefined symbol: nowhere"), "{}", e);
+    }
+}
@@ -0,0 +1,157 @@
+use super::*;
+#[cfg(feature = "jit")]
+use std::cell::Cell;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::{Rc, Weak};
+
+/// 一个基本块最多包含的指令数
+pub const MAX_BLOCK_LEN: usize = 64;
+
+/// 操作的处理函数. 返回 false 表示这条指令之后要离开基本块
+pub type Exec = fn(&mut CPU, &Op) -> Result<bool>;
+
+/// 线程化的操作: 译码时选好处理函数, 执行时不再 match 指令
+#[derive(Clone, Copy)]
+pub struct Op {
+    pub exec: Exec,
+    pub pc: u32,
+    pub raw: u32,
+    pub len: u32,
+    pub rd: u8,
+    pub rs1: u8,
+    pub rs2: u8,
+    /// 立即数, 跳转的偏移, 或者译码时算好的结果
+    pub imm: u32,
+}
+
+impl Op {
+    /// 下一条指令的地址
+    pub fn next_pc(&self) -> u32 {
+        self.pc.wrapping_add(self.len)
+    }
+}
+
+/// 只有最后一条指令可能跳转的指令序列
+pub struct Block {
+    pub pc: u32,
+    /// 最后一条指令之后的地址
+    pub end: u32,
+    /// 最后一条指令是跳转, 由它设置 pc
+    pub jumps: bool,
+    /// 构建时的特权级, 取指的权限检查与它有关
+    pub mode: Privilege,
+    pub ops: Vec<Op>,
+    generation: u64,
+    /// 链接的后继: [顺序执行, 跳转]
+    next: [RefCell<Weak<Block>>; 2],
+    /// 执行的次数, 到 JIT_THRESHOLD 时编译
+    #[cfg(feature = "jit")]
+    pub hits: Cell<u32>,
+    #[cfg(feature = "jit")]
+    pub code: Cell<Option<Code>>,
+}
+
+impl Block {
+    pub fn new(pc: u32, mode: Privilege, ops: Vec<Op>, jumps: bool) -> Self {
+        let end = ops.last().map_or(pc, Op::next_pc);
+        Self {
+            pc,
+            end,
+            jumps,
+            mode,
+            ops,
+            generation: 0,
+            next: Default::default(),
+            #[cfg(feature = "jit")]
+            hits: Cell::new(0),
+            #[cfg(feature = "jit")]
+            code: Cell::new(None),
+        }
+    }
+
+    fn slot(&self, pc: u32) -> usize {
+        (pc != self.end) as usize
+    }
+}
+
+/// 记录哪些字里有基本块的位图的大小, 地址按它取模, 重叠的地址再逐个检查基本块
+const CODE_BITS: u32 = 1 << 16;
+
+/// 按起始地址索引的基本块.
+/// 在 fence.i, sfence.vma, 修改 PMP 和写了基本块里的指令时清空.
+pub struct BlockCache {
+    blocks: HashMap<u32, Rc<Block>>,
+    /// 每次清空加一, 链接到旧的基本块不再有效
+    generation: u64,
+    /// 有基本块的字的位图, 写内存时先查它
+    code: Vec<u64>,
+}
+
+impl BlockCache {
+    pub fn new() -> Self {
+        Self {
+            blocks: HashMap::new(),
+            generation: 0,
+            code: vec![0; (CODE_BITS / 64) as usize],
+        }
+    }
+
+    pub fn get(&self, pc: u32, mode: Privilege) -> Option<Rc<Block>> {
+        self.blocks
+            .get(&pc)
+            .filter(|block| block.mode == mode)
+            .cloned()
+    }
+
+    pub fn insert(&mut self, mut block: Block) -> Rc<Block> {
+        block.generation = self.generation;
+        if block.end > block.pc {
+            for word in block.pc >> 2..=(block.end - 1) >> 2 {
+                let bit = word % CODE_BITS;
+                self.code[(bit / 64) as usize] |= 1 << (bit % 64);
+            }
+        }
+        let block = Rc::new(block);
+        self.blocks.insert(block.pc, block.clone());
+        block
+    }
+
+    /// block 执行完后跳到 pc, 取链接的后继, 不用查表
+    pub fn next(&self, block: &Block, pc: u32) -> Option<Rc<Block>> {
+        let next = block.next[block.slot(pc)].borrow().upgrade()?;
+        (next.pc == pc && next.generation == self.generation).then_some(next)
+    }
+
+    pub fn link(&self, block: &Block, next: &Rc<Block>) {
+        *block.next[block.slot(next.pc)].borrow_mut() = Rc::downgrade(next);
+    }
+
+    pub fn flush(&mut self) {
+        self.blocks.clear();
+        self.code.fill(0);
+        self.generation += 1;
+    }
+
+    /// 写了 [addr, addr + len) 之后, 丢掉包含这些指令的基本块. 返回是否丢掉了
+    pub fn invalidate(&mut self, addr: u32, len: u32) -> bool {
+        let last = addr.wrapping_add(len - 1);
+        (self.has_code(addr) || self.has_code(last)) && self.invalidate_range(addr, last)
+    }
+
+    fn has_code(&self, addr: u32) -> bool {
+        let bit = (addr >> 2) % CODE_BITS;
+        self.code[(bit / 64) as usize] & (1 << (bit % 64)) != 0
+    }
+
+    fn invalidate_range(&mut self, addr: u32, last: u32) -> bool {
+        let overlaps = self
+            .blocks
+            .values()
+            .any(|block| block.pc <= last && addr < block.end);
+        if overlaps {
+            self.flush();
+        }
+        overlaps
+    }
+}
@@ -0,0 +1,50 @@
+use colored::Colorize;
+
+use super::*;
+
+pub const SWITCH_ADDR: u32 = 0xffff_f070;
+pub const DIG_ADDR: u32 = 0xffff_f000;
+
+/// 开关/按键, 只读
+pub struct Switch {
+    value: u32,
+}
+
+impl Switch {
+    pub fn new() -> Self {
+        Self { value: 0xA00000 }
+    }
+
+    pub fn value(&self) -> u32 {
+        self.value
+    }
+
+    pub fn set_value(&mut self, value: u32) {
+        self.value = value;
+    }
+}
+
+impl Device for Switch {
+    fn read(&mut self, _offset: u32, _size: u32) -> Result<u32> {
+        Ok(self.value)
+    }
+
+    fn write(&mut self, offset: u32, _value: u32, _size: u32) -> Result<()> {
+        Err(anyhow!("switch: read-only: {:#x}", offset)).with_context(|| context!())
+    }
+}
+
+/// 数码管/LED, 只写, 写入的值打印出来
+pub struct Led;
+
+impl Device for Led {
+    fn read(&mut self, offset: u32, _size: u32) -> Result<u32> {
+        Err(anyhow!("led: write-only: {:#x}", offset)).with_context(|| context!())
+    }
+
+    fn write(&mut self, _offset: u32, value: u32, _size: u32) -> Result<()> {
+        let buf = format!("LED: {:#x}", value).blue();
+        println!("{}", buf);
+        Ok(())
+    }
+}
@@ -0,0 +1,437 @@
+use super::*;
+
+/// 挂在总线上的设备. offset 是相对设备基址的偏移, size 是访问的位数 (8, 16 或 32).
+/// 读写出错时由总线报告为访问异常.
+pub trait Device {
+    fn read(&mut self, offset: u32, size: u32) -> Result<u32>;
+
+    fn write(&mut self, offset: u32, value: u32, size: u32) -> Result<()>;
+
+    /// 时间前进 cycles 个周期, 每条指令执行完之后调用
+    fn tick(&mut self, _cycles: u64) {}
+
+    /// 中断线的电平, 在 tick 之后送到注册时指定的 PLIC 中断源
+    fn irq(&self) -> bool {
+        false
+    }
+}
+
+/// 一段地址由谁响应
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Slot {
+    Clint,
+    Plic,
+    Switch,
+    Led,
+    Dram,
+    /// Bus::devices 里的第几个
+    Device(usize),
+}
+
+/// 总线上的一段地址
+struct Mapping {
+    base: u32,
+    size: u32,
+    slot: Slot,
+}
+
+impl Mapping {
+    fn contains(&self, addr: u32) -> bool {
+        addr.wrapping_sub(self.base) < self.size
+    }
+}
+
+/// 用 add_device 注册的设备
+struct Attached {
+    /// 接到 PLIC 的哪个中断源
+    irq: Option<u32>,
+    /// 上一次 tick 之后中断线的电平
+    level: bool,
+    device: Box<dyn Device>,
+}
+
+/// 按地址把访存分给各个设备. 取指只走 IROM, 访存按注册的顺序查找,
+/// DRAM 排在最后, 和它重叠的设备优先
+pub struct Bus {
+    irom: IROM,
+    dram: DRAM,
+    clint: CLINT,
+    plic: PLIC,
+    switch: Switch,
+    led: Led,
+    mappings: Vec<Mapping>,
+    devices: Vec<Attached>,
+}
+
+impl Bus {
+    /// 开关和数码管总是映射, CLINT 和 PLIC 由 config 决定
+    pub fn new(irom: IROM, dram: DRAM, config: &Config) -> Self {
+        let mut bus = Self {
+            irom,
+            mappings: vec![Mapping {
+                base: dram.base(),
+                size: dram.size(),
+                slot: Slot::Dram,
+            }],
+            dram,
+            clint: CLINT::new(),
+            plic: PLIC::new(),
+            switch: Switch::new(),
+            led: Led,
+            devices: Vec::new(),
+        };
+        if config.clint {
+            bus.map(CLINT_BASE, CLINT_SIZE, Slot::Clint).unwrap();
+        }
+        if config.plic {
+            bus.map(PLIC_BASE, PLIC_SIZE, Slot::Plic).unwrap();
+        }
+        bus.map(SWITCH_ADDR, 4, Slot::Switch).unwrap();
+        bus.map(DIG_ADDR, 4, Slot::Led).unwrap();
+        bus
+    }
+
+    pub fn irom(&self) -> &IROM {
+        &self.irom
+    }
+
+    /// 取指的通路, 只有 IROM
+    pub fn fetch_half(&mut self, addr: u32) -> Result<u32> {
+        self.irom.read(addr, 16)
+    }
+
+    pub fn clint(&self) -> &CLINT {
+        &self.clint
+    }
+
+    /// jit_check 撤销编译的基本块时把 mtime 退回去
+    #[cfg(feature = "jit")]
+    pub fn clint_mut(&mut self) -> &mut CLINT {
+        &mut self.clint
+    }
+
+    pub fn plic(&self) -> &PLIC {
+        &self.plic
+    }
+
+    pub fn plic_mut(&mut self) -> &mut PLIC {
+        &mut self.plic
+    }
+
+    /// 在 [base, base + size) 上注册设备. 不能和 CLINT, PLIC 以及已有的设备重叠,
+    /// 和 DRAM 重叠的部分由设备响应
+    pub fn add_device(
+        &mut self,
+        base: u32,
+        size: u32,
+        irq: Option<u32>,
+        device: Box<dyn Device>,
+    ) -> Result<()> {
+        if let Some(source) = irq {
+            if source == 0 || source >= PLIC_SOURCES {
+                return Err(anyhow!("bus: invalid interrupt source {}", source))
+                    .with_context(|| context!());
+            }
+        }
+        self.map(base, size, Slot::Device(self.devices.len()))?;
+        self.devices.push(Attached {
+            irq,
+            level: false,
+            device,
+        });
+        Ok(())
+    }
+
+    /// 把 [base, base + size) 分给 slot, 排在 DRAM 前面
+    fn map(&mut self, base: u32, size: u32, slot: Slot) -> Result<()> {
+        let last = base as u64 + size as u64 - 1;
+        if size == 0 || last > u32::MAX as u64 {
+            return Err(anyhow!(
+                "bus: invalid range: {:#x} ({:#x} bytes)",
+                base,
+                size
+            ))
+            .with_context(|| context!());
+        }
+        let taken = self.mappings.iter().find(|mapping| {
+            mapping.slot != Slot::Dram
+                && (base as u64) < mapping.base as u64 + mapping.size as u64
+                && mapping.base as u64 <= last
+        });
+        if let Some(mapping) = taken {
+            return Err(anyhow!(
+                "bus: {:#x} overlaps the device at {:#x}",
+                base,
+                mapping.base
+            ))
+            .with_context(|| context!());
+        }
+        let dram = self.mappings.len() - 1;
+        self.mappings.insert(dram, Mapping { base, size, slot });
+        Ok(())
+    }
+
+    fn mapping(&self, addr: u32) -> Option<&Mapping> {
+        self.mappings.iter().find(|mapping| mapping.contains(addr))
+    }
+
+    /// 地址上是不是 DRAM 以外的设备, 访问它们可能改变中断状态
+    pub fn is_device(&self, addr: u32) -> bool {
+        self.mapping(addr)
+            .is_some_and(|mapping| mapping.slot != Slot::Dram)
+    }
+
+    /// 地址对应的设备和它的基址
+    fn route(&mut self, addr: u32) -> Option<(&mut dyn Device, u32)> {
+        let (base, slot) = self
+            .mapping(addr)
+            .map(|mapping| (mapping.base, mapping.slot))?;
+        let device: &mut dyn Device = match slot {
+            Slot::Clint => &mut self.clint,
+            Slot::Plic => &mut self.plic,
+            Slot::Switch => &mut self.switch,
+            Slot::Led => &mut self.led,
+            Slot::Dram => &mut self.dram,
+            Slot::Device(index) => self.devices[index].device.as_mut(),
+        };
+        Some((device, base))
+    }
+
+    pub fn load(&mut self, addr: u32, size: u32) -> Result<u32> {
+        match self.route(addr) {
+            Some((device, base)) => device
+                .read(addr - base, size)
+                .map_err(device_fault(Access::Load, addr)),
+            None => Err(MemError::OutOfRange {
+                access: Access::Load,
+                addr,
+                size,
+            })
+            .context(Exception::LoadAccessFault(addr))
+            .with_context(|| context!()),
+        }
+    }
+
+    pub fn store(&mut self, addr: u32, value: u32, size: u32) -> Result<()> {
+        match self.route(addr) {
+            Some((device, base)) => device
+                .write(addr - base, value, size)
+                .map_err(device_fault(Access::Store, addr)),
+            None => Err(MemError::OutOfRange {
+                access: Access::Store,
+                addr,
+                size,
+            })
+            .context(Exception::StoreAccessFault(addr))
+            .with_context(|| context!()),
+        }
+    }
+
+    /// 时间前进, 把设备的中断线送到 PLIC. 有中断线从低变高时返回 true
+    pub fn tick(&mut self, cycles: u64) -> bool {
+        self.clint.tick(cycles);
+        let mut raised = false;
+        for attached in self.devices.iter_mut() {
+            attached.device.tick(cycles);
+            if let Some(source) = attached.irq {
+                let level = attached.device.irq();
+                raised |= level && !attached.level;
+                attached.level = level;
+                self.plic.set_level(source, level).unwrap();
+            }
+        }
+        raised
+    }
+
+    /// 有没有接了中断线的设备
+    #[cfg(feature = "jit")]
+    pub fn has_device_irqs(&self) -> bool {
+        self.devices.iter().any(|attached| attached.irq.is_some())
+    }
+
+    /// 拨动开关/按键, 状态变化时触发外部中断
+    pub fn set_switch(&mut self, value: u32) {
+        if self.switch.value() != value {
+            self.switch.set_value(value);
+            self.plic.trigger(SWITCH_IRQ).unwrap();
+        }
+    }
+}
+
+/// 设备报告的错误没有对应的异常时, 当作访问异常
+fn device_fault(access: Access, addr: u32) -> impl FnOnce(anyhow::Error) -> anyhow::Error {
+    move |e| match e.downcast_ref::<Exception>() {
+        Some(_) => e,
+        None => e.context(access.fault(addr)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 读出最后一次写入的值
+    struct Latch(u32);
+
+    impl Device for Latch {
+        fn read(&mut self, _offset: u32, _size: u32) -> Result<u32> {
+            Ok(self.0)
+        }
+
+        fn write(&mut self, _offset: u32, value: u32, _size: u32) -> Result<()> {
+            self.0 = value;
+            Ok(())
+        }
+    }
+
+    fn irom() -> IROM {
+        IROM::new(&[0; 4], 0, &[0; 4], 0x1c09_0000)
+    }
+
+    fn bus() -> Bus {
+        let config = Config {
+            clint: true,
+            plic: true,
+            ..Config::default()
+        };
+        Bus::new(irom(), DRAM::new(&[], 0, 0x1000), &config)
+    }
+
+    #[test]
+    fn devices_take_precedence_over_dram() {
+        let mut bus = bus();
+        bus.store(0x800, 1, 32).unwrap();
+        bus.add_device(0x800, 0x10, None, Box::new(Latch(7)))
+            .unwrap();
+        assert!(bus.is_device(0x80c));
+        assert!(!bus.is_device(0x810));
+        assert_eq!(bus.load(0x800, 32).unwrap(), 7);
+        bus.store(0x804, 9, 32).unwrap();
+        assert_eq!(bus.load(0x800, 32).unwrap(), 9);
+    }
+
+    #[test]
+    fn overlapping_devices_are_rejected() {
+        let mut bus = bus();
+        for base in [CLINT_BASE + 0x100, PLIC_BASE, SWITCH_ADDR - 2, DIG_ADDR] {
+            assert!(bus.add_device(base, 4, None, Box::new(Latch(0))).is_err());
+        }
+        bus.add_device(0x8000_0000, 0x100, None, Box::new(Latch(0)))
+            .unwrap();
+        assert!(bus
+            .add_device(0x8000_00fc, 8, None, Box::new(Latch(0)))
+            .is_err());
+        assert!(bus
+            .add_device(0xffff_fffc, 8, None, Box::new(Latch(0)))
+            .is_err());
+        assert!(bus
+            .add_device(0x9000_0000, 4, Some(PLIC_SOURCES), Box::new(Latch(0)))
+            .is_err());
+    }
+
+    #[test]
+    fn builtin_devices_are_routed() {
+        let mut bus = bus();
+        assert!(bus.is_device(CLINT_BASE));
+        assert!(bus.is_device(PLIC_BASE));
+        assert_eq!(bus.load(SWITCH_ADDR, 32).unwrap(), 0xA00000);
+        assert!(bus.store(SWITCH_ADDR, 0, 32).is_err());
+        assert!(bus.load(DIG_ADDR, 32).is_err());
+        assert!(bus.load(0x1000, 32).is_err());
+    }
+
+    #[test]
+    fn clint_and_plic_are_mapped_only_when_enabled() {
+        for base in [CLINT_BASE, PLIC_BASE] {
+            let dram = DRAM::new(&[], base, 0x1000);
+            let mut bus = Bus::new(irom(), dram, &Config::default());
+            assert!(!bus.is_device(base));
+            bus.store(base, 5, 32).unwrap();
+            assert_eq!(bus.load(base, 32).unwrap(), 5);
+        }
+    }
+
+    /// PLIC 的寄存器: 中断源 n 的优先级在 4 * n, 使能在 0x2000, 阈值和 claim/complete 在 0x20_0000
+    fn plic_store(bus: &mut Bus, offset: u32, value: u32) {
+        bus.store(PLIC_BASE + offset, value, 32).unwrap();
+    }
+
+    fn claim(bus: &mut Bus) -> u32 {
+        bus.load(PLIC_BASE + 0x20_0004, 32).unwrap()
+    }
+
+    fn complete(bus: &mut Bus, source: u32) {
+        plic_store(bus, 0x20_0004, source);
+    }
+
+    #[test]
+    fn plic_threshold_masks_low_priorities() {
+        let mut bus = bus();
+        plic_store(&mut bus, 4 * 2, 1);
+        plic_store(&mut bus, 4 * 3, 3);
+        plic_store(&mut bus, 0x2000, 1 << 2 | 1 << 3);
+        plic_store(&mut bus, 0x20_0000, 3);
+        bus.plic_mut().trigger(2).unwrap();
+        bus.plic_mut().trigger(3).unwrap();
+        // 优先级必须严格大于阈值
+        assert!(!bus.plic().external_pending());
+        assert_eq!(claim(&mut bus), 0);
+        plic_store(&mut bus, 0x20_0000, 2);
+        assert!(bus.plic().external_pending());
+        assert_eq!(claim(&mut bus), 3);
+        assert!(!bus.plic().external_pending());
+        plic_store(&mut bus, 0x20_0000, 0);
+        assert!(bus.plic().external_pending());
+    }
+
+    #[test]
+    fn plic_claims_the_highest_priority_source() {
+        let mut bus = bus();
+        for (source, priority) in [(2, 1), (3, 5), (4, 5), (5, 7)] {
+            plic_store(&mut bus, 4 * source, priority);
+            bus.plic_mut().trigger(source).unwrap();
+        }
+        // 5 没有使能, 3 和 4 优先级相同时编号小的先
+        plic_store(&mut bus, 0x2000, 0b11100);
+        assert_eq!(claim(&mut bus), 3);
+        assert_eq!(claim(&mut bus), 4);
+        assert_eq!(claim(&mut bus), 2);
+        assert_eq!(claim(&mut bus), 0);
+        assert!(!bus.plic().external_pending());
+    }
+
+    #[test]
+    fn plic_complete_rearms_a_level_source() {
+        let mut bus = bus();
+        plic_store(&mut bus, 4 * 2, 1);
+        plic_store(&mut bus, 0x2000, 1 << 2);
+        bus.plic_mut().set_level(2, true).unwrap();
+        assert_eq!(claim(&mut bus), 2);
+        // 处理中的中断源不会再次 pending
+        bus.plic_mut().set_level(2, true).unwrap();
+        assert!(!bus.plic().external_pending());
+        complete(&mut bus, 2);
+        assert!(bus.plic().external_pending());
+        assert_eq!(claim(&mut bus), 2);
+        bus.plic_mut().set_level(2, false).unwrap();
+        complete(&mut bus, 2);
+        assert!(!bus.plic().external_pending());
+    }
+
+    #[test]
+    fn switch_interrupts_only_on_a_change() {
+        let mut bus = bus();
+        plic_store(&mut bus, 4 * SWITCH_IRQ, 1);
+        plic_store(&mut bus, 0x2000, 1 << SWITCH_IRQ);
+        let value = bus.load(SWITCH_ADDR, 32).unwrap();
+        bus.set_switch(value);
+        assert!(!bus.plic().external_pending());
+        bus.set_switch(value ^ 1);
+        assert!(bus.plic().external_pending());
+        assert_eq!(claim(&mut bus), SWITCH_IRQ);
+        complete(&mut bus, SWITCH_IRQ);
+        bus.set_switch(value ^ 1);
+        assert!(!bus.plic().external_pending());
+        assert_eq!(bus.load(SWITCH_ADDR, 32).unwrap(), value ^ 1);
+    }
+}
@@ -0,0 +1,93 @@
+use super::*;
+
+pub const CLINT_BASE: u32 = 0x0200_0000;
+pub const CLINT_SIZE: u32 = 0x1_0000;
+const MSIP: u32 = 0x0000;
+const MTIMECMP: u32 = 0x4000;
+const MTIMECMPH: u32 = 0x4004;
+const MTIME: u32 = 0xbff8;
+const MTIMEH: u32 = 0xbffc;
+
+/// 单核的 CLINT: msip, mtimecmp, mtime
+#[allow(clippy::upper_case_acronyms)]
+pub struct CLINT {
+    msip: u32,
+    mtimecmp: u64,
+    mtime: u64,
+}
+
+impl CLINT {
+    pub fn new() -> Self {
+        Self {
+            msip: 0,
+            // 复位后不产生定时器中断
+            mtimecmp: u64::MAX,
+            mtime: 0,
+        }
+    }
+
+    /// mtime 前进 ticks
+    pub fn tick(&mut self, ticks: u64) {
+        self.mtime = self.mtime.wrapping_add(ticks);
+    }
+
+    pub fn mtime(&self) -> u64 {
+        self.mtime
+    }
+
+    pub fn mtimecmp(&self) -> u64 {
+        self.mtimecmp
+    }
+
+    /// mip.MTIP
+    pub fn timer_pending(&self) -> bool {
+        self.mtime >= self.mtimecmp
+    }
+
+    /// mip.MSIP
+    pub fn software_pending(&self) -> bool {
+        self.msip & 1 != 0
+    }
+}
+
+impl Device for CLINT {
+    /// 寄存器都是 32 位的, 只支持按字访问
+    fn read(&mut self, offset: u32, size: u32) -> Result<u32> {
+        if size != 32 || offset % 4 != 0 {
+            return Err(anyhow!(
+                "clint: invalid access: {:#x} ({} bits)",
+                offset,
+                size
+            ))
+            .with_context(|| context!());
+        }
+        match offset {
+            MSIP => Ok(self.msip),
+            MTIMECMP => Ok(self.mtimecmp as u32),
+            MTIMECMPH => Ok((self.mtimecmp >> 32) as u32),
+            MTIME => Ok(self.mtime as u32),
+            MTIMEH => Ok((self.mtime >> 32) as u32),
+            _ => Ok(0),
+        }
+    }
+
+    fn write(&mut self, offset: u32, value: u32, size: u32) -> Result<()> {
+        if size != 32 || offset % 4 != 0 {
+            return Err(anyhow!(
+                "clint: invalid access: {:#x} ({} bits)",
+                offset,
+                size
+            ))
+            .with_context(|| context!());
+        }
+        match offset {
+            MSIP => self.msip = value & 1,
+            MTIMECMP => self.mtimecmp = (self.mtimecmp & !0xffff_ffff) | value as u64,
+            MTIMECMPH => self.mtimecmp = (self.mtimecmp & 0xffff_ffff) | ((value as u64) << 32),
+            MTIME => self.mtime = (self.mtime & !0xffff_ffff) | value as u64,
+            MTIMEH => self.mtime = (self.mtime & 0xffff_ffff) | ((value as u64) << 32),
+            _ => {}
+        }
+        Ok(())
+    }
+}
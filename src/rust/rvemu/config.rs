@@ -0,0 +1,83 @@
+use super::*;
+
+/// 运行时配置, 在 `CPU::new` 时传入
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct Config {
+    /// RV32E: 只有 x0-x15 这 16 个寄存器
+    pub rv32e: bool,
+    /// 地址计算扩展
+    pub zba: bool,
+    /// 基本位操作扩展
+    pub zbb: bool,
+    /// 单比特操作扩展
+    pub zbs: bool,
+    /// 单精度浮点扩展
+    pub f: bool,
+    /// 每条指令消耗的周期数, 决定 mcycle 的增长速度
+    pub cycles_per_inst: u32,
+    /// 无法译码的指令和未实现的 csr 进入非法指令异常, 而不是报错; ebreak 进入断点异常, 而不是停止
+    pub trap_illegal: bool,
+    /// 非对齐访存进入异常处理, 否则由硬件模拟完成
+    pub trap_misaligned: bool,
+    /// 取指和访存的地址越界进入异常处理, 而不是报错
+    pub trap_access_fault: bool,
+    /// 执行 xRET 后停止运行 (实验的 trap_handle 以此结束), 关掉后可以运行中断驱动的程序
+    pub halt_on_eret: bool,
+    /// 把 uret 当作 mret 执行 (兼容用 uret 返回的旧 trap_handle), 否则 uret 是非法指令
+    pub uret_as_mret: bool,
+    /// 在 0x0200_0000 处映射 CLINT (定时器和软件中断), 盖住那里的 DRAM
+    pub clint: bool,
+    /// 在 0x0c00_0000 处映射 64MiB 的 PLIC (外部中断), 盖住那里的 DRAM
+    pub plic: bool,
+    /// 用户程序从 U 模式开始运行, kernel_base 处的代码不能在 U 模式下执行
+    pub user_mode: bool,
+    /// 实现的 PMP 表项个数 (0-16), 0 表示不做 PMP 检查
+    pub pmp_entries: u32,
+    /// TLB 的表项个数, 0 表示每次访存都查页表
+    pub tlb_entries: u32,
+    /// 译码缓存的表项个数, 0 表示每条指令都重新译码
+    pub decode_cache_entries: u32,
+    /// run 时把热点基本块编译成 x86-64 代码 (需要打开 jit feature)
+    pub jit: bool,
+    /// 编译的基本块执行完后, 用解释器重新执行一遍比较状态, 不一致时报错
+    pub jit_check: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            rv32e: false,
+            zba: false,
+            zbb: false,
+            zbs: false,
+            f: false,
+            cycles_per_inst: 1,
+            trap_illegal: false,
+            trap_misaligned: false,
+            trap_access_fault: false,
+            halt_on_eret: true,
+            uret_as_mret: false,
+            clint: false,
+            plic: false,
+            user_mode: false,
+            pmp_entries: 0,
+            tlb_entries: 0,
+            decode_cache_entries: 4096,
+            jit: true,
+            jit_check: false,
+        }
+    }
+}
+
+impl Config {
+    /// 可选扩展是否打开
+    pub fn has_extension(&self, ext: Extension) -> bool {
+        match ext {
+            Extension::Zba => self.zba,
+            Extension::Zbb => self.zbb,
+            Extension::Zbs => self.zbs,
+            Extension::F => self.f,
+        }
+    }
+}
@@ -1,17 +1,51 @@
 use super::*;
+use std::rc::Rc;
 
 #[allow(clippy::upper_case_acronyms)]
 #[repr(C)]
 pub struct CPU {
     regs: [u32; 32],
+    /// f0-f31
+    fregs: [u32; 32],
     pc: u32,
     csrs: [u32; 4096],
-    irom: IROM,
-    dram: DRAM,
+    bus: Bus,
+    pmp: PMP,
+    mmu: MMU,
+    decode_cache: DecodeCache,
+    blocks: BlockCache,
+    config: Config,
+    /// 当前特权级
+    mode: Privilege,
+    /// lr.w 保留的地址
+    reservation: Option<u32>,
+    /// 取指时发生的异常: (pc, 异常)
+    fetch_fault: Option<(u32, Exception)>,
+    /// 上一次取指的 (pc, 指令长度), pc_step 据此前进
+    fetched: Option<(u32, u32)>,
+    /// 这条指令访问了 DRAM 以外的设备 (中断状态可能改变) 或者改了基本块里的指令, 基本块在它之后结束
+    leave_block: bool,
+    /// mcycle
+    cycle: u64,
+    /// minstret
+    instret: u64,
+    /// 这条指令写了 mcycle(h)/minstret(h): 写入的值在指令执行完之后生效, 不再加上这条指令
+    wrote_cycle: bool,
+    wrote_instret: bool,
+    #[cfg(feature = "jit")]
+    jit: Option<Jit>,
+    /// jit_check 时记录写内存: (地址, 大小, 原来的值, 写入的值)
+    #[cfg(feature = "jit")]
+    store_log: Option<Vec<(u32, u32, u32, u32)>>,
+    /// jit_check 用解释器重新执行基本块. 设备的时间在编译的代码执行时已经走过了,
+    /// 这时只有退回去的 mtime 再走一遍
+    #[cfg(feature = "jit")]
+    replaying: bool,
 }
 
 #[allow(clippy::upper_case_acronyms)]
 #[repr(C)]
+#[derive(Default)]
 /// 写回的信息
 pub struct WBInfo {
     pub wb_have_inst: u32,
@@ -20,19 +54,10 @@ pub struct WBInfo {
     pub wb_val: u32,
     pub wb_ena: u32,
     pub inst_valid: u32,
-}
-
-impl Default for WBInfo {
-    fn default() -> Self {
-        Self {
-            wb_have_inst: Default::default(),
-            wb_pc: Default::default(),
-            wb_rd: Default::default(),
-            wb_val: Default::default(),
-            wb_ena: Default::default(),
-            inst_valid: Default::default(),
-        }
-    }
+    /// wb_rd 是浮点寄存器
+    pub wb_fp: u32,
+    /// 这一步进入了异常处理. 响应中断时没有执行指令, wb_have_inst 和 inst_valid 为 0
+    pub wb_trap: u32,
 }
 
 impl CPU {
@@ -44,31 +69,119 @@ impl CPU {
         kernel_base: u32,
         dram_base: u32,
         dram_size: u32,
+        config: Config,
     ) -> Self {
         let mut regs: [u32; 32] = [0; 32]; // 默认寄存存放的是 无符号
                                            // regs[2] = dram_base.wrapping_add(dram_size); // sp
         regs[2] = 0; // sp
         let pc = user_base;
         let mut csrs = [0; 4096];
-        csrs[MTVAL as usize] = kernel_base;
+        csrs[MTVEC as usize] = kernel_base;
+        csrs[MSTATUS as usize] = MSTATUS_MPP;
+        // 没有浮点扩展时 FS 恒为 Off
+        if config.f {
+            csrs[MSTATUS as usize] |= FS_INITIAL << MSTATUS_FS_SHIFT;
+        }
         let irom = IROM::new(user, user_base, kernel, kernel_base);
         let dram = DRAM::new(user, dram_base, dram_size);
         Self {
             regs,
+            fregs: [0; 32],
             pc,
             csrs,
-            irom,
-            dram,
+            bus: Bus::new(irom, dram, &config),
+            pmp: PMP::new(config.pmp_entries as usize),
+            mmu: MMU::new(config.tlb_entries as usize),
+            decode_cache: DecodeCache::new(config.decode_cache_entries as usize),
+            blocks: BlockCache::new(),
+            config,
+            mode: if config.user_mode {
+                Privilege::User
+            } else {
+                Privilege::Machine
+            },
+            reservation: None,
+            fetch_fault: None,
+            fetched: None,
+            leave_block: false,
+            cycle: 0,
+            instret: 0,
+            wrote_cycle: false,
+            wrote_instret: false,
+            // 申请不到可执行内存时只用解释器
+            #[cfg(feature = "jit")]
+            jit: config
+                .jit
+                .then(|| {
+                    let (regs, pc) = (
+                        std::mem::offset_of!(CPU, regs),
+                        std::mem::offset_of!(CPU, pc),
+                    );
+                    Jit::new(regs, pc, jit_op).ok()
+                })
+                .flatten(),
+            #[cfg(feature = "jit")]
+            store_log: None,
+            #[cfg(feature = "jit")]
+            replaying: false,
         }
     }
 
     /// Get an instruction from the dram.
-    pub fn fetch(&self) -> Result<u32> {
-        self.irom.fetch(self.pc)
+    ///
+    /// 打开 `trap_access_fault` 时, 取指异常先记下来并返回 0,
+    /// 在下一次 `execute` 时进入异常处理.
+    pub fn fetch(&mut self) -> Result<u32> {
+        if let Some(inst) = self.fetch_fast(self.pc) {
+            self.fetched = Some((self.pc, inst_len(inst)));
+            return Ok(inst);
+        }
+        let result = if self.pc % 2 != 0 {
+            Err(Exception::InstructionAddressMisaligned(self.pc)).with_context(|| context!())
+        } else {
+            self.fetch_inst(self.pc)
+        };
+        let len = match result {
+            Ok(inst) => inst_len(inst),
+            Err(_) => 4,
+        };
+        self.fetched = Some((self.pc, len));
+        match result {
+            Err(e) if self.config.trap_access_fault => match e.downcast_ref::<Exception>() {
+                Some(&exception) => {
+                    self.fetch_fault = Some((self.pc, exception));
+                    Ok(0)
+                }
+                None => Err(e),
+            },
+            result => result,
+        }
+    }
+
+    /// 不做地址转换时直接读 IROM, 需要报告异常的情况交给完整的取指流程
+    fn fetch_fast(&self, addr: u32) -> Option<u32> {
+        if addr % 2 != 0
+            || self.translating(Access::Fetch)
+            || (self.mode == Privilege::User && self.bus.irom().in_kernel(addr))
+        {
+            return None;
+        }
+        let inst = self.bus.irom().get(addr)?;
+        self.pmp
+            .check(addr, inst_len(inst) * 8, Access::Fetch, self.mode)
+            .then_some(inst)
     }
 
+    /// pc 按照当前指令的长度前进
     pub fn pc_step(&mut self) {
-        self.pc += 4;
+        let len = match self.fetched.take() {
+            Some((pc, len)) if pc == self.pc => len,
+            _ => match self.bus.irom().fetch(self.pc) {
+                Ok(inst) => inst_len(inst),
+                Err(_) => 4,
+            },
+        };
+        self.pc = self.pc.wrapping_add(len);
     }
 
     pub fn dump(&self) {
@@ -81,61 +194,520 @@ impl CPU {
         self.pc
     }
 
+    pub fn reg(&self, r: Reg) -> u32 {
+        self.regs[r as usize]
+    }
+
+    pub fn mode(&self) -> Privilege {
+        self.mode
+    }
+
+    pub fn tlb_stats(&self) -> TlbStats {
+        self.mmu.stats()
+    }
+
+    /// 拨动开关/按键, 状态变化时触发外部中断
+    pub fn set_switch(&mut self, value: u32) {
+        self.bus.set_switch(value);
+    }
+
+    /// 设置外部中断线的电平
+    pub fn set_irq(&mut self, source: u32, level: bool) -> Result<()> {
+        self.bus.plic_mut().set_level(source, level)
+    }
+
+    /// 在 [base, base + size) 上挂一个 MMIO 外设, irq 是它的中断线接到的 PLIC 中断源.
+    /// 和 CLINT, PLIC, 板上的外设以及已经挂上的设备重叠时报错, 和 DRAM 重叠的部分由外设响应.
+    /// 接了中断线的外设每条指令之后都要检查, run 不再执行编译的代码.
+    pub fn add_device(
+        &mut self,
+        base: u32,
+        size: u32,
+        irq: Option<u32>,
+        device: Box<dyn Device>,
+    ) -> Result<()> {
+        self.bus.add_device(base, size, irq, device)
+    }
+
+    /// 按基本块运行, 最多执行 max_steps 步, 结果与依次调用 fetch, pc_step, execute 相同.
+    /// 一步是执行一条指令或者进入一次异常处理. 返回执行完的步数, 以及停下来的原因.
+    /// 不产生 WBInfo, 需要逐条比对的 trace 和 difftest 仍然用 execute.
+    pub fn run(&mut self, max_steps: u64) -> (u64, Result<()>) {
+        let mut steps = 0;
+        while steps < max_steps {
+            let result = match self.run_blocks(max_steps - steps, &mut steps) {
+                // 不能按基本块执行, 单步执行一条
+                Ok(false) => self.step().map(|_| steps += 1),
+                result => result.map(|_| ()),
+            };
+            if let Err(e) = result {
+                return (steps, Err(e));
+            }
+        }
+        (steps, Ok(()))
+    }
+
+    fn step(&mut self) -> Result<WBInfo> {
+        let inst = self.fetch().with_context(|| context!())?;
+        self.pc_step();
+        self.execute(inst)
+    }
+
+    /// 从 pc 开始执行链接起来的基本块, 离开基本块, 进入异常处理或者用完 budget 时返回.
+    /// 不能按基本块执行时返回 false.
+    fn run_blocks(&mut self, budget: u64, steps: &mut u64) -> Result<bool> {
+        // 基本块里的指令不改变特权级, 地址转换和中断使能, 只在开始时检查
+        if self.fetch_fault.is_some()
+            || self.translating(Access::Fetch)
+            || self.pending_interrupt().is_some()
+        {
+            return Ok(false);
+        }
+        // 编译结果的空间用完了, 这时没有正在执行的基本块, 可以全部丢掉
+        #[cfg(feature = "jit")]
+        if let Some(jit) = self.jit.as_mut().filter(|jit| jit.full()) {
+            jit.reset();
+            self.blocks.flush();
+        }
+        let mut block = self.block(self.pc);
+        if block.ops.is_empty() {
+            return Ok(false);
+        }
+        let mut budget = budget.min(self.timer_budget());
+        self.leave_block = false;
+        loop {
+            let count = block.ops.len().min(budget.try_into().unwrap_or(usize::MAX));
+            if count < block.ops.len() {
+                if self.interpret(&block.ops[..count], steps)? {
+                    self.pc = block.ops[count].pc;
+                }
+                return Ok(true);
+            }
+            if !self.execute_block(&block, steps)? {
+                return Ok(true);
+            }
+            budget -= count as u64;
+            if budget == 0 || std::mem::take(&mut self.leave_block) {
+                return Ok(true);
+            }
+            let next = match self.blocks.next(&block, self.pc) {
+                Some(next) => next,
+                None => {
+                    let next = self.block(self.pc);
+                    self.blocks.link(&block, &next);
+                    next
+                }
+            };
+            if next.ops.is_empty() {
+                return Ok(true);
+            }
+            block = next;
+        }
+    }
+
+    /// 依次执行 ops, 离开基本块或者进入异常处理时返回 false, 此时 pc 已经设置好.
+    /// 执行完最后一个操作时设备的中断线变高, 留给调用者在链接下一个基本块之前处理
+    fn interpret(&mut self, ops: &[Op], steps: &mut u64) -> Result<bool> {
+        for (index, op) in ops.iter().enumerate() {
+            self.regs[0] = 0;
+            match (op.exec)(self, op) {
+                // 基本块中间的操作都不跳转, 下一条就是 next_pc
+                Ok(true) if self.leave_block && index + 1 < ops.len() => {
+                    self.leave_block = false;
+                    *steps += 1;
+                    self.pc = op.next_pc();
+                    return Ok(false);
+                }
+                Ok(true) => *steps += 1,
+                Ok(false) => {
+                    *steps += 1;
+                    self.pc = op.next_pc();
+                    return Ok(false);
+                }
+                Err(e) => {
+                    self.pc = op.next_pc();
+                    self.fault(self.pc, op.raw, e)?;
+                    *steps += 1;
+                    return Ok(false);
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    /// 执行整个基本块, 热点基本块编译后执行. 返回值同 interpret, 执行完时 pc 是下一个基本块
+    fn execute_block(&mut self, block: &Block, steps: &mut u64) -> Result<bool> {
+        #[cfg(feature = "jit")]
+        if let Some(code) = self.compiled(block) {
+            return match self.config.jit_check {
+                true => self.check_native(block, code, steps),
+                false => self.native(block, code, steps),
+            };
+        }
+        if !self.interpret(&block.ops, steps)? {
+            return Ok(false);
+        }
+        if !block.jumps {
+            self.pc = block.end;
+        }
+        Ok(true)
+    }
+
+    /// 编译出的代码的字节数
+    #[cfg(all(test, feature = "jit"))]
+    pub(crate) fn jit_code_size(&self) -> usize {
+        self.jit.as_ref().map_or(0, Jit::used)
+    }
+
+    /// 编译好的代码, 执行次数到 JIT_THRESHOLD 时编译.
+    /// 编译的代码最后才一起 tick, 设备的中断会晚几条指令响应, 所以有设备接了中断线时只解释执行
+    #[cfg(feature = "jit")]
+    fn compiled(&mut self, block: &Block) -> Option<Code> {
+        if self.bus.has_device_irqs() {
+            return None;
+        }
+        if let Some(code) = block.code.get() {
+            return Some(code);
+        }
+        self.jit.as_ref()?;
+        let hits = block.hits.get() + 1;
+        block.hits.set(hits);
+        if hits != JIT_THRESHOLD {
+            return None;
+        }
+        let insts = block
+            .ops
+            .iter()
+            .map(|op| self.decode(op.raw))
+            .collect::<Result<Vec<_>>>()
+            .ok()?;
+        let code = self.jit.as_mut()?.compile(&block.ops, &insts, block.jumps);
+        block.code.set(code);
+        code
+    }
+
+    /// 执行编译好的基本块, 返回值同 execute_block
+    #[cfg(feature = "jit")]
+    fn native(&mut self, block: &Block, code: Code, steps: &mut u64) -> Result<bool> {
+        // 编译的代码和回调都通过这一个指针访问 CPU, 调用返回之前不能再用 self
+        let cpu: *mut CPU = self;
+        let mut ctx = JitCtx {
+            cpu,
+            ops: block.ops.as_ptr(),
+            retired: 0,
+            error: None,
+        };
+        let status = unsafe { code.call(cpu, &mut ctx) };
+        let (reason, index) = ((status >> 32) as u32, status as u32);
+        match reason {
+            EXIT_END => {
+                self.retire_many(block.ops.len() as u32 - ctx.retired);
+                *steps += block.ops.len() as u64;
+                if !block.jumps {
+                    self.pc = block.end;
+                }
+                Ok(true)
+            }
+            EXIT_LEAVE => {
+                *steps += index as u64 + 1;
+                self.pc = block.ops[index as usize].next_pc();
+                Ok(false)
+            }
+            _ => {
+                let op = &block.ops[index as usize];
+                let e = ctx
+                    .error
+                    .take()
+                    .unwrap_or_else(|| anyhow!("jit: missing error"));
+                *steps += index as u64;
+                self.pc = op.next_pc();
+                self.fault(self.pc, op.raw, e)?;
+                *steps += 1;
+                Ok(false)
+            }
+        }
+    }
+
+    /// 执行编译好的基本块, 执行完整个基本块时撤销它的结果, 再用解释器执行一遍, 比较两次的状态
+    #[cfg(feature = "jit")]
+    fn check_native(&mut self, block: &Block, code: Code, steps: &mut u64) -> Result<bool> {
+        let before = self.arch_state();
+        let before_steps = *steps;
+        self.store_log = Some(Vec::new());
+        let done = self.native(block, code, steps);
+        let native_log = self.store_log.take().unwrap_or_default();
+        if !matches!(done, Ok(true)) {
+            return done;
+        }
+        let native = self.arch_state();
+        for &(addr, size, old, _) in native_log.iter().rev() {
+            self.bus.store(addr, old, size)?;
+        }
+        self.restore_state(&before);
+
+        let mut interpreted_steps = before_steps;
+        self.store_log = Some(Vec::new());
+        self.replaying = true;
+        let done = self.interpret(&block.ops, &mut interpreted_steps);
+        self.replaying = false;
+        let log = self.store_log.take().unwrap_or_default();
+        if !block.jumps {
+            self.pc = block.end;
+        }
+        if !matches!(done, Ok(true)) {
+            return Err(anyhow!(
+                "jit: block {:#010x} left early in the interpreter",
+                block.pc
+            ))
+            .with_context(|| context!());
+        }
+        let interpreted = self.arch_state();
+        if interpreted != native || log != native_log || interpreted_steps != *steps {
+            return Err(anyhow!(
+                "jit: block {:#010x} differs from the interpreter\n  jit:         {:x?}\n  interpreter: {:x?}",
+                block.pc,
+                (native.diff(&interpreted), native_log),
+                (interpreted.diff(&native), log),
+            ))
+            .with_context(|| context!());
+        }
+        Ok(true)
+    }
+
+    #[cfg(feature = "jit")]
+    fn arch_state(&self) -> ArchState {
+        ArchState {
+            regs: self.regs,
+            fregs: self.fregs,
+            pc: self.pc,
+            csrs: self.csrs.to_vec(),
+            reservation: self.reservation,
+            cycle: self.cycle,
+            instret: self.instret,
+            mtime: self.bus.clint().mtime(),
+        }
+    }
+
+    #[cfg(feature = "jit")]
+    fn restore_state(&mut self, state: &ArchState) {
+        self.regs = state.regs;
+        self.fregs = state.fregs;
+        self.pc = state.pc;
+        self.csrs.copy_from_slice(&state.csrs);
+        self.reservation = state.reservation;
+        self.cycle = state.cycle;
+        self.instret = state.instret;
+        let mtime = self.bus.clint().mtime();
+        self.bus.clint_mut().tick(state.mtime.wrapping_sub(mtime));
+    }
+
+    /// 定时器中断到来之前还能执行的指令数, 已经待处理 (但被屏蔽) 时不受限制
+    fn timer_budget(&self) -> u64 {
+        let (mtime, mtimecmp) = (self.bus.clint().mtime(), self.bus.clint().mtimecmp());
+        match self.config.cycles_per_inst as u64 {
+            0 => u64::MAX,
+            _ if mtime >= mtimecmp => u64::MAX,
+            cycles => (mtimecmp - mtime - 1) / cycles + 1,
+        }
+    }
+
+    /// pc 处的基本块, 没有时译码一个
+    fn block(&mut self, pc: u32) -> Rc<Block> {
+        match self.blocks.get(pc, self.mode) {
+            Some(block) => block,
+            None => {
+                let block = self.build_block(pc);
+                self.blocks.insert(block)
+            }
+        }
+    }
+
+    /// 从 pc 开始译码, 到跳转, 需要单步执行的指令或者不能快速取指的地址为止
+    fn build_block(&self, pc: u32) -> Block {
+        let mut ops = Vec::new();
+        let mut addr = pc;
+        while ops.len() < MAX_BLOCK_LEN {
+            let Some(raw) = self.fetch_fast(addr) else {
+                break;
+            };
+            let Ok(inst) = self.decode(raw) else {
+                break;
+            };
+            let Some(op) = thread(&inst, addr, raw) else {
+                break;
+            };
+            ops.push(op);
+            if matches!(
+                inst,
+                Instr::JAL(..)
+                    | Instr::JALR(..)
+                    | Instr::BEQ(..)
+                    | Instr::BNE(..)
+                    | Instr::BLT(..)
+                    | Instr::BGE(..)
+                    | Instr::BLTU(..)
+                    | Instr::BGEU(..)
+            ) {
+                return Block::new(pc, self.mode, ops, true);
+            }
+            addr = op.next_pc();
+        }
+        Block::new(pc, self.mode, ops, false)
+    }
+
     /// Execute an instruction after decoding. Return true if an error happens, otherwise false.
     pub fn execute(&mut self, inst: u32) -> Result<WBInfo> {
+        let cur_pc = self.pc;
+        // 中断在两条指令之间响应, 这条指令还没有执行, 返回后重新执行
+        if let Some(interrupt) = self.pending_interrupt() {
+            let epc = match self.fetch_fault.take() {
+                Some((epc, _)) => epc,
+                None => cur_pc.wrapping_sub(inst_len(inst)),
+            };
+            return self.raise(cur_pc, epc, interrupt.cause(), 0);
+        }
+        if let Some((epc, exception)) = self.fetch_fault.take() {
+            return self.raise(cur_pc, epc, exception.cause(), exception.tval());
+        }
+        match self.execute_inst(inst) {
+            Err(e) => self.fault(cur_pc, inst, e),
+            result => result,
+        }
+    }
+
+    /// 指令没有执行完: 需要处理的异常进入异常处理, 其余的作为错误返回
+    fn fault(&mut self, cur_pc: u32, inst: u32, e: anyhow::Error) -> Result<WBInfo> {
+        match e.downcast_ref::<Exception>() {
+            Some(&exception) if self.traps(&exception) => {
+                // 非法指令的 mtval 是指令本身
+                let exception = match exception {
+                    Exception::IllegalInstruction(_) => Exception::IllegalInstruction(inst),
+                    exception => exception,
+                };
+                let inst_pc = cur_pc.wrapping_sub(inst_len(inst));
+                self.raise(cur_pc, inst_pc, exception.cause(), exception.tval())
+            }
+            _ => Err(e),
+        }
+    }
+
+    /// 异常是进入异常处理, 还是作为错误返回
+    fn traps(&self, exception: &Exception) -> bool {
+        match exception {
+            Exception::IllegalInstruction(_) => self.config.trap_illegal,
+            Exception::InstructionAccessFault(_)
+            | Exception::LoadAccessFault(_)
+            | Exception::StoreAccessFault(_) => self.config.trap_access_fault,
+            _ => true,
+        }
+    }
+
+    /// 当前可以响应的优先级最高的中断.
+    /// 交给 M 模式的中断在更低的特权级总是打开, 委托给 S 模式的中断在 M 模式总是屏蔽.
+    fn pending_interrupt(&self) -> Option<Interrupt> {
+        let mstatus = self.csrs[MSTATUS as usize];
+        let mideleg = self.csrs[MIDELEG as usize];
+        let pending = self.mip() & self.csrs[MIE as usize];
+        let mut enabled = 0;
+        if self.mode < Privilege::Machine || mstatus & MSTATUS_MIE != 0 {
+            enabled |= pending & !mideleg;
+        }
+        if self.mode < Privilege::Supervisor
+            || (self.mode == Privilege::Supervisor && mstatus & MSTATUS_SIE != 0)
+        {
+            enabled |= pending & mideleg;
+        }
+        Interrupt::ALL
+            .into_iter()
+            .find(|interrupt| enabled & (1 << interrupt.code()) != 0)
+    }
+
+    /// 指令没有执行完, 进入异常处理
+    fn raise(&mut self, cur_pc: u32, epc: u32, cause: u32, tval: u32) -> Result<WBInfo> {
+        self.pc = cur_pc;
+        self.trap(epc, cause, tval).with_context(|| context!())?;
+        self.tick(self.config.cycles_per_inst as u64);
+        // 中断在两条指令之间响应, 不算执行了一条指令
+        let executed = (cause >> 31 == 0) as u32;
+        Ok(WBInfo {
+            wb_have_inst: executed,
+            wb_pc: cur_pc,
+            inst_valid: executed,
+            wb_trap: 1,
+            ..Default::default()
+        })
+    }
+
+    fn execute_inst(&mut self, raw: u32) -> Result<WBInfo> {
         // Emulate that register x0 is hardwired with all bits equal to 0.
         self.regs[0] = 0;
         let cur_pc = self.pc;
-        let inst = Instr::try_from(inst).with_context(|| context!())?;
+        // pc 已经前进了 len 个字节
+        let len = inst_len(raw);
+        let inst_pc = cur_pc.wrapping_sub(len);
+        let inst = match self.decode_cache.get(inst_pc, raw) {
+            Some(inst) => inst.clone(),
+            None => {
+                let inst = self.decode(raw).with_context(|| context!())?;
+                self.decode_cache.insert(inst_pc, raw, inst.clone());
+                inst
+            }
+        };
+        // FS 在运行时变化, 不能在译码时检查
+        if inst.extension() == Some(Extension::F) && self.fs() == FS_OFF {
+            return Err(Exception::IllegalInstruction(raw))
+                .with_context(|| format!("{} (mstatus.FS = Off)", inst))
+                .with_context(|| context!());
+        }
         let (wb_rd, wb_val, wb_ena): (u32, u32, u32) = match inst {
             Instr::LUI(rd, imm) => {
                 self.regs[rd as usize] = imm;
                 (rd, imm, 1)
             }
             Instr::AUIPC(rd, imm) => {
-                let val = self.pc.wrapping_add(imm).wrapping_sub(4);
+                let val = self.pc.wrapping_add(imm).wrapping_sub(len);
                 self.regs[rd as usize] = val;
                 (rd, val, 1)
             }
             Instr::JAL(rd, offset) => {
                 let val = self.pc;
                 self.regs[rd as usize] = val;
-                self.pc = self.pc.wrapping_add(offset as u32).wrapping_sub(4);
+                self.pc = self.pc.wrapping_add(offset as u32).wrapping_sub(len);
                 (rd, val, 1)
             }
             Instr::BEQ(rs1, rs2, offset) => {
                 if self.regs[rs1 as usize] == self.regs[rs2 as usize] {
-                    self.pc = self.pc.wrapping_add(offset as u32).wrapping_sub(4);
+                    self.pc = self.pc.wrapping_add(offset as u32).wrapping_sub(len);
                 }
                 (0, 0, 0)
             }
             Instr::BNE(rs1, rs2, offset) => {
                 if self.regs[rs1 as usize] != self.regs[rs2 as usize] {
-                    self.pc = self.pc.wrapping_add(offset as u32).wrapping_sub(4);
+                    self.pc = self.pc.wrapping_add(offset as u32).wrapping_sub(len);
                 }
                 (0, 0, 0)
             }
             Instr::BLT(rs1, rs2, offset) => {
                 if (self.regs[rs1 as usize] as i32) < (self.regs[rs2 as usize] as i32) {
-                    self.pc = self.pc.wrapping_add(offset as u32).wrapping_sub(4);
+                    self.pc = self.pc.wrapping_add(offset as u32).wrapping_sub(len);
                 }
                 (0, 0, 0)
             }
             Instr::BGE(rs1, rs2, offset) => {
                 if (self.regs[rs1 as usize] as i32) >= (self.regs[rs2 as usize] as i32) {
-                    self.pc = self.pc.wrapping_add(offset as u32).wrapping_sub(4);
+                    self.pc = self.pc.wrapping_add(offset as u32).wrapping_sub(len);
                 }
                 (0, 0, 0)
             }
             Instr::BLTU(rs1, rs2, offset) => {
                 if self.regs[rs1 as usize] < self.regs[rs2 as usize] {
-                    self.pc = self.pc.wrapping_add(offset as u32).wrapping_sub(4);
+                    self.pc = self.pc.wrapping_add(offset as u32).wrapping_sub(len);
                 }
                 (0, 0, 0)
             }
             Instr::BGEU(rs1, rs2, offset) => {
                 if self.regs[rs1 as usize] >= self.regs[rs2 as usize] {
-                    self.pc = self.pc.wrapping_add(offset as u32).wrapping_sub(4);
+                    self.pc = self.pc.wrapping_add(offset as u32).wrapping_sub(len);
                 }
                 (0, 0, 0)
             }
@@ -295,19 +867,556 @@ impl CPU {
                 self.regs[rd as usize] = val;
                 (rd, val, 1)
             }
+            Instr::MUL(rd, rs1, rs2) => {
+                let val = self.regs[rs1 as usize].wrapping_mul(self.regs[rs2 as usize]);
+                self.regs[rd as usize] = val;
+                (rd, val, 1)
+            }
+            Instr::MULH(rd, rs1, rs2) => {
+                let lhs = self.regs[rs1 as usize] as i32 as i64;
+                let rhs = self.regs[rs2 as usize] as i32 as i64;
+                let val = (lhs.wrapping_mul(rhs) >> 32) as u32;
+                self.regs[rd as usize] = val;
+                (rd, val, 1)
+            }
+            Instr::MULHSU(rd, rs1, rs2) => {
+                let lhs = self.regs[rs1 as usize] as i32 as i64;
+                let rhs = self.regs[rs2 as usize] as u64 as i64;
+                let val = (lhs.wrapping_mul(rhs) >> 32) as u32;
+                self.regs[rd as usize] = val;
+                (rd, val, 1)
+            }
+            Instr::MULHU(rd, rs1, rs2) => {
+                let lhs = self.regs[rs1 as usize] as u64;
+                let rhs = self.regs[rs2 as usize] as u64;
+                let val = (lhs.wrapping_mul(rhs) >> 32) as u32;
+                self.regs[rd as usize] = val;
+                (rd, val, 1)
+            }
+            Instr::DIV(rd, rs1, rs2) => {
+                let dividend = self.regs[rs1 as usize] as i32;
+                let divisor = self.regs[rs2 as usize] as i32;
+                // x / 0 = -1, i32::MIN / -1 = i32::MIN
+                let val = if divisor == 0 {
+                    u32::MAX
+                } else {
+                    dividend.wrapping_div(divisor) as u32
+                };
+                self.regs[rd as usize] = val;
+                (rd, val, 1)
+            }
+            Instr::DIVU(rd, rs1, rs2) => {
+                let dividend = self.regs[rs1 as usize];
+                let divisor = self.regs[rs2 as usize];
+                let val = dividend.checked_div(divisor).unwrap_or(u32::MAX);
+                self.regs[rd as usize] = val;
+                (rd, val, 1)
+            }
+            Instr::REM(rd, rs1, rs2) => {
+                let dividend = self.regs[rs1 as usize] as i32;
+                let divisor = self.regs[rs2 as usize] as i32;
+                // x % 0 = x, i32::MIN % -1 = 0
+                let val = if divisor == 0 {
+                    dividend as u32
+                } else {
+                    dividend.wrapping_rem(divisor) as u32
+                };
+                self.regs[rd as usize] = val;
+                (rd, val, 1)
+            }
+            Instr::REMU(rd, rs1, rs2) => {
+                let dividend = self.regs[rs1 as usize];
+                let divisor = self.regs[rs2 as usize];
+                let val = dividend.checked_rem(divisor).unwrap_or(dividend);
+                self.regs[rd as usize] = val;
+                (rd, val, 1)
+            }
+            Instr::SH1ADD(rd, rs1, rs2) => {
+                let (lhs, rhs) = (self.regs[rs1 as usize], self.regs[rs2 as usize]);
+                let val = (lhs << 1).wrapping_add(rhs);
+                self.regs[rd as usize] = val;
+                (rd, val, 1)
+            }
+            Instr::SH2ADD(rd, rs1, rs2) => {
+                let (lhs, rhs) = (self.regs[rs1 as usize], self.regs[rs2 as usize]);
+                let val = (lhs << 2).wrapping_add(rhs);
+                self.regs[rd as usize] = val;
+                (rd, val, 1)
+            }
+            Instr::SH3ADD(rd, rs1, rs2) => {
+                let (lhs, rhs) = (self.regs[rs1 as usize], self.regs[rs2 as usize]);
+                let val = (lhs << 3).wrapping_add(rhs);
+                self.regs[rd as usize] = val;
+                (rd, val, 1)
+            }
+            Instr::ANDN(rd, rs1, rs2) => {
+                let (lhs, rhs) = (self.regs[rs1 as usize], self.regs[rs2 as usize]);
+                let val = lhs & !rhs;
+                self.regs[rd as usize] = val;
+                (rd, val, 1)
+            }
+            Instr::ORN(rd, rs1, rs2) => {
+                let (lhs, rhs) = (self.regs[rs1 as usize], self.regs[rs2 as usize]);
+                let val = lhs | !rhs;
+                self.regs[rd as usize] = val;
+                (rd, val, 1)
+            }
+            Instr::XNOR(rd, rs1, rs2) => {
+                let (lhs, rhs) = (self.regs[rs1 as usize], self.regs[rs2 as usize]);
+                let val = !(lhs ^ rhs);
+                self.regs[rd as usize] = val;
+                (rd, val, 1)
+            }
+            Instr::MAX(rd, rs1, rs2) => {
+                let (lhs, rhs) = (self.regs[rs1 as usize], self.regs[rs2 as usize]);
+                let val = (lhs as i32).max(rhs as i32) as u32;
+                self.regs[rd as usize] = val;
+                (rd, val, 1)
+            }
+            Instr::MAXU(rd, rs1, rs2) => {
+                let (lhs, rhs) = (self.regs[rs1 as usize], self.regs[rs2 as usize]);
+                let val = lhs.max(rhs);
+                self.regs[rd as usize] = val;
+                (rd, val, 1)
+            }
+            Instr::MIN(rd, rs1, rs2) => {
+                let (lhs, rhs) = (self.regs[rs1 as usize], self.regs[rs2 as usize]);
+                let val = (lhs as i32).min(rhs as i32) as u32;
+                self.regs[rd as usize] = val;
+                (rd, val, 1)
+            }
+            Instr::MINU(rd, rs1, rs2) => {
+                let (lhs, rhs) = (self.regs[rs1 as usize], self.regs[rs2 as usize]);
+                let val = lhs.min(rhs);
+                self.regs[rd as usize] = val;
+                (rd, val, 1)
+            }
+            Instr::ROL(rd, rs1, rs2) => {
+                let (lhs, rhs) = (self.regs[rs1 as usize], self.regs[rs2 as usize]);
+                let val = lhs.rotate_left(rhs & 0x1f);
+                self.regs[rd as usize] = val;
+                (rd, val, 1)
+            }
+            Instr::ROR(rd, rs1, rs2) => {
+                let (lhs, rhs) = (self.regs[rs1 as usize], self.regs[rs2 as usize]);
+                let val = lhs.rotate_right(rhs & 0x1f);
+                self.regs[rd as usize] = val;
+                (rd, val, 1)
+            }
+            Instr::RORI(rd, rs1, shamt) => {
+                let shamt = (shamt & 0x1f) as u32;
+                let val = self.regs[rs1 as usize].rotate_right(shamt);
+                self.regs[rd as usize] = val;
+                (rd, val, 1)
+            }
+            Instr::CLZ(rd, rs1) => {
+                let val = self.regs[rs1 as usize].leading_zeros();
+                self.regs[rd as usize] = val;
+                (rd, val, 1)
+            }
+            Instr::CTZ(rd, rs1) => {
+                let val = self.regs[rs1 as usize].trailing_zeros();
+                self.regs[rd as usize] = val;
+                (rd, val, 1)
+            }
+            Instr::CPOP(rd, rs1) => {
+                let val = self.regs[rs1 as usize].count_ones();
+                self.regs[rd as usize] = val;
+                (rd, val, 1)
+            }
+            Instr::SEXTB(rd, rs1) => {
+                let val = self.regs[rs1 as usize] as i8 as i32 as u32;
+                self.regs[rd as usize] = val;
+                (rd, val, 1)
+            }
+            Instr::SEXTH(rd, rs1) => {
+                let val = self.regs[rs1 as usize] as i16 as i32 as u32;
+                self.regs[rd as usize] = val;
+                (rd, val, 1)
+            }
+            Instr::ZEXTH(rd, rs1) => {
+                let val = self.regs[rs1 as usize] & 0xffff;
+                self.regs[rd as usize] = val;
+                (rd, val, 1)
+            }
+            Instr::ORCB(rd, rs1) => {
+                // 每个字节: 非零则全 1, 否则全 0
+                let val = u32::from_le_bytes(self.regs[rs1 as usize].to_le_bytes().map(|byte| {
+                    if byte == 0 {
+                        0
+                    } else {
+                        0xff
+                    }
+                }));
+                self.regs[rd as usize] = val;
+                (rd, val, 1)
+            }
+            Instr::REV8(rd, rs1) => {
+                let val = self.regs[rs1 as usize].swap_bytes();
+                self.regs[rd as usize] = val;
+                (rd, val, 1)
+            }
+            Instr::BCLR(rd, rs1, rs2) => {
+                let (lhs, rhs) = (self.regs[rs1 as usize], self.regs[rs2 as usize]);
+                let val = lhs & !(1 << (rhs & 0x1f));
+                self.regs[rd as usize] = val;
+                (rd, val, 1)
+            }
+            Instr::BEXT(rd, rs1, rs2) => {
+                let (lhs, rhs) = (self.regs[rs1 as usize], self.regs[rs2 as usize]);
+                let val = (lhs >> (rhs & 0x1f)) & 1;
+                self.regs[rd as usize] = val;
+                (rd, val, 1)
+            }
+            Instr::BINV(rd, rs1, rs2) => {
+                let (lhs, rhs) = (self.regs[rs1 as usize], self.regs[rs2 as usize]);
+                let val = lhs ^ (1 << (rhs & 0x1f));
+                self.regs[rd as usize] = val;
+                (rd, val, 1)
+            }
+            Instr::BSET(rd, rs1, rs2) => {
+                let (lhs, rhs) = (self.regs[rs1 as usize], self.regs[rs2 as usize]);
+                let val = lhs | (1 << (rhs & 0x1f));
+                self.regs[rd as usize] = val;
+                (rd, val, 1)
+            }
+            Instr::BCLRI(rd, rs1, shamt) => {
+                let shamt = (shamt & 0x1f) as u32;
+                let val = self.regs[rs1 as usize] & !(1 << shamt);
+                self.regs[rd as usize] = val;
+                (rd, val, 1)
+            }
+            Instr::BEXTI(rd, rs1, shamt) => {
+                let shamt = (shamt & 0x1f) as u32;
+                let val = (self.regs[rs1 as usize] >> shamt) & 1;
+                self.regs[rd as usize] = val;
+                (rd, val, 1)
+            }
+            Instr::BINVI(rd, rs1, shamt) => {
+                let shamt = (shamt & 0x1f) as u32;
+                let val = self.regs[rs1 as usize] ^ (1 << shamt);
+                self.regs[rd as usize] = val;
+                (rd, val, 1)
+            }
+            Instr::BSETI(rd, rs1, shamt) => {
+                let shamt = (shamt & 0x1f) as u32;
+                let val = self.regs[rs1 as usize] | (1 << shamt);
+                self.regs[rd as usize] = val;
+                (rd, val, 1)
+            }
+            Instr::LRW(rd, rs1, _) => {
+                let addr = self.regs[rs1 as usize];
+                if addr % 4 != 0 {
+                    return Err(Exception::LoadAddressMisaligned(addr)).with_context(|| context!());
+                }
+                let val = self.load(addr, 32).with_context(|| context!())?;
+                self.reservation = Some(addr);
+                self.regs[rd as usize] = val;
+                (rd, val, 1)
+            }
+            Instr::SCW(rd, rs2, rs1, _) => {
+                let addr = self.regs[rs1 as usize];
+                if addr % 4 != 0 {
+                    return Err(Exception::StoreAddressMisaligned(addr))
+                        .with_context(|| context!());
+                }
+                // 成功写 0, 失败写 1
+                let val = if self.reservation == Some(addr) {
+                    self.store(addr, self.regs[rs2 as usize], 32)
+                        .with_context(|| context!())?;
+                    0
+                } else {
+                    1
+                };
+                self.reservation = None;
+                self.regs[rd as usize] = val;
+                (rd, val, 1)
+            }
+            Instr::AMOSWAPW(rd, rs2, rs1, _) => self
+                .amo(rd, rs2, rs1, |_, rhs| rhs)
+                .with_context(|| context!())?,
+            Instr::AMOADDW(rd, rs2, rs1, _) => self
+                .amo(rd, rs2, rs1, |lhs, rhs| lhs.wrapping_add(rhs))
+                .with_context(|| context!())?,
+            Instr::AMOXORW(rd, rs2, rs1, _) => self
+                .amo(rd, rs2, rs1, |lhs, rhs| lhs ^ rhs)
+                .with_context(|| context!())?,
+            Instr::AMOANDW(rd, rs2, rs1, _) => self
+                .amo(rd, rs2, rs1, |lhs, rhs| lhs & rhs)
+                .with_context(|| context!())?,
+            Instr::AMOORW(rd, rs2, rs1, _) => self
+                .amo(rd, rs2, rs1, |lhs, rhs| lhs | rhs)
+                .with_context(|| context!())?,
+            Instr::AMOMINW(rd, rs2, rs1, _) => self
+                .amo(rd, rs2, rs1, |lhs, rhs| (lhs as i32).min(rhs as i32) as u32)
+                .with_context(|| context!())?,
+            Instr::AMOMAXW(rd, rs2, rs1, _) => self
+                .amo(rd, rs2, rs1, |lhs, rhs| (lhs as i32).max(rhs as i32) as u32)
+                .with_context(|| context!())?,
+            Instr::AMOMINUW(rd, rs2, rs1, _) => self
+                .amo(rd, rs2, rs1, |lhs, rhs| lhs.min(rhs))
+                .with_context(|| context!())?,
+            Instr::AMOMAXUW(rd, rs2, rs1, _) => self
+                .amo(rd, rs2, rs1, |lhs, rhs| lhs.max(rhs))
+                .with_context(|| context!())?,
+            Instr::FLW(rd, offset, base) => {
+                let addr = self.regs[base as usize].wrapping_add(offset as u32);
+                let val = self.load(addr, 32).with_context(|| context!())?;
+                self.write_freg(rd, val, 0)
+            }
+            Instr::FSW(rs2, offset, base) => {
+                let addr = self.regs[base as usize].wrapping_add(offset as u32);
+                self.store(addr, self.fregs[rs2 as usize], 32)
+                    .with_context(|| context!())?;
+                (0, 0, 0)
+            }
+            Instr::FMADDS(rd, rs1, rs2, rs3, rm) => {
+                let rm = self.rounding_mode(rm).with_context(|| context!())?;
+                let [a, b, c] = [rs1, rs2, rs3].map(|reg| self.fregs[reg as usize]);
+                let (val, flags) = float::fma(a, b, c, rm);
+                self.write_freg(rd, val, flags)
+            }
+            // 取反只改符号位, 不影响 NaN 的处理
+            Instr::FMSUBS(rd, rs1, rs2, rs3, rm) => {
+                let rm = self.rounding_mode(rm).with_context(|| context!())?;
+                let [a, b, c] = [rs1, rs2, rs3].map(|reg| self.fregs[reg as usize]);
+                let (val, flags) = float::fma(a, b, c ^ float::SIGN, rm);
+                self.write_freg(rd, val, flags)
+            }
+            Instr::FNMSUBS(rd, rs1, rs2, rs3, rm) => {
+                let rm = self.rounding_mode(rm).with_context(|| context!())?;
+                let [a, b, c] = [rs1, rs2, rs3].map(|reg| self.fregs[reg as usize]);
+                let (val, flags) = float::fma(a ^ float::SIGN, b, c, rm);
+                self.write_freg(rd, val, flags)
+            }
+            Instr::FNMADDS(rd, rs1, rs2, rs3, rm) => {
+                let rm = self.rounding_mode(rm).with_context(|| context!())?;
+                let [a, b, c] = [rs1, rs2, rs3].map(|reg| self.fregs[reg as usize]);
+                let (val, flags) = float::fma(a ^ float::SIGN, b, c ^ float::SIGN, rm);
+                self.write_freg(rd, val, flags)
+            }
+            Instr::FADDS(rd, rs1, rs2, rm) => {
+                let rm = self.rounding_mode(rm).with_context(|| context!())?;
+                let (val, flags) =
+                    float::add(self.fregs[rs1 as usize], self.fregs[rs2 as usize], rm);
+                self.write_freg(rd, val, flags)
+            }
+            Instr::FSUBS(rd, rs1, rs2, rm) => {
+                let rm = self.rounding_mode(rm).with_context(|| context!())?;
+                let (val, flags) =
+                    float::sub(self.fregs[rs1 as usize], self.fregs[rs2 as usize], rm);
+                self.write_freg(rd, val, flags)
+            }
+            Instr::FMULS(rd, rs1, rs2, rm) => {
+                let rm = self.rounding_mode(rm).with_context(|| context!())?;
+                let (val, flags) =
+                    float::mul(self.fregs[rs1 as usize], self.fregs[rs2 as usize], rm);
+                self.write_freg(rd, val, flags)
+            }
+            Instr::FDIVS(rd, rs1, rs2, rm) => {
+                let rm = self.rounding_mode(rm).with_context(|| context!())?;
+                let (val, flags) =
+                    float::div(self.fregs[rs1 as usize], self.fregs[rs2 as usize], rm);
+                self.write_freg(rd, val, flags)
+            }
+            Instr::FSQRTS(rd, rs1, rm) => {
+                let rm = self.rounding_mode(rm).with_context(|| context!())?;
+                let (val, flags) = float::sqrt(self.fregs[rs1 as usize], rm);
+                self.write_freg(rd, val, flags)
+            }
+            Instr::FSGNJS(rd, rs1, rs2) => {
+                let sign = self.fregs[rs2 as usize] & float::SIGN;
+                let val = (self.fregs[rs1 as usize] & !float::SIGN) | sign;
+                self.write_freg(rd, val, 0)
+            }
+            Instr::FSGNJNS(rd, rs1, rs2) => {
+                let sign = !self.fregs[rs2 as usize] & float::SIGN;
+                let val = (self.fregs[rs1 as usize] & !float::SIGN) | sign;
+                self.write_freg(rd, val, 0)
+            }
+            Instr::FSGNJXS(rd, rs1, rs2) => {
+                let sign = self.fregs[rs2 as usize] & float::SIGN;
+                let val = self.fregs[rs1 as usize] ^ sign;
+                self.write_freg(rd, val, 0)
+            }
+            Instr::FMINS(rd, rs1, rs2) => {
+                let (val, flags) = float::min(self.fregs[rs1 as usize], self.fregs[rs2 as usize]);
+                self.write_freg(rd, val, flags)
+            }
+            Instr::FMAXS(rd, rs1, rs2) => {
+                let (val, flags) = float::max(self.fregs[rs1 as usize], self.fregs[rs2 as usize]);
+                self.write_freg(rd, val, flags)
+            }
+            Instr::FCVTWS(rd, rs1, rm) => {
+                let rm = self.rounding_mode(rm).with_context(|| context!())?;
+                let (val, flags) = float::to_i32(self.fregs[rs1 as usize], rm);
+                self.set_fflags(flags);
+                self.regs[rd as usize] = val;
+                (rd, val, 1)
+            }
+            Instr::FCVTWUS(rd, rs1, rm) => {
+                let rm = self.rounding_mode(rm).with_context(|| context!())?;
+                let (val, flags) = float::to_u32(self.fregs[rs1 as usize], rm);
+                self.set_fflags(flags);
+                self.regs[rd as usize] = val;
+                (rd, val, 1)
+            }
+            Instr::FMVXW(rd, rs1) => {
+                let val = self.fregs[rs1 as usize];
+                self.regs[rd as usize] = val;
+                (rd, val, 1)
+            }
+            Instr::FCVTSW(rd, rs1, rm) => {
+                let rm = self.rounding_mode(rm).with_context(|| context!())?;
+                let (val, flags) = float::from_i32(self.regs[rs1 as usize], rm);
+                self.write_freg(rd, val, flags)
+            }
+            Instr::FCVTSWU(rd, rs1, rm) => {
+                let rm = self.rounding_mode(rm).with_context(|| context!())?;
+                let (val, flags) = float::from_u32(self.regs[rs1 as usize], rm);
+                self.write_freg(rd, val, flags)
+            }
+            Instr::FMVWX(rd, rs1) => self.write_freg(rd, self.regs[rs1 as usize], 0),
+            Instr::FEQS(rd, rs1, rs2) => {
+                let (val, flags) = float::eq(self.fregs[rs1 as usize], self.fregs[rs2 as usize]);
+                self.set_fflags(flags);
+                self.regs[rd as usize] = val as u32;
+                (rd, val as u32, 1)
+            }
+            Instr::FLTS(rd, rs1, rs2) => {
+                let (val, flags) = float::lt(self.fregs[rs1 as usize], self.fregs[rs2 as usize]);
+                self.set_fflags(flags);
+                self.regs[rd as usize] = val as u32;
+                (rd, val as u32, 1)
+            }
+            Instr::FLES(rd, rs1, rs2) => {
+                let (val, flags) = float::le(self.fregs[rs1 as usize], self.fregs[rs2 as usize]);
+                self.set_fflags(flags);
+                self.regs[rd as usize] = val as u32;
+                (rd, val as u32, 1)
+            }
+            Instr::FCLASSS(rd, rs1) => {
+                let val = float::classify(self.fregs[rs1 as usize]);
+                self.regs[rd as usize] = val;
+                (rd, val, 1)
+            }
+            // 单核, 访存按程序顺序完成
+            Instr::FENCE(_, _) => (0, 0, 0),
+            Instr::FENCEI => {
+                self.decode_cache.flush();
+                self.blocks.flush();
+                (0, 0, 0)
+            }
+            Instr::SFENCEVMA(rs1, rs2) => {
+                if self.mode == Privilege::User {
+                    return Err(Exception::IllegalInstruction(raw))
+                        .with_context(|| format!("{} in {:?}-mode", inst, self.mode))
+                        .with_context(|| context!());
+                }
+                // x0 表示所有的地址/地址空间
+                let vaddr = (rs1 != 0).then(|| self.regs[rs1 as usize]);
+                let asid = (rs2 != 0).then(|| self.regs[rs2 as usize] & 0x1ff);
+                self.mmu.flush(vaddr, asid);
+                // 译码缓存按虚拟地址索引
+                self.decode_cache.flush();
+                self.blocks.flush();
+                (0, 0, 0)
+            }
+            Instr::EBREAK => {
+                // 打开 trap_illegal 时进入断点异常, 否则停止运行
+                if self.config.trap_illegal {
+                    return Err(Exception::Breakpoint(inst_pc)).with_context(|| context!());
+                }
+                return Err(Halt::Ebreak).with_context(|| context!());
+            }
+            Instr::WFI => {
+                // 等待的只可能是定时器中断: 把时间快进到 mtimecmp, 其余情况当作 nop
+                let mie = self.csrs[MIE as usize];
+                if self.mip() & mie == 0 && mie & MIP_MTIP != 0 {
+                    let idle = self
+                        .bus
+                        .clint()
+                        .mtimecmp()
+                        .saturating_sub(self.bus.clint().mtime());
+                    self.tick(idle);
+                }
+                (0, 0, 0)
+            }
             Instr::ECALL => {
-                self.store_csr(MEPC, self.pc).with_context(|| context!())?;
-                let mtval = self.load_csr(MTVAL).with_context(|| context!())?;
-                self.pc = mtval;
-                self.store_csr(MCAUSE, 0x0000_000b)
+                let exception = match self.mode {
+                    Privilege::User => Exception::EnvironmentCallFromU,
+                    Privilege::Supervisor => Exception::EnvironmentCallFromS,
+                    Privilege::Machine => Exception::EnvironmentCallFromM,
+                };
+                self.trap(self.pc, exception.cause(), exception.tval())
                     .with_context(|| context!())?;
                 (0, 0, 0)
             }
-            Instr::ERET => {
+            // 没有实现 N 扩展, uret 是非法指令
+            Instr::URET if !self.config.uret_as_mret => {
+                return Err(Exception::IllegalInstruction(raw))
+                    .with_context(|| format!("{} without N extension", inst))
+                    .with_context(|| context!());
+            }
+            Instr::URET | Instr::MRET => {
+                if self.mode != Privilege::Machine {
+                    return Err(Exception::IllegalInstruction(raw))
+                        .with_context(|| format!("{} in {:?}-mode", inst, self.mode))
+                        .with_context(|| context!());
+                }
                 self.pc = self.load_csr(MEPC).with_context(|| context!())?;
-                return Err(anyhow!("eret happened")).with_context(|| context!());
+                // mie = mpie, mpie = 1, 回到 mpp, mpp = U
+                let mstatus = self.csrs[MSTATUS as usize];
+                let mie = if mstatus & MSTATUS_MPIE != 0 {
+                    MSTATUS_MIE
+                } else {
+                    0
+                };
+                self.mode = Privilege::from_bits(mstatus >> MSTATUS_MPP_SHIFT);
+                // 回到更低的特权级时清除 mprv
+                let mprv = if self.mode == Privilege::Machine {
+                    mstatus & MSTATUS_MPRV
+                } else {
+                    0
+                };
+                self.csrs[MSTATUS as usize] = (mstatus
+                    & !(MSTATUS_MIE | MSTATUS_MPP | MSTATUS_MPRV))
+                    | mie
+                    | mprv
+                    | MSTATUS_MPIE;
+                if self.config.halt_on_eret {
+                    return Err(Halt::Eret).with_context(|| context!());
+                }
+                (0, 0, 0)
+            }
+            Instr::SRET => {
+                if self.mode == Privilege::User {
+                    return Err(Exception::IllegalInstruction(raw))
+                        .with_context(|| format!("{} in {:?}-mode", inst, self.mode))
+                        .with_context(|| context!());
+                }
+                self.pc = self.load_csr(SEPC).with_context(|| context!())?;
+                // sie = spie, spie = 1, 回到 spp, spp = U
+                let mstatus = self.csrs[MSTATUS as usize];
+                let sie = if mstatus & MSTATUS_SPIE != 0 {
+                    MSTATUS_SIE
+                } else {
+                    0
+                };
+                self.mode = if mstatus & MSTATUS_SPP != 0 {
+                    Privilege::Supervisor
+                } else {
+                    Privilege::User
+                };
+                self.csrs[MSTATUS as usize] =
+                    (mstatus & !(MSTATUS_SIE | MSTATUS_SPP | MSTATUS_MPRV)) | sie | MSTATUS_SPIE;
+                if self.config.halt_on_eret {
+                    return Err(Halt::Eret).with_context(|| context!());
+                }
+                (0, 0, 0)
             }
             Instr::CSRRW(rd, csr_addr, rs1) => {
+                self.check_csr(csr_addr).with_context(|| context!())?;
                 let val = self.load_csr(csr_addr).with_context(|| context!())?;
                 self.store_csr(csr_addr, self.regs[rs1 as usize])
                     .with_context(|| context!())?;
@@ -315,71 +1424,587 @@ impl CPU {
                 (rd, val, 1)
             }
             Instr::CSRRS(rd, csr_addr, rs1) => {
+                self.check_csr(csr_addr).with_context(|| context!())?;
                 let val = self.load_csr(csr_addr).with_context(|| context!())?;
-                self.store_csr(csr_addr, val | self.regs[rs1 as usize])
-                    .with_context(|| context!())?;
+                // rs1 = x0 时不写 csr
+                if rs1 != 0 {
+                    self.store_csr(csr_addr, val | self.regs[rs1 as usize])
+                        .with_context(|| context!())?;
+                }
                 self.regs[rd as usize] = val;
                 (rd, val, 1)
             }
             Instr::CSRRC(rd, csr_addr, rs1) => {
+                self.check_csr(csr_addr).with_context(|| context!())?;
                 let val = self.load_csr(csr_addr).with_context(|| context!())?;
-                self.store_csr(csr_addr, val & !self.regs[rs1 as usize])
-                    .with_context(|| context!())?;
+                if rs1 != 0 {
+                    self.store_csr(csr_addr, val & !self.regs[rs1 as usize])
+                        .with_context(|| context!())?;
+                }
                 self.regs[rd as usize] = val;
                 (rd, val, 1)
             }
             Instr::CSRRWI(rd, csr_addr, zimm) => {
+                self.check_csr(csr_addr).with_context(|| context!())?;
                 let val = self.load_csr(csr_addr).with_context(|| context!())?;
-                self.regs[rd as usize] = val;
                 self.store_csr(csr_addr, zimm).with_context(|| context!())?;
+                self.regs[rd as usize] = val;
                 (rd, val, 1)
             }
             Instr::CSRRSI(rd, csr_addr, zimm) => {
+                self.check_csr(csr_addr).with_context(|| context!())?;
                 let val = self.load_csr(csr_addr).with_context(|| context!())?;
-                self.store_csr(csr_addr, val | zimm)
-                    .with_context(|| context!())?;
+                // zimm = 0 时不写 csr
+                if zimm != 0 {
+                    self.store_csr(csr_addr, val | zimm)
+                        .with_context(|| context!())?;
+                }
                 self.regs[rd as usize] = val;
                 (rd, val, 1)
             }
             Instr::CSRRCI(rd, csr_addr, zimm) => {
+                self.check_csr(csr_addr).with_context(|| context!())?;
                 let val = self.load_csr(csr_addr).with_context(|| context!())?;
-                self.store_csr(csr_addr, val & !zimm)
-                    .with_context(|| context!())?;
+                if zimm != 0 {
+                    self.store_csr(csr_addr, val & !zimm)
+                        .with_context(|| context!())?;
+                }
                 self.regs[rd as usize] = val;
                 (rd, val, 1)
             }
+            Instr::C(_, _) => unreachable!("compressed instructions are expanded above"),
         };
 
+        self.retire();
+
+        // f0 不是零寄存器, 写 f0 也要报告
+        let wb_fp = inst.writes_freg();
         Ok(WBInfo {
             wb_have_inst: 1,
             wb_pc: cur_pc,
             wb_rd,
             wb_val,
-            wb_ena: if wb_rd == 0 { 0 } else { wb_ena },
+            wb_ena: if wb_rd == 0 && !wb_fp { 0 } else { wb_ena },
             inst_valid: 1,
+            wb_fp: wb_fp as u32,
+            wb_trap: (inst == Instr::ECALL) as u32,
         })
     }
 
+    /// 译码并展开压缩指令, 检查寄存器个数和扩展是否打开
+    fn decode(&self, raw: u32) -> Result<Instr> {
+        let inst = match Instr::try_from(raw)
+            .context(Exception::IllegalInstruction(raw))
+            .with_context(|| context!())?
+        {
+            Instr::C(_, expanded) => *expanded,
+            inst => inst,
+        };
+        if self.config.rv32e && inst.regs().iter().any(|&reg| reg >= 16) {
+            return Err(DecodeError::Rv32e(raw))
+                .context(Exception::IllegalInstruction(raw))
+                .with_context(|| context!());
+        }
+        if let Some(ext) = inst.extension() {
+            if !self.config.has_extension(ext) {
+                return Err(DecodeError::Disabled(raw, ext))
+                    .context(Exception::IllegalInstruction(raw))
+                    .with_context(|| context!());
+            }
+        }
+        Ok(inst)
+    }
+
+    /// mstatus.FS
+    fn fs(&self) -> u32 {
+        (self.csrs[MSTATUS as usize] & MSTATUS_FS) >> MSTATUS_FS_SHIFT
+    }
+
+    /// 浮点状态被修改, FS 置为 Dirty
+    fn dirty_fs(&mut self) {
+        self.csrs[MSTATUS as usize] |= FS_DIRTY << MSTATUS_FS_SHIFT;
+    }
+
+    /// 累加异常标志
+    fn set_fflags(&mut self, flags: u32) {
+        if flags != 0 {
+            self.csrs[FCSR as usize] |= flags;
+            self.dirty_fs();
+        }
+    }
+
+    fn write_freg(&mut self, rd: Reg, val: u32, flags: u32) -> (u32, u32, u32) {
+        self.fregs[rd as usize] = val;
+        self.set_fflags(flags);
+        self.dirty_fs();
+        (rd, val, 1)
+    }
+
+    /// rm = 7 (dyn) 时使用 frm, frm 为保留的编码时是非法指令
+    fn rounding_mode(&self, rm: u32) -> Result<float::RoundingMode> {
+        let rm = if rm == 0b111 {
+            self.csrs[FCSR as usize] >> 5
+        } else {
+            rm
+        };
+        float::RoundingMode::from_bits(rm)
+            .ok_or(Exception::IllegalInstruction(0))
+            .with_context(|| format!("invalid rounding mode: {}", rm))
+            .with_context(|| context!())
+    }
+
+    /// 原子地读-改-写一个字, rd 得到旧值
+    fn amo(
+        &mut self,
+        rd: Reg,
+        rs2: Reg,
+        rs1: Reg,
+        op: fn(u32, u32) -> u32,
+    ) -> Result<(u32, u32, u32)> {
+        let addr = self.regs[rs1 as usize];
+        // amo 不能模拟非对齐访问
+        if addr % 4 != 0 {
+            return Err(Exception::StoreAddressMisaligned(addr)).with_context(|| context!());
+        }
+        let val = self.load(addr, 32).with_context(|| context!())?;
+        self.store(addr, op(val, self.regs[rs2 as usize]), 32)
+            .with_context(|| context!())?;
+        self.regs[rd as usize] = val;
+        Ok((rd, val, 1))
+    }
+
+    /// 进入异常处理: 委托给 S 模式时用 sepc/stvec, 否则 mepc 保存返回地址, 跳转到 mtvec
+    fn trap(&mut self, epc: u32, cause: u32, tval: u32) -> Result<()> {
+        let interrupt = cause >> 31 != 0;
+        let code = cause & !(1 << 31);
+        let deleg = if interrupt {
+            self.csrs[MIDELEG as usize]
+        } else {
+            self.csrs[MEDELEG as usize]
+        };
+        let mstatus = self.csrs[MSTATUS as usize];
+        let tvec = if self.mode <= Privilege::Supervisor && deleg & (1 << code) != 0 {
+            self.store_csr(SEPC, epc).with_context(|| context!())?;
+            self.store_csr(SCAUSE, cause).with_context(|| context!())?;
+            self.store_csr(STVAL, tval).with_context(|| context!())?;
+            // spie = sie, sie = 0, spp = 当前特权级
+            let spie = if mstatus & MSTATUS_SIE != 0 {
+                MSTATUS_SPIE
+            } else {
+                0
+            };
+            let spp = if self.mode == Privilege::Supervisor {
+                MSTATUS_SPP
+            } else {
+                0
+            };
+            self.csrs[MSTATUS as usize] =
+                (mstatus & !(MSTATUS_SIE | MSTATUS_SPIE | MSTATUS_SPP)) | spie | spp;
+            self.mode = Privilege::Supervisor;
+            self.load_csr(STVEC).with_context(|| context!())?
+        } else {
+            self.store_csr(MEPC, epc).with_context(|| context!())?;
+            self.store_csr(MCAUSE, cause).with_context(|| context!())?;
+            self.store_csr(MTVAL, tval).with_context(|| context!())?;
+            // mpie = mie, mie = 0, mpp = 当前特权级
+            let mpie = if mstatus & MSTATUS_MIE != 0 {
+                MSTATUS_MPIE
+            } else {
+                0
+            };
+            let mpp = (self.mode as u32) << MSTATUS_MPP_SHIFT;
+            self.csrs[MSTATUS as usize] =
+                (mstatus & !(MSTATUS_MIE | MSTATUS_MPIE | MSTATUS_MPP)) | mpie | mpp;
+            self.mode = Privilege::Machine;
+            self.load_csr(MTVEC).with_context(|| context!())?
+        };
+        // 同步异常总是跳到 base, 向量模式下中断跳到 base + 4 * cause
+        let base = tvec & !0b11;
+        self.pc = if tvec & 0b11 == 1 && interrupt {
+            base.wrapping_add(4 * code)
+        } else {
+            base
+        };
+        Ok(())
+    }
+
+    /// csr 指令的权限检查: csr[9:8] 是能访问的最低特权级, 计数器还受 xcounteren 控制
+    fn check_csr(&self, addr: u32) -> Result<()> {
+        if matches!(addr, FFLAGS..=FCSR) && (!self.config.f || self.fs() == FS_OFF) {
+            return Err(CsrError::FsOff(addr))
+                .context(Exception::IllegalInstruction(0))
+                .with_context(|| context!());
+        }
+        if (self.mode as u32) < (addr >> 8) & 0b11 {
+            return Err(CsrError::Privilege(addr, self.mode))
+                .context(Exception::IllegalInstruction(0))
+                .with_context(|| context!());
+        }
+        if matches!(addr, CYCLE..=INSTRET | CYCLEH..=INSTRETH) {
+            let bit = 1 << (addr & 0x1f);
+            let mcounteren = self.csrs[MCOUNTEREN as usize];
+            let scounteren = self.csrs[SCOUNTEREN as usize];
+            let enabled = match self.mode {
+                Privilege::Machine => true,
+                Privilege::Supervisor => mcounteren & bit != 0,
+                Privilege::User => mcounteren & scounteren & bit != 0,
+            };
+            if !enabled {
+                return Err(CsrError::CounterDisabled(addr, self.mode))
+                    .context(Exception::IllegalInstruction(0))
+                    .with_context(|| context!());
+            }
+        }
+        Ok(())
+    }
+
+    /// 时间前进: mcycle 和 mtime 一起增长
+    /// 一条指令执行完
+    fn retire(&mut self) {
+        let cycles = self.config.cycles_per_inst as u64;
+        if std::mem::take(&mut self.wrote_cycle) {
+            self.tick_bus(cycles);
+        } else {
+            self.tick(cycles);
+        }
+        if !std::mem::take(&mut self.wrote_instret) {
+            self.instret = self.instret.wrapping_add(1);
+        }
+    }
+
+    /// 连续执行完 count 条指令
+    #[cfg(feature = "jit")]
+    fn retire_many(&mut self, count: u32) {
+        self.tick(self.config.cycles_per_inst as u64 * count as u64);
+        self.instret = self.instret.wrapping_add(count as u64);
+    }
+
+    fn tick(&mut self, cycles: u64) {
+        self.cycle = self.cycle.wrapping_add(cycles);
+        self.tick_bus(cycles);
+    }
+
+    /// 设备的中断线变高时离开基本块, 在下一条指令之前响应中断
+    fn tick_bus(&mut self, cycles: u64) {
+        #[cfg(feature = "jit")]
+        if self.replaying {
+            self.bus.clint_mut().tick(cycles);
+            return;
+        }
+        if self.bus.tick(cycles) {
+            self.leave_block = true;
+        }
+    }
+
+    /// mip 由各个中断源的状态拼成
+    fn mip(&self) -> u32 {
+        // S 模式的待处理位由 M 模式软件写入
+        let mut mip = self.csrs[MIP as usize];
+        if self.bus.clint().software_pending() {
+            mip |= MIP_MSIP;
+        }
+        if self.bus.clint().timer_pending() {
+            mip |= MIP_MTIP;
+        }
+        if self.bus.plic().external_pending() {
+            mip |= MIP_MEIP;
+        }
+        mip
+    }
+
+    /// misa: MXL = 32, 以及打开的扩展
+    fn misa(&self) -> u32 {
+        let ext = |c: u8| 1 << (c - b'A');
+        let mut misa = (1 << 30) | ext(b'M') | ext(b'A') | ext(b'C') | ext(b'S') | ext(b'U');
+        misa |= if self.config.rv32e {
+            ext(b'E')
+        } else {
+            ext(b'I')
+        };
+        if self.config.zba && self.config.zbb && self.config.zbs {
+            misa |= ext(b'B');
+        }
+        if self.config.f {
+            misa |= ext(b'F');
+        }
+        misa
+    }
+
+    /// 没有浮点扩展时 FS 只读为 0
+    fn fs_mask(&self) -> u32 {
+        if self.config.f {
+            !0
+        } else {
+            !MSTATUS_FS
+        }
+    }
+
+    fn sd(&self) -> u32 {
+        if self.fs() == FS_DIRTY {
+            MSTATUS_SD
+        } else {
+            0
+        }
+    }
+
+    /// PMP 检查, 失败时是访问异常
+    fn check_pmp(&self, addr: u32, size: u32, access: Access, mode: Privilege) -> Result<()> {
+        if self.pmp.check(addr, size, access, mode) {
+            return Ok(());
+        }
+        Err(access.fault(addr))
+            .with_context(|| format!("pmp: {:?} {:#x} in {:?}-mode", access, addr, mode))
+            .with_context(|| context!())
+    }
+
+    /// 访存使用的特权级: M 模式下打开 mprv 时按 mpp 访存
+    fn data_mode(&self) -> Privilege {
+        let mstatus = self.csrs[MSTATUS as usize];
+        if self.mode == Privilege::Machine && mstatus & MSTATUS_MPRV != 0 {
+            Privilege::from_bits(mstatus >> MSTATUS_MPP_SHIFT)
+        } else {
+            self.mode
+        }
+    }
+
+    /// 是否需要地址转换: satp.MODE = Sv32, 且不在 M 模式下访问
+    fn translating(&self, access: Access) -> bool {
+        let mode = match access {
+            Access::Fetch => self.mode,
+            Access::Load | Access::Store => self.data_mode(),
+        };
+        self.csrs[SATP as usize] >> 31 != 0 && mode != Privilege::Machine
+    }
+
+    /// 虚拟地址转换为物理地址
+    fn translate(&mut self, vaddr: u32, access: Access) -> Result<u32> {
+        if !self.translating(access) {
+            return Ok(vaddr);
+        }
+        let mode = match access {
+            Access::Fetch => self.mode,
+            Access::Load | Access::Store => self.data_mode(),
+        };
+        let paddr = self.mmu.translate(
+            vaddr,
+            access,
+            mode,
+            self.csrs[SATP as usize],
+            self.csrs[MSTATUS as usize],
+            &mut self.bus,
+            &self.pmp,
+        );
+        // 页表项可能和代码在同一页
+        if let Some(pte_addr) = self.mmu.take_written() {
+            self.wrote(pte_addr, pte_addr, 4);
+        }
+        paddr
+    }
+
+    /// 取指: 先取 16 位, 若不是压缩指令再取高 16 位, 两半可能在不同的页
+    fn fetch_inst(&mut self, addr: u32) -> Result<u32> {
+        let lo = self.fetch_half(addr).with_context(|| context!())?;
+        if is_compressed(lo) {
+            return Ok(lo);
+        }
+        let hi = self
+            .fetch_half(addr.wrapping_add(2))
+            .with_context(|| context!())?;
+        Ok(lo | (hi << 16))
+    }
+
+    fn fetch_half(&mut self, vaddr: u32) -> Result<u32> {
+        let paddr = self
+            .translate(vaddr, Access::Fetch)
+            .with_context(|| context!())?;
+        let result = if self.mode == Privilege::User && self.bus.irom().in_kernel(paddr) {
+            Err(Exception::InstructionAccessFault(paddr))
+                .with_context(|| format!("kernel code in {:?}-mode: {:#x}", self.mode, paddr))
+                .with_context(|| context!())
+        } else {
+            self.check_pmp(paddr, 16, Access::Fetch, self.mode)
+                .and_then(|_| self.bus.fetch_half(paddr))
+        };
+        result.map_err(physical_fault(Access::Fetch, vaddr))
+    }
+
     /// Load a value from a dram.
-    fn load(&self, addr: u32, size: u32) -> Result<u32> {
-        self.dram.load(addr, size)
+    fn load(&mut self, addr: u32, size: u32) -> Result<u32> {
+        if self.config.trap_misaligned && addr % (size / 8) != 0 {
+            return Err(Exception::LoadAddressMisaligned(addr)).with_context(|| context!());
+        }
+        // 跨页的非对齐访问按字节拆开, 每个字节单独转换
+        if (addr & 0xfff) + size / 8 > 0x1000 && self.translating(Access::Load) {
+            let mut value = 0;
+            for i in 0..size / 8 {
+                value |= self.load(addr.wrapping_add(i), 8)? << (8 * i);
+            }
+            return Ok(value);
+        }
+        let paddr = self
+            .translate(addr, Access::Load)
+            .with_context(|| context!())?;
+        self.load_physical(paddr, size)
+            .map_err(physical_fault(Access::Load, addr))
+    }
+
+    fn load_physical(&mut self, addr: u32, size: u32) -> Result<u32> {
+        self.check_pmp(addr, size, Access::Load, self.data_mode())
+            .with_context(|| context!())?;
+        if self.bus.is_device(addr) {
+            self.leave_block = true;
+        }
+        self.bus.load(addr, size)
     }
 
     /// Store a value to a dram.
     fn store(&mut self, addr: u32, value: u32, size: u32) -> Result<()> {
-        self.dram.store(addr, value, size)
+        if self.config.trap_misaligned && addr % (size / 8) != 0 {
+            return Err(Exception::StoreAddressMisaligned(addr)).with_context(|| context!());
+        }
+        if (addr & 0xfff) + size / 8 > 0x1000 && self.translating(Access::Store) {
+            for i in 0..size / 8 {
+                self.store(addr.wrapping_add(i), value >> (8 * i), 8)?;
+            }
+            return Ok(());
+        }
+        let paddr = self
+            .translate(addr, Access::Store)
+            .with_context(|| context!())?;
+        self.store_physical(paddr, value, size)
+            .map_err(physical_fault(Access::Store, addr))?;
+        self.wrote(addr, paddr, size / 8);
+        // 写保留的字会使 lr/sc 的保留失效
+        if let Some(reserved) = self.reservation {
+            let last = addr.wrapping_add(size / 8 - 1);
+            if addr & !0x3 == reserved || last & !0x3 == reserved {
+                self.reservation = None;
+            }
+        }
+        Ok(())
+    }
+
+    /// 写指令存储器时丢掉缓存的译码结果和基本块, 改了正在执行的基本块时离开它
+    fn wrote(&mut self, vaddr: u32, paddr: u32, bytes: u32) {
+        if self.bus.irom().contains(paddr) {
+            self.decode_cache.invalidate(vaddr, bytes);
+            if self.blocks.invalidate(paddr, bytes) {
+                self.leave_block = true;
+            }
+        }
+    }
+
+    fn store_physical(&mut self, addr: u32, value: u32, size: u32) -> Result<()> {
+        self.check_pmp(addr, size, Access::Store, self.data_mode())
+            .with_context(|| context!())?;
+        if self.bus.is_device(addr) {
+            self.leave_block = true;
+            return self.bus.store(addr, value, size);
+        }
+        #[cfg(feature = "jit")]
+        if let Some(log) = &mut self.store_log {
+            // 越界的写由下面报告
+            if let Ok(old) = self.bus.load(addr, size) {
+                log.push((addr, size, old, value));
+            }
+        }
+        self.bus.store(addr, value, size)
     }
 
     /// Store a value to a CSR.
     #[allow(dead_code)]
     fn store_csr(&mut self, addr: u32, value: u32) -> Result<()> {
+        // csr[11:10] = 0b11 是只读的
+        if addr >> 10 == 0b11 {
+            return Err(CsrError::ReadOnly(addr))
+                .context(Exception::IllegalInstruction(0))
+                .with_context(|| context!());
+        }
         match addr {
-            MSTATUS | MEPC | MCAUSE | MTVAL => {
+            MSTATUS => {
+                let mask = SSTATUS_MASK | MSTATUS_MIE | MSTATUS_MPIE | MSTATUS_MPP | MSTATUS_MPRV;
+                let mut value = value & mask & self.fs_mask();
+                // WARL: mpp 不能是保留的 0b10
+                if (value & MSTATUS_MPP) >> MSTATUS_MPP_SHIFT == 0b10 {
+                    value &= !MSTATUS_MPP;
+                }
+                self.csrs[addr as usize] = value;
+            }
+            SSTATUS => {
+                let mstatus = self.csrs[MSTATUS as usize];
+                let mask = SSTATUS_MASK & self.fs_mask();
+                self.csrs[MSTATUS as usize] = (mstatus & !mask) | (value & mask);
+            }
+            // fflags 和 frm 是 fcsr 的两个字段
+            FFLAGS | FRM | FCSR => {
+                let (mask, shift) = match addr {
+                    FFLAGS => (0x1f, 0),
+                    FRM => (0x7 << 5, 5),
+                    _ => (0xff, 0),
+                };
+                let fcsr = self.csrs[FCSR as usize];
+                self.csrs[FCSR as usize] = (fcsr & !mask) | ((value << shift) & mask);
+                self.dirty_fs();
+            }
+            // 只支持 direct(0) 和 vectored(1) 模式
+            MTVEC | STVEC => self.csrs[addr as usize] = value & !0b10,
+            MEPC | SEPC => self.csrs[addr as usize] = value & !0b1,
+            MEDELEG => self.csrs[addr as usize] = value & MEDELEG_MASK,
+            MIDELEG => self.csrs[addr as usize] = value & MIDELEG_MASK,
+            MIE => {
+                let mask = MIDELEG_MASK | MIP_MSIP | MIP_MTIP | MIP_MEIP;
+                self.csrs[addr as usize] = value & mask;
+            }
+            SIE => {
+                let mideleg = self.csrs[MIDELEG as usize];
+                let mie = self.csrs[MIE as usize];
+                self.csrs[MIE as usize] = (mie & !mideleg) | (value & mideleg);
+            }
+            // M 模式的待处理位由中断源决定, 只有 S 模式的可以写
+            MIP => self.csrs[addr as usize] = value & MIDELEG_MASK,
+            // S 模式只能写 ssip
+            SIP => {
+                let mask = MIP_SSIP & self.csrs[MIDELEG as usize];
+                let mip = self.csrs[MIP as usize];
+                self.csrs[MIP as usize] = (mip & !mask) | (value & mask);
+            }
+            // 只有 cycle, time, instret 三个计数器
+            MCOUNTEREN | SCOUNTEREN => self.csrs[addr as usize] = value & 0b111,
+            // 基本块构建时检查过取指的权限
+            PMPCFG0..=PMPCFG3 => {
+                self.pmp.store_cfg(addr - PMPCFG0, value);
+                self.blocks.flush();
+            }
+            PMPADDR0..=PMPADDR15 => {
+                self.pmp.store_addr(addr - PMPADDR0, value);
+                self.blocks.flush();
+            }
+            // satp 修改后需要 sfence.vma 才能保证生效
+            MCAUSE | MTVAL | MSCRATCH | SCAUSE | STVAL | SSCRATCH | SATP => {
                 let addr = addr as usize;
                 self.csrs[addr] = value;
             }
+            // WARL: 不支持修改
+            MISA => {}
+            MCYCLE => {
+                self.cycle = (self.cycle & !0xffff_ffff) | value as u64;
+                self.wrote_cycle = true;
+            }
+            MCYCLEH => {
+                self.cycle = (self.cycle & 0xffff_ffff) | ((value as u64) << 32);
+                self.wrote_cycle = true;
+            }
+            MINSTRET => {
+                self.instret = (self.instret & !0xffff_ffff) | value as u64;
+                self.wrote_instret = true;
+            }
+            MINSTRETH => {
+                self.instret = (self.instret & 0xffff_ffff) | ((value as u64) << 32);
+                self.wrote_instret = true;
+            }
             _ => {
-                return Err(anyhow!("not implemented yet: csr {:#x}", addr))
+                return Err(CsrError::Unimplemented(addr))
+                    .context(Exception::IllegalInstruction(0))
                     .with_context(|| context!());
             }
         }
@@ -389,50 +2014,492 @@ impl CPU {
     /// Load a value from a CSR.
     fn load_csr(&self, addr: u32) -> Result<u32> {
         match addr {
-            MSTATUS | MEPC | MCAUSE | MTVAL => {
+            // sd 由 FS 决定
+            MSTATUS => Ok(self.csrs[MSTATUS as usize] | self.sd()),
+            SSTATUS => Ok((self.csrs[MSTATUS as usize] & SSTATUS_MASK) | self.sd()),
+            FFLAGS => Ok(self.csrs[FCSR as usize] & 0x1f),
+            FRM => Ok((self.csrs[FCSR as usize] >> 5) & 0x7),
+            FCSR => Ok(self.csrs[FCSR as usize] & 0xff),
+            MEPC | MCAUSE | MTVAL | MTVEC | MSCRATCH | MIE | MEDELEG | MIDELEG | MCOUNTEREN
+            | SEPC | SCAUSE | STVAL | STVEC | SSCRATCH | SCOUNTEREN | SATP => {
                 let addr = addr as usize;
                 Ok(self.csrs[addr])
             }
-            _ => Err(anyhow!("not implemented yet: csr {:#x}", addr)).with_context(|| context!()),
+            PMPCFG0..=PMPCFG3 => Ok(self.pmp.load_cfg(addr - PMPCFG0)),
+            PMPADDR0..=PMPADDR15 => Ok(self.pmp.load_addr(addr - PMPADDR0)),
+            SIE => Ok(self.csrs[MIE as usize] & self.csrs[MIDELEG as usize]),
+            MISA => Ok(self.misa()),
+            MIP => Ok(self.mip()),
+            SIP => Ok(self.mip() & self.csrs[MIDELEG as usize]),
+            MVENDORID | MARCHID | MIMPID | MHARTID => Ok(0),
+            MCYCLE | CYCLE => Ok(self.cycle as u32),
+            MCYCLEH | CYCLEH => Ok((self.cycle >> 32) as u32),
+            TIME => Ok(self.bus.clint().mtime() as u32),
+            TIMEH => Ok((self.bus.clint().mtime() >> 32) as u32),
+            MINSTRET | INSTRET => Ok(self.instret as u32),
+            MINSTRETH | INSTRETH => Ok((self.instret >> 32) as u32),
+            _ => Err(CsrError::Unimplemented(addr))
+                .context(Exception::IllegalInstruction(0))
+                .with_context(|| context!()),
         }
     }
 
-    /// Print values in all registers (x0-x31).
-    #[allow(clippy::format_in_format_args)]
+    /// 寄存器的 ABI 名字, RV32E 只有 x0-x15
+    pub fn abi(&self) -> &'static [&'static str] {
+        if self.config.rv32e {
+            &ABI_E
+        } else {
+            &ABI
+        }
+    }
+
+    /// Print values in all registers (x0-x31, or x0-x15 for RV32E).
     fn dump_registers(&self) {
+        println!("{}", self.format_registers());
+        println!("pc = {:#x}", self.pc);
+    }
+
+    /// 每行 4 个寄存器
+    #[allow(clippy::format_in_format_args)]
+    fn format_registers(&self) -> String {
+        let abi = self.abi();
         let mut output = String::from("");
-        for i in (0..32).step_by(4) {
+        for i in (0..abi.len()).step_by(4) {
             output = format!(
                 "{}\n{}",
                 output,
                 format!(
                     " x{:02}({})={:#x} x{:02}({})={:#x} x{:02}({})={:#x} x{:02}({})={:#x} ",
                     i,
-                    ABI[i],
+                    abi[i],
                     self.regs[i],
                     i + 1,
-                    ABI[i + 1],
+                    abi[i + 1],
                     self.regs[i + 1],
                     i + 2,
-                    ABI[i + 2],
+                    abi[i + 2],
                     self.regs[i + 2],
                     i + 3,
-                    ABI[i + 3],
+                    abi[i + 3],
                     self.regs[i + 3],
                 )
             );
         }
-        println!("{}", output);
-        println!("pc = {:#x}", self.pc);
+        output
     }
 
     /// Print values in some csrs.
     fn dump_csrs(&self) {
         println!(
-            "mstatus={:#x}\tmepc={:#x}\tmcause={:#x}",
+            "mstatus={:#x}\tmepc={:#x}\tmcause={:#x}\tmtval={:#x}\tmtvec={:#x}",
             self.load_csr(MSTATUS).unwrap(),
             self.load_csr(MEPC).unwrap(),
             self.load_csr(MCAUSE).unwrap(),
+            self.load_csr(MTVAL).unwrap(),
+            self.load_csr(MTVEC).unwrap(),
         );
     }
 }
+
+/// jit_check 比较的状态
+#[cfg(feature = "jit")]
+#[derive(PartialEq)]
+struct ArchState {
+    regs: [u32; 32],
+    fregs: [u32; 32],
+    pc: u32,
+    csrs: Vec<u32>,
+    reservation: Option<u32>,
+    cycle: u64,
+    instret: u64,
+    mtime: u64,
+}
+
+#[cfg(feature = "jit")]
+impl ArchState {
+    /// 与 other 不同的部分
+    fn diff(&self, other: &Self) -> Vec<String> {
+        let mut diff = Vec::new();
+        let words = |name: &str, lhs: &[u32], rhs: &[u32], diff: &mut Vec<String>| {
+            for (i, (lhs, _)) in lhs.iter().zip(rhs).enumerate().filter(|(_, (l, r))| l != r) {
+                diff.push(format!("{}{}={:#x}", name, i, lhs));
+            }
+        };
+        words("x", &self.regs, &other.regs, &mut diff);
+        words("f", &self.fregs, &other.fregs, &mut diff);
+        words("csr", &self.csrs, &other.csrs, &mut diff);
+        if self.pc != other.pc {
+            diff.push(format!("pc={:#x}", self.pc));
+        }
+        if self.reservation != other.reservation {
+            diff.push(format!("reservation={:x?}", self.reservation));
+        }
+        if (self.cycle, self.instret, self.mtime) != (other.cycle, other.instret, other.mtime) {
+            diff.push(format!(
+                "cycle={} instret={} mtime={}",
+                self.cycle, self.instret, self.mtime
+            ));
+        }
+        diff
+    }
+}
+
+/// 编译的代码调用的回调: 补上前面的指令的 retire, 再用解释器执行第 index 个操作
+#[cfg(feature = "jit")]
+unsafe extern "C" fn jit_op(ctx: *mut JitCtx, index: u32) -> u64 {
+    let ctx = &mut *ctx;
+    // native 在调用返回之前不碰 self, 这是回调期间唯一的 &mut CPU
+    let cpu = &mut *ctx.cpu;
+    let op = &*ctx.ops.add(index as usize);
+    cpu.retire_many(index - ctx.retired);
+    ctx.retired = index;
+    cpu.regs[0] = 0;
+    match (op.exec)(cpu, op) {
+        Ok(true) => {
+            ctx.retired = index + 1;
+            0
+        }
+        Ok(false) => exit(EXIT_LEAVE, index),
+        Err(e) => {
+            ctx.error = Some(e);
+            exit(EXIT_FAULT, index)
+        }
+    }
+}
+
+/// 物理地址上的访问异常, mtval 换成虚拟地址
+fn physical_fault(access: Access, vaddr: u32) -> impl FnOnce(anyhow::Error) -> anyhow::Error {
+    move |e| match e.downcast_ref::<Exception>() {
+        Some(&exception) if exception == access.fault(exception.tval()) => {
+            e.context(access.fault(vaddr))
+        }
+        _ => e,
+    }
+}
+
+/// 把指令翻译成基本块里的操作, 会改变特权级, csr 或者地址映射的指令单步执行
+fn thread(inst: &Instr, pc: u32, raw: u32) -> Option<Op> {
+    let op = |exec: Exec, rd: Reg, rs1: Reg, rs2: Reg, imm: u32| Op {
+        exec,
+        pc,
+        raw,
+        len: inst_len(raw),
+        rd: rd as u8,
+        rs1: rs1 as u8,
+        rs2: rs2 as u8,
+        imm,
+    };
+    Some(match *inst {
+        Instr::LUI(rd, imm) => op(op_li, rd, 0, 0, imm),
+        Instr::AUIPC(rd, imm) => op(op_li, rd, 0, 0, pc.wrapping_add(imm)),
+        Instr::JAL(rd, offset) => op(op_jal, rd, 0, 0, offset as u32),
+        Instr::JALR(rd, offset, base) => op(op_jalr, rd, base, 0, offset as u32),
+        Instr::BEQ(rs1, rs2, offset) => op(op_beq, 0, rs1, rs2, offset as u32),
+        Instr::BNE(rs1, rs2, offset) => op(op_bne, 0, rs1, rs2, offset as u32),
+        Instr::BLT(rs1, rs2, offset) => op(op_blt, 0, rs1, rs2, offset as u32),
+        Instr::BGE(rs1, rs2, offset) => op(op_bge, 0, rs1, rs2, offset as u32),
+        Instr::BLTU(rs1, rs2, offset) => op(op_bltu, 0, rs1, rs2, offset as u32),
+        Instr::BGEU(rs1, rs2, offset) => op(op_bgeu, 0, rs1, rs2, offset as u32),
+        Instr::LB(rd, offset, base) => op(op_lb, rd, base, 0, offset as u32),
+        Instr::LH(rd, offset, base) => op(op_lh, rd, base, 0, offset as u32),
+        Instr::LW(rd, offset, base) => op(op_lw, rd, base, 0, offset as u32),
+        Instr::LBU(rd, offset, base) => op(op_lbu, rd, base, 0, offset as u32),
+        Instr::LHU(rd, offset, base) => op(op_lhu, rd, base, 0, offset as u32),
+        Instr::SB(rs2, offset, rs1) => op(op_sb, 0, rs1, rs2, offset as u32),
+        Instr::SH(rs2, offset, rs1) => op(op_sh, 0, rs1, rs2, offset as u32),
+        Instr::SW(rs2, offset, rs1) => op(op_sw, 0, rs1, rs2, offset as u32),
+        Instr::ADDI(rd, rs1, imm) => op(op_addi, rd, rs1, 0, imm as u32),
+        Instr::ANDI(rd, rs1, imm) => op(op_andi, rd, rs1, 0, imm as u32),
+        Instr::ORI(rd, rs1, imm) => op(op_ori, rd, rs1, 0, imm as u32),
+        Instr::XORI(rd, rs1, imm) => op(op_xori, rd, rs1, 0, imm as u32),
+        Instr::SLLI(rd, rs1, imm) => op(op_slli, rd, rs1, 0, imm as u32),
+        Instr::SRLI(rd, rs1, imm) => op(op_srli, rd, rs1, 0, imm as u32),
+        Instr::SRAI(rd, rs1, imm) => op(op_srai, rd, rs1, 0, imm as u32),
+        Instr::SLTI(rd, rs1, imm) => op(op_slti, rd, rs1, 0, imm as u32),
+        Instr::SLTIU(rd, rs1, imm) => op(op_sltiu, rd, rs1, 0, imm as u32),
+        Instr::ADD(rd, rs1, rs2) => op(op_add, rd, rs1, rs2, 0),
+        Instr::SUB(rd, rs1, rs2) => op(op_sub, rd, rs1, rs2, 0),
+        Instr::SLL(rd, rs1, rs2) => op(op_sll, rd, rs1, rs2, 0),
+        Instr::SLT(rd, rs1, rs2) => op(op_slt, rd, rs1, rs2, 0),
+        Instr::SLTU(rd, rs1, rs2) => op(op_sltu, rd, rs1, rs2, 0),
+        Instr::XOR(rd, rs1, rs2) => op(op_xor, rd, rs1, rs2, 0),
+        Instr::SRL(rd, rs1, rs2) => op(op_srl, rd, rs1, rs2, 0),
+        Instr::SRA(rd, rs1, rs2) => op(op_sra, rd, rs1, rs2, 0),
+        Instr::OR(rd, rs1, rs2) => op(op_or, rd, rs1, rs2, 0),
+        Instr::AND(rd, rs1, rs2) => op(op_and, rd, rs1, rs2, 0),
+        Instr::MUL(rd, rs1, rs2) => op(op_mul, rd, rs1, rs2, 0),
+        Instr::MULH(rd, rs1, rs2) => op(op_mulh, rd, rs1, rs2, 0),
+        Instr::MULHSU(rd, rs1, rs2) => op(op_mulhsu, rd, rs1, rs2, 0),
+        Instr::MULHU(rd, rs1, rs2) => op(op_mulhu, rd, rs1, rs2, 0),
+        Instr::DIV(rd, rs1, rs2) => op(op_div, rd, rs1, rs2, 0),
+        Instr::DIVU(rd, rs1, rs2) => op(op_divu, rd, rs1, rs2, 0),
+        Instr::REM(rd, rs1, rs2) => op(op_rem, rd, rs1, rs2, 0),
+        Instr::REMU(rd, rs1, rs2) => op(op_remu, rd, rs1, rs2, 0),
+        Instr::FENCE(_, _) => op(op_nop, 0, 0, 0, 0),
+        Instr::FENCEI
+        | Instr::SFENCEVMA(_, _)
+        | Instr::EBREAK
+        | Instr::WFI
+        | Instr::ECALL
+        | Instr::URET
+        | Instr::SRET
+        | Instr::MRET
+        | Instr::CSRRW(..)
+        | Instr::CSRRS(..)
+        | Instr::CSRRC(..)
+        | Instr::CSRRWI(..)
+        | Instr::CSRRSI(..)
+        | Instr::CSRRCI(..) => return None,
+        // 其余的指令 (位操作, 原子, 浮点) 交给 execute_inst
+        _ => op(op_inst, 0, 0, 0, 0),
+    })
+}
+
+// 基本块里的操作, 与 execute_inst 中对应的分支相同
+
+macro_rules! reg_op {
+    ($name:ident, |$lhs:ident, $rhs:ident| $val:expr) => {
+        fn $name(cpu: &mut CPU, op: &Op) -> Result<bool> {
+            let $lhs = cpu.regs[op.rs1 as usize];
+            let $rhs = cpu.regs[op.rs2 as usize];
+            cpu.regs[op.rd as usize] = $val;
+            cpu.retire();
+            Ok(true)
+        }
+    };
+}
+
+macro_rules! imm_op {
+    ($name:ident, |$lhs:ident, $imm:ident| $val:expr) => {
+        fn $name(cpu: &mut CPU, op: &Op) -> Result<bool> {
+            let $lhs = cpu.regs[op.rs1 as usize];
+            let $imm = op.imm;
+            cpu.regs[op.rd as usize] = $val;
+            cpu.retire();
+            Ok(true)
+        }
+    };
+}
+
+macro_rules! branch_op {
+    ($name:ident, |$lhs:ident, $rhs:ident| $cond:expr) => {
+        fn $name(cpu: &mut CPU, op: &Op) -> Result<bool> {
+            let $lhs = cpu.regs[op.rs1 as usize];
+            let $rhs = cpu.regs[op.rs2 as usize];
+            cpu.pc = if $cond {
+                op.pc.wrapping_add(op.imm)
+            } else {
+                op.next_pc()
+            };
+            cpu.retire();
+            Ok(true)
+        }
+    };
+}
+
+/// 访问了 DRAM 以外的设备之后离开基本块
+macro_rules! load_op {
+    ($name:ident, $size:expr, |$val:ident| $ext:expr) => {
+        fn $name(cpu: &mut CPU, op: &Op) -> Result<bool> {
+            let addr = cpu.regs[op.rs1 as usize].wrapping_add(op.imm);
+            let $val = cpu.load(addr, $size).with_context(|| context!())?;
+            cpu.regs[op.rd as usize] = $ext;
+            cpu.retire();
+            Ok(!std::mem::take(&mut cpu.leave_block))
+        }
+    };
+}
+
+macro_rules! store_op {
+    ($name:ident, $size:expr) => {
+        fn $name(cpu: &mut CPU, op: &Op) -> Result<bool> {
+            let addr = cpu.regs[op.rs1 as usize].wrapping_add(op.imm);
+            cpu.store(addr, cpu.regs[op.rs2 as usize], $size)
+                .with_context(|| context!())?;
+            cpu.retire();
+            Ok(!std::mem::take(&mut cpu.leave_block))
+        }
+    };
+}
+
+fn op_nop(cpu: &mut CPU, _: &Op) -> Result<bool> {
+    cpu.retire();
+    Ok(true)
+}
+
+/// lui, 以及算好结果的 auipc
+fn op_li(cpu: &mut CPU, op: &Op) -> Result<bool> {
+    cpu.regs[op.rd as usize] = op.imm;
+    cpu.retire();
+    Ok(true)
+}
+
+fn op_jal(cpu: &mut CPU, op: &Op) -> Result<bool> {
+    cpu.regs[op.rd as usize] = op.next_pc();
+    cpu.pc = op.pc.wrapping_add(op.imm);
+    cpu.retire();
+    Ok(true)
+}
+
+fn op_jalr(cpu: &mut CPU, op: &Op) -> Result<bool> {
+    cpu.pc = cpu.regs[op.rs1 as usize].wrapping_add(op.imm) & !1;
+    cpu.regs[op.rd as usize] = op.next_pc();
+    cpu.retire();
+    Ok(true)
+}
+
+/// 没有专门处理函数的指令
+fn op_inst(cpu: &mut CPU, op: &Op) -> Result<bool> {
+    cpu.pc = op.next_pc();
+    cpu.execute_inst(op.raw)?;
+    Ok(!std::mem::take(&mut cpu.leave_block))
+}
+
+branch_op!(op_beq, |lhs, rhs| lhs == rhs);
+branch_op!(op_bne, |lhs, rhs| lhs != rhs);
+branch_op!(op_blt, |lhs, rhs| (lhs as i32) < (rhs as i32));
+branch_op!(op_bge, |lhs, rhs| (lhs as i32) >= (rhs as i32));
+branch_op!(op_bltu, |lhs, rhs| lhs < rhs);
+branch_op!(op_bgeu, |lhs, rhs| lhs >= rhs);
+
+load_op!(op_lb, 8, |val| val as i8 as i32 as u32);
+load_op!(op_lh, 16, |val| val as i16 as i32 as u32);
+load_op!(op_lw, 32, |val| val);
+load_op!(op_lbu, 8, |val| val as u8 as u32);
+load_op!(op_lhu, 16, |val| val as u16 as u32);
+
+store_op!(op_sb, 8);
+store_op!(op_sh, 16);
+store_op!(op_sw, 32);
+
+imm_op!(op_addi, |lhs, imm| lhs.wrapping_add(imm));
+imm_op!(op_andi, |lhs, imm| lhs & imm);
+imm_op!(op_ori, |lhs, imm| lhs | imm);
+imm_op!(op_xori, |lhs, imm| lhs ^ imm);
+imm_op!(op_slli, |lhs, imm| lhs.wrapping_shl(imm & 0x1f));
+imm_op!(op_srli, |lhs, imm| lhs.wrapping_shr(imm & 0x1f));
+imm_op!(op_srai, |lhs, imm| (lhs as i32).wrapping_shr(imm & 0x1f)
+    as u32);
+imm_op!(op_slti, |lhs, imm| ((lhs as i32) < (imm as i32)) as u32);
+imm_op!(op_sltiu, |lhs, imm| (lhs < imm) as u32);
+
+reg_op!(op_add, |lhs, rhs| lhs.wrapping_add(rhs));
+reg_op!(op_sub, |lhs, rhs| lhs.wrapping_sub(rhs));
+reg_op!(op_sll, |lhs, rhs| lhs.wrapping_shl(rhs & 0x3f));
+reg_op!(op_slt, |lhs, rhs| ((lhs as i32) < (rhs as i32)) as u32);
+reg_op!(op_sltu, |lhs, rhs| (lhs < rhs) as u32);
+reg_op!(op_xor, |lhs, rhs| lhs ^ rhs);
+reg_op!(op_srl, |lhs, rhs| lhs.wrapping_shr(rhs & 0x3f));
+reg_op!(op_sra, |lhs, rhs| (lhs as i32).wrapping_shr(rhs & 0x3f)
+    as u32);
+reg_op!(op_or, |lhs, rhs| lhs | rhs);
+reg_op!(op_and, |lhs, rhs| lhs & rhs);
+reg_op!(op_mul, |lhs, rhs| lhs.wrapping_mul(rhs));
+reg_op!(op_mulh, |lhs, rhs| {
+    ((lhs as i32 as i64).wrapping_mul(rhs as i32 as i64) >> 32) as u32
+});
+reg_op!(op_mulhsu, |lhs, rhs| {
+    ((lhs as i32 as i64).wrapping_mul(rhs as u64 as i64) >> 32) as u32
+});
+reg_op!(op_mulhu, |lhs, rhs| {
+    ((lhs as u64).wrapping_mul(rhs as u64) >> 32) as u32
+});
+reg_op!(op_div, |lhs, rhs| if rhs == 0 {
+    u32::MAX
+} else {
+    (lhs as i32).wrapping_div(rhs as i32) as u32
+});
+reg_op!(op_divu, |lhs, rhs| lhs.checked_div(rhs).unwrap_or(u32::MAX));
+reg_op!(op_rem, |lhs, rhs| if rhs == 0 {
+    lhs
+} else {
+    (lhs as i32).wrapping_rem(rhs as i32) as u32
+});
+reg_op!(op_remu, |lhs, rhs| lhs.checked_rem(rhs).unwrap_or(lhs));
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{cpu, cpu_with, run_to_error, step};
+    use super::*;
+
+    fn rv32e() -> Config {
+        Config {
+            rv32e: true,
+            trap_illegal: true,
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn rv32e_rejects_x16_to_x31() {
+        for inst in [
+            "li a6, 1",
+            "add a0, a1, a6",
+            "add a0, t6, a1",
+            "sw s2, 0(sp)",
+            "lw a0, 0(s11)",
+            // c.mv a0, a7
+            ".half 0x8546",
+        ] {
+            let mut cpu = cpu_with(inst, "csrr a0, mcause\ncsrr a1, mtval\nmret", rv32e());
+            run_to_error(&mut cpu, 10);
+            let raw = assemble(inst, 0).unwrap().text;
+            let mut bytes = [0; 4];
+            bytes[..raw.len()].copy_from_slice(&raw);
+            assert_eq!(cpu.reg(10), 2, "{}", inst);
+            assert_eq!(cpu.reg(11), u32::from_le_bytes(bytes), "{}", inst);
+        }
+        let mut cpu = cpu_with(
+            "li a5, 1\nebreak",
+            "mret",
+            Config {
+                trap_illegal: false,
+                ..rv32e()
+            },
+        );
+        run_to_error(&mut cpu, 10);
+        assert_eq!(cpu.reg(15), 1);
+    }
+
+    #[test]
+    fn rv32e_dumps_16_registers_with_ilp32e_names() {
+        let e = cpu_with("ebreak", "ebreak", rv32e());
+        assert_eq!(e.abi(), &ABI[..16]);
+        let dump = e.format_registers();
+        assert_eq!(dump.matches('=').count(), 16);
+        assert!(dump.contains("x15( a5 )"));
+        assert!(!dump.contains("x16"));
+
+        let dump = cpu("ebreak").format_registers();
+        assert_eq!(dump.matches('=').count(), 32);
+        assert!(dump.contains("x31( t6 )"));
+    }
+
+    #[test]
+    fn store_to_irom_and_fence_i_drop_cached_decode() {
+        let source = "
+            addi a0, a0, 1
+            li t0, 0
+            sw zero, 0(t0)
+            fence.i
+            ebreak
+        ";
+        let text = assemble(source, 0).unwrap().text;
+        let raw = |pc: usize| u32::from_le_bytes(text[pc..pc + 4].try_into().unwrap());
+        let mut cpu = cpu(source);
+        for _ in 0..2 {
+            step(&mut cpu).unwrap();
+        }
+        assert!(cpu.decode_cache.get(0, raw(0)).is_some());
+        assert!(cpu.decode_cache.get(4, raw(4)).is_some());
+
+        // 只丢掉被覆盖的指令
+        step(&mut cpu).unwrap();
+        assert!(cpu.decode_cache.get(0, raw(0)).is_none());
+        assert!(cpu.decode_cache.get(4, raw(4)).is_some());
+
+        step(&mut cpu).unwrap();
+        assert!(cpu.decode_cache.get(4, raw(4)).is_none());
+        assert!(cpu.decode_cache.get(8, raw(8)).is_none());
+    }
+}
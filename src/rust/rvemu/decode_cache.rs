@@ -0,0 +1,74 @@
+use super::*;
+
+/// 已经译码的指令
+#[derive(Clone)]
+struct Decoded {
+    pc: u32,
+    raw: u32,
+    /// 压缩指令展开后的形式
+    inst: Instr,
+}
+
+/// 按 pc 直接映射的译码缓存.
+/// 命中时还要比较指令本身, 所以过期的表项只会多一次译码, 不会执行错误的指令.
+pub struct DecodeCache {
+    entries: Vec<Option<Decoded>>,
+    /// entries.len() - 1, 表项个数是 2 的幂
+    mask: usize,
+}
+
+impl DecodeCache {
+    /// entries = 0 时不缓存, 每次都重新译码
+    pub fn new(entries: usize) -> Self {
+        let entries = if entries == 0 {
+            0
+        } else {
+            entries.next_power_of_two()
+        };
+        Self {
+            entries: vec![None; entries],
+            mask: entries.saturating_sub(1),
+        }
+    }
+
+    fn index(&self, pc: u32) -> usize {
+        (pc >> 1) as usize & self.mask
+    }
+
+    pub fn get(&self, pc: u32, raw: u32) -> Option<&Instr> {
+        match self.entries.get(self.index(pc)) {
+            Some(Some(entry)) if entry.pc == pc && entry.raw == raw => Some(&entry.inst),
+            _ => None,
+        }
+    }
+
+    pub fn insert(&mut self, pc: u32, raw: u32, inst: Instr) {
+        if self.entries.is_empty() {
+            return;
+        }
+        let index = self.index(pc);
+        self.entries[index] = Some(Decoded { pc, raw, inst });
+    }
+
+    /// 写 [addr, addr + bytes) 后, 丢掉覆盖这些字节的指令
+    pub fn invalidate(&mut self, addr: u32, bytes: u32) {
+        if self.entries.is_empty() {
+            return;
+        }
+        // 32 位指令可能从前一个半字开始
+        let start = (addr & !1).wrapping_sub(2);
+        let halves = (addr.wrapping_add(bytes).wrapping_sub(start) + 1) / 2;
+        for i in 0..halves {
+            let pc = start.wrapping_add(2 * i);
+            let index = self.index(pc);
+            if matches!(&self.entries[index], Some(entry) if entry.pc == pc) {
+                self.entries[index] = None;
+            }
+        }
+    }
+
+    /// fence.i 以及地址映射改变时清空
+    pub fn flush(&mut self) {
+        self.entries.iter_mut().for_each(|entry| *entry = None);
+    }
+}
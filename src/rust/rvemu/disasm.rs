@@ -0,0 +1,279 @@
+use super::*;
+use std::collections::BTreeMap;
+
+/// 反汇编用的符号表: 地址 -> 名字, 以及镜像占的地址范围
+#[derive(Default)]
+pub struct SymbolTable {
+    names: BTreeMap<u32, String>,
+    /// [start, end), 跳转目标和符号不在同一段里时不附上符号
+    regions: Vec<(u32, u64)>,
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, addr: u32, name: String) {
+        self.names.insert(addr, name);
+    }
+
+    pub fn get(&self, addr: &u32) -> Option<&String> {
+        self.names.get(addr)
+    }
+
+    pub fn contains_key(&self, addr: &u32) -> bool {
+        self.names.contains_key(addr)
+    }
+
+    /// 镜像里的一段, 长度为 len 字节
+    pub fn add_region(&mut self, start: u32, len: u32) {
+        self.regions.push((start, start as u64 + len as u64));
+    }
+
+    /// addr 所属的符号和偏移. 符号的范围到下一个符号为止;
+    /// 最后一个符号只延伸到它所在的段的末尾, 没有段时只匹配它自己的地址.
+    pub fn lookup(&self, addr: u32) -> Option<(&str, u32)> {
+        let (&base, name) = self.names.range(..=addr).next_back()?;
+        let bounded = self.names.range(addr.checked_add(1)?..).next().is_some();
+        let region = |addr: u32| {
+            self.regions
+                .iter()
+                .position(|&(start, end)| start <= addr && (addr as u64) < end)
+        };
+        let inside = base == addr
+            || match region(base) {
+                Some(index) => region(addr) == Some(index),
+                None => bounded && self.regions.is_empty(),
+            };
+        inside.then(|| (name.as_str(), addr - base))
+    }
+}
+
+fn x(reg: &Reg) -> &'static str {
+    ABI[*reg as usize].trim()
+}
+
+fn f(reg: &Reg) -> &'static str {
+    FABI[*reg as usize].trim()
+}
+
+/// 没有名字的 csr 直接打印地址
+fn csr_name(csr: &CSR) -> String {
+    let name = csr_abi(csr);
+    if name.starts_with("csr_") {
+        format!("{:#x}", csr)
+    } else {
+        name
+    }
+}
+
+/// 跳转目标的绝对地址, 有符号表时附上 <symbol+off>
+fn target(pc: u32, offset: i32, symbols: Option<&SymbolTable>) -> String {
+    let addr = pc.wrapping_add(offset as u32);
+    match symbols.and_then(|symbols| symbols.lookup(addr)) {
+        Some((name, 0)) => format!("{:#x} <{}>", addr, name),
+        Some((name, offset)) => format!("{:#x} <{}+{:#x}>", addr, name, offset),
+        None => format!("{:#x}", addr),
+    }
+}
+
+impl Instr {
+    /// Display 的第一个词
+    fn mnemonic(&self) -> String {
+        let text = self.to_string();
+        text.split_whitespace()
+            .next()
+            .unwrap_or_default()
+            .to_string()
+    }
+
+    /// objdump 风格的反汇编: 使用伪指令, 跳转目标是绝对地址, 立即数是有符号十进制.
+    /// pc 是这条指令的地址, 压缩指令按展开后的形式打印.
+    pub fn disasm(&self, pc: u32, symbols: Option<&SymbolTable>) -> String {
+        let m = self.mnemonic();
+        match self {
+            Instr::C(_, expanded) => expanded.disasm(pc, symbols),
+            // 伪指令
+            Instr::ADDI(0, 0, 0) => "nop".to_string(),
+            Instr::ADDI(rd, 0, imm) => format!("li {}, {}", x(rd), imm),
+            Instr::ADDI(rd, rs1, 0) => format!("mv {}, {}", x(rd), x(rs1)),
+            Instr::XORI(rd, rs1, -1) => format!("not {}, {}", x(rd), x(rs1)),
+            Instr::SUB(rd, 0, rs2) => format!("neg {}, {}", x(rd), x(rs2)),
+            Instr::JAL(0, offset) => format!("j {}", target(pc, *offset, symbols)),
+            Instr::JAL(1, offset) => format!("jal {}", target(pc, *offset, symbols)),
+            Instr::JALR(0, 0, 1) => "ret".to_string(),
+            Instr::JALR(0, 0, rs1) => format!("jr {}", x(rs1)),
+            Instr::JALR(1, 0, rs1) => format!("jalr {}", x(rs1)),
+            Instr::BEQ(rs1, 0, offset) => {
+                format!("beqz {}, {}", x(rs1), target(pc, *offset, symbols))
+            }
+            Instr::BNE(rs1, 0, offset) => {
+                format!("bnez {}, {}", x(rs1), target(pc, *offset, symbols))
+            }
+            Instr::BLT(rs1, 0, offset) => {
+                format!("bltz {}, {}", x(rs1), target(pc, *offset, symbols))
+            }
+            Instr::BGE(rs1, 0, offset) => {
+                format!("bgez {}, {}", x(rs1), target(pc, *offset, symbols))
+            }
+            Instr::BLT(0, rs2, offset) => {
+                format!("bgtz {}, {}", x(rs2), target(pc, *offset, symbols))
+            }
+            Instr::BGE(0, rs2, offset) => {
+                format!("blez {}, {}", x(rs2), target(pc, *offset, symbols))
+            }
+            Instr::CSRRS(rd, csr, 0) => format!("csrr {}, {}", x(rd), csr_name(csr)),
+            Instr::CSRRW(0, csr, rs1) => format!("csrw {}, {}", csr_name(csr), x(rs1)),
+            Instr::CSRRS(0, csr, rs1) => format!("csrs {}, {}", csr_name(csr), x(rs1)),
+            Instr::CSRRC(0, csr, rs1) => format!("csrc {}, {}", csr_name(csr), x(rs1)),
+            Instr::CSRRWI(0, csr, zimm) => format!("csrwi {}, {}", csr_name(csr), zimm),
+            Instr::CSRRSI(0, csr, zimm) => format!("csrsi {}, {}", csr_name(csr), zimm),
+            Instr::CSRRCI(0, csr, zimm) => format!("csrci {}, {}", csr_name(csr), zimm),
+            Instr::FSGNJS(rd, rs1, rs2) if rs1 == rs2 => format!("fmv.s {}, {}", f(rd), f(rs1)),
+            Instr::FSGNJNS(rd, rs1, rs2) if rs1 == rs2 => format!("fneg.s {}, {}", f(rd), f(rs1)),
+            Instr::FSGNJXS(rd, rs1, rs2) if rs1 == rs2 => format!("fabs.s {}, {}", f(rd), f(rs1)),
+            Instr::FENCE(0xf, 0xf) => "fence".to_string(),
+            Instr::SFENCEVMA(0, 0) => "sfence.vma".to_string(),
+            // 跳转
+            Instr::JAL(rd, offset) => format!("jal {}, {}", x(rd), target(pc, *offset, symbols)),
+            Instr::BEQ(rs1, rs2, offset)
+            | Instr::BNE(rs1, rs2, offset)
+            | Instr::BLT(rs1, rs2, offset)
+            | Instr::BGE(rs1, rs2, offset)
+            | Instr::BLTU(rs1, rs2, offset)
+            | Instr::BGEU(rs1, rs2, offset) => format!(
+                "{} {}, {}, {}",
+                m,
+                x(rs1),
+                x(rs2),
+                target(pc, *offset, symbols)
+            ),
+            // 立即数
+            Instr::LUI(rd, umm) | Instr::AUIPC(rd, umm) => {
+                format!("{} {}, {:#x}", m, x(rd), umm >> 12)
+            }
+            Instr::ADDI(rd, rs1, imm)
+            | Instr::ANDI(rd, rs1, imm)
+            | Instr::ORI(rd, rs1, imm)
+            | Instr::XORI(rd, rs1, imm)
+            | Instr::SLLI(rd, rs1, imm)
+            | Instr::SRLI(rd, rs1, imm)
+            | Instr::SRAI(rd, rs1, imm)
+            | Instr::SLTI(rd, rs1, imm)
+            | Instr::SLTIU(rd, rs1, imm)
+            | Instr::RORI(rd, rs1, imm)
+            | Instr::BCLRI(rd, rs1, imm)
+            | Instr::BEXTI(rd, rs1, imm)
+            | Instr::BINVI(rd, rs1, imm)
+            | Instr::BSETI(rd, rs1, imm) => format!("{} {}, {}, {}", m, x(rd), x(rs1), imm),
+            Instr::LB(rd, offset, base)
+            | Instr::LH(rd, offset, base)
+            | Instr::LW(rd, offset, base)
+            | Instr::LBU(rd, offset, base)
+            | Instr::LHU(rd, offset, base)
+            | Instr::SB(rd, offset, base)
+            | Instr::SH(rd, offset, base)
+            | Instr::SW(rd, offset, base)
+            | Instr::JALR(rd, offset, base) => format!("{} {}, {}({})", m, x(rd), offset, x(base)),
+            Instr::FLW(rd, offset, base) | Instr::FSW(rd, offset, base) => {
+                format!("{} {}, {}({})", m, f(rd), offset, x(base))
+            }
+            Instr::CSRRW(rd, csr, rs1)
+            | Instr::CSRRS(rd, csr, rs1)
+            | Instr::CSRRC(rd, csr, rs1) => {
+                format!("{} {}, {}, {}", m, x(rd), csr_name(csr), x(rs1))
+            }
+            Instr::CSRRWI(rd, csr, zimm)
+            | Instr::CSRRSI(rd, csr, zimm)
+            | Instr::CSRRCI(rd, csr, zimm) => {
+                format!("{} {}, {}, {}", m, x(rd), csr_name(csr), zimm)
+            }
+            // 其余的指令没有立即数, 只去掉 Display 里寄存器名的对齐空格
+            _ => {
+                let text = self.to_string();
+                match text.split_once(' ') {
+                    Some((_, operands)) => {
+                        let operands: Vec<String> =
+                            operands.split(',').map(|op| op.replace(' ', "")).collect();
+                        format!("{} {}", m, operands.join(", "))
+                    }
+                    None => text,
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 汇编一条指令再反汇编回来. 跳转的操作数是相对 pc 的偏移
+    fn disasm_at(source: &str, pc: u32, symbols: Option<&SymbolTable>) -> String {
+        let program = assemble(source, pc).unwrap();
+        let word = u32::from_le_bytes(program.text[..4].try_into().unwrap());
+        Instr::try_from(word).unwrap().disasm(pc, symbols)
+    }
+
+    fn disasm(source: &str) -> String {
+        disasm_at(source, 0x1000, None)
+    }
+
+    #[test]
+    fn aliases() {
+        assert_eq!(disasm("nop"), "nop");
+        assert_eq!(disasm("li a0, -5"), "li a0, -5");
+        assert_eq!(disasm("mv a0, a1"), "mv a0, a1");
+        assert_eq!(disasm("not a0, a1"), "not a0, a1");
+        assert_eq!(disasm("neg a0, a1"), "neg a0, a1");
+        assert_eq!(disasm("j 0x10"), "j 0x1010");
+        assert_eq!(disasm("jr a5"), "jr a5");
+        assert_eq!(disasm("ret"), "ret");
+        assert_eq!(disasm("beqz a3, -8"), "beqz a3, 0xff8");
+        assert_eq!(disasm("bnez a3, 8"), "bnez a3, 0x1008");
+        assert_eq!(disasm("csrr a0, mstatus"), "csrr a0, mstatus");
+        assert_eq!(disasm("csrw mtvec, a0"), "csrw mtvec, a0");
+    }
+
+    #[test]
+    fn targets_inside_a_symbol_are_annotated() {
+        let mut symbols = SymbolTable::new();
+        symbols.insert(0x1000, "start".to_string());
+        symbols.insert(0x1020, "loop".to_string());
+        symbols.add_region(0x1000, 0x40);
+        let symbols = Some(&symbols);
+        assert_eq!(disasm_at("j 0x20", 0x1000, symbols), "j 0x1020 <loop>");
+        assert_eq!(
+            disasm_at("bnez a0, 8", 0x1000, symbols),
+            "bnez a0, 0x1008 <start+0x8>"
+        );
+        assert_eq!(disasm_at("j 0x38", 0x1000, symbols), "j 0x1038 <loop+0x18>");
+    }
+
+    #[test]
+    fn targets_outside_the_image_are_bare() {
+        let mut symbols = SymbolTable::new();
+        symbols.insert(0x0, "label_00000000".to_string());
+        symbols.insert(0x2c, "label_0000002c".to_string());
+        symbols.add_region(0x0, 0x40);
+        let symbols = Some(&symbols);
+        // 跳到镜像之前 (地址回绕) 和之后的目标都不附上最近的符号
+        assert_eq!(
+            disasm_at("beqz a3, -0x74", 0x2c, symbols),
+            "beqz a3, 0xffffffb8"
+        );
+        assert_eq!(disasm_at("j 0xd4", 0x2c, symbols), "j 0x100");
+    }
+
+    #[test]
+    fn last_symbol_without_regions_matches_only_itself() {
+        let mut symbols = SymbolTable::new();
+        symbols.insert(0x1000, "start".to_string());
+        symbols.insert(0x1020, "end".to_string());
+        assert_eq!(symbols.lookup(0x1010), Some(("start", 0x10)));
+        assert_eq!(symbols.lookup(0x1020), Some(("end", 0)));
+        assert_eq!(symbols.lookup(0x1024), None);
+        assert_eq!(symbols.lookup(0xffc), None);
+    }
+}
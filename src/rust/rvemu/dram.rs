@@ -1,11 +1,3 @@
-use colored::Colorize;
-
-#[allow(unused)]
-use rand::Rng;
-
-#[allow(unused)]
-use std::io::{self, Write};
-
 use super::*;
 
 #[allow(clippy::upper_case_acronyms)]
@@ -27,82 +19,75 @@ impl DRAM {
         Self { data, base }
     }
 
+    /// [addr, addr + size / 8) 是否都在 dram 里
+    pub fn contains(&self, addr: u32, size: u32) -> bool {
+        let end = addr as u64 + (size / 8) as u64;
+        self.base <= addr && end <= self.base as u64 + self.data.len() as u64
+    }
+
+    pub fn base(&self) -> u32 {
+        self.base
+    }
+
+    /// 字节数, 截到地址空间的末尾
+    pub fn size(&self) -> u32 {
+        (self.data.len() as u64).min((1 << 32) - self.base as u64) as u32
+    }
+
     pub fn load(&self, addr: u32, size: u32) -> Result<u32> {
-        if self.base <= addr && addr < self.base + self.data.len() as u32 {
+        if self.contains(addr, size) {
             let offset = (addr - self.base) as usize;
             match size {
-                8 => {
-                    let data = self.data[offset] as u32;
-                    Ok(data)
-                }
-                16 => {
-                    let data = (self.data[offset] as u32) | ((self.data[offset + 1] as u32) << 8);
-                    Ok(data)
-                }
+                8 => Ok(self.data[offset] as u32),
+                16 => Ok(u16::from_le_bytes([self.data[offset], self.data[offset + 1]]) as u32),
                 32 => {
-                    let data = (self.data[offset] as u32)
-                        | ((self.data[offset + 1] as u32) << 8)
-                        | ((self.data[offset + 2] as u32) << 16)
-                        | ((self.data[offset + 3] as u32) << 24);
-                    Ok(data)
+                    let bytes = &self.data[offset..offset + 4];
+                    Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
                 }
-                _ => Err(anyhow!("Invalid data size: {}", size)).with_context(|| context!()),
+                _ => Err(MemError::InvalidSize { addr, size }).with_context(|| context!()),
             }
-        } else if addr == SWITCH_ADDR {
-            // print!("btn: ");
-            // io::stdout().flush().with_context(|| context!())?;
-            // let mut buf = String::new();
-            // io::stdin()
-            //     .read_line(&mut buf)
-            //     .with_context(|| context!())?;
-            // let input = buf.trim();
-            // input.parse::<u32>().with_context(|| context!())
-            Ok(0xA00000)
-            // let mut rng = rand::thread_rng();
-            // let random_number: u32 = rng.gen();
-            // println!("switch: {:#x}", random_number);
-            // Ok(random_number)
         } else {
-            Err(anyhow!("Invalid data address: 0x{:08x}", addr)).with_context(|| context!())
+            Err(MemError::OutOfRange {
+                access: Access::Load,
+                addr,
+                size,
+            })
+            .context(Exception::LoadAccessFault(addr))
+            .with_context(|| context!())
             // Ok(0)
         }
     }
 
     pub fn store(&mut self, addr: u32, data: u32, size: u32) -> Result<()> {
-        if self.base <= addr && (addr as usize) < self.base as usize + self.data.len() {
+        if self.contains(addr, size) {
             let offset = (addr - self.base) as usize;
             match size {
-                8 => {
-                    self.data[offset] = data as u8;
-                    Ok(())
-                }
-                16 => {
-                    self.data[offset] = data as u8;
-                    self.data[offset + 1] = (data >> 8) as u8;
-                    Ok(())
-                }
-                32 => {
-                    self.data[offset] = data as u8;
-                    self.data[offset + 1] = (data >> 8) as u8;
-                    self.data[offset + 2] = (data >> 16) as u8;
-                    self.data[offset + 3] = (data >> 24) as u8;
+                8 | 16 | 32 => {
+                    let bytes = (size / 8) as usize;
+                    self.data[offset..offset + bytes].copy_from_slice(&data.to_le_bytes()[..bytes]);
                     Ok(())
                 }
-                _ => Err(anyhow!("Invalid data size: {}", size)).with_context(|| context!()),
+                _ => Err(MemError::InvalidSize { addr, size }).with_context(|| context!()),
             }
-        } else if addr == DIG_ADDR {
-            let buf = format!("LED: {:#x}", data).blue();
-            println!("{}", buf);
-            Ok(())
         } else {
-            Err(anyhow!(
-                "Invalid data address: 0x{:08x} <= 0x{:08x} < 0x{:08x}",
-                self.base,
+            Err(MemError::OutOfRange {
+                access: Access::Store,
                 addr,
-                self.base + self.data.len() as u32
-            ))
+                size,
+            })
+            .context(Exception::StoreAccessFault(addr))
             .with_context(|| context!())
             // Ok(())
         }
     }
 }
+
+impl Device for DRAM {
+    fn read(&mut self, offset: u32, size: u32) -> Result<u32> {
+        self.load(self.base.wrapping_add(offset), size)
+    }
+
+    fn write(&mut self, offset: u32, value: u32, size: u32) -> Result<()> {
+        self.store(self.base.wrapping_add(offset), value, size)
+    }
+}
@@ -0,0 +1,421 @@
+use super::*;
+
+/// 寄存器编号只有 5 位
+pub fn reg(r: Reg) -> Result<u32> {
+    if r >= 32 {
+        return Err(anyhow!("invalid register: x{}", r)).with_context(|| context!());
+    }
+    Ok(r)
+}
+
+/// width 位的有符号立即数, 且是 align 的倍数
+pub fn signed(imm: i32, width: u32, align: i32) -> Result<u32> {
+    let min = -(1i64 << (width - 1));
+    let max = (1i64 << (width - 1)) - 1;
+    if (imm as i64) < min || (imm as i64) > max || imm % align != 0 {
+        return Err(anyhow!(
+            "immediate {} out of range: {}-bit signed, multiple of {}",
+            imm,
+            width,
+            align
+        ))
+        .with_context(|| context!());
+    }
+    Ok(imm as u32)
+}
+
+/// width 位的无符号立即数, 且是 align 的倍数
+pub fn unsigned(imm: i32, width: u32, align: i32) -> Result<u32> {
+    if imm < 0 || (imm as i64) >> width != 0 || imm % align != 0 {
+        return Err(anyhow!(
+            "immediate {} out of range: {}-bit unsigned, multiple of {}",
+            imm,
+            width,
+            align
+        ))
+        .with_context(|| context!());
+    }
+    Ok(imm as u32)
+}
+
+/// 浮点指令的 rm: 5, 6 是保留的编码
+fn rm(rm: u32) -> Result<u32> {
+    if rm > 0b111 || matches!(rm, 0b101 | 0b110) {
+        return Err(anyhow!("invalid rounding mode: {}", rm)).with_context(|| context!());
+    }
+    Ok(rm)
+}
+
+fn r_type(opcode: u32, rd: Reg, funct3: u32, rs1: Reg, rs2: Reg, funct7: u32) -> Result<u32> {
+    Ok(funct7 << 25 | reg(rs2)? << 20 | reg(rs1)? << 15 | funct3 << 12 | reg(rd)? << 7 | opcode)
+}
+
+/// 移位的 shamt 和 zbb 的一元操作都放在 rs2 的位置
+fn shift(opcode: u32, rd: Reg, funct3: u32, rs1: Reg, shamt: i32, funct7: u32) -> Result<u32> {
+    let shamt = unsigned(shamt, 5, 1).with_context(|| context!())?;
+    r_type(opcode, rd, funct3, rs1, shamt, funct7)
+}
+
+fn i_type(opcode: u32, rd: Reg, funct3: u32, rs1: Reg, imm: i32) -> Result<u32> {
+    let imm = signed(imm, 12, 1).with_context(|| context!())?;
+    Ok((imm & 0xfff) << 20 | reg(rs1)? << 15 | funct3 << 12 | reg(rd)? << 7 | opcode)
+}
+
+fn s_type(opcode: u32, funct3: u32, rs1: Reg, rs2: Reg, imm: i32) -> Result<u32> {
+    let imm = signed(imm, 12, 1).with_context(|| context!())?;
+    // imm[11:5|4:0] = inst[31:25|11:7]
+    Ok((imm >> 5 & 0x7f) << 25
+        | reg(rs2)? << 20
+        | reg(rs1)? << 15
+        | funct3 << 12
+        | (imm & 0x1f) << 7
+        | opcode)
+}
+
+fn b_type(funct3: u32, rs1: Reg, rs2: Reg, imm: i32) -> Result<u32> {
+    let imm = signed(imm, 13, 2).with_context(|| context!())?;
+    // imm[12|10:5|4:1|11] = inst[31|30:25|11:8|7]
+    Ok((imm >> 12 & 0x1) << 31
+        | (imm >> 5 & 0x3f) << 25
+        | reg(rs2)? << 20
+        | reg(rs1)? << 15
+        | funct3 << 12
+        | (imm >> 1 & 0xf) << 8
+        | (imm >> 11 & 0x1) << 7
+        | 0x63)
+}
+
+fn u_type(opcode: u32, rd: Reg, imm: u32) -> Result<u32> {
+    if imm & 0xfff != 0 {
+        return Err(anyhow!("immediate {:#x} has non-zero low 12 bits", imm))
+            .with_context(|| context!());
+    }
+    Ok(imm | reg(rd)? << 7 | opcode)
+}
+
+fn j_type(rd: Reg, imm: i32) -> Result<u32> {
+    let imm = signed(imm, 21, 2).with_context(|| context!())?;
+    // imm[20|10:1|11|19:12] = inst[31|30:21|20|19:12]
+    Ok((imm >> 20 & 0x1) << 31
+        | (imm >> 1 & 0x3ff) << 21
+        | (imm >> 11 & 0x1) << 20
+        | (imm & 0xff000)
+        | reg(rd)? << 7
+        | 0x6f)
+}
+
+fn amo(funct5: u32, rd: Reg, rs2: Reg, rs1: Reg, aqrl: u32) -> Result<u32> {
+    if aqrl > 0b11 {
+        return Err(anyhow!("invalid aq|rl: {:#x}", aqrl)).with_context(|| context!());
+    }
+    r_type(0x2f, rd, 0x2, rs1, rs2, funct5 << 2 | aqrl)
+}
+
+fn fma(opcode: u32, rd: Reg, rs1: Reg, rs2: Reg, rs3: Reg, rm_: u32) -> Result<u32> {
+    // fmt = 0: 单精度
+    let funct7 = reg(rs3)? << 2;
+    r_type(opcode, rd, rm(rm_)?, rs1, rs2, funct7)
+}
+
+fn csr(rd: Reg, funct3: u32, csr: CSR, rs1: u32) -> Result<u32> {
+    if csr >= 4096 {
+        return Err(anyhow!("invalid csr: {:#x}", csr)).with_context(|| context!());
+    }
+    Ok(csr << 20 | reg(rs1)? << 15 | funct3 << 12 | reg(rd)? << 7 | 0x73)
+}
+
+impl Instr {
+    /// 编码成机器码, 压缩指令只有低 16 位.
+    /// 立即数超出范围或不对齐、寄存器编号超过 31 时返回错误.
+    pub fn encode(&self) -> Result<u32> {
+        self.encode_inst()
+            .with_context(|| format!("cannot encode {:?}", self))
+    }
+
+    fn encode_inst(&self) -> Result<u32> {
+        match *self {
+            // u
+            Instr::LUI(rd, imm) => u_type(0x37, rd, imm),
+            Instr::AUIPC(rd, imm) => u_type(0x17, rd, imm),
+            // j
+            Instr::JAL(rd, imm) => j_type(rd, imm),
+            // b
+            Instr::BEQ(rs1, rs2, imm) => b_type(0x0, rs1, rs2, imm),
+            Instr::BNE(rs1, rs2, imm) => b_type(0x1, rs1, rs2, imm),
+            Instr::BLT(rs1, rs2, imm) => b_type(0x4, rs1, rs2, imm),
+            Instr::BGE(rs1, rs2, imm) => b_type(0x5, rs1, rs2, imm),
+            Instr::BLTU(rs1, rs2, imm) => b_type(0x6, rs1, rs2, imm),
+            Instr::BGEU(rs1, rs2, imm) => b_type(0x7, rs1, rs2, imm),
+            // s
+            Instr::SB(rs2, offset, rs1) => s_type(0x23, 0x0, rs1, rs2, offset),
+            Instr::SH(rs2, offset, rs1) => s_type(0x23, 0x1, rs1, rs2, offset),
+            Instr::SW(rs2, offset, rs1) => s_type(0x23, 0x2, rs1, rs2, offset),
+            // i
+            Instr::ADDI(rd, rs1, imm) => i_type(0x13, rd, 0x0, rs1, imm),
+            Instr::ANDI(rd, rs1, imm) => i_type(0x13, rd, 0x7, rs1, imm),
+            Instr::ORI(rd, rs1, imm) => i_type(0x13, rd, 0x6, rs1, imm),
+            Instr::XORI(rd, rs1, imm) => i_type(0x13, rd, 0x4, rs1, imm),
+            Instr::SLLI(rd, rs1, imm) => shift(0x13, rd, 0x1, rs1, imm, 0x00),
+            Instr::SRLI(rd, rs1, imm) => shift(0x13, rd, 0x5, rs1, imm, 0x00),
+            Instr::SRAI(rd, rs1, imm) => shift(0x13, rd, 0x5, rs1, imm, 0x20),
+            Instr::SLTI(rd, rs1, imm) => i_type(0x13, rd, 0x2, rs1, imm),
+            Instr::SLTIU(rd, rs1, imm) => i_type(0x13, rd, 0x3, rs1, imm),
+            Instr::LB(rd, offset, rs1) => i_type(0x03, rd, 0x0, rs1, offset),
+            Instr::LH(rd, offset, rs1) => i_type(0x03, rd, 0x1, rs1, offset),
+            Instr::LW(rd, offset, rs1) => i_type(0x03, rd, 0x2, rs1, offset),
+            Instr::LBU(rd, offset, rs1) => i_type(0x03, rd, 0x4, rs1, offset),
+            Instr::LHU(rd, offset, rs1) => i_type(0x03, rd, 0x5, rs1, offset),
+            Instr::JALR(rd, offset, rs1) => i_type(0x67, rd, 0x0, rs1, offset),
+            // r
+            Instr::ADD(rd, rs1, rs2) => r_type(0x33, rd, 0x0, rs1, rs2, 0x00),
+            Instr::SUB(rd, rs1, rs2) => r_type(0x33, rd, 0x0, rs1, rs2, 0x20),
+            Instr::SLL(rd, rs1, rs2) => r_type(0x33, rd, 0x1, rs1, rs2, 0x00),
+            Instr::SLT(rd, rs1, rs2) => r_type(0x33, rd, 0x2, rs1, rs2, 0x00),
+            Instr::SLTU(rd, rs1, rs2) => r_type(0x33, rd, 0x3, rs1, rs2, 0x00),
+            Instr::XOR(rd, rs1, rs2) => r_type(0x33, rd, 0x4, rs1, rs2, 0x00),
+            Instr::SRL(rd, rs1, rs2) => r_type(0x33, rd, 0x5, rs1, rs2, 0x00),
+            Instr::SRA(rd, rs1, rs2) => r_type(0x33, rd, 0x5, rs1, rs2, 0x20),
+            Instr::OR(rd, rs1, rs2) => r_type(0x33, rd, 0x6, rs1, rs2, 0x00),
+            Instr::AND(rd, rs1, rs2) => r_type(0x33, rd, 0x7, rs1, rs2, 0x00),
+            // m
+            Instr::MUL(rd, rs1, rs2) => r_type(0x33, rd, 0x0, rs1, rs2, 0x01),
+            Instr::MULH(rd, rs1, rs2) => r_type(0x33, rd, 0x1, rs1, rs2, 0x01),
+            Instr::MULHSU(rd, rs1, rs2) => r_type(0x33, rd, 0x2, rs1, rs2, 0x01),
+            Instr::MULHU(rd, rs1, rs2) => r_type(0x33, rd, 0x3, rs1, rs2, 0x01),
+            Instr::DIV(rd, rs1, rs2) => r_type(0x33, rd, 0x4, rs1, rs2, 0x01),
+            Instr::DIVU(rd, rs1, rs2) => r_type(0x33, rd, 0x5, rs1, rs2, 0x01),
+            Instr::REM(rd, rs1, rs2) => r_type(0x33, rd, 0x6, rs1, rs2, 0x01),
+            Instr::REMU(rd, rs1, rs2) => r_type(0x33, rd, 0x7, rs1, rs2, 0x01),
+            // zba
+            Instr::SH1ADD(rd, rs1, rs2) => r_type(0x33, rd, 0x2, rs1, rs2, 0x10),
+            Instr::SH2ADD(rd, rs1, rs2) => r_type(0x33, rd, 0x4, rs1, rs2, 0x10),
+            Instr::SH3ADD(rd, rs1, rs2) => r_type(0x33, rd, 0x6, rs1, rs2, 0x10),
+            // zbb
+            Instr::ANDN(rd, rs1, rs2) => r_type(0x33, rd, 0x7, rs1, rs2, 0x20),
+            Instr::ORN(rd, rs1, rs2) => r_type(0x33, rd, 0x6, rs1, rs2, 0x20),
+            Instr::XNOR(rd, rs1, rs2) => r_type(0x33, rd, 0x4, rs1, rs2, 0x20),
+            Instr::MAX(rd, rs1, rs2) => r_type(0x33, rd, 0x6, rs1, rs2, 0x05),
+            Instr::MAXU(rd, rs1, rs2) => r_type(0x33, rd, 0x7, rs1, rs2, 0x05),
+            Instr::MIN(rd, rs1, rs2) => r_type(0x33, rd, 0x4, rs1, rs2, 0x05),
+            Instr::MINU(rd, rs1, rs2) => r_type(0x33, rd, 0x5, rs1, rs2, 0x05),
+            Instr::ROL(rd, rs1, rs2) => r_type(0x33, rd, 0x1, rs1, rs2, 0x30),
+            Instr::ROR(rd, rs1, rs2) => r_type(0x33, rd, 0x5, rs1, rs2, 0x30),
+            Instr::RORI(rd, rs1, shamt) => shift(0x13, rd, 0x5, rs1, shamt, 0x30),
+            Instr::CLZ(rd, rs1) => shift(0x13, rd, 0x1, rs1, 0x00, 0x30),
+            Instr::CTZ(rd, rs1) => shift(0x13, rd, 0x1, rs1, 0x01, 0x30),
+            Instr::CPOP(rd, rs1) => shift(0x13, rd, 0x1, rs1, 0x02, 0x30),
+            Instr::SEXTB(rd, rs1) => shift(0x13, rd, 0x1, rs1, 0x04, 0x30),
+            Instr::SEXTH(rd, rs1) => shift(0x13, rd, 0x1, rs1, 0x05, 0x30),
+            Instr::ZEXTH(rd, rs1) => r_type(0x33, rd, 0x4, rs1, 0, 0x04),
+            Instr::ORCB(rd, rs1) => shift(0x13, rd, 0x5, rs1, 0x07, 0x14),
+            Instr::REV8(rd, rs1) => shift(0x13, rd, 0x5, rs1, 0x18, 0x34),
+            // zbs
+            Instr::BCLR(rd, rs1, rs2) => r_type(0x33, rd, 0x1, rs1, rs2, 0x24),
+            Instr::BEXT(rd, rs1, rs2) => r_type(0x33, rd, 0x5, rs1, rs2, 0x24),
+            Instr::BINV(rd, rs1, rs2) => r_type(0x33, rd, 0x1, rs1, rs2, 0x34),
+            Instr::BSET(rd, rs1, rs2) => r_type(0x33, rd, 0x1, rs1, rs2, 0x14),
+            Instr::BCLRI(rd, rs1, shamt) => shift(0x13, rd, 0x1, rs1, shamt, 0x24),
+            Instr::BEXTI(rd, rs1, shamt) => shift(0x13, rd, 0x5, rs1, shamt, 0x24),
+            Instr::BINVI(rd, rs1, shamt) => shift(0x13, rd, 0x1, rs1, shamt, 0x34),
+            Instr::BSETI(rd, rs1, shamt) => shift(0x13, rd, 0x1, rs1, shamt, 0x14),
+            // a
+            Instr::LRW(rd, rs1, aqrl) => amo(0x02, rd, 0, rs1, aqrl),
+            Instr::SCW(rd, rs2, rs1, aqrl) => amo(0x03, rd, rs2, rs1, aqrl),
+            Instr::AMOSWAPW(rd, rs2, rs1, aqrl) => amo(0x01, rd, rs2, rs1, aqrl),
+            Instr::AMOADDW(rd, rs2, rs1, aqrl) => amo(0x00, rd, rs2, rs1, aqrl),
+            Instr::AMOXORW(rd, rs2, rs1, aqrl) => amo(0x04, rd, rs2, rs1, aqrl),
+            Instr::AMOANDW(rd, rs2, rs1, aqrl) => amo(0x0c, rd, rs2, rs1, aqrl),
+            Instr::AMOORW(rd, rs2, rs1, aqrl) => amo(0x08, rd, rs2, rs1, aqrl),
+            Instr::AMOMINW(rd, rs2, rs1, aqrl) => amo(0x10, rd, rs2, rs1, aqrl),
+            Instr::AMOMAXW(rd, rs2, rs1, aqrl) => amo(0x14, rd, rs2, rs1, aqrl),
+            Instr::AMOMINUW(rd, rs2, rs1, aqrl) => amo(0x18, rd, rs2, rs1, aqrl),
+            Instr::AMOMAXUW(rd, rs2, rs1, aqrl) => amo(0x1c, rd, rs2, rs1, aqrl),
+            // f
+            Instr::FLW(rd, offset, rs1) => i_type(0x07, rd, 0x2, rs1, offset),
+            Instr::FSW(rs2, offset, rs1) => s_type(0x27, 0x2, rs1, rs2, offset),
+            Instr::FMADDS(rd, rs1, rs2, rs3, rm) => fma(0x43, rd, rs1, rs2, rs3, rm),
+            Instr::FMSUBS(rd, rs1, rs2, rs3, rm) => fma(0x47, rd, rs1, rs2, rs3, rm),
+            Instr::FNMSUBS(rd, rs1, rs2, rs3, rm) => fma(0x4b, rd, rs1, rs2, rs3, rm),
+            Instr::FNMADDS(rd, rs1, rs2, rs3, rm) => fma(0x4f, rd, rs1, rs2, rs3, rm),
+            Instr::FADDS(rd, rs1, rs2, rm_) => r_type(0x53, rd, rm(rm_)?, rs1, rs2, 0x00),
+            Instr::FSUBS(rd, rs1, rs2, rm_) => r_type(0x53, rd, rm(rm_)?, rs1, rs2, 0x04),
+            Instr::FMULS(rd, rs1, rs2, rm_) => r_type(0x53, rd, rm(rm_)?, rs1, rs2, 0x08),
+            Instr::FDIVS(rd, rs1, rs2, rm_) => r_type(0x53, rd, rm(rm_)?, rs1, rs2, 0x0c),
+            Instr::FSQRTS(rd, rs1, rm_) => r_type(0x53, rd, rm(rm_)?, rs1, 0, 0x2c),
+            Instr::FSGNJS(rd, rs1, rs2) => r_type(0x53, rd, 0x0, rs1, rs2, 0x10),
+            Instr::FSGNJNS(rd, rs1, rs2) => r_type(0x53, rd, 0x1, rs1, rs2, 0x10),
+            Instr::FSGNJXS(rd, rs1, rs2) => r_type(0x53, rd, 0x2, rs1, rs2, 0x10),
+            Instr::FMINS(rd, rs1, rs2) => r_type(0x53, rd, 0x0, rs1, rs2, 0x14),
+            Instr::FMAXS(rd, rs1, rs2) => r_type(0x53, rd, 0x1, rs1, rs2, 0x14),
+            Instr::FCVTWS(rd, rs1, rm_) => r_type(0x53, rd, rm(rm_)?, rs1, 0, 0x60),
+            Instr::FCVTWUS(rd, rs1, rm_) => r_type(0x53, rd, rm(rm_)?, rs1, 1, 0x60),
+            Instr::FMVXW(rd, rs1) => r_type(0x53, rd, 0x0, rs1, 0, 0x70),
+            Instr::FCVTSW(rd, rs1, rm_) => r_type(0x53, rd, rm(rm_)?, rs1, 0, 0x68),
+            Instr::FCVTSWU(rd, rs1, rm_) => r_type(0x53, rd, rm(rm_)?, rs1, 1, 0x68),
+            Instr::FMVWX(rd, rs1) => r_type(0x53, rd, 0x0, rs1, 0, 0x78),
+            Instr::FEQS(rd, rs1, rs2) => r_type(0x53, rd, 0x2, rs1, rs2, 0x50),
+            Instr::FLTS(rd, rs1, rs2) => r_type(0x53, rd, 0x1, rs1, rs2, 0x50),
+            Instr::FLES(rd, rs1, rs2) => r_type(0x53, rd, 0x0, rs1, rs2, 0x50),
+            Instr::FCLASSS(rd, rs1) => r_type(0x53, rd, 0x1, rs1, 0, 0x70),
+            // fence
+            Instr::FENCE(pred, succ) => {
+                if pred > 0xf || succ > 0xf {
+                    return Err(anyhow!("invalid fence set: {:#x}, {:#x}", pred, succ))
+                        .with_context(|| context!());
+                }
+                Ok(pred << 24 | succ << 20 | 0x0f)
+            }
+            Instr::FENCEI => Ok(0x0000_100f),
+            Instr::SFENCEVMA(rs1, rs2) => r_type(0x73, 0, 0x0, rs1, rs2, 0x09),
+            // system
+            Instr::EBREAK => Ok(0x0010_0073),
+            Instr::WFI => Ok(0x1050_0073),
+            // zicsr
+            Instr::ECALL => Ok(0x0000_0073),
+            Instr::URET => Ok(0x0020_0073),
+            Instr::SRET => Ok(0x1020_0073),
+            Instr::MRET => Ok(0x3020_0073),
+            Instr::CSRRW(rd, addr, rs1) => csr(rd, 0x1, addr, rs1),
+            Instr::CSRRS(rd, addr, rs1) => csr(rd, 0x2, addr, rs1),
+            Instr::CSRRC(rd, addr, rs1) => csr(rd, 0x3, addr, rs1),
+            // zimm 在 rs1 的位置
+            Instr::CSRRWI(rd, addr, zimm) => csr(rd, 0x5, addr, unsigned(zimm as i32, 5, 1)?),
+            Instr::CSRRSI(rd, addr, zimm) => csr(rd, 0x6, addr, unsigned(zimm as i32, 5, 1)?),
+            Instr::CSRRCI(rd, addr, zimm) => csr(rd, 0x7, addr, unsigned(zimm as i32, 5, 1)?),
+            // c
+            Instr::C(mnemonic, ref expanded) => rvc::compress(mnemonic, expanded),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    /// 能译码的字编码后再译码, 得到同样的指令
+    fn round_trip(word: u32) -> bool {
+        let Ok(inst) = Instr::try_from(word) else {
+            return false;
+        };
+        let encoded = inst
+            .encode()
+            .unwrap_or_else(|e| panic!("{:#010x}: {:?}", word, e));
+        assert_eq!(
+            Instr::try_from(encoded).ok(),
+            Some(inst),
+            "{:#010x} -> {:#010x}",
+            word,
+            encoded
+        );
+        true
+    }
+
+    #[test]
+    fn round_trip_all_compressed() {
+        let decoded = (0..=0xffff)
+            .filter(|&word| is_compressed(word))
+            .filter(|&word| round_trip(word))
+            .count();
+        assert!(decoded > 30000, "only {} decodable words", decoded);
+    }
+
+    #[test]
+    fn round_trip_sampled_32_bit() {
+        // 随机的 funct/寄存器/立即数, opcode 取所有的主操作码
+        let mut rng = StdRng::seed_from_u64(0x5eed);
+        let mut decoded = 0;
+        for _ in 0..1 << 20 {
+            let word = rng.gen::<u32>() | 0b11;
+            if round_trip(word) {
+                decoded += 1;
+            }
+        }
+        assert!(decoded > 100000, "only {} decodable words", decoded);
+    }
+
+    #[test]
+    fn round_trip_fixed_encodings() {
+        for word in [
+            0x0000_0073, // ecall
+            0x0010_0073, // ebreak
+            0x1050_0073, // wfi
+            0x3020_0073, // mret
+            0x1020_0073, // sret
+            0x0000_100f, // fence.i
+            0x0ff0_000f, // fence iorw, iorw
+            0x1200_0073, // sfence.vma
+        ] {
+            assert!(round_trip(word), "{:#010x}", word);
+        }
+    }
+
+    #[test]
+    fn rejects_out_of_range_immediates() {
+        assert!(Instr::ADDI(1, 2, 2047).encode().is_ok());
+        assert!(Instr::ADDI(1, 2, 2048).encode().is_err());
+        assert!(Instr::ADDI(1, 2, -2049).encode().is_err());
+        assert!(Instr::SW(1, -2048, 2).encode().is_ok());
+        assert!(Instr::SW(1, 2048, 2).encode().is_err());
+        assert!(Instr::JAL(1, 1 << 20).encode().is_err());
+        assert!(Instr::SLLI(1, 1, 32).encode().is_err());
+        assert!(Instr::SLLI(1, 1, -1).encode().is_err());
+        assert!(Instr::CSRRWI(1, MSTATUS, 32).encode().is_err());
+        assert!(Instr::CSRRW(1, 4096, 2).encode().is_err());
+        assert!(Instr::LUI(1, 0x1234_5001).encode().is_err());
+    }
+
+    #[test]
+    fn rejects_misaligned_immediates() {
+        assert!(Instr::BEQ(1, 2, 4094).encode().is_ok());
+        assert!(Instr::BEQ(1, 2, 3).encode().is_err());
+        assert!(Instr::BEQ(1, 2, 4096).encode().is_err());
+        assert!(Instr::JAL(0, 7).encode().is_err());
+        let c_lw = |offset| Instr::C("c.lw", Box::new(Instr::LW(8, offset, 9)));
+        assert!(c_lw(124).encode().is_ok());
+        assert!(c_lw(2).encode().is_err());
+        assert!(c_lw(128).encode().is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_registers_and_fields() {
+        assert!(Instr::ADD(32, 1, 2).encode().is_err());
+        assert!(Instr::FADDS(1, 2, 3, 0b101).encode().is_err());
+        assert!(Instr::FENCE(0x10, 0).encode().is_err());
+        assert!(Instr::AMOADDW(1, 2, 3, 4).encode().is_err());
+    }
+
+    #[test]
+    fn compressed_registers_must_be_x8_to_x15() {
+        // uncreg: rd'/rs1'/rs2' 只能是 x8-x15
+        let c_lw = |rd, base| Instr::C("c.lw", Box::new(Instr::LW(rd, 0, base)));
+        assert!(c_lw(8, 15).encode().is_ok());
+        assert!(c_lw(7, 8).encode().is_err());
+        assert!(c_lw(8, 16).encode().is_err());
+        let c_sub = |rd, rs2| Instr::C("c.sub", Box::new(Instr::SUB(rd, rd, rs2)));
+        assert!(c_sub(9, 10).encode().is_ok());
+        assert!(c_sub(1, 10).encode().is_err());
+    }
+
+    #[test]
+    fn compressed_operands_must_be_non_zero() {
+        // nonzero: c.mv/c.jr 等的 x0 是保留的 (c.mv 的 rd = x0 是 HINT)
+        let c_mv = |rd, rs2| Instr::C("c.mv", Box::new(Instr::ADD(rd, 0, rs2)));
+        assert!(c_mv(1, 2).encode().is_ok());
+        assert!(c_mv(0, 2).encode().is_ok());
+        assert!(c_mv(1, 0).encode().is_err());
+        let c_jr = |rs1| Instr::C("c.jr", Box::new(Instr::JALR(0, 0, rs1)));
+        assert!(c_jr(1).encode().is_ok());
+        assert!(c_jr(0).encode().is_err());
+        // c.addi4spn/c.addi16sp/c.lui 的立即数不能是 0
+        let c_addi4spn = |imm| Instr::C("c.addi4spn", Box::new(Instr::ADDI(8, 2, imm)));
+        assert!(c_addi4spn(4).encode().is_ok());
+        assert!(c_addi4spn(0).encode().is_err());
+        let c_addi16sp = |imm| Instr::C("c.addi16sp", Box::new(Instr::ADDI(2, 2, imm)));
+        assert!(c_addi16sp(16).encode().is_ok());
+        assert!(c_addi16sp(0).encode().is_err());
+        assert!(Instr::C("c.lui", Box::new(Instr::LUI(1, 0)))
+            .encode()
+            .is_err());
+    }
+}
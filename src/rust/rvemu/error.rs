@@ -0,0 +1,378 @@
+use super::*;
+
+/// 指令译码失败. 执行时会再包一层 `Exception::IllegalInstruction`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DecodeError {
+    /// 没有实现的 32 位编码
+    Unimplemented {
+        raw: u32,
+        opcode: u32,
+        funct3: u32,
+        funct7: u32,
+    },
+    /// 非法或保留的压缩指令
+    Compressed(u32),
+    /// RV32E 下用到了 x16-x31
+    Rv32e(u32),
+    /// 指令所属的扩展没有打开
+    Disabled(u32, Extension),
+}
+
+impl DecodeError {
+    /// 按 32 位指令的字段报告没有实现的编码
+    pub fn unimplemented(raw: u32) -> Self {
+        DecodeError::Unimplemented {
+            raw,
+            opcode: raw & 0x7f,
+            funct3: (raw >> 12) & 0x7,
+            funct7: raw >> 25,
+        }
+    }
+}
+
+impl Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            DecodeError::Unimplemented {
+                raw,
+                opcode,
+                funct3,
+                funct7,
+            } => write!(
+                f,
+                "not implemented yet: {:#010x} (opcode {:#x} funct3 {:#x} funct7 {:#x})",
+                raw, opcode, funct3, funct7
+            ),
+            DecodeError::Compressed(raw) => {
+                write!(f, "illegal compressed instruction: {:#06x}", raw)
+            }
+            DecodeError::Rv32e(raw) => write!(f, "{:#010x} (RV32E has only x0-x15)", raw),
+            DecodeError::Disabled(raw, ext) => write!(f, "{:#010x} ({:?} disabled)", raw, ext),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// 存储器访问失败. 需要进入异常处理时会再包一层对应的访问异常
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MemError {
+    /// 地址不在存储器里
+    OutOfRange {
+        access: Access,
+        addr: u32,
+        size: u32,
+    },
+    /// 访问的位数不是 8, 16 或 32
+    InvalidSize { addr: u32, size: u32 },
+}
+
+impl Display for MemError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            MemError::OutOfRange { access, addr, size } => {
+                let access = match access {
+                    Access::Fetch => "instruction",
+                    Access::Load => "load",
+                    Access::Store => "store",
+                };
+                write!(
+                    f,
+                    "invalid {} address: {:#010x} ({} bits)",
+                    access, addr, size
+                )
+            }
+            MemError::InvalidSize { addr, size } => {
+                write!(f, "invalid data size: {} at {:#010x}", size, addr)
+            }
+        }
+    }
+}
+
+impl std::error::Error for MemError {}
+
+/// csr 访问失败. 执行时会再包一层 `Exception::IllegalInstruction`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CsrError {
+    /// 没有实现的 csr
+    Unimplemented(CSR),
+    /// 写只读的 csr
+    ReadOnly(CSR),
+    /// 当前特权级不够
+    Privilege(CSR, Privilege),
+    /// mstatus.FS = Off 时访问浮点 csr
+    FsOff(CSR),
+    /// 计数器没有在 mcounteren/scounteren 中打开
+    CounterDisabled(CSR, Privilege),
+}
+
+impl Display for CsrError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CsrError::Unimplemented(csr) => write!(f, "not implemented yet: csr {:#x}", csr),
+            CsrError::ReadOnly(csr) => write!(f, "read-only csr: {}", csr_abi(csr)),
+            CsrError::Privilege(csr, mode) => write!(f, "{} in {:?}-mode", csr_abi(csr), mode),
+            CsrError::FsOff(csr) => write!(f, "{} with mstatus.FS = Off", csr_abi(csr)),
+            CsrError::CounterDisabled(csr, mode) => {
+                write!(f, "{} disabled in {:?}-mode", csr_abi(csr), mode)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CsrError {}
+
+/// 程序主动停下来的原因, 不是模拟器的错误
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Halt {
+    /// 没有打开 trap_illegal 时执行了 ebreak
+    Ebreak,
+    /// 打开 halt_on_eret 时执行了 xRET
+    Eret,
+}
+
+impl Display for Halt {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Halt::Ebreak => write!(f, "ebreak happened"),
+            Halt::Eret => write!(f, "eret happened"),
+        }
+    }
+}
+
+impl std::error::Error for Halt {}
+
+// C 接口的错误码, 与 emu.h 中的 RvemuError 相同
+pub const ERROR_NONE: u32 = 0;
+pub const ERROR_HALT_EBREAK: u32 = 1;
+pub const ERROR_HALT_ERET: u32 = 2;
+pub const ERROR_DECODE_UNIMPLEMENTED: u32 = 0x10;
+pub const ERROR_DECODE_COMPRESSED: u32 = 0x11;
+pub const ERROR_DECODE_RV32E: u32 = 0x12;
+pub const ERROR_DECODE_DISABLED: u32 = 0x13;
+pub const ERROR_CSR_UNIMPLEMENTED: u32 = 0x20;
+pub const ERROR_CSR_READ_ONLY: u32 = 0x21;
+pub const ERROR_CSR_PRIVILEGE: u32 = 0x22;
+pub const ERROR_CSR_FS_OFF: u32 = 0x23;
+pub const ERROR_CSR_COUNTER_DISABLED: u32 = 0x24;
+pub const ERROR_MEM_OUT_OF_RANGE: u32 = 0x30;
+pub const ERROR_MEM_INVALID_SIZE: u32 = 0x31;
+/// 没有进入异常处理的异常, 加上 mcause
+pub const ERROR_EXCEPTION: u32 = 0x100;
+/// 其他错误
+pub const ERROR_OTHER: u32 = 0xffff_ffff;
+
+/// 错误对应的错误码: 先找具体的原因, 再找异常
+pub fn error_code(e: &anyhow::Error) -> u32 {
+    if let Some(halt) = e.downcast_ref::<Halt>() {
+        return match halt {
+            Halt::Ebreak => ERROR_HALT_EBREAK,
+            Halt::Eret => ERROR_HALT_ERET,
+        };
+    }
+    if let Some(e) = e.downcast_ref::<DecodeError>() {
+        return match e {
+            DecodeError::Unimplemented { .. } => ERROR_DECODE_UNIMPLEMENTED,
+            DecodeError::Compressed(_) => ERROR_DECODE_COMPRESSED,
+            DecodeError::Rv32e(_) => ERROR_DECODE_RV32E,
+            DecodeError::Disabled(..) => ERROR_DECODE_DISABLED,
+        };
+    }
+    if let Some(e) = e.downcast_ref::<CsrError>() {
+        return match e {
+            CsrError::Unimplemented(_) => ERROR_CSR_UNIMPLEMENTED,
+            CsrError::ReadOnly(_) => ERROR_CSR_READ_ONLY,
+            CsrError::Privilege(..) => ERROR_CSR_PRIVILEGE,
+            CsrError::FsOff(_) => ERROR_CSR_FS_OFF,
+            CsrError::CounterDisabled(..) => ERROR_CSR_COUNTER_DISABLED,
+        };
+    }
+    if let Some(e) = e.downcast_ref::<MemError>() {
+        return match e {
+            MemError::OutOfRange { .. } => ERROR_MEM_OUT_OF_RANGE,
+            MemError::InvalidSize { .. } => ERROR_MEM_INVALID_SIZE,
+        };
+    }
+    match e.downcast_ref::<Exception>() {
+        Some(exception) => ERROR_EXCEPTION + exception.cause(),
+        None => ERROR_OTHER,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{cpu_with, step};
+    use super::*;
+
+    /// 运行到出错, 返回错误码
+    fn code_with(user: &str, config: Config) -> u32 {
+        let mut cpu = cpu_with(user, "mret", config);
+        let e = (0..100).find_map(|_| step(&mut cpu).err()).unwrap();
+        error_code(&e)
+    }
+
+    fn code(user: &str) -> u32 {
+        code_with(user, Config::default())
+    }
+
+    fn user_mode() -> Config {
+        Config {
+            user_mode: true,
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn codes_match_emu_h() {
+        let header = include_str!("../../../inc/emu.h");
+        let codes = [
+            ("RVEMU_ERROR_NONE", ERROR_NONE),
+            ("RVEMU_HALT_EBREAK", ERROR_HALT_EBREAK),
+            ("RVEMU_HALT_ERET", ERROR_HALT_ERET),
+            ("RVEMU_DECODE_UNIMPLEMENTED", ERROR_DECODE_UNIMPLEMENTED),
+            ("RVEMU_DECODE_COMPRESSED", ERROR_DECODE_COMPRESSED),
+            ("RVEMU_DECODE_RV32E", ERROR_DECODE_RV32E),
+            ("RVEMU_DECODE_DISABLED", ERROR_DECODE_DISABLED),
+            ("RVEMU_CSR_UNIMPLEMENTED", ERROR_CSR_UNIMPLEMENTED),
+            ("RVEMU_CSR_READ_ONLY", ERROR_CSR_READ_ONLY),
+            ("RVEMU_CSR_PRIVILEGE", ERROR_CSR_PRIVILEGE),
+            ("RVEMU_CSR_FS_OFF", ERROR_CSR_FS_OFF),
+            ("RVEMU_CSR_COUNTER_DISABLED", ERROR_CSR_COUNTER_DISABLED),
+            ("RVEMU_MEM_OUT_OF_RANGE", ERROR_MEM_OUT_OF_RANGE),
+            ("RVEMU_MEM_INVALID_SIZE", ERROR_MEM_INVALID_SIZE),
+            ("RVEMU_EXCEPTION", ERROR_EXCEPTION),
+            ("RVEMU_ERROR_OTHER", ERROR_OTHER),
+        ];
+        let enum_body = &header[header.find("enum RvemuError").unwrap()..];
+        let enum_body = &enum_body[..enum_body.find("};").unwrap()];
+        let values: Vec<(&str, u32)> = enum_body
+            .lines()
+            .filter_map(|line| line.trim().strip_suffix(',')?.split_once(" = "))
+            .map(|(name, value)| {
+                let value = match value.strip_prefix("0x") {
+                    Some(hex) => u32::from_str_radix(hex, 16),
+                    None => value.parse(),
+                };
+                (name, value.unwrap())
+            })
+            .collect();
+        assert_eq!(values, codes);
+    }
+
+    #[test]
+    fn none_after_success() {
+        let mut cpu = cpu_with("nop", "mret", Config::default());
+        let result = step(&mut cpu);
+        assert_eq!(result.err().map_or(ERROR_NONE, |e| error_code(&e)), 0);
+    }
+
+    #[test]
+    fn halt() {
+        assert_eq!(code("ebreak"), ERROR_HALT_EBREAK);
+        assert_eq!(code("ecall"), ERROR_HALT_ERET);
+    }
+
+    #[test]
+    fn decode_unimplemented() {
+        assert_eq!(code(".word 0xffffffff"), ERROR_DECODE_UNIMPLEMENTED);
+    }
+
+    #[test]
+    fn decode_compressed() {
+        // 全 0 的半字是保留的压缩指令
+        assert_eq!(code(".half 0"), ERROR_DECODE_COMPRESSED);
+    }
+
+    #[test]
+    fn decode_rv32e() {
+        let config = Config {
+            rv32e: true,
+            ..Config::default()
+        };
+        assert_eq!(code_with("li a6, 1", config), ERROR_DECODE_RV32E);
+    }
+
+    #[test]
+    fn decode_disabled() {
+        assert_eq!(code("sh1add a0, a1, a2"), ERROR_DECODE_DISABLED);
+    }
+
+    #[test]
+    fn csr_unimplemented() {
+        assert_eq!(code("csrr a0, 0x7ff"), ERROR_CSR_UNIMPLEMENTED);
+    }
+
+    #[test]
+    fn csr_read_only() {
+        assert_eq!(code("csrw mhartid, a0"), ERROR_CSR_READ_ONLY);
+    }
+
+    #[test]
+    fn csr_privilege() {
+        assert_eq!(
+            code_with("csrr a0, mstatus", user_mode()),
+            ERROR_CSR_PRIVILEGE
+        );
+    }
+
+    #[test]
+    fn csr_fs_off() {
+        let config = Config {
+            f: true,
+            ..Config::default()
+        };
+        let user = "
+            li t0, 0x6000
+            csrrc zero, mstatus, t0
+            csrr a0, fcsr
+        ";
+        assert_eq!(code_with(user, config), ERROR_CSR_FS_OFF);
+    }
+
+    #[test]
+    fn csr_counter_disabled() {
+        assert_eq!(
+            code_with("csrr a0, cycle", user_mode()),
+            ERROR_CSR_COUNTER_DISABLED
+        );
+    }
+
+    #[test]
+    fn mem_out_of_range() {
+        assert_eq!(
+            code("lui t0, 0x40000\nlw a0, 0(t0)"),
+            ERROR_MEM_OUT_OF_RANGE
+        );
+    }
+
+    #[test]
+    fn mem_invalid_size() {
+        let e = anyhow::Error::new(MemError::InvalidSize { addr: 0, size: 64 })
+            .context(Exception::LoadAccessFault(0));
+        assert_eq!(error_code(&e), ERROR_MEM_INVALID_SIZE);
+    }
+
+    #[test]
+    fn exception_adds_mcause() {
+        let e = anyhow::Error::new(Exception::Breakpoint(0)).context("step");
+        assert_eq!(error_code(&e), ERROR_EXCEPTION + 3);
+        let e = anyhow::Error::new(Exception::LoadAccessFault(0));
+        assert_eq!(error_code(&e), ERROR_EXCEPTION + 5);
+    }
+
+    #[test]
+    fn other() {
+        assert_eq!(error_code(&anyhow!("something else")), ERROR_OTHER);
+    }
+
+    #[test]
+    fn cause_takes_precedence_over_exception() {
+        // 具体的原因外面包着异常, 先报告原因
+        let e = anyhow::Error::new(CsrError::Unimplemented(0x7ff))
+            .context(Exception::IllegalInstruction(0));
+        assert_eq!(error_code(&e), ERROR_CSR_UNIMPLEMENTED);
+        // Halt 优先于其他原因
+        let e = anyhow::Error::new(DecodeError::Compressed(0)).context(Halt::Ebreak);
+        assert_eq!(error_code(&e), ERROR_HALT_EBREAK);
+    }
+}
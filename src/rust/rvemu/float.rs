@@ -0,0 +1,694 @@
+//! 单精度浮点的软件实现, 舍入和异常标志与 IEEE 754 / RISC-V 一致.
+//! 中间结果用 u128 精确表示, 最后只舍入一次.
+
+// fflags
+pub const FLAG_NX: u32 = 1 << 0;
+pub const FLAG_UF: u32 = 1 << 1;
+pub const FLAG_OF: u32 = 1 << 2;
+pub const FLAG_DZ: u32 = 1 << 3;
+pub const FLAG_NV: u32 = 1 << 4;
+
+/// RISC-V 的 canonical NaN
+pub const CANONICAL_NAN: u32 = 0x7fc0_0000;
+pub const SIGN: u32 = 1 << 31;
+const QUIET: u32 = 1 << 22;
+
+/// 舍入模式, 数值与 frm 的编码相同
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RoundingMode {
+    /// 就近舍入, 平局时取偶数
+    RNE = 0,
+    /// 向零舍入
+    RTZ = 1,
+    /// 向负无穷舍入
+    RDN = 2,
+    /// 向正无穷舍入
+    RUP = 3,
+    /// 就近舍入, 平局时远离零
+    RMM = 4,
+}
+
+impl RoundingMode {
+    /// 5, 6 是保留的编码, 7 (dyn) 要由调用者换成 frm
+    pub fn from_bits(bits: u32) -> Option<Self> {
+        match bits {
+            0 => Some(RoundingMode::RNE),
+            1 => Some(RoundingMode::RTZ),
+            2 => Some(RoundingMode::RDN),
+            3 => Some(RoundingMode::RUP),
+            4 => Some(RoundingMode::RMM),
+            _ => None,
+        }
+    }
+}
+
+fn is_nan(a: u32) -> bool {
+    a & 0x7fff_ffff > 0x7f80_0000
+}
+
+fn is_snan(a: u32) -> bool {
+    is_nan(a) && a & QUIET == 0
+}
+
+fn is_inf(a: u32) -> bool {
+    a & 0x7fff_ffff == 0x7f80_0000
+}
+
+fn is_zero(a: u32) -> bool {
+    a & 0x7fff_ffff == 0
+}
+
+fn sign_bit(sign: bool) -> u32 {
+    if sign {
+        SIGN
+    } else {
+        0
+    }
+}
+
+/// 有 NaN 参与运算时的结果: 总是 canonical NaN, signaling NaN 置 NV
+fn propagate_nan(operands: &[u32]) -> (u32, u32) {
+    let flags = if operands.iter().any(|&a| is_snan(a)) {
+        FLAG_NV
+    } else {
+        0
+    };
+    (CANONICAL_NAN, flags)
+}
+
+/// 拆成 (符号, e, m), 数值为 m * 2^e
+fn unpack(a: u32) -> (bool, i32, u128) {
+    let sign = a & SIGN != 0;
+    let exp = ((a >> 23) & 0xff) as i32;
+    let frac = (a & 0x7f_ffff) as u128;
+    if exp == 0 {
+        (sign, -149, frac)
+    } else {
+        (sign, exp - 150, frac | 1 << 23)
+    }
+}
+
+/// m 右移 shift 位并按 rm 舍入, 返回 (舍入后的值, 是否不精确).
+/// sticky 表示 m 的最低位之后还有非零的位.
+fn shift_round(sign: bool, m: u128, shift: i32, sticky: bool, rm: RoundingMode) -> (u128, bool) {
+    if shift <= 0 {
+        return (m << -shift, sticky);
+    }
+    let shift = shift as u32;
+    let kept = m.checked_shr(shift).unwrap_or(0);
+    let round = shift <= 128 && (m >> (shift - 1)) & 1 != 0;
+    let rest_mask = 1u128
+        .checked_shl(shift - 1)
+        .map_or(u128::MAX, |bit| bit - 1);
+    let rest = m & rest_mask != 0 || sticky;
+    let increment = match rm {
+        RoundingMode::RNE => round && (rest || kept & 1 != 0),
+        RoundingMode::RTZ => false,
+        RoundingMode::RDN => sign && (round || rest),
+        RoundingMode::RUP => !sign && (round || rest),
+        RoundingMode::RMM => round,
+    };
+    (kept + increment as u128, round || rest)
+}
+
+/// 最高位的指数: m * 2^e 落在 [2^top, 2^(top+1)) 里
+fn top_exponent(e: i32, m: u128) -> i32 {
+    e + 127 - m.leading_zeros() as i32
+}
+
+/// 把 m * 2^e 舍入成单精度浮点数.
+/// 调用者保证 m != 0, 有 sticky 时 m 至少比尾数多两位.
+fn round_pack(sign: bool, e: i32, m: u128, sticky: bool, rm: RoundingMode) -> (u32, u32) {
+    if m == 0 {
+        return (sign_bit(sign), 0);
+    }
+    let top = top_exponent(e, m);
+    // 尾数 24 位, 非规格化数的最低位固定是 2^-149
+    let mut lsb = (top - 23).max(-149);
+    let (mut sig, inexact) = shift_round(sign, m, lsb - e, sticky, rm);
+    if sig >> 24 != 0 {
+        sig >>= 1;
+        lsb += 1;
+    }
+    let mut flags = if inexact { FLAG_NX } else { 0 };
+    // 舍入之后判断 tiny: 假设指数范围无限, 舍入的结果仍然小于 2^-126
+    if inexact && top < -126 {
+        let (unbounded, _) = shift_round(sign, m, top - 23 - e, sticky, rm);
+        let carry = (unbounded >> 24) as i32;
+        if top + carry < -126 {
+            flags |= FLAG_UF;
+        }
+    }
+    if sig >> 23 == 0 {
+        // 非规格化数
+        return (sign_bit(sign) | sig as u32, flags);
+    }
+    let biased = lsb + 150;
+    if biased >= 0xff {
+        let to_inf = match rm {
+            RoundingMode::RNE | RoundingMode::RMM => true,
+            RoundingMode::RTZ => false,
+            RoundingMode::RDN => sign,
+            RoundingMode::RUP => !sign,
+        };
+        let magnitude = if to_inf { 0x7f80_0000 } else { 0x7f7f_ffff };
+        return (sign_bit(sign) | magnitude, flags | FLAG_OF | FLAG_NX);
+    }
+    let bits = sign_bit(sign) | (biased as u32) << 23 | (sig as u32 & 0x7f_ffff);
+    (bits, flags)
+}
+
+/// 把 m 规格化成 49 位, 乘积最多只有 48 位
+fn normalize(e: i32, m: u128) -> (i32, u128) {
+    let shift = m.leading_zeros() as i32 - (128 - 49);
+    (e - shift, m << shift)
+}
+
+/// 精确地求两个有限数的和, 再舍入一次
+fn add_exact(a: (bool, i32, u128), b: (bool, i32, u128), rm: RoundingMode) -> (u32, u32) {
+    let (sa, ea, ma) = a;
+    let (sb, eb, mb) = b;
+    if ma == 0 && mb == 0 {
+        // 同号的零保持符号, 异号时只有 RDN 得到 -0
+        let sign = if sa == sb {
+            sa
+        } else {
+            rm == RoundingMode::RDN
+        };
+        return (sign_bit(sign), 0);
+    }
+    if ma == 0 {
+        return round_pack(sb, eb, mb, false, rm);
+    }
+    if mb == 0 {
+        return round_pack(sa, ea, ma, false, rm);
+    }
+    let (ea, ma) = normalize(ea, ma);
+    let (eb, mb) = normalize(eb, mb);
+    let ((sa, ea, ma), (sb, eb, mb)) = if ea >= eb {
+        ((sa, ea, ma), (sb, eb, mb))
+    } else {
+        ((sb, eb, mb), (sa, ea, ma))
+    };
+    // 指数相差很大时, 小的数只影响 sticky, 换成同号的一个更小的数不改变舍入结果
+    let diff = ea - eb;
+    let (e, ma, mb) = if diff > 64 {
+        (ea - 64, ma << 64, 1)
+    } else {
+        (eb, ma << diff, mb)
+    };
+    let (sign, m) = if sa == sb {
+        (sa, ma + mb)
+    } else if ma >= mb {
+        (sa, ma - mb)
+    } else {
+        (sb, mb - ma)
+    };
+    if m == 0 {
+        return (sign_bit(rm == RoundingMode::RDN), 0);
+    }
+    round_pack(sign, e, m, false, rm)
+}
+
+pub fn add(a: u32, b: u32, rm: RoundingMode) -> (u32, u32) {
+    if is_nan(a) || is_nan(b) {
+        return propagate_nan(&[a, b]);
+    }
+    match (is_inf(a), is_inf(b)) {
+        (true, true) if (a ^ b) & SIGN != 0 => (CANONICAL_NAN, FLAG_NV),
+        (true, _) => (a, 0),
+        (_, true) => (b, 0),
+        _ => add_exact(unpack(a), unpack(b), rm),
+    }
+}
+
+pub fn sub(a: u32, b: u32, rm: RoundingMode) -> (u32, u32) {
+    if is_nan(a) || is_nan(b) {
+        return propagate_nan(&[a, b]);
+    }
+    add(a, b ^ SIGN, rm)
+}
+
+pub fn mul(a: u32, b: u32, rm: RoundingMode) -> (u32, u32) {
+    if is_nan(a) || is_nan(b) {
+        return propagate_nan(&[a, b]);
+    }
+    let sign = (a ^ b) & SIGN != 0;
+    if is_inf(a) || is_inf(b) {
+        if is_zero(a) || is_zero(b) {
+            return (CANONICAL_NAN, FLAG_NV);
+        }
+        return (sign_bit(sign) | 0x7f80_0000, 0);
+    }
+    let (_, ea, ma) = unpack(a);
+    let (_, eb, mb) = unpack(b);
+    round_pack(sign, ea + eb, ma * mb, false, rm)
+}
+
+/// a * b + c, 只舍入一次
+pub fn fma(a: u32, b: u32, c: u32, rm: RoundingMode) -> (u32, u32) {
+    let inf_times_zero = (is_inf(a) && is_zero(b)) || (is_zero(a) && is_inf(b));
+    if is_nan(a) || is_nan(b) || is_nan(c) {
+        // 乘数是无穷和零时, 即使加数是 quiet NaN 也要置 NV
+        let (nan, flags) = propagate_nan(&[a, b, c]);
+        return (nan, if inf_times_zero { FLAG_NV } else { flags });
+    }
+    if inf_times_zero {
+        return (CANONICAL_NAN, FLAG_NV);
+    }
+    let sign = (a ^ b) & SIGN != 0;
+    if is_inf(a) || is_inf(b) {
+        if is_inf(c) && (c & SIGN != 0) != sign {
+            return (CANONICAL_NAN, FLAG_NV);
+        }
+        return (sign_bit(sign) | 0x7f80_0000, 0);
+    }
+    if is_inf(c) {
+        return (c, 0);
+    }
+    let (_, ea, ma) = unpack(a);
+    let (_, eb, mb) = unpack(b);
+    add_exact((sign, ea + eb, ma * mb), unpack(c), rm)
+}
+
+pub fn div(a: u32, b: u32, rm: RoundingMode) -> (u32, u32) {
+    if is_nan(a) || is_nan(b) {
+        return propagate_nan(&[a, b]);
+    }
+    let sign = (a ^ b) & SIGN != 0;
+    match (is_inf(a), is_inf(b), is_zero(a), is_zero(b)) {
+        (true, true, _, _) | (_, _, true, true) => return (CANONICAL_NAN, FLAG_NV),
+        (true, _, _, _) => return (sign_bit(sign) | 0x7f80_0000, 0),
+        (_, true, _, _) | (_, _, true, _) => return (sign_bit(sign), 0),
+        (_, _, _, true) => return (sign_bit(sign) | 0x7f80_0000, FLAG_DZ),
+        _ => {}
+    }
+    let (_, ea, ma) = unpack(a);
+    let (_, eb, mb) = unpack(b);
+    // 商至少有 40 位, 余数决定 sticky
+    let dividend = ma << 64;
+    let quotient = dividend / mb;
+    let sticky = dividend % mb != 0;
+    round_pack(sign, ea - eb - 64, quotient, sticky, rm)
+}
+
+/// 整数平方根, 向下取整
+fn isqrt(n: u128) -> u128 {
+    if n == 0 {
+        return 0;
+    }
+    let mut x = 1u128 << ((128 - n.leading_zeros() + 1) / 2);
+    loop {
+        let y = (x + n / x) / 2;
+        if y >= x {
+            return x;
+        }
+        x = y;
+    }
+}
+
+pub fn sqrt(a: u32, rm: RoundingMode) -> (u32, u32) {
+    if is_nan(a) {
+        return propagate_nan(&[a]);
+    }
+    if is_zero(a) {
+        return (a, 0);
+    }
+    if a & SIGN != 0 {
+        return (CANONICAL_NAN, FLAG_NV);
+    }
+    if is_inf(a) {
+        return (a, 0);
+    }
+    let (_, mut e, mut m) = unpack(a);
+    if e % 2 != 0 {
+        m <<= 1;
+        e -= 1;
+    }
+    // 平方根至少有 33 位
+    let m = m << 64;
+    let root = isqrt(m);
+    round_pack(false, (e - 64) / 2, root, root * root != m, rm)
+}
+
+/// 转换成整数, 超出范围时饱和并置 NV
+fn to_int(a: u32, rm: RoundingMode, signed: bool) -> (u32, u32) {
+    let (min, max) = if signed {
+        (0x8000_0000, 0x7fff_ffff)
+    } else {
+        (0, 0xffff_ffff)
+    };
+    if is_nan(a) {
+        return (max, FLAG_NV);
+    }
+    let (sign, e, m) = unpack(a);
+    if is_inf(a) {
+        return (if sign { min } else { max }, FLAG_NV);
+    }
+    let (magnitude, inexact) = if e >= 0 {
+        (m << e, false)
+    } else {
+        shift_round(sign, m, -e, false, rm)
+    };
+    let flags = if inexact { FLAG_NX } else { 0 };
+    match (signed, sign) {
+        (true, false) if magnitude <= 0x7fff_ffff => (magnitude as u32, flags),
+        (true, true) if magnitude <= 0x8000_0000 => ((magnitude as u32).wrapping_neg(), flags),
+        (false, false) if magnitude <= 0xffff_ffff => (magnitude as u32, flags),
+        (false, true) if magnitude == 0 => (0, flags),
+        (_, false) => (max, FLAG_NV),
+        (_, true) => (min, FLAG_NV),
+    }
+}
+
+pub fn to_i32(a: u32, rm: RoundingMode) -> (u32, u32) {
+    to_int(a, rm, true)
+}
+
+pub fn to_u32(a: u32, rm: RoundingMode) -> (u32, u32) {
+    to_int(a, rm, false)
+}
+
+pub fn from_i32(x: u32, rm: RoundingMode) -> (u32, u32) {
+    let x = x as i32;
+    round_pack(x < 0, 0, x.unsigned_abs() as u128, false, rm)
+}
+
+pub fn from_u32(x: u32, rm: RoundingMode) -> (u32, u32) {
+    round_pack(false, 0, x as u128, false, rm)
+}
+
+/// a < b, 两者都不是 NaN
+fn less(a: u32, b: u32) -> bool {
+    if is_zero(a) && is_zero(b) {
+        return false;
+    }
+    match (a & SIGN != 0, b & SIGN != 0) {
+        (false, false) => a < b,
+        (true, true) => a > b,
+        (sign_a, _) => sign_a,
+    }
+}
+
+/// feq 是 quiet 比较, 只有 signaling NaN 置 NV
+pub fn eq(a: u32, b: u32) -> (bool, u32) {
+    if is_nan(a) || is_nan(b) {
+        return (false, propagate_nan(&[a, b]).1);
+    }
+    (a == b || (is_zero(a) && is_zero(b)), 0)
+}
+
+/// flt 是 signaling 比较, 任何 NaN 都置 NV
+pub fn lt(a: u32, b: u32) -> (bool, u32) {
+    if is_nan(a) || is_nan(b) {
+        return (false, FLAG_NV);
+    }
+    (less(a, b), 0)
+}
+
+pub fn le(a: u32, b: u32) -> (bool, u32) {
+    if is_nan(a) || is_nan(b) {
+        return (false, FLAG_NV);
+    }
+    (!less(b, a), 0)
+}
+
+/// fmin/fmax: 只有一个 NaN 时返回另一个数, -0 小于 +0
+fn min_max(a: u32, b: u32, max: bool) -> (u32, u32) {
+    let flags = propagate_nan(&[a, b]).1;
+    let result = match (is_nan(a), is_nan(b)) {
+        (true, true) => CANONICAL_NAN,
+        (true, false) => b,
+        (false, true) => a,
+        _ if is_zero(a) && is_zero(b) => {
+            if max {
+                a & b
+            } else {
+                a | b
+            }
+        }
+        _ if less(a, b) != max => a,
+        _ => b,
+    };
+    (result, flags)
+}
+
+pub fn min(a: u32, b: u32) -> (u32, u32) {
+    min_max(a, b, false)
+}
+
+pub fn max(a: u32, b: u32) -> (u32, u32) {
+    min_max(a, b, true)
+}
+
+/// fclass.s 的结果, 只有一位为 1
+pub fn classify(a: u32) -> u32 {
+    let sign = a & SIGN != 0;
+    let exp = (a >> 23) & 0xff;
+    let bit = if is_nan(a) {
+        if is_snan(a) {
+            8
+        } else {
+            9
+        }
+    } else if is_inf(a) {
+        if sign {
+            0
+        } else {
+            7
+        }
+    } else if exp == 0 && is_zero(a) {
+        if sign {
+            3
+        } else {
+            4
+        }
+    } else if exp == 0 {
+        if sign {
+            2
+        } else {
+            5
+        }
+    } else if sign {
+        1
+    } else {
+        6
+    };
+    1 << bit
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use RoundingMode::*;
+
+    const ONE: u32 = 0x3f80_0000;
+    const INF: u32 = 0x7f80_0000;
+    const MAX: u32 = 0x7f7f_ffff;
+    const QNAN: u32 = 0x7fc0_0001;
+    const SNAN: u32 = 0x7f80_0001;
+    const MODES: [RoundingMode; 5] = [RNE, RTZ, RDN, RUP, RMM];
+
+    #[test]
+    fn round_pack_modes_and_ties() {
+        // 1 + 2^-24: 正好在 1.0 和 1 + 2^-23 中间, 保留的最低位是偶数
+        let tie_even = (1 << 24) | 1;
+        // 1 + 3 * 2^-24: 平局, 保留的最低位是奇数
+        let tie_odd = (1 << 24) | 3;
+        let cases = [
+            (false, tie_even, [ONE, ONE, ONE, ONE + 1, ONE + 1]),
+            (
+                false,
+                tie_odd,
+                [ONE + 2, ONE + 1, ONE + 1, ONE + 2, ONE + 2],
+            ),
+            (
+                true,
+                tie_even,
+                [
+                    SIGN | ONE,
+                    SIGN | ONE,
+                    SIGN | (ONE + 1),
+                    SIGN | ONE,
+                    SIGN | (ONE + 1),
+                ],
+            ),
+        ];
+        for (sign, m, expected) in cases {
+            for (rm, bits) in MODES.into_iter().zip(expected) {
+                assert_eq!(
+                    round_pack(sign, -24, m, false, rm),
+                    (bits, FLAG_NX),
+                    "{:?}",
+                    rm
+                );
+            }
+        }
+        // 平局之后还有非零的位: 不再是平局
+        assert_eq!(
+            round_pack(false, -25, (1 << 25) | 2, true, RNE),
+            (ONE + 1, FLAG_NX)
+        );
+        // 精确的结果不置 NX
+        for rm in MODES {
+            assert_eq!(round_pack(false, -23, 3 << 22, false, rm), (0x3fc0_0000, 0));
+        }
+    }
+
+    #[test]
+    fn tininess_is_detected_after_rounding() {
+        // (2^24 - 1) * 2^-150: 按 24 位舍入仍小于 2^-126, 是 tiny, 结果进位成最小的规格化数
+        assert_eq!(
+            round_pack(false, -150, 0xff_ffff, false, RNE),
+            (0x0080_0000, FLAG_UF | FLAG_NX)
+        );
+        // (2^25 - 1) * 2^-151: 按 24 位舍入到 2^-126, 不是 tiny, 只置 NX
+        assert_eq!(
+            round_pack(false, -151, 0x1ff_ffff, false, RNE),
+            (0x0080_0000, FLAG_NX)
+        );
+        // 精确的非规格化数不置 UF
+        assert_eq!(round_pack(false, -149, 1, false, RNE), (1, 0));
+        assert_eq!(mul(0x0080_0000, 0x3f00_0000, RNE), (0x0040_0000, 0));
+        // 不精确的非规格化数置 UF | NX
+        assert_eq!(
+            round_pack(false, -150, 3, false, RNE),
+            (2, FLAG_UF | FLAG_NX)
+        );
+        assert_eq!(
+            round_pack(false, -150, 3, false, RTZ),
+            (1, FLAG_UF | FLAG_NX)
+        );
+    }
+
+    #[test]
+    fn overflow_depends_on_rounding_mode() {
+        let positive = [INF, MAX, MAX, INF, INF];
+        let negative = [INF, MAX, INF, MAX, INF].map(|bits| SIGN | bits);
+        for (i, rm) in MODES.into_iter().enumerate() {
+            let flags = FLAG_OF | FLAG_NX;
+            assert_eq!(round_pack(false, 128, 1, false, rm), (positive[i], flags));
+            assert_eq!(round_pack(true, 128, 1, false, rm), (negative[i], flags));
+            assert_eq!(mul(MAX, 0x4000_0000, rm), (positive[i], flags), "{:?}", rm);
+        }
+    }
+
+    #[test]
+    fn nan_results_are_canonical() {
+        assert_eq!(add(QNAN, ONE, RNE), (CANONICAL_NAN, 0));
+        assert_eq!(add(SNAN, ONE, RNE), (CANONICAL_NAN, FLAG_NV));
+        assert_eq!(mul(ONE, SIGN | SNAN, RNE), (CANONICAL_NAN, FLAG_NV));
+        assert_eq!(sqrt(SIGN | ONE, RNE), (CANONICAL_NAN, FLAG_NV));
+        // 无效运算
+        assert_eq!(sub(INF, INF, RNE), (CANONICAL_NAN, FLAG_NV));
+        assert_eq!(add(INF, SIGN | INF, RNE), (CANONICAL_NAN, FLAG_NV));
+        assert_eq!(mul(0, INF, RNE), (CANONICAL_NAN, FLAG_NV));
+        assert_eq!(div(0, SIGN, RNE), (CANONICAL_NAN, FLAG_NV));
+        assert_eq!(div(INF, INF, RNE), (CANONICAL_NAN, FLAG_NV));
+        // 除以零是 DZ, 不是 NV
+        assert_eq!(div(SIGN | ONE, 0, RNE), (SIGN | INF, FLAG_DZ));
+    }
+
+    #[test]
+    fn min_max_zeros_and_nans() {
+        assert_eq!(min(0, SIGN), (SIGN, 0));
+        assert_eq!(min(SIGN, 0), (SIGN, 0));
+        assert_eq!(max(0, SIGN), (0, 0));
+        assert_eq!(max(SIGN, 0), (0, 0));
+        // 只有一个 NaN 时返回另一个数, signaling NaN 置 NV
+        assert_eq!(min(QNAN, ONE), (ONE, 0));
+        assert_eq!(max(ONE, QNAN), (ONE, 0));
+        assert_eq!(min(SNAN, ONE), (ONE, FLAG_NV));
+        assert_eq!(max(ONE, SNAN), (ONE, FLAG_NV));
+        assert_eq!(min(QNAN, QNAN), (CANONICAL_NAN, 0));
+        assert_eq!(max(SNAN, QNAN), (CANONICAL_NAN, FLAG_NV));
+    }
+
+    #[test]
+    fn convert_to_int_saturates() {
+        let nan = [QNAN, SNAN, CANONICAL_NAN];
+        for a in nan {
+            assert_eq!(to_i32(a, RNE), (0x7fff_ffff, FLAG_NV));
+            assert_eq!(to_u32(a, RNE), (0xffff_ffff, FLAG_NV));
+        }
+        assert_eq!(to_i32(INF, RNE), (0x7fff_ffff, FLAG_NV));
+        assert_eq!(to_i32(SIGN | INF, RNE), (0x8000_0000, FLAG_NV));
+        // 3e9 和 -3e9
+        assert_eq!(to_i32(0x4f32_d05e, RNE), (0x7fff_ffff, FLAG_NV));
+        assert_eq!(to_i32(0xcf32_d05e, RNE), (0x8000_0000, FLAG_NV));
+        // -2^31 正好能表示
+        assert_eq!(to_i32(0xcf00_0000, RNE), (0x8000_0000, 0));
+        // 2^31 超出范围
+        assert_eq!(to_i32(0x4f00_0000, RNE), (0x7fff_ffff, FLAG_NV));
+        // 5e9, -1.0, -0.5
+        assert_eq!(to_u32(0x4f95_02f9, RNE), (0xffff_ffff, FLAG_NV));
+        assert_eq!(to_u32(SIGN | ONE, RNE), (0, FLAG_NV));
+        assert_eq!(to_u32(0xbf00_0000, RTZ), (0, FLAG_NX));
+        assert_eq!(to_u32(0xbf00_0000, RDN), (0, FLAG_NV));
+        // 2.5
+        let cases = [(RNE, 2), (RTZ, 2), (RDN, 2), (RUP, 3), (RMM, 3)];
+        for (rm, expected) in cases {
+            assert_eq!(to_i32(0x4020_0000, rm), (expected, FLAG_NX), "{:?}", rm);
+        }
+    }
+
+    #[test]
+    fn fma_inf_times_zero_is_invalid() {
+        assert_eq!(fma(INF, 0, QNAN, RNE), (CANONICAL_NAN, FLAG_NV));
+        assert_eq!(fma(SIGN, SIGN | INF, QNAN, RNE), (CANONICAL_NAN, FLAG_NV));
+        assert_eq!(fma(INF, 0, ONE, RNE), (CANONICAL_NAN, FLAG_NV));
+        assert_eq!(fma(ONE, ONE, QNAN, RNE), (CANONICAL_NAN, 0));
+        assert_eq!(fma(INF, ONE, SIGN | INF, RNE), (CANONICAL_NAN, FLAG_NV));
+        // 只舍入一次: (1 + 2^-12)^2 - 1 = 2^-11 + 2^-24, 先舍入乘积会丢掉 2^-24
+        assert_eq!(
+            fma(0x3f80_0800, 0x3f80_0800, SIGN | ONE, RNE),
+            (0x3a00_0400, 0)
+        );
+    }
+
+    #[test]
+    fn classify_all_classes() {
+        let cases = [
+            (SIGN | INF, 0),
+            (SIGN | ONE, 1),
+            (SIGN | 1, 2),
+            (SIGN, 3),
+            (0, 4),
+            (1, 5),
+            (ONE, 6),
+            (INF, 7),
+            (SNAN, 8),
+            (QNAN, 9),
+        ];
+        for (a, bit) in cases {
+            assert_eq!(classify(a), 1 << bit, "{:#x}", a);
+        }
+    }
+
+    #[test]
+    fn matches_host_arithmetic_on_rne() {
+        use rand::{rngs::StdRng, Rng, SeedableRng};
+
+        let mut rng = StdRng::seed_from_u64(0x5eed);
+        for _ in 0..100_000 {
+            let (a, b): (u32, u32) = (rng.gen(), rng.gen());
+            let (x, y) = (f32::from_bits(a), f32::from_bits(b));
+            let cases = [
+                (add(a, b, RNE).0, x + y),
+                (sub(a, b, RNE).0, x - y),
+                (mul(a, b, RNE).0, x * y),
+                (div(a, b, RNE).0, x / y),
+                (sqrt(a, RNE).0, x.sqrt()),
+                (fma(a, b, a, RNE).0, x.mul_add(y, x)),
+            ];
+            for (i, (ours, host)) in cases.into_iter().enumerate() {
+                if host.is_nan() {
+                    assert_eq!(ours, CANONICAL_NAN, "op {} {:#x} {:#x}", i, a, b);
+                } else {
+                    assert_eq!(ours, host.to_bits(), "op {} {:#x} {:#x}", i, a, b);
+                }
+            }
+        }
+    }
+}
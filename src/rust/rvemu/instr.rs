@@ -6,6 +6,7 @@ pub type Reg = u32;
 pub type CSR = u32;
 
 #[allow(clippy::upper_case_acronyms)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Instr {
     // u
     LUI(Reg /* rd */, u32),
@@ -58,15 +59,389 @@ pub enum Instr {
     SRA(Reg, Reg, Reg),
     OR(Reg, Reg, Reg),
     AND(Reg, Reg, Reg),
+    // m
+    MUL(Reg /* rd */, Reg /* rs1 */, Reg /* rs2 */),
+    MULH(Reg, Reg, Reg),
+    MULHSU(Reg, Reg, Reg),
+    MULHU(Reg, Reg, Reg),
+    DIV(Reg, Reg, Reg),
+    DIVU(Reg, Reg, Reg),
+    REM(Reg, Reg, Reg),
+    REMU(Reg, Reg, Reg),
+    // zba
+    SH1ADD(Reg /* rd */, Reg /* rs1 */, Reg /* rs2 */),
+    SH2ADD(Reg, Reg, Reg),
+    SH3ADD(Reg, Reg, Reg),
+    // zbb
+    ANDN(Reg /* rd */, Reg /* rs1 */, Reg /* rs2 */),
+    ORN(Reg, Reg, Reg),
+    XNOR(Reg, Reg, Reg),
+    MAX(Reg, Reg, Reg),
+    MAXU(Reg, Reg, Reg),
+    MIN(Reg, Reg, Reg),
+    MINU(Reg, Reg, Reg),
+    ROL(Reg, Reg, Reg),
+    ROR(Reg, Reg, Reg),
+    RORI(Reg /* rd */, Reg /* rs1 */, i32 /* shamt */),
+    CLZ(Reg /* rd */, Reg /* rs1 */),
+    CTZ(Reg, Reg),
+    CPOP(Reg, Reg),
+    SEXTB(Reg, Reg),
+    SEXTH(Reg, Reg),
+    ZEXTH(Reg, Reg),
+    ORCB(Reg, Reg),
+    REV8(Reg, Reg),
+    // zbs
+    BCLR(Reg /* rd */, Reg /* rs1 */, Reg /* rs2 */),
+    BEXT(Reg, Reg, Reg),
+    BINV(Reg, Reg, Reg),
+    BSET(Reg, Reg, Reg),
+    BCLRI(Reg /* rd */, Reg /* rs1 */, i32 /* shamt */),
+    BEXTI(Reg, Reg, i32),
+    BINVI(Reg, Reg, i32),
+    BSETI(Reg, Reg, i32),
+    // a
+    LRW(Reg /* rd */, Reg /* rs1 */, u32 /* aq|rl */),
+    SCW(
+        Reg, /* rd */
+        Reg, /* rs2 */
+        Reg, /* rs1 */
+        u32, /* aq|rl */
+    ),
+    AMOSWAPW(Reg, Reg, Reg, u32),
+    AMOADDW(Reg, Reg, Reg, u32),
+    AMOXORW(Reg, Reg, Reg, u32),
+    AMOANDW(Reg, Reg, Reg, u32),
+    AMOORW(Reg, Reg, Reg, u32),
+    AMOMINW(Reg, Reg, Reg, u32),
+    AMOMAXW(Reg, Reg, Reg, u32),
+    AMOMINUW(Reg, Reg, Reg, u32),
+    AMOMAXUW(Reg, Reg, Reg, u32),
+    // f
+    FLW(Reg /* rd */, i32 /* offset */, Reg /* rs1 */),
+    FSW(
+        Reg, /* rs2 */
+        i32, /* offset */
+        Reg, /* rs1 */
+    ),
+    FMADDS(
+        Reg, /* rd */
+        Reg, /* rs1 */
+        Reg, /* rs2 */
+        Reg, /* rs3 */
+        u32, /* rm */
+    ),
+    FMSUBS(Reg, Reg, Reg, Reg, u32),
+    FNMSUBS(Reg, Reg, Reg, Reg, u32),
+    FNMADDS(Reg, Reg, Reg, Reg, u32),
+    FADDS(
+        Reg, /* rd */
+        Reg, /* rs1 */
+        Reg, /* rs2 */
+        u32, /* rm */
+    ),
+    FSUBS(Reg, Reg, Reg, u32),
+    FMULS(Reg, Reg, Reg, u32),
+    FDIVS(Reg, Reg, Reg, u32),
+    FSQRTS(Reg /* rd */, Reg /* rs1 */, u32 /* rm */),
+    FSGNJS(Reg /* rd */, Reg /* rs1 */, Reg /* rs2 */),
+    FSGNJNS(Reg, Reg, Reg),
+    FSGNJXS(Reg, Reg, Reg),
+    FMINS(Reg, Reg, Reg),
+    FMAXS(Reg, Reg, Reg),
+    /// 浮点数转换为整数, rd 是整数寄存器
+    FCVTWS(Reg /* rd */, Reg /* rs1 */, u32 /* rm */),
+    FCVTWUS(Reg, Reg, u32),
+    FMVXW(Reg /* rd */, Reg /* rs1 */),
+    /// 整数转换为浮点数, rs1 是整数寄存器
+    FCVTSW(Reg /* rd */, Reg /* rs1 */, u32 /* rm */),
+    FCVTSWU(Reg, Reg, u32),
+    FMVWX(Reg /* rd */, Reg /* rs1 */),
+    /// 比较的结果写到整数寄存器 rd
+    FEQS(Reg /* rd */, Reg /* rs1 */, Reg /* rs2 */),
+    FLTS(Reg, Reg, Reg),
+    FLES(Reg, Reg, Reg),
+    FCLASSS(Reg /* rd */, Reg /* rs1 */),
+    // fence
+    FENCE(u32 /* pred */, u32 /* succ */),
+    FENCEI,
+    SFENCEVMA(Reg /* rs1 */, Reg /* rs2 */),
+    // system
+    EBREAK,
+    WFI,
     // zicsr
     ECALL,
-    ERET,
+    /// 实验的 trap_handle 用 uret 返回, 行为与 mret 相同
+    URET,
+    SRET,
+    MRET,
     CSRRW(Reg /* rd */, CSR, Reg /* rs1 */),
     CSRRS(Reg, CSR, Reg),
     CSRRC(Reg, CSR, Reg),
     CSRRWI(Reg /* rd */, CSR, u32 /* zimm */),
     CSRRSI(Reg, CSR, u32),
     CSRRCI(Reg, CSR, u32),
+    // c
+    /// 16 位压缩指令, 保存助记符和展开后的 32 位指令
+    C(&'static str, Box<Instr>),
+}
+
+/// 可以在运行时开关的扩展
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Extension {
+    Zba,
+    Zbb,
+    Zbs,
+    F,
+}
+
+impl Instr {
+    /// 指令所属的可选扩展, 基础指令集返回 `None`
+    pub fn extension(&self) -> Option<Extension> {
+        match self {
+            Instr::SH1ADD(..) | Instr::SH2ADD(..) | Instr::SH3ADD(..) => Some(Extension::Zba),
+            Instr::ANDN(..)
+            | Instr::ORN(..)
+            | Instr::XNOR(..)
+            | Instr::MAX(..)
+            | Instr::MAXU(..)
+            | Instr::MIN(..)
+            | Instr::MINU(..)
+            | Instr::ROL(..)
+            | Instr::ROR(..)
+            | Instr::RORI(..)
+            | Instr::CLZ(..)
+            | Instr::CTZ(..)
+            | Instr::CPOP(..)
+            | Instr::SEXTB(..)
+            | Instr::SEXTH(..)
+            | Instr::ZEXTH(..)
+            | Instr::ORCB(..)
+            | Instr::REV8(..) => Some(Extension::Zbb),
+            Instr::BCLR(..)
+            | Instr::BEXT(..)
+            | Instr::BINV(..)
+            | Instr::BSET(..)
+            | Instr::BCLRI(..)
+            | Instr::BEXTI(..)
+            | Instr::BINVI(..)
+            | Instr::BSETI(..) => Some(Extension::Zbs),
+            Instr::FLW(..)
+            | Instr::FSW(..)
+            | Instr::FMADDS(..)
+            | Instr::FMSUBS(..)
+            | Instr::FNMSUBS(..)
+            | Instr::FNMADDS(..)
+            | Instr::FADDS(..)
+            | Instr::FSUBS(..)
+            | Instr::FMULS(..)
+            | Instr::FDIVS(..)
+            | Instr::FSQRTS(..)
+            | Instr::FSGNJS(..)
+            | Instr::FSGNJNS(..)
+            | Instr::FSGNJXS(..)
+            | Instr::FMINS(..)
+            | Instr::FMAXS(..)
+            | Instr::FCVTWS(..)
+            | Instr::FCVTWUS(..)
+            | Instr::FMVXW(..)
+            | Instr::FCVTSW(..)
+            | Instr::FCVTSWU(..)
+            | Instr::FMVWX(..)
+            | Instr::FEQS(..)
+            | Instr::FLTS(..)
+            | Instr::FLES(..)
+            | Instr::FCLASSS(..) => Some(Extension::F),
+            Instr::C(_, expanded) => expanded.extension(),
+            _ => None,
+        }
+    }
+
+    /// 指令引用的整数寄存器
+    pub fn regs(&self) -> Vec<Reg> {
+        match self {
+            Instr::LUI(rd, _) | Instr::AUIPC(rd, _) | Instr::JAL(rd, _) => vec![*rd],
+            Instr::BEQ(rs1, rs2, _)
+            | Instr::BNE(rs1, rs2, _)
+            | Instr::BLT(rs1, rs2, _)
+            | Instr::BGE(rs1, rs2, _)
+            | Instr::BLTU(rs1, rs2, _)
+            | Instr::BGEU(rs1, rs2, _) => vec![*rs1, *rs2],
+            Instr::SB(rs2, _, rs1) | Instr::SH(rs2, _, rs1) | Instr::SW(rs2, _, rs1) => {
+                vec![*rs2, *rs1]
+            }
+            Instr::ADDI(rd, rs1, _)
+            | Instr::ANDI(rd, rs1, _)
+            | Instr::ORI(rd, rs1, _)
+            | Instr::XORI(rd, rs1, _)
+            | Instr::SLLI(rd, rs1, _)
+            | Instr::SRLI(rd, rs1, _)
+            | Instr::SRAI(rd, rs1, _)
+            | Instr::SLTI(rd, rs1, _)
+            | Instr::SLTIU(rd, rs1, _)
+            | Instr::LB(rd, _, rs1)
+            | Instr::LH(rd, _, rs1)
+            | Instr::LW(rd, _, rs1)
+            | Instr::LBU(rd, _, rs1)
+            | Instr::LHU(rd, _, rs1)
+            | Instr::JALR(rd, _, rs1) => vec![*rd, *rs1],
+            Instr::ADD(rd, rs1, rs2)
+            | Instr::SUB(rd, rs1, rs2)
+            | Instr::SLL(rd, rs1, rs2)
+            | Instr::SLT(rd, rs1, rs2)
+            | Instr::SLTU(rd, rs1, rs2)
+            | Instr::XOR(rd, rs1, rs2)
+            | Instr::SRL(rd, rs1, rs2)
+            | Instr::SRA(rd, rs1, rs2)
+            | Instr::OR(rd, rs1, rs2)
+            | Instr::AND(rd, rs1, rs2)
+            | Instr::MUL(rd, rs1, rs2)
+            | Instr::MULH(rd, rs1, rs2)
+            | Instr::MULHSU(rd, rs1, rs2)
+            | Instr::MULHU(rd, rs1, rs2)
+            | Instr::DIV(rd, rs1, rs2)
+            | Instr::DIVU(rd, rs1, rs2)
+            | Instr::REM(rd, rs1, rs2)
+            | Instr::REMU(rd, rs1, rs2)
+            | Instr::SH1ADD(rd, rs1, rs2)
+            | Instr::SH2ADD(rd, rs1, rs2)
+            | Instr::SH3ADD(rd, rs1, rs2)
+            | Instr::ANDN(rd, rs1, rs2)
+            | Instr::ORN(rd, rs1, rs2)
+            | Instr::XNOR(rd, rs1, rs2)
+            | Instr::MAX(rd, rs1, rs2)
+            | Instr::MAXU(rd, rs1, rs2)
+            | Instr::MIN(rd, rs1, rs2)
+            | Instr::MINU(rd, rs1, rs2)
+            | Instr::ROL(rd, rs1, rs2)
+            | Instr::ROR(rd, rs1, rs2)
+            | Instr::BCLR(rd, rs1, rs2)
+            | Instr::BEXT(rd, rs1, rs2)
+            | Instr::BINV(rd, rs1, rs2)
+            | Instr::BSET(rd, rs1, rs2) => vec![*rd, *rs1, *rs2],
+            Instr::RORI(rd, rs1, _)
+            | Instr::BCLRI(rd, rs1, _)
+            | Instr::BEXTI(rd, rs1, _)
+            | Instr::BINVI(rd, rs1, _)
+            | Instr::BSETI(rd, rs1, _) => vec![*rd, *rs1],
+            Instr::CLZ(rd, rs1)
+            | Instr::CTZ(rd, rs1)
+            | Instr::CPOP(rd, rs1)
+            | Instr::SEXTB(rd, rs1)
+            | Instr::SEXTH(rd, rs1)
+            | Instr::ZEXTH(rd, rs1)
+            | Instr::ORCB(rd, rs1)
+            | Instr::REV8(rd, rs1) => vec![*rd, *rs1],
+            Instr::LRW(rd, rs1, _) => vec![*rd, *rs1],
+            Instr::SCW(rd, rs2, rs1, _)
+            | Instr::AMOSWAPW(rd, rs2, rs1, _)
+            | Instr::AMOADDW(rd, rs2, rs1, _)
+            | Instr::AMOXORW(rd, rs2, rs1, _)
+            | Instr::AMOANDW(rd, rs2, rs1, _)
+            | Instr::AMOORW(rd, rs2, rs1, _)
+            | Instr::AMOMINW(rd, rs2, rs1, _)
+            | Instr::AMOMAXW(rd, rs2, rs1, _)
+            | Instr::AMOMINUW(rd, rs2, rs1, _)
+            | Instr::AMOMAXUW(rd, rs2, rs1, _) => vec![*rd, *rs2, *rs1],
+            Instr::FLW(_, _, rs1) | Instr::FSW(_, _, rs1) => vec![*rs1],
+            Instr::FMADDS(..)
+            | Instr::FMSUBS(..)
+            | Instr::FNMSUBS(..)
+            | Instr::FNMADDS(..)
+            | Instr::FADDS(..)
+            | Instr::FSUBS(..)
+            | Instr::FMULS(..)
+            | Instr::FDIVS(..)
+            | Instr::FSQRTS(..)
+            | Instr::FSGNJS(..)
+            | Instr::FSGNJNS(..)
+            | Instr::FSGNJXS(..)
+            | Instr::FMINS(..)
+            | Instr::FMAXS(..) => vec![],
+            Instr::FCVTWS(rd, _, _)
+            | Instr::FCVTWUS(rd, _, _)
+            | Instr::FMVXW(rd, _)
+            | Instr::FEQS(rd, _, _)
+            | Instr::FLTS(rd, _, _)
+            | Instr::FLES(rd, _, _)
+            | Instr::FCLASSS(rd, _) => vec![*rd],
+            Instr::FCVTSW(_, rs1, _) | Instr::FCVTSWU(_, rs1, _) | Instr::FMVWX(_, rs1) => {
+                vec![*rs1]
+            }
+            Instr::FENCE(_, _) | Instr::FENCEI | Instr::EBREAK | Instr::WFI => vec![],
+            Instr::SFENCEVMA(rs1, rs2) => vec![*rs1, *rs2],
+            Instr::ECALL | Instr::URET | Instr::SRET | Instr::MRET => vec![],
+            Instr::CSRRW(rd, _, rs1) | Instr::CSRRS(rd, _, rs1) | Instr::CSRRC(rd, _, rs1) => {
+                vec![*rd, *rs1]
+            }
+            Instr::CSRRWI(rd, _, _) | Instr::CSRRSI(rd, _, _) | Instr::CSRRCI(rd, _, _) => {
+                vec![*rd]
+            }
+            Instr::C(_, expanded) => expanded.regs(),
+        }
+    }
+
+    /// rd 是否是浮点寄存器
+    pub fn writes_freg(&self) -> bool {
+        match self {
+            Instr::FLW(..)
+            | Instr::FMADDS(..)
+            | Instr::FMSUBS(..)
+            | Instr::FNMSUBS(..)
+            | Instr::FNMADDS(..)
+            | Instr::FADDS(..)
+            | Instr::FSUBS(..)
+            | Instr::FMULS(..)
+            | Instr::FDIVS(..)
+            | Instr::FSQRTS(..)
+            | Instr::FSGNJS(..)
+            | Instr::FSGNJNS(..)
+            | Instr::FSGNJXS(..)
+            | Instr::FMINS(..)
+            | Instr::FMAXS(..)
+            | Instr::FCVTSW(..)
+            | Instr::FCVTSWU(..)
+            | Instr::FMVWX(..) => true,
+            Instr::C(_, expanded) => expanded.writes_freg(),
+            _ => false,
+        }
+    }
+}
+
+/// 原子指令的内存序后缀
+fn aqrl_suffix(aqrl: u32) -> &'static str {
+    match aqrl & 0b11 {
+        0b00 => "",
+        0b01 => ".rl",
+        0b10 => ".aq",
+        _ => ".aqrl",
+    }
+}
+
+/// 浮点指令的舍入模式, dyn (使用 frm) 时省略
+fn rm_suffix(rm: u32) -> &'static str {
+    match rm {
+        0b000 => ", rne",
+        0b001 => ", rtz",
+        0b010 => ", rdn",
+        0b011 => ", rup",
+        0b100 => ", rmm",
+        _ => "",
+    }
+}
+
+/// fence 的 pred/succ: i(设备输入) o(设备输出) r(读) w(写)
+fn fence_set(set: u32) -> String {
+    let set: String = [(0x8, 'i'), (0x4, 'o'), (0x2, 'r'), (0x1, 'w')]
+        .iter()
+        .filter(|(bit, _)| set & bit != 0)
+        .map(|(_, c)| c)
+        .collect();
+    if set.is_empty() {
+        "0".to_string()
+    } else {
+        set
+    }
 }
 
 impl Display for Instr {
@@ -251,9 +626,448 @@ impl Display for Instr {
                 "sltu {}, {}, {}",
                 ABI[*rd as usize], ABI[*rs1 as usize], ABI[*rs2 as usize]
             ),
+            // m
+            Instr::MUL(rd, rs1, rs2) => write!(
+                f,
+                "mul {}, {}, {}",
+                ABI[*rd as usize], ABI[*rs1 as usize], ABI[*rs2 as usize]
+            ),
+            Instr::MULH(rd, rs1, rs2) => write!(
+                f,
+                "mulh {}, {}, {}",
+                ABI[*rd as usize], ABI[*rs1 as usize], ABI[*rs2 as usize]
+            ),
+            Instr::MULHSU(rd, rs1, rs2) => write!(
+                f,
+                "mulhsu {}, {}, {}",
+                ABI[*rd as usize], ABI[*rs1 as usize], ABI[*rs2 as usize]
+            ),
+            Instr::MULHU(rd, rs1, rs2) => write!(
+                f,
+                "mulhu {}, {}, {}",
+                ABI[*rd as usize], ABI[*rs1 as usize], ABI[*rs2 as usize]
+            ),
+            Instr::DIV(rd, rs1, rs2) => write!(
+                f,
+                "div {}, {}, {}",
+                ABI[*rd as usize], ABI[*rs1 as usize], ABI[*rs2 as usize]
+            ),
+            Instr::DIVU(rd, rs1, rs2) => write!(
+                f,
+                "divu {}, {}, {}",
+                ABI[*rd as usize], ABI[*rs1 as usize], ABI[*rs2 as usize]
+            ),
+            Instr::REM(rd, rs1, rs2) => write!(
+                f,
+                "rem {}, {}, {}",
+                ABI[*rd as usize], ABI[*rs1 as usize], ABI[*rs2 as usize]
+            ),
+            Instr::REMU(rd, rs1, rs2) => write!(
+                f,
+                "remu {}, {}, {}",
+                ABI[*rd as usize], ABI[*rs1 as usize], ABI[*rs2 as usize]
+            ),
+            // zba
+            Instr::SH1ADD(rd, rs1, rs2) => write!(
+                f,
+                "sh1add {}, {}, {}",
+                ABI[*rd as usize], ABI[*rs1 as usize], ABI[*rs2 as usize]
+            ),
+            Instr::SH2ADD(rd, rs1, rs2) => write!(
+                f,
+                "sh2add {}, {}, {}",
+                ABI[*rd as usize], ABI[*rs1 as usize], ABI[*rs2 as usize]
+            ),
+            Instr::SH3ADD(rd, rs1, rs2) => write!(
+                f,
+                "sh3add {}, {}, {}",
+                ABI[*rd as usize], ABI[*rs1 as usize], ABI[*rs2 as usize]
+            ),
+            // zbb
+            Instr::ANDN(rd, rs1, rs2) => write!(
+                f,
+                "andn {}, {}, {}",
+                ABI[*rd as usize], ABI[*rs1 as usize], ABI[*rs2 as usize]
+            ),
+            Instr::ORN(rd, rs1, rs2) => write!(
+                f,
+                "orn {}, {}, {}",
+                ABI[*rd as usize], ABI[*rs1 as usize], ABI[*rs2 as usize]
+            ),
+            Instr::XNOR(rd, rs1, rs2) => write!(
+                f,
+                "xnor {}, {}, {}",
+                ABI[*rd as usize], ABI[*rs1 as usize], ABI[*rs2 as usize]
+            ),
+            Instr::MAX(rd, rs1, rs2) => write!(
+                f,
+                "max {}, {}, {}",
+                ABI[*rd as usize], ABI[*rs1 as usize], ABI[*rs2 as usize]
+            ),
+            Instr::MAXU(rd, rs1, rs2) => write!(
+                f,
+                "maxu {}, {}, {}",
+                ABI[*rd as usize], ABI[*rs1 as usize], ABI[*rs2 as usize]
+            ),
+            Instr::MIN(rd, rs1, rs2) => write!(
+                f,
+                "min {}, {}, {}",
+                ABI[*rd as usize], ABI[*rs1 as usize], ABI[*rs2 as usize]
+            ),
+            Instr::MINU(rd, rs1, rs2) => write!(
+                f,
+                "minu {}, {}, {}",
+                ABI[*rd as usize], ABI[*rs1 as usize], ABI[*rs2 as usize]
+            ),
+            Instr::ROL(rd, rs1, rs2) => write!(
+                f,
+                "rol {}, {}, {}",
+                ABI[*rd as usize], ABI[*rs1 as usize], ABI[*rs2 as usize]
+            ),
+            Instr::ROR(rd, rs1, rs2) => write!(
+                f,
+                "ror {}, {}, {}",
+                ABI[*rd as usize], ABI[*rs1 as usize], ABI[*rs2 as usize]
+            ),
+            Instr::RORI(rd, rs1, shamt) => write!(
+                f,
+                "rori {}, {}, {:#x}",
+                ABI[*rd as usize], ABI[*rs1 as usize], shamt
+            ),
+            Instr::CLZ(rd, rs1) => {
+                write!(f, "clz {}, {}", ABI[*rd as usize], ABI[*rs1 as usize])
+            }
+            Instr::CTZ(rd, rs1) => {
+                write!(f, "ctz {}, {}", ABI[*rd as usize], ABI[*rs1 as usize])
+            }
+            Instr::CPOP(rd, rs1) => {
+                write!(f, "cpop {}, {}", ABI[*rd as usize], ABI[*rs1 as usize])
+            }
+            Instr::SEXTB(rd, rs1) => {
+                write!(f, "sext.b {}, {}", ABI[*rd as usize], ABI[*rs1 as usize])
+            }
+            Instr::SEXTH(rd, rs1) => {
+                write!(f, "sext.h {}, {}", ABI[*rd as usize], ABI[*rs1 as usize])
+            }
+            Instr::ZEXTH(rd, rs1) => {
+                write!(f, "zext.h {}, {}", ABI[*rd as usize], ABI[*rs1 as usize])
+            }
+            Instr::ORCB(rd, rs1) => {
+                write!(f, "orc.b {}, {}", ABI[*rd as usize], ABI[*rs1 as usize])
+            }
+            Instr::REV8(rd, rs1) => {
+                write!(f, "rev8 {}, {}", ABI[*rd as usize], ABI[*rs1 as usize])
+            }
+            // zbs
+            Instr::BCLR(rd, rs1, rs2) => write!(
+                f,
+                "bclr {}, {}, {}",
+                ABI[*rd as usize], ABI[*rs1 as usize], ABI[*rs2 as usize]
+            ),
+            Instr::BEXT(rd, rs1, rs2) => write!(
+                f,
+                "bext {}, {}, {}",
+                ABI[*rd as usize], ABI[*rs1 as usize], ABI[*rs2 as usize]
+            ),
+            Instr::BINV(rd, rs1, rs2) => write!(
+                f,
+                "binv {}, {}, {}",
+                ABI[*rd as usize], ABI[*rs1 as usize], ABI[*rs2 as usize]
+            ),
+            Instr::BSET(rd, rs1, rs2) => write!(
+                f,
+                "bset {}, {}, {}",
+                ABI[*rd as usize], ABI[*rs1 as usize], ABI[*rs2 as usize]
+            ),
+            Instr::BCLRI(rd, rs1, shamt) => write!(
+                f,
+                "bclri {}, {}, {:#x}",
+                ABI[*rd as usize], ABI[*rs1 as usize], shamt
+            ),
+            Instr::BEXTI(rd, rs1, shamt) => write!(
+                f,
+                "bexti {}, {}, {:#x}",
+                ABI[*rd as usize], ABI[*rs1 as usize], shamt
+            ),
+            Instr::BINVI(rd, rs1, shamt) => write!(
+                f,
+                "binvi {}, {}, {:#x}",
+                ABI[*rd as usize], ABI[*rs1 as usize], shamt
+            ),
+            Instr::BSETI(rd, rs1, shamt) => write!(
+                f,
+                "bseti {}, {}, {:#x}",
+                ABI[*rd as usize], ABI[*rs1 as usize], shamt
+            ),
+            // a
+            Instr::LRW(rd, rs1, aqrl) => write!(
+                f,
+                "lr.w{} {}, ({})",
+                aqrl_suffix(*aqrl),
+                ABI[*rd as usize],
+                ABI[*rs1 as usize]
+            ),
+            Instr::SCW(rd, rs2, rs1, aqrl) => write!(
+                f,
+                "sc.w{} {}, {}, ({})",
+                aqrl_suffix(*aqrl),
+                ABI[*rd as usize],
+                ABI[*rs2 as usize],
+                ABI[*rs1 as usize]
+            ),
+            Instr::AMOSWAPW(rd, rs2, rs1, aqrl) => write!(
+                f,
+                "amoswap.w{} {}, {}, ({})",
+                aqrl_suffix(*aqrl),
+                ABI[*rd as usize],
+                ABI[*rs2 as usize],
+                ABI[*rs1 as usize]
+            ),
+            Instr::AMOADDW(rd, rs2, rs1, aqrl) => write!(
+                f,
+                "amoadd.w{} {}, {}, ({})",
+                aqrl_suffix(*aqrl),
+                ABI[*rd as usize],
+                ABI[*rs2 as usize],
+                ABI[*rs1 as usize]
+            ),
+            Instr::AMOXORW(rd, rs2, rs1, aqrl) => write!(
+                f,
+                "amoxor.w{} {}, {}, ({})",
+                aqrl_suffix(*aqrl),
+                ABI[*rd as usize],
+                ABI[*rs2 as usize],
+                ABI[*rs1 as usize]
+            ),
+            Instr::AMOANDW(rd, rs2, rs1, aqrl) => write!(
+                f,
+                "amoand.w{} {}, {}, ({})",
+                aqrl_suffix(*aqrl),
+                ABI[*rd as usize],
+                ABI[*rs2 as usize],
+                ABI[*rs1 as usize]
+            ),
+            Instr::AMOORW(rd, rs2, rs1, aqrl) => write!(
+                f,
+                "amoor.w{} {}, {}, ({})",
+                aqrl_suffix(*aqrl),
+                ABI[*rd as usize],
+                ABI[*rs2 as usize],
+                ABI[*rs1 as usize]
+            ),
+            Instr::AMOMINW(rd, rs2, rs1, aqrl) => write!(
+                f,
+                "amomin.w{} {}, {}, ({})",
+                aqrl_suffix(*aqrl),
+                ABI[*rd as usize],
+                ABI[*rs2 as usize],
+                ABI[*rs1 as usize]
+            ),
+            Instr::AMOMAXW(rd, rs2, rs1, aqrl) => write!(
+                f,
+                "amomax.w{} {}, {}, ({})",
+                aqrl_suffix(*aqrl),
+                ABI[*rd as usize],
+                ABI[*rs2 as usize],
+                ABI[*rs1 as usize]
+            ),
+            Instr::AMOMINUW(rd, rs2, rs1, aqrl) => write!(
+                f,
+                "amominu.w{} {}, {}, ({})",
+                aqrl_suffix(*aqrl),
+                ABI[*rd as usize],
+                ABI[*rs2 as usize],
+                ABI[*rs1 as usize]
+            ),
+            Instr::AMOMAXUW(rd, rs2, rs1, aqrl) => write!(
+                f,
+                "amomaxu.w{} {}, {}, ({})",
+                aqrl_suffix(*aqrl),
+                ABI[*rd as usize],
+                ABI[*rs2 as usize],
+                ABI[*rs1 as usize]
+            ),
+            // f
+            Instr::FLW(rd, offset, base) => write!(
+                f,
+                "flw {}, {:#x}({})",
+                FABI[*rd as usize], offset, ABI[*base as usize]
+            ),
+            Instr::FSW(rs2, offset, base) => write!(
+                f,
+                "fsw {}, {:#x}({})",
+                FABI[*rs2 as usize], offset, ABI[*base as usize]
+            ),
+            Instr::FMADDS(rd, rs1, rs2, rs3, rm) => write!(
+                f,
+                "fmadd.s {}, {}, {}, {}{}",
+                FABI[*rd as usize],
+                FABI[*rs1 as usize],
+                FABI[*rs2 as usize],
+                FABI[*rs3 as usize],
+                rm_suffix(*rm)
+            ),
+            Instr::FMSUBS(rd, rs1, rs2, rs3, rm) => write!(
+                f,
+                "fmsub.s {}, {}, {}, {}{}",
+                FABI[*rd as usize],
+                FABI[*rs1 as usize],
+                FABI[*rs2 as usize],
+                FABI[*rs3 as usize],
+                rm_suffix(*rm)
+            ),
+            Instr::FNMSUBS(rd, rs1, rs2, rs3, rm) => write!(
+                f,
+                "fnmsub.s {}, {}, {}, {}{}",
+                FABI[*rd as usize],
+                FABI[*rs1 as usize],
+                FABI[*rs2 as usize],
+                FABI[*rs3 as usize],
+                rm_suffix(*rm)
+            ),
+            Instr::FNMADDS(rd, rs1, rs2, rs3, rm) => write!(
+                f,
+                "fnmadd.s {}, {}, {}, {}{}",
+                FABI[*rd as usize],
+                FABI[*rs1 as usize],
+                FABI[*rs2 as usize],
+                FABI[*rs3 as usize],
+                rm_suffix(*rm)
+            ),
+            Instr::FADDS(rd, rs1, rs2, rm) => write!(
+                f,
+                "fadd.s {}, {}, {}{}",
+                FABI[*rd as usize],
+                FABI[*rs1 as usize],
+                FABI[*rs2 as usize],
+                rm_suffix(*rm)
+            ),
+            Instr::FSUBS(rd, rs1, rs2, rm) => write!(
+                f,
+                "fsub.s {}, {}, {}{}",
+                FABI[*rd as usize],
+                FABI[*rs1 as usize],
+                FABI[*rs2 as usize],
+                rm_suffix(*rm)
+            ),
+            Instr::FMULS(rd, rs1, rs2, rm) => write!(
+                f,
+                "fmul.s {}, {}, {}{}",
+                FABI[*rd as usize],
+                FABI[*rs1 as usize],
+                FABI[*rs2 as usize],
+                rm_suffix(*rm)
+            ),
+            Instr::FDIVS(rd, rs1, rs2, rm) => write!(
+                f,
+                "fdiv.s {}, {}, {}{}",
+                FABI[*rd as usize],
+                FABI[*rs1 as usize],
+                FABI[*rs2 as usize],
+                rm_suffix(*rm)
+            ),
+            Instr::FSQRTS(rd, rs1, rm) => write!(
+                f,
+                "fsqrt.s {}, {}{}",
+                FABI[*rd as usize],
+                FABI[*rs1 as usize],
+                rm_suffix(*rm)
+            ),
+            Instr::FSGNJS(rd, rs1, rs2) => write!(
+                f,
+                "fsgnj.s {}, {}, {}",
+                FABI[*rd as usize], FABI[*rs1 as usize], FABI[*rs2 as usize]
+            ),
+            Instr::FSGNJNS(rd, rs1, rs2) => write!(
+                f,
+                "fsgnjn.s {}, {}, {}",
+                FABI[*rd as usize], FABI[*rs1 as usize], FABI[*rs2 as usize]
+            ),
+            Instr::FSGNJXS(rd, rs1, rs2) => write!(
+                f,
+                "fsgnjx.s {}, {}, {}",
+                FABI[*rd as usize], FABI[*rs1 as usize], FABI[*rs2 as usize]
+            ),
+            Instr::FMINS(rd, rs1, rs2) => write!(
+                f,
+                "fmin.s {}, {}, {}",
+                FABI[*rd as usize], FABI[*rs1 as usize], FABI[*rs2 as usize]
+            ),
+            Instr::FMAXS(rd, rs1, rs2) => write!(
+                f,
+                "fmax.s {}, {}, {}",
+                FABI[*rd as usize], FABI[*rs1 as usize], FABI[*rs2 as usize]
+            ),
+            Instr::FCVTWS(rd, rs1, rm) => write!(
+                f,
+                "fcvt.w.s {}, {}{}",
+                ABI[*rd as usize],
+                FABI[*rs1 as usize],
+                rm_suffix(*rm)
+            ),
+            Instr::FCVTWUS(rd, rs1, rm) => write!(
+                f,
+                "fcvt.wu.s {}, {}{}",
+                ABI[*rd as usize],
+                FABI[*rs1 as usize],
+                rm_suffix(*rm)
+            ),
+            Instr::FMVXW(rd, rs1) => {
+                write!(f, "fmv.x.w {}, {}", ABI[*rd as usize], FABI[*rs1 as usize])
+            }
+            Instr::FCVTSW(rd, rs1, rm) => write!(
+                f,
+                "fcvt.s.w {}, {}{}",
+                FABI[*rd as usize],
+                ABI[*rs1 as usize],
+                rm_suffix(*rm)
+            ),
+            Instr::FCVTSWU(rd, rs1, rm) => write!(
+                f,
+                "fcvt.s.wu {}, {}{}",
+                FABI[*rd as usize],
+                ABI[*rs1 as usize],
+                rm_suffix(*rm)
+            ),
+            Instr::FMVWX(rd, rs1) => {
+                write!(f, "fmv.w.x {}, {}", FABI[*rd as usize], ABI[*rs1 as usize])
+            }
+            Instr::FEQS(rd, rs1, rs2) => write!(
+                f,
+                "feq.s {}, {}, {}",
+                ABI[*rd as usize], FABI[*rs1 as usize], FABI[*rs2 as usize]
+            ),
+            Instr::FLTS(rd, rs1, rs2) => write!(
+                f,
+                "flt.s {}, {}, {}",
+                ABI[*rd as usize], FABI[*rs1 as usize], FABI[*rs2 as usize]
+            ),
+            Instr::FLES(rd, rs1, rs2) => write!(
+                f,
+                "fle.s {}, {}, {}",
+                ABI[*rd as usize], FABI[*rs1 as usize], FABI[*rs2 as usize]
+            ),
+            Instr::FCLASSS(rd, rs1) => {
+                write!(f, "fclass.s {}, {}", ABI[*rd as usize], FABI[*rs1 as usize])
+            }
+            // fence
+            Instr::FENCE(pred, succ) => {
+                write!(f, "fence {}, {}", fence_set(*pred), fence_set(*succ))
+            }
+            Instr::FENCEI => write!(f, "fence.i"),
+            // system
+            Instr::EBREAK => write!(f, "ebreak"),
+            Instr::WFI => write!(f, "wfi"),
+            Instr::SFENCEVMA(rs1, rs2) => write!(
+                f,
+                "sfence.vma {}, {}",
+                ABI[*rs1 as usize], ABI[*rs2 as usize]
+            ),
             // zicsr
             Instr::ECALL => write!(f, "ecall"),
-            Instr::ERET => write!(f, "eret"),
+            Instr::URET => write!(f, "uret"),
+            Instr::SRET => write!(f, "sret"),
+            Instr::MRET => write!(f, "mret"),
             Instr::CSRRW(rd, csr, rs1) => write!(
                 f,
                 "csrrw {}, {}, {}",
@@ -296,6 +1110,8 @@ impl Display for Instr {
                 csr_abi(csr),
                 zimm
             ),
+            // c
+            Instr::C(mnemonic, expanded) => rvc::fmt_compressed(f, mnemonic, expanded),
         }
     }
 }
@@ -304,6 +1120,9 @@ impl TryFrom<u32> for Instr {
     type Error = anyhow::Error;
 
     fn try_from(value: u32) -> std::result::Result<Self, Self::Error> {
+        if rvc::is_compressed(value) {
+            return rvc::decompress(value).with_context(|| context!());
+        }
         let opcode = value & 0x0000007f;
         let rd = (value & 0x00000f80) >> 7;
         let rs1 = (value & 0x000f8000) >> 15;
@@ -318,34 +1137,45 @@ impl TryFrom<u32> for Instr {
                 // "The shift amount is encoded in the lower 6 bits of the I-immediate field for RV64I."
                 match funct3 {
                     0x0 => Ok(Self::ADDI(rd, rs1, imm)),
-                    0x1 => Ok(Self::SLLI(rd, rs1, imm)),
+                    0x1 => {
+                        let shamt = imm & 0x3f;
+                        match (funct7, rs2) {
+                            (0x00, _) => Ok(Self::SLLI(rd, rs1, imm)),
+                            // zbb
+                            (0x30, 0x00) => Ok(Self::CLZ(rd, rs1)),
+                            (0x30, 0x01) => Ok(Self::CTZ(rd, rs1)),
+                            (0x30, 0x02) => Ok(Self::CPOP(rd, rs1)),
+                            (0x30, 0x04) => Ok(Self::SEXTB(rd, rs1)),
+                            (0x30, 0x05) => Ok(Self::SEXTH(rd, rs1)),
+                            // zbs
+                            (0x14, _) => Ok(Self::BSETI(rd, rs1, shamt)),
+                            (0x24, _) => Ok(Self::BCLRI(rd, rs1, shamt)),
+                            (0x34, _) => Ok(Self::BINVI(rd, rs1, shamt)),
+                            _ => Err(DecodeError::unimplemented(value)).with_context(|| context!()),
+                        }
+                    }
                     0x2 => Ok(Self::SLTI(rd, rs1, imm)),
                     0x3 => Ok(Self::SLTIU(rd, rs1, imm)),
                     0x4 => Ok(Self::XORI(rd, rs1, imm)),
                     0x5 => {
                         let shamt = imm & 0x3f;
-                        match funct7 >> 1 {
+                        match (funct7, rs2) {
                             // srli
-                            0x00 => Ok(Self::SRLI(rd, rs1, shamt)),
+                            (0x00, _) => Ok(Self::SRLI(rd, rs1, shamt)),
                             // srai
-                            0x10 => Ok(Self::SRAI(rd, rs1, shamt)),
-                            _ => Err(anyhow!(
-                                "not implemented yet: opcode {:#x} funct3 {:#x} funct7 {:#x}",
-                                opcode,
-                                funct3,
-                                funct7
-                            ))
-                            .with_context(|| context!()),
+                            (0x20, _) => Ok(Self::SRAI(rd, rs1, shamt)),
+                            // zbb
+                            (0x30, _) => Ok(Self::RORI(rd, rs1, shamt)),
+                            (0x14, 0x07) => Ok(Self::ORCB(rd, rs1)),
+                            (0x34, 0x18) => Ok(Self::REV8(rd, rs1)),
+                            // zbs
+                            (0x24, _) => Ok(Self::BEXTI(rd, rs1, shamt)),
+                            _ => Err(DecodeError::unimplemented(value)).with_context(|| context!()),
                         }
                     }
                     0x6 => Ok(Self::ORI(rd, rs1, imm)),
                     0x7 => Ok(Self::ANDI(rd, rs1, imm)),
-                    _ => Err(anyhow!(
-                        "not implemented yet: opcode {:#x} funct3 {:#x}",
-                        opcode,
-                        funct3
-                    ))
-                    .with_context(|| context!()),
+                    _ => Err(DecodeError::unimplemented(value)).with_context(|| context!()),
                 }
             }
             0x03 => {
@@ -357,12 +1187,7 @@ impl TryFrom<u32> for Instr {
                     0x2 => Ok(Self::LW(rd, offset, rs1)),
                     0x4 => Ok(Self::LBU(rd, offset, rs1)),
                     0x5 => Ok(Self::LHU(rd, offset, rs1)),
-                    _ => Err(anyhow!(
-                        "not implemented yet: opcode {:#x} funct3 {:#x}",
-                        opcode,
-                        funct3
-                    ))
-                    .with_context(|| context!()),
+                    _ => Err(DecodeError::unimplemented(value)).with_context(|| context!()),
                 }
             }
             0x67 => {
@@ -403,12 +1228,7 @@ impl TryFrom<u32> for Instr {
                     0x5 => Ok(Self::BGE(rs1, rs2, imm)),
                     0x6 => Ok(Self::BLTU(rs1, rs2, imm)),
                     0x7 => Ok(Self::BGEU(rs1, rs2, imm)),
-                    _ => Err(anyhow!(
-                        "not implemented yet: opcode {:#x} funct3 {:#x}",
-                        opcode,
-                        funct3
-                    ))
-                    .with_context(|| context!()),
+                    _ => Err(DecodeError::unimplemented(value)).with_context(|| context!()),
                 }
             }
             // s
@@ -420,12 +1240,7 @@ impl TryFrom<u32> for Instr {
                     0x0 => Ok(Self::SB(rs2, offset, rs1)), // sb
                     0x1 => Ok(Self::SH(rs2, offset, rs1)), // sh
                     0x2 => Ok(Self::SW(rs2, offset, rs1)), // sw
-                    _ => Err(anyhow!(
-                        "not implemented yet: opcode {:#x} funct3 {:#x}",
-                        opcode,
-                        funct3
-                    ))
-                    .with_context(|| context!()),
+                    _ => Err(DecodeError::unimplemented(value)).with_context(|| context!()),
                 }
             }
             // r
@@ -440,50 +1255,136 @@ impl TryFrom<u32> for Instr {
                 (0x5, 0x20) => Ok(Self::SRA(rd, rs1, rs2)),
                 (0x6, 0x00) => Ok(Self::OR(rd, rs1, rs2)),
                 (0x7, 0x00) => Ok(Self::AND(rd, rs1, rs2)),
-                _ => Err(anyhow!(
-                    "not implemented yet: opcode {:#x} funct3 {:#x} funct7 {:#x}",
-                    opcode,
-                    funct3,
-                    funct7
-                ))
-                .with_context(|| context!()),
+                // m
+                (0x0, 0x01) => Ok(Self::MUL(rd, rs1, rs2)),
+                (0x1, 0x01) => Ok(Self::MULH(rd, rs1, rs2)),
+                (0x2, 0x01) => Ok(Self::MULHSU(rd, rs1, rs2)),
+                (0x3, 0x01) => Ok(Self::MULHU(rd, rs1, rs2)),
+                (0x4, 0x01) => Ok(Self::DIV(rd, rs1, rs2)),
+                (0x5, 0x01) => Ok(Self::DIVU(rd, rs1, rs2)),
+                (0x6, 0x01) => Ok(Self::REM(rd, rs1, rs2)),
+                (0x7, 0x01) => Ok(Self::REMU(rd, rs1, rs2)),
+                // zba
+                (0x2, 0x10) => Ok(Self::SH1ADD(rd, rs1, rs2)),
+                (0x4, 0x10) => Ok(Self::SH2ADD(rd, rs1, rs2)),
+                (0x6, 0x10) => Ok(Self::SH3ADD(rd, rs1, rs2)),
+                // zbb
+                (0x7, 0x20) => Ok(Self::ANDN(rd, rs1, rs2)),
+                (0x6, 0x20) => Ok(Self::ORN(rd, rs1, rs2)),
+                (0x4, 0x20) => Ok(Self::XNOR(rd, rs1, rs2)),
+                (0x6, 0x05) => Ok(Self::MAX(rd, rs1, rs2)),
+                (0x7, 0x05) => Ok(Self::MAXU(rd, rs1, rs2)),
+                (0x4, 0x05) => Ok(Self::MIN(rd, rs1, rs2)),
+                (0x5, 0x05) => Ok(Self::MINU(rd, rs1, rs2)),
+                (0x1, 0x30) => Ok(Self::ROL(rd, rs1, rs2)),
+                (0x5, 0x30) => Ok(Self::ROR(rd, rs1, rs2)),
+                (0x4, 0x04) if rs2 == 0 => Ok(Self::ZEXTH(rd, rs1)),
+                // zbs
+                (0x1, 0x24) => Ok(Self::BCLR(rd, rs1, rs2)),
+                (0x5, 0x24) => Ok(Self::BEXT(rd, rs1, rs2)),
+                (0x1, 0x34) => Ok(Self::BINV(rd, rs1, rs2)),
+                (0x1, 0x14) => Ok(Self::BSET(rd, rs1, rs2)),
+                _ => Err(DecodeError::unimplemented(value)).with_context(|| context!()),
+            },
+            // a
+            0x2f if funct3 == 0x2 => {
+                let aqrl = funct7 & 0b11;
+                match funct7 >> 2 {
+                    0x02 if rs2 == 0 => Ok(Self::LRW(rd, rs1, aqrl)),
+                    0x03 => Ok(Self::SCW(rd, rs2, rs1, aqrl)),
+                    0x01 => Ok(Self::AMOSWAPW(rd, rs2, rs1, aqrl)),
+                    0x00 => Ok(Self::AMOADDW(rd, rs2, rs1, aqrl)),
+                    0x04 => Ok(Self::AMOXORW(rd, rs2, rs1, aqrl)),
+                    0x0c => Ok(Self::AMOANDW(rd, rs2, rs1, aqrl)),
+                    0x08 => Ok(Self::AMOORW(rd, rs2, rs1, aqrl)),
+                    0x10 => Ok(Self::AMOMINW(rd, rs2, rs1, aqrl)),
+                    0x14 => Ok(Self::AMOMAXW(rd, rs2, rs1, aqrl)),
+                    0x18 => Ok(Self::AMOMINUW(rd, rs2, rs1, aqrl)),
+                    0x1c => Ok(Self::AMOMAXUW(rd, rs2, rs1, aqrl)),
+                    _ => Err(DecodeError::unimplemented(value)).with_context(|| context!()),
+                }
+            }
+            // f
+            0x07 if funct3 == 0x2 => {
+                let offset = (value as i32) >> 20;
+                Ok(Self::FLW(rd, offset, rs1))
+            }
+            0x27 if funct3 == 0x2 => {
+                let offset = (((value & 0xfe000000) as i32 >> 20) as u32) | ((value >> 7) & 0x1f);
+                Ok(Self::FSW(rs2, offset as i32, rs1))
+            }
+            // rm = 5, 6 是保留的编码
+            0x43 | 0x47 | 0x4b | 0x4f if funct7 & 0b11 == 0 && !matches!(funct3, 0x5 | 0x6) => {
+                let rs3 = value >> 27;
+                let rm = funct3;
+                match opcode {
+                    0x43 => Ok(Self::FMADDS(rd, rs1, rs2, rs3, rm)),
+                    0x47 => Ok(Self::FMSUBS(rd, rs1, rs2, rs3, rm)),
+                    0x4b => Ok(Self::FNMSUBS(rd, rs1, rs2, rs3, rm)),
+                    _ => Ok(Self::FNMADDS(rd, rs1, rs2, rs3, rm)),
+                }
+            }
+            0x53 => {
+                let rm = funct3;
+                let rm_valid = !matches!(rm, 0x5 | 0x6);
+                match (funct7, rs2, funct3) {
+                    (0x00, _, _) if rm_valid => Ok(Self::FADDS(rd, rs1, rs2, rm)),
+                    (0x04, _, _) if rm_valid => Ok(Self::FSUBS(rd, rs1, rs2, rm)),
+                    (0x08, _, _) if rm_valid => Ok(Self::FMULS(rd, rs1, rs2, rm)),
+                    (0x0c, _, _) if rm_valid => Ok(Self::FDIVS(rd, rs1, rs2, rm)),
+                    (0x2c, 0x00, _) if rm_valid => Ok(Self::FSQRTS(rd, rs1, rm)),
+                    (0x10, _, 0x0) => Ok(Self::FSGNJS(rd, rs1, rs2)),
+                    (0x10, _, 0x1) => Ok(Self::FSGNJNS(rd, rs1, rs2)),
+                    (0x10, _, 0x2) => Ok(Self::FSGNJXS(rd, rs1, rs2)),
+                    (0x14, _, 0x0) => Ok(Self::FMINS(rd, rs1, rs2)),
+                    (0x14, _, 0x1) => Ok(Self::FMAXS(rd, rs1, rs2)),
+                    (0x60, 0x00, _) if rm_valid => Ok(Self::FCVTWS(rd, rs1, rm)),
+                    (0x60, 0x01, _) if rm_valid => Ok(Self::FCVTWUS(rd, rs1, rm)),
+                    (0x70, 0x00, 0x0) => Ok(Self::FMVXW(rd, rs1)),
+                    (0x70, 0x00, 0x1) => Ok(Self::FCLASSS(rd, rs1)),
+                    (0x50, _, 0x2) => Ok(Self::FEQS(rd, rs1, rs2)),
+                    (0x50, _, 0x1) => Ok(Self::FLTS(rd, rs1, rs2)),
+                    (0x50, _, 0x0) => Ok(Self::FLES(rd, rs1, rs2)),
+                    (0x68, 0x00, _) if rm_valid => Ok(Self::FCVTSW(rd, rs1, rm)),
+                    (0x68, 0x01, _) if rm_valid => Ok(Self::FCVTSWU(rd, rs1, rm)),
+                    (0x78, 0x00, 0x0) => Ok(Self::FMVWX(rd, rs1)),
+                    _ => Err(DecodeError::unimplemented(value)).with_context(|| context!()),
+                }
+            }
+            // fence
+            0x0f => match funct3 {
+                // fm 和 tso 都当作普通的 fence
+                0x0 => Ok(Self::FENCE((value >> 24) & 0xf, (value >> 20) & 0xf)),
+                0x1 => Ok(Self::FENCEI),
+                _ => Err(DecodeError::unimplemented(value)).with_context(|| context!()),
             },
             0x73 => {
                 let csr_addr = (value & 0xfff00000) >> 20;
                 let zimm = rs1;
                 match funct3 {
+                    0x0 if funct7 == 0x09 && rd == 0 => Ok(Self::SFENCEVMA(rs1, rs2)),
                     0x0 => match rs2 {
                         0x0 => Ok(Self::ECALL),
-                        0x02 => Ok(Self::ERET), // uret
-                        _ => {
-                            Err(anyhow!(
-                                "not implemented yet: opcode {:#x} funct3 {:#x} rs2 {:#x}",
-                                opcode,
-                                funct3,
-                                rs2
-                            ))
-                            .with_context(|| context!())
-                        }
+                        0x01 => Ok(Self::EBREAK),
+                        0x02 => match funct7 {
+                            0x00 => Ok(Self::URET),
+                            0x08 => Ok(Self::SRET),
+                            0x18 => Ok(Self::MRET),
+                            _ => Err(DecodeError::unimplemented(value)).with_context(|| context!()),
+                        },
+                        0x05 if funct7 == 0x08 => Ok(Self::WFI),
+                        _ => Err(DecodeError::unimplemented(value)).with_context(|| context!()),
                     },
-                    0x08 /* sret */ | 0x18 /* mret */ => Ok(Self::ERET),
                     0x1 => Ok(Self::CSRRW(rd, csr_addr, rs1)),
                     0x2 => Ok(Self::CSRRS(rd, csr_addr, rs1)),
                     0x3 => Ok(Self::CSRRC(rd, csr_addr, rs1)),
                     0x5 => Ok(Self::CSRRWI(rd, csr_addr, zimm)),
                     0x6 => Ok(Self::CSRRSI(rd, csr_addr, zimm)),
                     0x7 => Ok(Self::CSRRCI(rd, csr_addr, zimm)),
-                    _ => {
-                        Err(anyhow!(
-                            "not implemented yet: opcode {:#x} funct3 {:#x}",
-                            opcode,
-                            funct3
-                        ))
-                        .with_context(|| context!())
-                    }
+                    _ => Err(DecodeError::unimplemented(value)).with_context(|| context!()),
                 }
             }
-            _ => Err(anyhow!("not implemented yet: opcode {:#x}", opcode))
-                .with_context(|| context!()),
+            _ => Err(DecodeError::unimplemented(value)).with_context(|| context!()),
         }
     }
 }
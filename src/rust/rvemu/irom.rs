@@ -18,23 +18,118 @@ impl IROM {
         }
     }
 
+    /// 是否是指令存储器的地址
+    pub fn contains(&self, addr: u32) -> bool {
+        offset(self.user_base, &self.user, addr).is_some() || self.in_kernel(addr)
+    }
+
+    /// 是否是内核的代码
+    pub fn in_kernel(&self, addr: u32) -> bool {
+        offset(self.kernel_base, &self.kernel, addr).is_some()
+    }
+
+    /// 取指: 先取 16 位, 若不是压缩指令再取高 16 位
     pub fn fetch(&self, addr: u32) -> Result<u32> {
-        if self.user_base <= addr && addr < self.user_base + self.user.len() as u32 {
-            let offset = (addr - self.user_base) as usize;
-            let inst = (self.user[offset] as u32)
-                | ((self.user[offset + 1] as u32) << 8)
-                | ((self.user[offset + 2] as u32) << 16)
-                | ((self.user[offset + 3] as u32) << 24);
-            Ok(inst)
-        } else if self.kernel_base <= addr && addr < self.kernel_base + self.kernel.len() as u32 {
-            let offset = (addr - self.kernel_base) as usize;
-            let inst = (self.kernel[offset] as u32)
-                | ((self.kernel[offset + 1] as u32) << 8)
-                | ((self.kernel[offset + 2] as u32) << 16)
-                | ((self.kernel[offset + 3] as u32) << 24);
-            Ok(inst)
+        let lo = self.fetch_half(addr).with_context(|| context!())?;
+        if rvc::is_compressed(lo) {
+            return Ok(lo);
+        }
+        let hi = self
+            .fetch_half(addr.wrapping_add(2))
+            .with_context(|| context!())?;
+        Ok(lo | (hi << 16))
+    }
+
+    /// 取指的快速路径, 不在 IROM 里时返回 None, 由 fetch 报告异常
+    pub fn get(&self, addr: u32) -> Option<u32> {
+        let (rom, offset) = if self.in_kernel(addr) {
+            (&self.kernel, addr - self.kernel_base)
         } else {
-            Err(anyhow!("Invalid instruction address: 0x{:08x}", addr)).with_context(|| context!())
+            (&self.user, addr.checked_sub(self.user_base)?)
+        };
+        let offset = offset as usize;
+        let half = |offset: usize| {
+            let bytes = rom.get(offset..offset + 2)?;
+            Some(u16::from_le_bytes([bytes[0], bytes[1]]) as u32)
+        };
+        let lo = half(offset)?;
+        if rvc::is_compressed(lo) {
+            return Some(lo);
         }
+        Some(lo | (half(offset + 2)? << 16))
+    }
+
+    pub fn fetch_half(&self, addr: u32) -> Result<u32> {
+        let bytes = if let Some(offset) = offset(self.user_base, &self.user, addr) {
+            self.user.get(offset..offset + 2)
+        } else if let Some(offset) = offset(self.kernel_base, &self.kernel, addr) {
+            self.kernel.get(offset..offset + 2)
+        } else {
+            None
+        };
+        match bytes {
+            Some(bytes) => Ok(u16::from_le_bytes([bytes[0], bytes[1]]) as u32),
+            // 包括只剩一个字节的末尾
+            None => Err(MemError::OutOfRange {
+                access: Access::Fetch,
+                addr,
+                size: 16,
+            })
+            .context(Exception::InstructionAccessFault(addr))
+            .with_context(|| context!()),
+        }
+    }
+}
+
+/// addr 在 rom 里时返回相对 base 的偏移, base + rom.len() 可能超出 32 位
+fn offset(base: u32, rom: &[u8], addr: u32) -> Option<usize> {
+    let offset = addr.checked_sub(base)? as usize;
+    (offset < rom.len()).then_some(offset)
+}
+
+/// 指令存储器只读, 挂在取指的通路上, offset 就是地址
+impl Device for IROM {
+    fn read(&mut self, offset: u32, size: u32) -> Result<u32> {
+        match size {
+            16 => self.fetch_half(offset),
+            32 => {
+                let lo = self.fetch_half(offset).with_context(|| context!())?;
+                let hi = self
+                    .fetch_half(offset.wrapping_add(2))
+                    .with_context(|| context!())?;
+                Ok(lo | (hi << 16))
+            }
+            _ => Err(MemError::InvalidSize { addr: offset, size }).with_context(|| context!()),
+        }
+    }
+
+    fn write(&mut self, offset: u32, _value: u32, _size: u32) -> Result<()> {
+        Err(anyhow!("irom: read-only: {:#x}", offset)).with_context(|| context!())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn odd_length_image_is_bounds_checked() {
+        let irom = IROM::new(&[0x13, 0x00, 0x00], 0, &[0x73], 0x1c09_0000);
+        assert_eq!(irom.fetch_half(0).unwrap(), 0x13);
+        assert!(irom.fetch_half(2).is_err());
+        assert!(irom.fetch_half(0x1c09_0000).is_err());
+        assert_eq!(irom.get(2), None);
+    }
+
+    #[test]
+    fn image_at_the_top_of_the_address_space() {
+        let nop = 0x0000_0013u32.to_le_bytes();
+        let irom = IROM::new(&nop, 0xffff_fffc, &nop, 0x1c09_0000);
+        assert!(irom.contains(0xffff_fffc));
+        assert!(irom.contains(0xffff_ffff));
+        assert!(!irom.contains(0));
+        assert_eq!(irom.fetch(0xffff_fffc).unwrap(), 0x13);
+        assert_eq!(irom.get(0xffff_fffc), Some(0x13));
+        assert!(irom.fetch_half(0).is_err());
     }
 }
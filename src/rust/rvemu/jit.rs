@@ -0,0 +1,463 @@
+use super::*;
+
+#[cfg(not(all(target_arch = "x86_64", unix)))]
+compile_error!("the jit feature needs an x86-64 unix host");
+
+/// 基本块执行多少次之后编译
+pub const JIT_THRESHOLD: u32 = 16;
+/// 存放编译结果的可执行内存大小, 用完后全部丢掉重新编译
+const CODE_SIZE: usize = 16 << 20;
+
+/// 编译的代码执行完整个基本块
+pub const EXIT_END: u32 = 0;
+/// 在某个操作之后离开基本块 (访问了 DRAM 以外的设备等)
+pub const EXIT_LEAVE: u32 = 1;
+/// 某个操作出错, 错误放在 JitCtx::error 中
+pub const EXIT_FAULT: u32 = 2;
+
+/// 编译的代码与回调之间传递的状态
+#[repr(C)]
+pub struct JitCtx {
+    /// 和传给编译的代码的是同一个指针, 回调只通过它访问 CPU
+    pub cpu: *mut CPU,
+    pub ops: *const Op,
+    /// 前面已经 retire 的操作个数, 编译的代码自己不更新计数器
+    pub retired: u32,
+    pub error: Option<anyhow::Error>,
+}
+
+/// 编译的代码不处理的操作交给回调, 用解释器执行第 index 个操作.
+/// 返回 0 表示继续, 否则是 exit(原因, index)
+pub type Helper = unsafe extern "C" fn(*mut JitCtx, u32) -> u64;
+
+/// 编译好的基本块, 返回 exit(原因, 操作的下标)
+#[derive(Clone, Copy)]
+pub struct Code(unsafe extern "C" fn(*mut CPU, *mut JitCtx) -> u64);
+
+impl Code {
+    /// # Safety
+    /// cpu 和 ctx 必须有效, ctx.cpu 就是 cpu, 调用期间不能有其它指向 CPU 的引用.
+    /// ctx.ops 是编译时的那个基本块的操作
+    pub unsafe fn call(self, cpu: *mut CPU, ctx: *mut JitCtx) -> u64 {
+        (self.0)(cpu, ctx)
+    }
+}
+
+pub fn exit(reason: u32, index: u32) -> u64 {
+    ((reason as u64) << 32) | index as u64
+}
+
+/// x86-64 的编译器, 寄存器都留在 CPU 里, 每条指令从内存读写.
+/// 存放代码的内存平时只读可执行, 写入新的基本块时临时改成可读写 (W^X)
+pub struct Jit {
+    mem: *mut u8,
+    used: usize,
+    page: usize,
+    /// 剩下的空间放不下一个基本块
+    full: bool,
+    /// CPU 里 regs 和 pc 的偏移
+    regs: i32,
+    pc: i32,
+    helper: Helper,
+}
+
+impl Jit {
+    pub fn new(regs: usize, pc: usize, helper: Helper) -> Result<Self> {
+        let mem = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                CODE_SIZE,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            )
+        };
+        if mem == libc::MAP_FAILED {
+            return Err(std::io::Error::last_os_error()).with_context(|| context!());
+        }
+        // 先确认这块内存可以改成可执行, 不允许时只用解释器
+        if unsafe { libc::mprotect(mem, CODE_SIZE, libc::PROT_READ | libc::PROT_EXEC) } != 0 {
+            let e = std::io::Error::last_os_error();
+            unsafe { libc::munmap(mem, CODE_SIZE) };
+            return Err(e).with_context(|| context!());
+        }
+        let page = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+        Ok(Self {
+            mem: mem as *mut u8,
+            used: 0,
+            page,
+            full: false,
+            regs: regs as i32,
+            pc: pc as i32,
+            helper,
+        })
+    }
+
+    #[cfg(test)]
+    pub fn used(&self) -> usize {
+        self.used
+    }
+
+    pub fn full(&self) -> bool {
+        self.full
+    }
+
+    /// 丢掉所有编译结果, 调用前必须保证旧的 Code 不会再执行
+    pub fn reset(&mut self) {
+        self.used = 0;
+        self.full = false;
+    }
+
+    /// 编译基本块, insts 是 ops 对应的指令.
+    /// 大部分操作都要调用回调的基本块不如直接解释执行, 与空间不够时一样返回 None
+    pub fn compile(&mut self, ops: &[Op], insts: &[Instr], jumps: bool) -> Option<Code> {
+        let mut asm = Asm {
+            code: Vec::new(),
+            to_epilogue: Vec::new(),
+            regs: self.regs,
+            pc: self.pc,
+        };
+        asm.prologue();
+        // 上一个基本块可能写了 x0
+        asm.zero_x0();
+        let mut dirty = false;
+        let mut helpers = 0;
+        for (index, (op, inst)) in ops.iter().zip(insts).enumerate() {
+            if dirty {
+                asm.zero_x0();
+            }
+            // 回调执行的操作和写 x0 的指令之后要把 x0 清零
+            let native = asm.inst(op, inst);
+            if !native {
+                asm.call(self.helper, index as u32);
+                helpers += 1;
+            }
+            dirty = !native || op.rd == 0;
+        }
+        if helpers * 2 > ops.len() {
+            return None;
+        }
+        if !jumps {
+            asm.end();
+        }
+        asm.epilogue();
+
+        let code = asm.code;
+        if self.used + code.len() > CODE_SIZE {
+            self.full = true;
+            return None;
+        }
+        // 和前面的基本块共用的页也要改成可写, 这时没有编译的代码正在执行
+        let start = self.used / self.page * self.page;
+        let end = (self.used + code.len()).next_multiple_of(self.page);
+        unsafe {
+            let pages = self.mem.add(start) as *mut libc::c_void;
+            if libc::mprotect(pages, end - start, libc::PROT_READ | libc::PROT_WRITE) != 0 {
+                return None;
+            }
+            let dst = self.mem.add(self.used);
+            std::ptr::copy_nonoverlapping(code.as_ptr(), dst, code.len());
+            if libc::mprotect(pages, end - start, libc::PROT_READ | libc::PROT_EXEC) != 0 {
+                // 前面的基本块也在这些页里, 不能留在不可执行的状态
+                panic!("jit: mprotect: {}", std::io::Error::last_os_error());
+            }
+            self.used += code.len().next_multiple_of(16);
+            Some(Code(std::mem::transmute::<
+                *mut u8,
+                unsafe extern "C" fn(*mut CPU, *mut JitCtx) -> u64,
+            >(dst)))
+        }
+    }
+}
+
+impl Drop for Jit {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.mem as *mut libc::c_void, CODE_SIZE);
+        }
+    }
+}
+
+// x86-64 寄存器编号
+const EAX: u8 = 0;
+const ECX: u8 = 1;
+const EDX: u8 = 2;
+
+/// 生成代码. rbx 指向 CPU, r12 指向 JitCtx
+struct Asm {
+    code: Vec<u8>,
+    /// 跳到 epilogue 的 rel32 的位置
+    to_epilogue: Vec<usize>,
+    regs: i32,
+    pc: i32,
+}
+
+impl Asm {
+    fn emit(&mut self, bytes: &[u8]) {
+        self.code.extend_from_slice(bytes);
+    }
+
+    fn imm32(&mut self, imm: u32) {
+        self.emit(&imm.to_le_bytes());
+    }
+
+    /// [rbx + disp32]
+    fn mem(&mut self, reg: u8, disp: i32) {
+        self.emit(&[0x80 | (reg << 3) | 3]);
+        self.imm32(disp as u32);
+    }
+
+    fn reg(&self, r: u8) -> i32 {
+        self.regs + 4 * r as i32
+    }
+
+    /// opcode reg, [rbx + regs[r]]
+    fn op_reg(&mut self, opcode: &[u8], reg: u8, r: u8) {
+        self.emit(opcode);
+        self.mem(reg, self.reg(r));
+    }
+
+    fn load(&mut self, reg: u8, r: u8) {
+        self.op_reg(&[0x8b], reg, r);
+    }
+
+    fn store(&mut self, reg: u8, r: u8) {
+        self.op_reg(&[0x89], reg, r);
+    }
+
+    /// mov dword [rbx + disp], imm
+    fn store_imm(&mut self, disp: i32, imm: u32) {
+        self.emit(&[0xc7]);
+        self.mem(0, disp);
+        self.imm32(imm);
+    }
+
+    fn zero_x0(&mut self) {
+        self.store_imm(self.regs, 0);
+    }
+
+    fn prologue(&mut self) {
+        // push rbx; push r12; push rax (对齐栈); mov rbx, rdi; mov r12, rsi
+        self.emit(&[0x53, 0x41, 0x54, 0x50, 0x48, 0x89, 0xfb, 0x49, 0x89, 0xf4]);
+    }
+
+    fn epilogue(&mut self) {
+        let target = self.code.len();
+        for &at in &self.to_epilogue {
+            let rel = (target - (at + 4)) as u32;
+            self.code[at..at + 4].copy_from_slice(&rel.to_le_bytes());
+        }
+        // pop rcx; pop r12; pop rbx; ret
+        self.emit(&[0x59, 0x41, 0x5c, 0x5b, 0xc3]);
+    }
+
+    /// jmp/jcc 到 epilogue, 返回值已经在 rax 中
+    fn jump_epilogue(&mut self, opcode: &[u8]) {
+        self.emit(opcode);
+        self.to_epilogue.push(self.code.len());
+        self.imm32(0);
+    }
+
+    /// 执行完整个基本块
+    fn end(&mut self) {
+        // xor eax, eax
+        self.emit(&[0x31, 0xc0]);
+        self.jump_epilogue(&[0xe9]);
+    }
+
+    fn set_pc(&mut self, pc: u32) {
+        self.store_imm(self.pc, pc);
+    }
+
+    /// 调用回调执行第 index 个操作, 不是继续时退出
+    fn call(&mut self, helper: Helper, index: u32) {
+        // mov rdi, r12; mov esi, index
+        self.emit(&[0x4c, 0x89, 0xe7, 0xbe]);
+        self.imm32(index);
+        // mov rax, helper; call rax; test rax, rax
+        self.emit(&[0x48, 0xb8]);
+        self.emit(&(helper as usize as u64).to_le_bytes());
+        self.emit(&[0xff, 0xd0, 0x48, 0x85, 0xc0]);
+        // jnz epilogue
+        self.jump_epilogue(&[0x0f, 0x85]);
+    }
+
+    /// rd = rs1 op rs2
+    fn alu(&mut self, opcode: u8, op: &Op) {
+        self.load(EAX, op.rs1);
+        self.op_reg(&[opcode], EAX, op.rs2);
+        self.store(EAX, op.rd);
+    }
+
+    /// rd = rs1 op imm, opcode 是 op eax, imm32 的编码
+    fn alu_imm(&mut self, opcode: u8, op: &Op) {
+        self.load(EAX, op.rs1);
+        self.emit(&[opcode]);
+        self.imm32(op.imm);
+        self.store(EAX, op.rd);
+    }
+
+    /// 按移位量的低 5 位移位, ext 是 shl/shr/sar 的 ModRM
+    fn shift(&mut self, ext: u8, op: &Op) {
+        self.load(EAX, op.rs1);
+        self.load(ECX, op.rs2);
+        self.emit(&[0xd3, ext]);
+        self.store(EAX, op.rd);
+    }
+
+    fn shift_imm(&mut self, ext: u8, op: &Op) {
+        self.load(EAX, op.rs1);
+        self.emit(&[0xc1, ext, (op.imm & 0x1f) as u8]);
+        self.store(EAX, op.rd);
+    }
+
+    /// 比较之后 setcc, setcc 是 0f 9x 的第二个字节
+    fn set(&mut self, setcc: u8, op: &Op) {
+        // setcc al; movzx eax, al
+        self.emit(&[0x0f, setcc, 0xc0, 0x0f, 0xb6, 0xc0]);
+        self.store(EAX, op.rd);
+    }
+
+    /// edx:eax = rs1 * rs2, ext 是 mul/imul 的 /r
+    fn mul_high(&mut self, ext: u8, op: &Op) {
+        self.load(EAX, op.rs1);
+        self.emit(&[0xf7]);
+        self.mem(ext, self.reg(op.rs2));
+        self.store(EDX, op.rd);
+    }
+
+    fn branch(&mut self, jcc: u8, op: &Op) {
+        self.load(EAX, op.rs1);
+        self.op_reg(&[0x3b], EAX, op.rs2);
+        // jcc taken
+        self.emit(&[0x0f, jcc]);
+        let at = self.code.len();
+        self.imm32(0);
+        self.set_pc(op.next_pc());
+        self.end();
+        let rel = (self.code.len() - (at + 4)) as u32;
+        self.code[at..at + 4].copy_from_slice(&rel.to_le_bytes());
+        self.set_pc(op.pc.wrapping_add(op.imm));
+        self.end();
+    }
+
+    /// 生成一条指令的代码, 不能直接生成时返回 false
+    fn inst(&mut self, op: &Op, inst: &Instr) -> bool {
+        match inst {
+            Instr::LUI(..) | Instr::AUIPC(..) => self.store_imm(self.reg(op.rd), op.imm),
+            Instr::FENCE(..) => {}
+            Instr::JAL(..) => {
+                self.store_imm(self.reg(op.rd), op.next_pc());
+                self.set_pc(op.pc.wrapping_add(op.imm));
+                self.end();
+            }
+            Instr::JALR(..) => {
+                // add eax, imm; and eax, -2; mov [pc], eax
+                self.load(EAX, op.rs1);
+                self.emit(&[0x05]);
+                self.imm32(op.imm);
+                self.emit(&[0x25]);
+                self.imm32(!1);
+                self.emit(&[0x89]);
+                self.mem(EAX, self.pc);
+                self.store_imm(self.reg(op.rd), op.next_pc());
+                self.end();
+            }
+            Instr::BEQ(..) => self.branch(0x84, op),
+            Instr::BNE(..) => self.branch(0x85, op),
+            Instr::BLT(..) => self.branch(0x8c, op),
+            Instr::BGE(..) => self.branch(0x8d, op),
+            Instr::BLTU(..) => self.branch(0x82, op),
+            Instr::BGEU(..) => self.branch(0x83, op),
+            Instr::ADDI(..) => self.alu_imm(0x05, op),
+            Instr::ANDI(..) => self.alu_imm(0x25, op),
+            Instr::ORI(..) => self.alu_imm(0x0d, op),
+            Instr::XORI(..) => self.alu_imm(0x35, op),
+            Instr::SLLI(..) => self.shift_imm(0xe0, op),
+            Instr::SRLI(..) => self.shift_imm(0xe8, op),
+            Instr::SRAI(..) => self.shift_imm(0xf8, op),
+            Instr::SLTI(..) | Instr::SLTIU(..) => {
+                self.load(EAX, op.rs1);
+                // cmp eax, imm32
+                self.emit(&[0x3d]);
+                self.imm32(op.imm);
+                let setcc = if matches!(inst, Instr::SLTI(..)) {
+                    0x9c
+                } else {
+                    0x92
+                };
+                self.set(setcc, op);
+            }
+            Instr::ADD(..) => self.alu(0x03, op),
+            Instr::SUB(..) => self.alu(0x2b, op),
+            Instr::AND(..) => self.alu(0x23, op),
+            Instr::OR(..) => self.alu(0x0b, op),
+            Instr::XOR(..) => self.alu(0x33, op),
+            Instr::SLL(..) => self.shift(0xe0, op),
+            Instr::SRL(..) => self.shift(0xe8, op),
+            Instr::SRA(..) => self.shift(0xf8, op),
+            Instr::SLT(..) | Instr::SLTU(..) => {
+                self.load(EAX, op.rs1);
+                self.op_reg(&[0x3b], EAX, op.rs2);
+                let setcc = if matches!(inst, Instr::SLT(..)) {
+                    0x9c
+                } else {
+                    0x92
+                };
+                self.set(setcc, op);
+            }
+            Instr::MUL(..) => {
+                self.load(EAX, op.rs1);
+                self.op_reg(&[0x0f, 0xaf], EAX, op.rs2);
+                self.store(EAX, op.rd);
+            }
+            Instr::MULH(..) => self.mul_high(5, op),
+            Instr::MULHU(..) => self.mul_high(4, op),
+            Instr::MULHSU(..) => {
+                // movsxd rax, [rs1]; mov ecx, [rs2]; imul rax, rcx; shr rax, 32
+                self.op_reg(&[0x48, 0x63], EAX, op.rs1);
+                self.load(ECX, op.rs2);
+                self.emit(&[0x48, 0x0f, 0xaf, 0xc1, 0x48, 0xc1, 0xe8, 0x20]);
+                self.store(EAX, op.rd);
+            }
+            _ => return false,
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    unsafe extern "C" fn no_helper(_ctx: *mut JitCtx, _index: u32) -> u64 {
+        unreachable!()
+    }
+
+    /// /proc/self/maps 里 addr 所在映射的权限
+    fn protection(addr: *const u8) -> String {
+        let addr = addr as usize;
+        let maps = std::fs::read_to_string("/proc/self/maps").unwrap();
+        maps.lines()
+            .find_map(|line| {
+                let (range, rest) = line.split_once(' ')?;
+                let (start, end) = range.split_once('-')?;
+                let start = usize::from_str_radix(start, 16).ok()?;
+                let end = usize::from_str_radix(end, 16).ok()?;
+                (start..end).contains(&addr).then(|| rest[..4].to_string())
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn code_is_never_writable_and_executable() {
+        let mut jit = Jit::new(0, 0, no_helper).unwrap();
+        assert_eq!(protection(jit.mem), "r-xp");
+        for _ in 0..3 {
+            jit.compile(&[], &[], false).unwrap();
+            assert_eq!(protection(jit.mem), "r-xp");
+        }
+        assert!(jit.used() > 0);
+    }
+}
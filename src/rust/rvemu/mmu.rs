@@ -0,0 +1,526 @@
+use super::*;
+
+// pte
+const PTE_V: u32 = 1 << 0;
+const PTE_R: u32 = 1 << 1;
+const PTE_W: u32 = 1 << 2;
+const PTE_X: u32 = 1 << 3;
+const PTE_U: u32 = 1 << 4;
+const PTE_G: u32 = 1 << 5;
+const PTE_A: u32 = 1 << 6;
+const PTE_D: u32 = 1 << 7;
+
+const PAGE_SIZE: u64 = 4096;
+
+/// TLB 的命中统计
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TlbStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+/// 缓存的页表项
+#[derive(Clone, Copy)]
+struct TlbEntry {
+    /// vaddr[31:12]
+    vpn: u32,
+    asid: u32,
+    pte: u32,
+    /// 4MiB 的大页
+    superpage: bool,
+}
+
+impl TlbEntry {
+    fn matches(&self, vaddr: u32, asid: u32) -> bool {
+        let vpn = vaddr >> 12;
+        let same_page = if self.superpage {
+            self.vpn >> 10 == vpn >> 10
+        } else {
+            self.vpn == vpn
+        };
+        same_page && (self.asid == asid || self.pte & PTE_G != 0)
+    }
+
+    /// 物理地址有 34 位
+    fn paddr(&self, vaddr: u32) -> u64 {
+        let ppn = (self.pte >> 10) as u64;
+        if self.superpage {
+            (ppn >> 10) << 22 | (vaddr & 0x3f_ffff) as u64
+        } else {
+            ppn << 12 | (vaddr & 0xfff) as u64
+        }
+    }
+}
+
+/// Sv32 地址转换, 以及可选的全相联 TLB
+#[allow(clippy::upper_case_acronyms)]
+pub struct MMU {
+    tlb: Vec<Option<TlbEntry>>,
+    /// 轮转替换的下一项
+    next: usize,
+    stats: TlbStats,
+    /// 上次查页表时写回 A/D 位的页表项地址
+    written: Option<u32>,
+}
+
+impl MMU {
+    /// entries = 0 时不缓存, 每次访存都查页表
+    pub fn new(entries: usize) -> Self {
+        Self {
+            tlb: vec![None; entries],
+            next: 0,
+            stats: TlbStats::default(),
+            written: None,
+        }
+    }
+
+    pub fn stats(&self) -> TlbStats {
+        self.stats
+    }
+
+    /// 取出写回过的页表项地址, 由调用者丢掉这里缓存的代码
+    pub fn take_written(&mut self) -> Option<u32> {
+        self.written.take()
+    }
+
+    /// sfence.vma: vaddr/asid 为 None 表示所有的地址/地址空间, 全局页只按地址刷新
+    pub fn flush(&mut self, vaddr: Option<u32>, asid: Option<u32>) {
+        for slot in self.tlb.iter_mut() {
+            if let Some(entry) = slot {
+                let vaddr_hit = vaddr.map_or(true, |vaddr| entry.matches(vaddr, entry.asid));
+                let asid_hit =
+                    asid.map_or(true, |asid| entry.pte & PTE_G == 0 && entry.asid == asid);
+                if vaddr_hit && asid_hit {
+                    *slot = None;
+                }
+            }
+        }
+    }
+
+    /// 虚拟地址转换为物理地址. 页表通过总线访问, 查页表的访存按 S 模式做 PMP 检查.
+    #[allow(clippy::too_many_arguments)]
+    pub fn translate(
+        &mut self,
+        vaddr: u32,
+        access: Access,
+        mode: Privilege,
+        satp: u32,
+        mstatus: u32,
+        bus: &mut Bus,
+        pmp: &PMP,
+    ) -> Result<u32> {
+        let asid = (satp >> 22) & 0x1ff;
+        let cached = self.tlb.iter().flatten().find(|entry| {
+            // 写一个 D = 0 的页要重新查页表, 把 D 写回页表项
+            entry.matches(vaddr, asid) && (access != Access::Store || entry.pte & PTE_D != 0)
+        });
+        let entry = match cached {
+            Some(&entry) => {
+                self.stats.hits += 1;
+                entry
+            }
+            None => {
+                self.stats.misses += 1;
+                let entry = self
+                    .walk(vaddr, access, mode, satp, mstatus, bus, pmp)
+                    .with_context(|| context!())?;
+                self.insert(entry);
+                entry
+            }
+        };
+        // 权限可能随 mstatus 和特权级变化, 命中时也要检查
+        if !Self::permits(entry.pte, access, mode, mstatus) {
+            return Err(access.page_fault(vaddr))
+                .with_context(|| format!("sv32: permission denied: {:#x}", vaddr))
+                .with_context(|| context!());
+        }
+        let paddr = entry.paddr(vaddr);
+        if paddr > u32::MAX as u64 {
+            return Err(access.fault(vaddr))
+                .with_context(|| format!("sv32: {:#x} -> {:#x}", vaddr, paddr))
+                .with_context(|| context!());
+        }
+        Ok(paddr as u32)
+    }
+
+    fn insert(&mut self, entry: TlbEntry) {
+        if self.tlb.is_empty() {
+            return;
+        }
+        // 同一页只保留一项
+        for slot in self.tlb.iter_mut() {
+            if matches!(slot, Some(old) if old.vpn == entry.vpn && old.asid == entry.asid) {
+                *slot = Some(entry);
+                return;
+            }
+        }
+        self.tlb[self.next] = Some(entry);
+        self.next = (self.next + 1) % self.tlb.len();
+    }
+
+    fn permits(pte: u32, access: Access, mode: Privilege, mstatus: u32) -> bool {
+        let allowed = match access {
+            Access::Fetch => pte & PTE_X != 0,
+            // mxr: 可执行的页也可以读
+            Access::Load => pte & PTE_R != 0 || (mstatus & MSTATUS_MXR != 0 && pte & PTE_X != 0),
+            Access::Store => pte & PTE_W != 0,
+        };
+        let user_page = pte & PTE_U != 0;
+        let privileged = match mode {
+            Privilege::User => user_page,
+            // sum: S 模式可以读写用户页, 但不能执行
+            Privilege::Supervisor => {
+                !user_page || (access != Access::Fetch && mstatus & MSTATUS_SUM != 0)
+            }
+            Privilege::Machine => true,
+        };
+        allowed && privileged
+    }
+
+    /// 两级页表的遍历, 顺便设置 A/D 位
+    #[allow(clippy::too_many_arguments)]
+    fn walk(
+        &mut self,
+        vaddr: u32,
+        access: Access,
+        mode: Privilege,
+        satp: u32,
+        mstatus: u32,
+        bus: &mut Bus,
+        pmp: &PMP,
+    ) -> Result<TlbEntry> {
+        let page_fault = |reason: &str| {
+            Err(access.page_fault(vaddr))
+                .with_context(|| format!("sv32: {}: {:#x}", reason, vaddr))
+                .with_context(|| context!())
+        };
+        let vpn = [(vaddr >> 12) & 0x3ff, vaddr >> 22];
+        let mut table = (satp & 0x3f_ffff) as u64 * PAGE_SIZE;
+        let mut level = 1;
+        let (pte, pte_addr) = loop {
+            let pte_addr = table + vpn[level] as u64 * 4;
+            if pte_addr > u32::MAX as u64
+                || !pmp.check(pte_addr as u32, 32, Access::Load, Privilege::Supervisor)
+            {
+                return Err(access.fault(vaddr))
+                    .with_context(|| format!("sv32: pte at {:#x}", pte_addr))
+                    .with_context(|| context!());
+            }
+            let pte_addr = pte_addr as u32;
+            let pte = bus
+                .load(pte_addr, 32)
+                .map_err(|e| e.context(access.fault(vaddr)))
+                .with_context(|| context!())?;
+            if pte & PTE_V == 0 || (pte & PTE_R == 0 && pte & PTE_W != 0) {
+                return page_fault("invalid pte");
+            }
+            if pte & (PTE_R | PTE_X) != 0 {
+                break (pte, pte_addr);
+            }
+            if level == 0 {
+                return page_fault("no leaf pte");
+            }
+            level -= 1;
+            table = (pte >> 10) as u64 * PAGE_SIZE;
+        };
+        let superpage = level == 1;
+        if superpage && (pte >> 10) & 0x3ff != 0 {
+            return page_fault("misaligned superpage");
+        }
+        if !Self::permits(pte, access, mode, mstatus) {
+            return page_fault("permission denied");
+        }
+        // 硬件更新 A/D 位
+        let mut new_pte = pte | PTE_A;
+        if access == Access::Store {
+            new_pte |= PTE_D;
+        }
+        if new_pte != pte {
+            if !pmp.check(pte_addr, 32, Access::Store, Privilege::Supervisor) {
+                return Err(access.fault(vaddr))
+                    .with_context(|| format!("sv32: pte at {:#x}", pte_addr))
+                    .with_context(|| context!());
+            }
+            bus.store(pte_addr, new_pte, 32)
+                .map_err(|e| e.context(access.fault(vaddr)))
+                .with_context(|| context!())?;
+            self.written = Some(pte_addr);
+        }
+        Ok(TlbEntry {
+            vpn: vaddr >> 12,
+            asid: (satp >> 22) & 0x1ff,
+            pte: new_pte,
+            superpage,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{cpu_with, run_to_error};
+    use super::*;
+
+    /// 根页表在 0x1000, 所有的二级页表项都指向 0x2000 处的页表
+    const ROOT: u32 = 0x1000;
+    const TABLE: u32 = 0x2000;
+
+    fn satp(asid: u32) -> u32 {
+        1 << 31 | asid << 22 | ROOT >> 12
+    }
+
+    struct Env {
+        mmu: MMU,
+        bus: Bus,
+        pmp: PMP,
+        asid: u32,
+    }
+
+    impl Env {
+        fn new(tlb_entries: usize) -> Self {
+            let irom = IROM::new(&[0; 4], 0, &[0; 4], 0x1c09_0000);
+            Self {
+                mmu: MMU::new(tlb_entries),
+                bus: Bus::new(irom, DRAM::new(&[], 0, 0x1_0000), &Config::default()),
+                pmp: PMP::new(0),
+                asid: 0,
+            }
+        }
+
+        /// 用 4KiB 的页把 vaddr 映射到 paddr
+        fn map(&mut self, vaddr: u32, paddr: u32, flags: u32) {
+            self.bus
+                .store(ROOT + (vaddr >> 22) * 4, TABLE >> 12 << 10 | PTE_V, 32)
+                .unwrap();
+            self.bus
+                .store(self.leaf(vaddr), paddr >> 12 << 10 | flags | PTE_V, 32)
+                .unwrap();
+        }
+
+        /// 用 4MiB 的大页把 vaddr 映射到 ppn
+        fn map_superpage(&mut self, vaddr: u32, ppn: u32, flags: u32) {
+            self.bus
+                .store(ROOT + (vaddr >> 22) * 4, ppn << 10 | flags | PTE_V, 32)
+                .unwrap();
+        }
+
+        fn leaf(&self, vaddr: u32) -> u32 {
+            TABLE + ((vaddr >> 12) & 0x3ff) * 4
+        }
+
+        fn pte(&mut self, vaddr: u32) -> u32 {
+            self.bus.load(self.leaf(vaddr), 32).unwrap()
+        }
+
+        fn translate_as(
+            &mut self,
+            vaddr: u32,
+            access: Access,
+            mode: Privilege,
+            mstatus: u32,
+        ) -> Result<u32> {
+            let satp = satp(self.asid);
+            self.mmu
+                .translate(vaddr, access, mode, satp, mstatus, &mut self.bus, &self.pmp)
+        }
+
+        fn translate(&mut self, vaddr: u32, access: Access) -> Result<u32> {
+            self.translate_as(vaddr, access, Privilege::Supervisor, 0)
+        }
+
+        fn fault(&mut self, vaddr: u32, access: Access, mode: Privilege, mstatus: u32) -> u32 {
+            let e = self.translate_as(vaddr, access, mode, mstatus).unwrap_err();
+            let exception = *e.downcast_ref::<Exception>().unwrap();
+            assert_eq!(exception.tval(), vaddr);
+            exception.cause()
+        }
+
+        /// 转换一次, 返回是否命中 TLB
+        fn hits(&mut self, vaddr: u32) -> bool {
+            let hits = self.mmu.stats().hits;
+            self.translate(vaddr, Access::Load).unwrap();
+            self.mmu.stats().hits > hits
+        }
+    }
+
+    #[test]
+    fn two_level_walk() {
+        let mut env = Env::new(0);
+        env.map(0x0040_3000, 0x5000, PTE_R | PTE_W);
+        assert_eq!(env.translate(0x0040_3123, Access::Load).unwrap(), 0x5123);
+        assert!(env.translate(0x0040_4000, Access::Load).is_err());
+        assert!(env.translate(0x0080_3000, Access::Load).is_err());
+    }
+
+    #[test]
+    fn superpage() {
+        let mut env = Env::new(0);
+        env.map_superpage(0x0080_0000, 0x400, PTE_R);
+        assert_eq!(
+            env.translate(0x00bf_1234, Access::Load).unwrap(),
+            0x007f_1234
+        );
+        // 大页的 ppn[0] 必须是 0
+        env.map_superpage(0x00c0_0000, 0x401, PTE_R);
+        assert_eq!(
+            env.fault(0x00c0_0000, Access::Load, Privilege::Supervisor, 0),
+            13
+        );
+    }
+
+    #[test]
+    fn walk_sets_accessed_and_dirty() {
+        let mut env = Env::new(0);
+        env.map(0x3000, 0x5000, PTE_R | PTE_W);
+        env.translate(0x3000, Access::Load).unwrap();
+        assert_eq!(env.pte(0x3000) & (PTE_A | PTE_D), PTE_A);
+        env.translate(0x3000, Access::Store).unwrap();
+        assert_eq!(env.pte(0x3000) & (PTE_A | PTE_D), PTE_A | PTE_D);
+        assert_eq!(env.mmu.take_written(), Some(env.leaf(0x3000)));
+    }
+
+    #[test]
+    fn store_to_a_clean_tlb_entry_walks_again() {
+        let mut env = Env::new(4);
+        env.map(0x3000, 0x5000, PTE_R | PTE_W);
+        assert!(!env.hits(0x3000));
+        assert!(env.hits(0x3004));
+        env.translate(0x3000, Access::Store).unwrap();
+        assert_eq!(env.mmu.stats().misses, 2);
+        assert_ne!(env.pte(0x3000) & PTE_D, 0);
+        // 缓存的表项已经是脏的
+        env.translate(0x3000, Access::Store).unwrap();
+        assert_eq!(env.mmu.stats().misses, 2);
+        assert_eq!(env.mmu.stats().hits, 2);
+    }
+
+    #[test]
+    fn page_fault_causes() {
+        let mut env = Env::new(0);
+        env.map(0x3000, 0x5000, PTE_R);
+        let s = Privilege::Supervisor;
+        assert_eq!(env.fault(0x3000, Access::Fetch, s, 0), 12);
+        assert_eq!(env.fault(0x4000, Access::Load, s, 0), 13);
+        assert_eq!(env.fault(0x3000, Access::Store, s, 0), 15);
+        // w 没有 r 是保留的组合
+        env.map(0x6000, 0x5000, PTE_W);
+        assert_eq!(env.fault(0x6000, Access::Load, s, 0), 13);
+    }
+
+    #[test]
+    fn sum_and_mxr() {
+        let mut env = Env::new(0);
+        env.map(0x3000, 0x5000, PTE_R | PTE_X | PTE_U);
+        env.map(0x4000, 0x5000, PTE_X);
+        let (s, u) = (Privilege::Supervisor, Privilege::User);
+        assert_eq!(env.fault(0x3000, Access::Load, s, 0), 13);
+        assert!(env
+            .translate_as(0x3000, Access::Load, s, MSTATUS_SUM)
+            .is_ok());
+        // sum 不允许 S 模式执行用户页
+        assert_eq!(env.fault(0x3000, Access::Fetch, s, MSTATUS_SUM), 12);
+        assert!(env.translate_as(0x3000, Access::Fetch, u, 0).is_ok());
+        assert_eq!(env.fault(0x4000, Access::Fetch, u, 0), 12);
+        assert_eq!(env.fault(0x4000, Access::Load, s, 0), 13);
+        assert!(env
+            .translate_as(0x4000, Access::Load, s, MSTATUS_MXR)
+            .is_ok());
+    }
+
+    #[test]
+    fn flush_by_address_and_asid() {
+        let mut env = Env::new(8);
+        env.map(0x3000, 0x5000, PTE_R);
+        env.map(0x4000, 0x5000, PTE_R | PTE_G);
+        env.asid = 1;
+        env.translate(0x3000, Access::Load).unwrap();
+        env.asid = 2;
+        env.translate(0x3000, Access::Load).unwrap();
+        env.translate(0x4000, Access::Load).unwrap();
+
+        // 只刷新一个地址空间
+        env.mmu.flush(None, Some(1));
+        env.asid = 1;
+        assert!(!env.hits(0x3000));
+        env.asid = 2;
+        assert!(env.hits(0x3000));
+        // 全局页不按地址空间刷新
+        assert!(env.hits(0x4000));
+
+        // 只刷新一个地址
+        env.mmu.flush(Some(0x3000), None);
+        assert!(!env.hits(0x3000));
+        assert!(env.hits(0x4000));
+        env.mmu.flush(Some(0x4000), Some(2));
+        assert!(env.hits(0x4000));
+
+        env.mmu.flush(None, None);
+        assert!(!env.hits(0x3000));
+        assert!(!env.hits(0x4000));
+    }
+
+    /// 每个字都读出同一个页表项
+    struct PageTable(u32);
+
+    impl Device for PageTable {
+        fn read(&mut self, _offset: u32, _size: u32) -> Result<u32> {
+            Ok(self.0)
+        }
+
+        fn write(&mut self, _offset: u32, value: u32, _size: u32) -> Result<()> {
+            self.0 = value;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn page_table_in_a_device() {
+        let mut env = Env::new(0);
+        let pte = PTE_V | PTE_R | PTE_W | PTE_A | PTE_D;
+        env.bus
+            .add_device(ROOT, 0x1000, None, Box::new(PageTable(pte)))
+            .unwrap();
+        assert_eq!(
+            env.translate(0x0012_3456, Access::Load).unwrap(),
+            0x0012_3456
+        );
+    }
+
+    /// M 模式取指不转换, mprv 让访存按 S 模式转换. 0x4000 处的根页表把 0-4MiB 映射到自己
+    #[test]
+    fn sfence_vma_and_tlb_stats() {
+        let config = Config {
+            tlb_entries: 4,
+            ..Config::default()
+        };
+        let mut cpu = cpu_with(
+            "
+            li t0, 0x4000
+            li t1, 0x7
+            sw t1, 0(t0)
+            li t0, 0x80000004
+            csrw satp, t0
+            li t0, 0x1800
+            csrrc zero, mstatus, t0
+            li t0, 0x20800
+            csrrs zero, mstatus, t0
+            lw a0, 0x100(zero)
+            lw a0, 0x104(zero)
+            sfence.vma
+            lw a0, 0x100(zero)
+            li t0, 0x100
+            sfence.vma t0
+            lw a0, 0x100(zero)
+            li t0, 1
+            sfence.vma zero, t0
+            lw a0, 0x100(zero)
+            ebreak
+        ",
+            "ebreak",
+            config,
+        );
+        run_to_error(&mut cpu, 100);
+        let stats = cpu.tlb_stats();
+        assert_eq!((stats.hits, stats.misses), (2, 3));
+    }
+}
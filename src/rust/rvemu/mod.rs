@@ -2,21 +2,138 @@ use super::*;
 use anyhow::{anyhow, Context, Result};
 use std::fmt::Display;
 
+mod asm;
+mod block;
+mod board;
+mod bus;
+mod clint;
+mod config;
 mod cpu;
+mod decode_cache;
+mod disasm;
 mod dram;
+mod encode;
+mod error;
+mod float;
 mod instr;
 mod irom;
+#[cfg(feature = "jit")]
+mod jit;
+mod mmu;
+mod plic;
+mod pmp;
+mod rvc;
+#[cfg(test)]
+mod tests;
+mod trap;
 
+use block::*;
+use board::*;
+use bus::*;
+use clint::*;
+use decode_cache::*;
 use dram::*;
 use irom::*;
+#[cfg(feature = "jit")]
+use jit::*;
+use mmu::*;
+use plic::*;
+use pmp::*;
 
+pub use asm::{assemble, Program};
+pub use board::{DIG_ADDR, SWITCH_ADDR};
+pub use bus::Device;
+pub use config::*;
 pub use cpu::*;
+pub use disasm::SymbolTable;
+pub use error::*;
 pub use instr::*;
+pub use mmu::TlbStats;
+pub use plic::{PLIC_SOURCES, SWITCH_IRQ};
+pub use rvc::{inst_len, is_compressed};
+pub use trap::*;
 
+const FFLAGS: u32 = 0x0001;
+const FRM: u32 = 0x0002;
+const FCSR: u32 = 0x0003;
+const SSTATUS: u32 = 0x0100;
+const SIE: u32 = 0x0104;
+const STVEC: u32 = 0x0105;
+const SCOUNTEREN: u32 = 0x0106;
+const SSCRATCH: u32 = 0x0140;
+const SEPC: u32 = 0x0141;
+const SCAUSE: u32 = 0x0142;
+const STVAL: u32 = 0x0143;
+const SIP: u32 = 0x0144;
+const SATP: u32 = 0x0180;
 const MSTATUS: u32 = 0x0300;
+const MISA: u32 = 0x0301;
+const MEDELEG: u32 = 0x0302;
+const MIDELEG: u32 = 0x0303;
+const MIE: u32 = 0x0304;
+const MTVEC: u32 = 0x0305;
+const MCOUNTEREN: u32 = 0x0306;
+const PMPCFG0: u32 = 0x03a0;
+const PMPCFG3: u32 = 0x03a3;
+const PMPADDR0: u32 = 0x03b0;
+const PMPADDR15: u32 = 0x03bf;
+const MSCRATCH: u32 = 0x0340;
 const MEPC: u32 = 0x0341;
 const MCAUSE: u32 = 0x0342;
 const MTVAL: u32 = 0x343;
+const MIP: u32 = 0x0344;
+const MVENDORID: u32 = 0x0f11;
+const MARCHID: u32 = 0x0f12;
+const MIMPID: u32 = 0x0f13;
+const MHARTID: u32 = 0x0f14;
+
+// mstatus
+const MSTATUS_SIE: u32 = 1 << 1;
+const MSTATUS_MIE: u32 = 1 << 3;
+const MSTATUS_SPIE: u32 = 1 << 5;
+const MSTATUS_MPIE: u32 = 1 << 7;
+const MSTATUS_SPP: u32 = 1 << 8;
+const MSTATUS_MPP_SHIFT: u32 = 11;
+const MSTATUS_MPP: u32 = 0b11 << MSTATUS_MPP_SHIFT;
+const MSTATUS_FS_SHIFT: u32 = 13;
+const MSTATUS_FS: u32 = 0b11 << MSTATUS_FS_SHIFT;
+const MSTATUS_MPRV: u32 = 1 << 17;
+const MSTATUS_SUM: u32 = 1 << 18;
+const MSTATUS_MXR: u32 = 1 << 19;
+/// FS = Dirty 时为 1, 只读
+const MSTATUS_SD: u32 = 1 << 31;
+/// sstatus 能看到的 mstatus 字段
+const SSTATUS_MASK: u32 =
+    MSTATUS_SIE | MSTATUS_SPIE | MSTATUS_SPP | MSTATUS_FS | MSTATUS_SUM | MSTATUS_MXR;
+
+// mstatus.FS: 浮点状态
+const FS_OFF: u32 = 0;
+const FS_INITIAL: u32 = 1;
+const FS_DIRTY: u32 = 3;
+
+// mie / mip
+const MIP_SSIP: u32 = 1 << 1;
+const MIP_MSIP: u32 = 1 << 3;
+const MIP_STIP: u32 = 1 << 5;
+const MIP_MTIP: u32 = 1 << 7;
+const MIP_SEIP: u32 = 1 << 9;
+const MIP_MEIP: u32 = 1 << 11;
+/// 可以委托给 S 模式的中断
+const MIDELEG_MASK: u32 = MIP_SSIP | MIP_STIP | MIP_SEIP;
+/// 可以委托给 S 模式的异常: 除了 M 模式的 ecall 和保留的异常号
+const MEDELEG_MASK: u32 = 0xb3ff;
+
+// zicntr
+const CYCLE: u32 = 0xc00;
+const TIME: u32 = 0xc01;
+const INSTRET: u32 = 0xc02;
+const CYCLEH: u32 = 0xc80;
+const TIMEH: u32 = 0xc81;
+const INSTRETH: u32 = 0xc82;
+const MCYCLE: u32 = 0xb00;
+const MINSTRET: u32 = 0xb02;
+const MCYCLEH: u32 = 0xb80;
+const MINSTRETH: u32 = 0xb82;
 
 pub const ABI: [&str; 32] = [
     "zero", " ra ", " sp ", " gp ", " tp ", " t0 ", " t1 ", " t2 ", " s0 ", " s1 ", " a0 ", " a1 ",
@@ -24,14 +141,62 @@ pub const ABI: [&str; 32] = [
     " s8 ", " s9 ", " s10", " s11", " t3 ", " t4 ", " t5 ", " t6 ",
 ];
 
-const SWITCH_ADDR: u32 = 0xffff_f070;
-const DIG_ADDR: u32 = 0xffff_f000;
+/// ILP32E: 只有 x0-x15
+pub const ABI_E: [&str; 16] = [
+    "zero", " ra ", " sp ", " gp ", " tp ", " t0 ", " t1 ", " t2 ", " s0 ", " s1 ", " a0 ", " a1 ",
+    " a2 ", " a3 ", " a4 ", " a5 ",
+];
+
+/// 浮点寄存器的 ABI 名字
+pub const FABI: [&str; 32] = [
+    " ft0", " ft1", " ft2", " ft3", " ft4", " ft5", " ft6", " ft7", " fs0", " fs1", " fa0", " fa1",
+    " fa2", " fa3", " fa4", " fa5", " fa6", " fa7", " fs2", " fs3", " fs4", " fs5", " fs6", " fs7",
+    " fs8", " fs9", "fs10", "fs11", " ft8", " ft9", "ft10", "ft11",
+];
 
 fn csr_abi(csr: &CSR) -> String {
     match *csr {
         MCAUSE => "mcause".to_string(),
         MSTATUS => "mstatus".to_string(),
         MEPC => "mepc".to_string(),
+        MISA => "misa".to_string(),
+        MTVEC => "mtvec".to_string(),
+        MSCRATCH => "mscratch".to_string(),
+        MTVAL => "mtval".to_string(),
+        MIE => "mie".to_string(),
+        MIP => "mip".to_string(),
+        MEDELEG => "medeleg".to_string(),
+        MIDELEG => "mideleg".to_string(),
+        MCOUNTEREN => "mcounteren".to_string(),
+        FFLAGS => "fflags".to_string(),
+        FRM => "frm".to_string(),
+        FCSR => "fcsr".to_string(),
+        SSTATUS => "sstatus".to_string(),
+        SIE => "sie".to_string(),
+        STVEC => "stvec".to_string(),
+        SCOUNTEREN => "scounteren".to_string(),
+        SSCRATCH => "sscratch".to_string(),
+        SEPC => "sepc".to_string(),
+        SCAUSE => "scause".to_string(),
+        STVAL => "stval".to_string(),
+        SIP => "sip".to_string(),
+        SATP => "satp".to_string(),
+        MVENDORID => "mvendorid".to_string(),
+        MARCHID => "marchid".to_string(),
+        MIMPID => "mimpid".to_string(),
+        MHARTID => "mhartid".to_string(),
+        CYCLE => "cycle".to_string(),
+        TIME => "time".to_string(),
+        INSTRET => "instret".to_string(),
+        CYCLEH => "cycleh".to_string(),
+        TIMEH => "timeh".to_string(),
+        INSTRETH => "instreth".to_string(),
+        MCYCLE => "mcycle".to_string(),
+        MINSTRET => "minstret".to_string(),
+        MCYCLEH => "mcycleh".to_string(),
+        MINSTRETH => "minstreth".to_string(),
+        PMPCFG0..=PMPCFG3 => format!("pmpcfg{}", csr - PMPCFG0),
+        PMPADDR0..=PMPADDR15 => format!("pmpaddr{}", csr - PMPADDR0),
         _ => format!("csr_{:#x}", csr),
     }
 }
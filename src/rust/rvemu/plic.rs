@@ -0,0 +1,149 @@
+use super::*;
+
+pub const PLIC_BASE: u32 = 0x0c00_0000;
+pub const PLIC_SIZE: u32 = 0x0400_0000;
+const PRIORITY: u32 = 0x00_0000;
+const PENDING: u32 = 0x00_1000;
+const ENABLE: u32 = 0x00_2000;
+const THRESHOLD: u32 = 0x20_0000;
+const CLAIM: u32 = 0x20_0004;
+
+/// 中断源的个数, 0 号保留
+pub const PLIC_SOURCES: u32 = 32;
+/// 开关/按键的中断源
+pub const SWITCH_IRQ: u32 = 1;
+
+/// 简化的 PLIC: 单核, 只有一个 M 模式的 context
+#[allow(clippy::upper_case_acronyms)]
+pub struct PLIC {
+    priority: [u32; PLIC_SOURCES as usize],
+    pending: u32,
+    enable: u32,
+    threshold: u32,
+    /// 中断线当前的电平
+    level: u32,
+    /// 已经 claim, 还没有 complete 的中断源
+    claimed: u32,
+}
+
+impl PLIC {
+    pub fn new() -> Self {
+        Self {
+            priority: [0; PLIC_SOURCES as usize],
+            pending: 0,
+            enable: 0,
+            threshold: 0,
+            level: 0,
+            claimed: 0,
+        }
+    }
+
+    /// 电平触发: 中断线为高且不在处理中时置 pending
+    pub fn set_level(&mut self, source: u32, level: bool) -> Result<()> {
+        let bit = Self::bit(source).with_context(|| context!())?;
+        if level {
+            self.level |= bit;
+            self.pending |= bit & !self.claimed;
+        } else {
+            self.level &= !bit;
+        }
+        Ok(())
+    }
+
+    /// 边沿触发: 直接置 pending
+    pub fn trigger(&mut self, source: u32) -> Result<()> {
+        let bit = Self::bit(source).with_context(|| context!())?;
+        self.pending |= bit;
+        Ok(())
+    }
+
+    /// mip.MEIP
+    pub fn external_pending(&self) -> bool {
+        self.pending & self.enable != 0 && self.best().is_some()
+    }
+
+    fn bit(source: u32) -> Result<u32> {
+        if source == 0 || source >= PLIC_SOURCES {
+            return Err(anyhow!("plic: invalid interrupt source {}", source))
+                .with_context(|| context!());
+        }
+        Ok(1 << source)
+    }
+
+    /// 优先级最高 (相同时编号最小) 且超过阈值的待处理中断源
+    fn best(&self) -> Option<u32> {
+        let candidates = self.pending & self.enable;
+        (1..PLIC_SOURCES)
+            .filter(|&source| candidates & (1 << source) != 0)
+            .filter(|&source| self.priority[source as usize] > self.threshold)
+            .min_by_key(|&source| std::cmp::Reverse(self.priority[source as usize]))
+    }
+
+    fn claim(&mut self) -> u32 {
+        match self.best() {
+            Some(source) => {
+                self.pending &= !(1 << source);
+                self.claimed |= 1 << source;
+                source
+            }
+            None => 0,
+        }
+    }
+
+    fn complete(&mut self, source: u32) {
+        if let Ok(bit) = Self::bit(source) {
+            self.claimed &= !bit;
+            // 电平仍然为高, 再次进入 pending
+            self.pending |= bit & self.level;
+        }
+    }
+}
+
+impl Device for PLIC {
+    /// 寄存器都是 32 位的, 只支持按字访问. 读 claim 寄存器有副作用.
+    fn read(&mut self, offset: u32, size: u32) -> Result<u32> {
+        if size != 32 || offset % 4 != 0 {
+            return Err(anyhow!(
+                "plic: invalid access: {:#x} ({} bits)",
+                offset,
+                size
+            ))
+            .with_context(|| context!());
+        }
+        match offset {
+            offset if offset < PRIORITY + 4 * PLIC_SOURCES => {
+                Ok(self.priority[(offset / 4) as usize])
+            }
+            PENDING => Ok(self.pending),
+            ENABLE => Ok(self.enable),
+            THRESHOLD => Ok(self.threshold),
+            CLAIM => Ok(self.claim()),
+            _ => Ok(0),
+        }
+    }
+
+    fn write(&mut self, offset: u32, value: u32, size: u32) -> Result<()> {
+        if size != 32 || offset % 4 != 0 {
+            return Err(anyhow!(
+                "plic: invalid access: {:#x} ({} bits)",
+                offset,
+                size
+            ))
+            .with_context(|| context!());
+        }
+        match offset {
+            // 0 号中断源不存在
+            0 => {}
+            offset if offset < PRIORITY + 4 * PLIC_SOURCES => {
+                self.priority[(offset / 4) as usize] = value & 0x7
+            }
+            // pending 只读
+            PENDING => {}
+            ENABLE => self.enable = value & !1,
+            THRESHOLD => self.threshold = value & 0x7,
+            CLAIM => self.complete(value),
+            _ => {}
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,255 @@
+use super::*;
+
+/// pmpcfg 中每个表项的字段
+const PMP_R: u8 = 1 << 0;
+const PMP_W: u8 = 1 << 1;
+const PMP_X: u8 = 1 << 2;
+const PMP_A: u8 = 0b11 << 3;
+const PMP_L: u8 = 1 << 7;
+
+const PMP_OFF: u8 = 0;
+const PMP_TOR: u8 = 1;
+const PMP_NA4: u8 = 2;
+const PMP_NAPOT: u8 = 3;
+
+/// 最多 16 个表项
+pub const PMP_ENTRIES: usize = 16;
+
+/// 物理内存保护, 粒度为 4 字节
+#[allow(clippy::upper_case_acronyms)]
+pub struct PMP {
+    /// 实现了的表项个数, 其余的 csr 恒为 0
+    entries: usize,
+    cfg: [u8; PMP_ENTRIES],
+    addr: [u32; PMP_ENTRIES],
+}
+
+impl PMP {
+    pub fn new(entries: usize) -> Self {
+        Self {
+            entries: entries.min(PMP_ENTRIES),
+            cfg: [0; PMP_ENTRIES],
+            addr: [0; PMP_ENTRIES],
+        }
+    }
+
+    /// pmpcfg{index}: 每个 csr 包含 4 个表项的配置
+    pub fn load_cfg(&self, index: u32) -> u32 {
+        (0..4).fold(0, |value, i| {
+            value | (self.cfg[(index * 4 + i) as usize] as u32) << (i * 8)
+        })
+    }
+
+    pub fn store_cfg(&mut self, index: u32, value: u32) {
+        for i in 0..4 {
+            let entry = (index * 4 + i) as usize;
+            // 锁住的表项只有复位才能修改
+            if entry >= self.entries || self.cfg[entry] & PMP_L != 0 {
+                continue;
+            }
+            let mut cfg = (value >> (i * 8)) as u8 & (PMP_R | PMP_W | PMP_X | PMP_A | PMP_L);
+            // WARL: R = 0, W = 1 是保留的组合
+            if cfg & (PMP_R | PMP_W) == PMP_W {
+                cfg &= !PMP_W;
+            }
+            self.cfg[entry] = cfg;
+        }
+    }
+
+    pub fn load_addr(&self, index: u32) -> u32 {
+        self.addr[index as usize]
+    }
+
+    pub fn store_addr(&mut self, index: u32, value: u32) {
+        let entry = index as usize;
+        if entry >= self.entries || self.cfg[entry] & PMP_L != 0 {
+            return;
+        }
+        // 下一项是锁住的 TOR 时, 这一项是它的下界, 也不能修改
+        if let Some(&next) = self.cfg.get(entry + 1) {
+            if next & PMP_L != 0 && (next & PMP_A) >> 3 == PMP_TOR {
+                return;
+            }
+        }
+        self.addr[entry] = value;
+    }
+
+    /// 表项匹配的地址范围 [lo, hi)
+    fn range(&self, entry: usize) -> Option<(u64, u64)> {
+        let addr = self.addr[entry] as u64;
+        match (self.cfg[entry] & PMP_A) >> 3 {
+            PMP_OFF => None,
+            PMP_TOR => {
+                let lo = if entry == 0 {
+                    0
+                } else {
+                    (self.addr[entry - 1] as u64) << 2
+                };
+                Some((lo, addr << 2))
+            }
+            PMP_NA4 => Some((addr << 2, (addr << 2) + 4)),
+            PMP_NAPOT => {
+                // pmpaddr 末尾 n 个 1 表示大小为 2^(n+3) 字节
+                let ones = self.addr[entry].trailing_ones() as u64;
+                let base = (addr & !((1 << ones) - 1)) << 2;
+                Some((base, base + (1 << (ones + 3))))
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    /// [addr, addr + size / 8) 能否以 access 的方式访问.
+    /// 编号最小的匹配项决定结果, 没有匹配项时只有 M 模式可以访问.
+    pub fn check(&self, addr: u32, size: u32, access: Access, mode: Privilege) -> bool {
+        let lo = addr as u64;
+        let hi = lo + (size / 8) as u64;
+        for entry in 0..self.entries {
+            let Some((start, end)) = self.range(entry) else {
+                continue;
+            };
+            if hi <= start || end <= lo {
+                continue;
+            }
+            // 只匹配了一部分的访问总是失败
+            if lo < start || end < hi {
+                return false;
+            }
+            let cfg = self.cfg[entry];
+            if mode == Privilege::Machine && cfg & PMP_L == 0 {
+                return true;
+            }
+            let perm = match access {
+                Access::Fetch => PMP_X,
+                Access::Load => PMP_R,
+                Access::Store => PMP_W,
+            };
+            return cfg & perm != 0;
+        }
+        mode == Privilege::Machine || self.entries == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const S: Privilege = Privilege::Supervisor;
+    const M: Privilege = Privilege::Machine;
+
+    /// 按表项编号设置 pmpaddr 和 pmpcfg
+    fn pmp(entries: &[(u32, u8)]) -> PMP {
+        let mut pmp = PMP::new(PMP_ENTRIES);
+        for (i, &(addr, _)) in entries.iter().enumerate() {
+            pmp.store_addr(i as u32, addr);
+        }
+        for (i, &(_, cfg)) in entries.iter().enumerate() {
+            let index = i as u32 / 4;
+            let value = pmp.load_cfg(index) | (cfg as u32) << (i % 4 * 8);
+            pmp.store_cfg(index, value);
+        }
+        pmp
+    }
+
+    const fn a(mode: u8) -> u8 {
+        mode << 3
+    }
+
+    #[test]
+    fn tor_ranges() {
+        let pmp = pmp(&[
+            (0x1000 >> 2, a(PMP_TOR) | PMP_R),
+            (0x3000 >> 2, a(PMP_TOR) | PMP_R | PMP_W),
+        ]);
+        // 表项 0 的下界是 0
+        assert_eq!(pmp.range(0), Some((0, 0x1000)));
+        assert_eq!(pmp.range(1), Some((0x1000, 0x3000)));
+        assert!(pmp.check(0, 32, Access::Load, S));
+        assert!(!pmp.check(0xffc, 32, Access::Store, S));
+        assert!(pmp.check(0x1000, 32, Access::Store, S));
+        assert!(pmp.check(0x2ffc, 32, Access::Store, S));
+        assert!(!pmp.check(0x3000, 32, Access::Load, S));
+    }
+
+    #[test]
+    fn na4_and_napot_ranges() {
+        let pmp = pmp(&[
+            (0x1000 >> 2, a(PMP_NA4) | PMP_R),
+            ((0x2000 >> 2) | 0x1ff, a(PMP_NAPOT) | PMP_R),
+            (u32::MAX, a(PMP_NAPOT) | PMP_X),
+        ]);
+        assert_eq!(pmp.range(0), Some((0x1000, 0x1004)));
+        assert_eq!(pmp.range(1), Some((0x2000, 0x3000)));
+        // 全 1 时大小是 2^35 字节, 覆盖整个物理地址空间
+        assert_eq!(pmp.range(2), Some((0, 1 << 35)));
+        assert!(pmp.check(0x1000, 32, Access::Load, S));
+        assert!(!pmp.check(0x1004, 32, Access::Load, S));
+        assert!(pmp.check(0x2ffc, 32, Access::Load, S));
+        assert!(pmp.check(0xffff_fffc, 32, Access::Fetch, S));
+    }
+
+    #[test]
+    fn partial_match_is_denied() {
+        let pmp = pmp(&[
+            (0x1000 >> 2, a(PMP_NA4) | PMP_R | PMP_W),
+            (u32::MAX, a(PMP_NAPOT) | PMP_R | PMP_W),
+        ]);
+        assert!(pmp.check(0x1000, 32, Access::Load, S));
+        // 跨过表项 0 的边界, 即使表项 1 包含整个访问也失败
+        assert!(!pmp.check(0x1002, 32, Access::Load, S));
+        assert!(!pmp.check(0x0ffe, 32, Access::Load, S));
+        assert!(!pmp.check(0x1002, 32, Access::Load, M));
+    }
+
+    #[test]
+    fn lowest_numbered_entry_wins() {
+        let pmp = pmp(&[(0x1000 >> 2, a(PMP_NA4)), (u32::MAX, a(PMP_NAPOT) | PMP_R)]);
+        assert!(!pmp.check(0x1000, 32, Access::Load, S));
+        assert!(pmp.check(0x1004, 32, Access::Load, S));
+    }
+
+    #[test]
+    fn unmatched_access_needs_machine_mode() {
+        assert!(PMP::new(0).check(0x1000, 32, Access::Load, S));
+        // 实现了表项就要有匹配项, 哪怕所有表项都关着
+        let pmp = PMP::new(4);
+        assert!(!pmp.check(0x1000, 32, Access::Load, S));
+        assert!(!pmp.check(0x1000, 32, Access::Load, Privilege::User));
+        assert!(pmp.check(0x1000, 32, Access::Load, M));
+    }
+
+    #[test]
+    fn locked_entry_applies_to_machine_mode() {
+        let pmp = pmp(&[
+            (0x1000 >> 2, a(PMP_NA4) | PMP_R | PMP_L),
+            (0x1004 >> 2, a(PMP_NA4) | PMP_R),
+        ]);
+        assert!(pmp.check(0x1000, 32, Access::Load, M));
+        assert!(!pmp.check(0x1000, 32, Access::Store, M));
+        assert!(pmp.check(0x1004, 32, Access::Store, M));
+        assert!(!pmp.check(0x1004, 32, Access::Store, S));
+    }
+
+    #[test]
+    fn locked_entries_ignore_writes() {
+        let mut pmp = pmp(&[
+            (0x1000 >> 2, a(PMP_OFF)),
+            (0x3000 >> 2, a(PMP_TOR) | PMP_R | PMP_L),
+            (0x4000 >> 2, a(PMP_OFF)),
+            (0x5000 >> 2, a(PMP_NA4) | PMP_L),
+        ]);
+        pmp.store_cfg(0, 0);
+        let locked =
+            ((a(PMP_TOR) | PMP_R | PMP_L) as u32) << 8 | ((a(PMP_NA4) | PMP_L) as u32) << 24;
+        assert_eq!(pmp.load_cfg(0), locked);
+        pmp.store_addr(1, 0);
+        pmp.store_addr(3, 0);
+        // 锁住的 TOR 表项的下界也不能修改
+        pmp.store_addr(0, 0);
+        // 下一项锁住但不是 TOR 时可以修改
+        pmp.store_addr(2, 0x6000 >> 2);
+        assert_eq!(pmp.load_addr(0), 0x1000 >> 2);
+        assert_eq!(pmp.load_addr(1), 0x3000 >> 2);
+        assert_eq!(pmp.load_addr(2), 0x6000 >> 2);
+        assert_eq!(pmp.load_addr(3), 0x5000 >> 2);
+    }
+}
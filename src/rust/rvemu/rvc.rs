@@ -0,0 +1,479 @@
+use super::*;
+
+/// 16 位压缩指令: 最低两位不是 0b11
+pub fn is_compressed(inst: u32) -> bool {
+    inst & 0b11 != 0b11
+}
+
+/// 指令长度 (字节)
+pub fn inst_len(inst: u32) -> u32 {
+    if is_compressed(inst) {
+        2
+    } else {
+        4
+    }
+}
+
+/// rd'/rs1'/rs2' 只能编码 x8-x15
+fn creg(bits: u32) -> Reg {
+    8 + (bits & 0x7)
+}
+
+/// 从 inst 中取出 [hi:lo] 并放到 imm 的 pos 位置
+fn bits(inst: u32, hi: u32, lo: u32, pos: u32) -> u32 {
+    ((inst >> lo) & ((1 << (hi - lo + 1)) - 1)) << pos
+}
+
+/// bits 的逆操作: 把 imm[pos+hi-lo:pos] 放到 inst 的 [hi:lo]
+fn unbits(imm: u32, hi: u32, lo: u32, pos: u32) -> u32 {
+    ((imm >> pos) & ((1 << (hi - lo + 1)) - 1)) << lo
+}
+
+/// 将低 width 位符号扩展
+fn sext(imm: u32, width: u32) -> i32 {
+    ((imm << (32 - width)) as i32) >> (32 - width)
+}
+
+fn illegal(inst: u32) -> DecodeError {
+    DecodeError::Compressed(inst)
+}
+
+fn compressed(mnemonic: &'static str, expanded: Instr) -> Result<Instr> {
+    Ok(Instr::C(mnemonic, Box::new(expanded)))
+}
+
+/// 将 16 位压缩指令展开成对应的 32 位指令
+pub fn decompress(inst: u32) -> Result<Instr> {
+    let inst = inst & 0xffff;
+    let op = inst & 0b11;
+    let funct3 = (inst >> 13) & 0x7;
+    let rd = (inst >> 7) & 0x1f;
+    let rs2 = (inst >> 2) & 0x1f;
+    let rd_ = creg(inst >> 2); // [4:2]
+    let rs1_ = creg(inst >> 7); // [9:7]
+    match (op, funct3) {
+        // quadrant 0
+        (0b00, 0b000) => {
+            // nzuimm[5:4|9:6|2|3] = inst[12:11|10:7|6|5]
+            let imm = bits(inst, 12, 11, 4)
+                | bits(inst, 10, 7, 6)
+                | bits(inst, 6, 6, 2)
+                | bits(inst, 5, 5, 3);
+            if imm == 0 {
+                return Err(illegal(inst)).with_context(|| context!());
+            }
+            compressed("c.addi4spn", Instr::ADDI(rd_, 2, imm as i32))
+        }
+        (0b00, 0b010) => {
+            // offset[5:3|2|6] = inst[12:10|6|5]
+            let offset = bits(inst, 12, 10, 3) | bits(inst, 6, 6, 2) | bits(inst, 5, 5, 6);
+            compressed("c.lw", Instr::LW(rd_, offset as i32, rs1_))
+        }
+        (0b00, 0b011) => {
+            let offset = bits(inst, 12, 10, 3) | bits(inst, 6, 6, 2) | bits(inst, 5, 5, 6);
+            compressed("c.flw", Instr::FLW(rd_, offset as i32, rs1_))
+        }
+        (0b00, 0b110) => {
+            let offset = bits(inst, 12, 10, 3) | bits(inst, 6, 6, 2) | bits(inst, 5, 5, 6);
+            compressed("c.sw", Instr::SW(rd_, offset as i32, rs1_))
+        }
+        (0b00, 0b111) => {
+            let offset = bits(inst, 12, 10, 3) | bits(inst, 6, 6, 2) | bits(inst, 5, 5, 6);
+            compressed("c.fsw", Instr::FSW(rd_, offset as i32, rs1_))
+        }
+        // quadrant 1
+        (0b01, 0b000) => {
+            // imm[5|4:0] = inst[12|6:2]
+            let imm = sext(bits(inst, 12, 12, 5) | bits(inst, 6, 2, 0), 6);
+            if rd == 0 {
+                compressed("c.nop", Instr::ADDI(0, 0, 0))
+            } else {
+                compressed("c.addi", Instr::ADDI(rd, rd, imm))
+            }
+        }
+        (0b01, 0b001) | (0b01, 0b101) => {
+            // offset[11|4|9:8|10|6|7|3:1|5] = inst[12|11|10:9|8|7|6|5:3|2]
+            let offset = bits(inst, 12, 12, 11)
+                | bits(inst, 11, 11, 4)
+                | bits(inst, 10, 9, 8)
+                | bits(inst, 8, 8, 10)
+                | bits(inst, 7, 7, 6)
+                | bits(inst, 6, 6, 7)
+                | bits(inst, 5, 3, 1)
+                | bits(inst, 2, 2, 5);
+            let offset = sext(offset, 12);
+            if funct3 == 0b001 {
+                compressed("c.jal", Instr::JAL(1, offset))
+            } else {
+                compressed("c.j", Instr::JAL(0, offset))
+            }
+        }
+        (0b01, 0b010) => {
+            let imm = sext(bits(inst, 12, 12, 5) | bits(inst, 6, 2, 0), 6);
+            compressed("c.li", Instr::ADDI(rd, 0, imm))
+        }
+        (0b01, 0b011) if rd == 2 => {
+            // nzimm[9|4|6|8:7|5] = inst[12|6|5|4:3|2]
+            let imm = bits(inst, 12, 12, 9)
+                | bits(inst, 6, 6, 4)
+                | bits(inst, 5, 5, 6)
+                | bits(inst, 4, 3, 7)
+                | bits(inst, 2, 2, 5);
+            if imm == 0 {
+                return Err(illegal(inst)).with_context(|| context!());
+            }
+            compressed("c.addi16sp", Instr::ADDI(2, 2, sext(imm, 10)))
+        }
+        (0b01, 0b011) => {
+            // nzimm[17|16:12] = inst[12|6:2]
+            let imm = bits(inst, 12, 12, 17) | bits(inst, 6, 2, 12);
+            // rd = x0 是 HINT, 当作 nop 执行; 只有 nzimm = 0 是保留的
+            if imm == 0 {
+                return Err(illegal(inst)).with_context(|| context!());
+            }
+            compressed("c.lui", Instr::LUI(rd, sext(imm, 18) as u32))
+        }
+        (0b01, 0b100) => {
+            let rd = rs1_;
+            let imm = bits(inst, 12, 12, 5) | bits(inst, 6, 2, 0);
+            match (inst >> 10) & 0b11 {
+                // RV32C: shamt[5] 必须为 0
+                0b00 if imm & 0x20 == 0 => compressed("c.srli", Instr::SRLI(rd, rd, imm as i32)),
+                0b01 if imm & 0x20 == 0 => compressed("c.srai", Instr::SRAI(rd, rd, imm as i32)),
+                0b10 => compressed("c.andi", Instr::ANDI(rd, rd, sext(imm, 6))),
+                0b11 if inst & (1 << 12) == 0 => match (inst >> 5) & 0b11 {
+                    0b00 => compressed("c.sub", Instr::SUB(rd, rd, rd_)),
+                    0b01 => compressed("c.xor", Instr::XOR(rd, rd, rd_)),
+                    0b10 => compressed("c.or", Instr::OR(rd, rd, rd_)),
+                    _ => compressed("c.and", Instr::AND(rd, rd, rd_)),
+                },
+                _ => Err(illegal(inst)).with_context(|| context!()),
+            }
+        }
+        (0b01, 0b110) | (0b01, 0b111) => {
+            // offset[8|4:3|7:6|2:1|5] = inst[12|11:10|6:5|4:3|2]
+            let offset = bits(inst, 12, 12, 8)
+                | bits(inst, 11, 10, 3)
+                | bits(inst, 6, 5, 6)
+                | bits(inst, 4, 3, 1)
+                | bits(inst, 2, 2, 5);
+            let offset = sext(offset, 9);
+            if funct3 == 0b110 {
+                compressed("c.beqz", Instr::BEQ(rs1_, 0, offset))
+            } else {
+                compressed("c.bnez", Instr::BNE(rs1_, 0, offset))
+            }
+        }
+        // quadrant 2
+        (0b10, 0b000) => {
+            let shamt = bits(inst, 12, 12, 5) | bits(inst, 6, 2, 0);
+            if shamt & 0x20 != 0 {
+                return Err(illegal(inst)).with_context(|| context!());
+            }
+            compressed("c.slli", Instr::SLLI(rd, rd, shamt as i32))
+        }
+        (0b10, 0b010) => {
+            // offset[5|4:2|7:6] = inst[12|6:4|3:2]
+            let offset = bits(inst, 12, 12, 5) | bits(inst, 6, 4, 2) | bits(inst, 3, 2, 6);
+            if rd == 0 {
+                return Err(illegal(inst)).with_context(|| context!());
+            }
+            compressed("c.lwsp", Instr::LW(rd, offset as i32, 2))
+        }
+        (0b10, 0b011) => {
+            // f0 也可以作为 rd
+            let offset = bits(inst, 12, 12, 5) | bits(inst, 6, 4, 2) | bits(inst, 3, 2, 6);
+            compressed("c.flwsp", Instr::FLW(rd, offset as i32, 2))
+        }
+        (0b10, 0b100) => match (inst & (1 << 12) != 0, rd, rs2) {
+            (false, 0, 0) => Err(illegal(inst)).with_context(|| context!()),
+            (false, rs1, 0) => compressed("c.jr", Instr::JALR(0, 0, rs1)),
+            (false, rd, rs2) => compressed("c.mv", Instr::ADD(rd, 0, rs2)),
+            (true, 0, 0) => compressed("c.ebreak", Instr::EBREAK),
+            (true, rs1, 0) => compressed("c.jalr", Instr::JALR(1, 0, rs1)),
+            (true, rd, rs2) => compressed("c.add", Instr::ADD(rd, rd, rs2)),
+        },
+        (0b10, 0b110) => {
+            // offset[5:2|7:6] = inst[12:9|8:7]
+            let offset = bits(inst, 12, 9, 2) | bits(inst, 8, 7, 6);
+            compressed("c.swsp", Instr::SW(rs2, offset as i32, 2))
+        }
+        (0b10, 0b111) => {
+            let offset = bits(inst, 12, 9, 2) | bits(inst, 8, 7, 6);
+            compressed("c.fswsp", Instr::FSW(rs2, offset as i32, 2))
+        }
+        _ => Err(illegal(inst)).with_context(|| context!()),
+    }
+}
+
+/// x8-x15 编码成 3 位
+fn uncreg(reg: Reg) -> Result<u32> {
+    if !(8..16).contains(&reg) {
+        return Err(anyhow!("x{} is not one of x8-x15", reg)).with_context(|| context!());
+    }
+    Ok(reg - 8)
+}
+
+/// 压缩指令中 5 位的寄存器, x0 通常是保留的
+fn nonzero(reg: Reg) -> Result<u32> {
+    if reg == 0 {
+        return Err(anyhow!("x0 is reserved here")).with_context(|| context!());
+    }
+    encode::reg(reg)
+}
+
+fn mismatch(mnemonic: &str, expanded: &Instr) -> anyhow::Error {
+    anyhow!("{} cannot encode {:?}", mnemonic, expanded)
+}
+
+/// decompress 的逆操作: 按助记符把展开后的指令编码成 16 位
+pub fn compress(mnemonic: &str, expanded: &Instr) -> Result<u32> {
+    match (mnemonic, expanded) {
+        // quadrant 0
+        ("c.addi4spn", &Instr::ADDI(rd, 2, imm)) => {
+            let imm = encode::unsigned(imm, 10, 4).with_context(|| context!())?;
+            if imm == 0 {
+                return Err(anyhow!("c.addi4spn needs a non-zero immediate"))
+                    .with_context(|| context!());
+            }
+            Ok(unbits(imm, 12, 11, 4)
+                | unbits(imm, 10, 7, 6)
+                | unbits(imm, 6, 6, 2)
+                | unbits(imm, 5, 5, 3)
+                | uncreg(rd)? << 2)
+        }
+        ("c.lw", &Instr::LW(r, offset, base))
+        | ("c.sw", &Instr::SW(r, offset, base))
+        | ("c.flw", &Instr::FLW(r, offset, base))
+        | ("c.fsw", &Instr::FSW(r, offset, base)) => {
+            let funct3 = match mnemonic {
+                "c.lw" => 0b010,
+                "c.flw" => 0b011,
+                "c.sw" => 0b110,
+                _ => 0b111,
+            };
+            let offset = encode::unsigned(offset, 7, 4).with_context(|| context!())?;
+            Ok(funct3 << 13
+                | unbits(offset, 12, 10, 3)
+                | unbits(offset, 6, 6, 2)
+                | unbits(offset, 5, 5, 6)
+                | uncreg(base)? << 7
+                | uncreg(r)? << 2)
+        }
+        // quadrant 1
+        ("c.nop", &Instr::ADDI(0, 0, 0)) => Ok(0b01),
+        ("c.addi", &Instr::ADDI(rd, rs1, imm)) if rd == rs1 => {
+            let imm = encode::signed(imm, 6, 1).with_context(|| context!())?;
+            Ok(unbits(imm, 12, 12, 5) | nonzero(rd)? << 7 | unbits(imm, 6, 2, 0) | 0b01)
+        }
+        ("c.jal", &Instr::JAL(1, offset)) | ("c.j", &Instr::JAL(0, offset)) => {
+            let funct3 = if mnemonic == "c.jal" { 0b001 } else { 0b101 };
+            let offset = encode::signed(offset, 12, 2).with_context(|| context!())?;
+            Ok(funct3 << 13
+                | unbits(offset, 12, 12, 11)
+                | unbits(offset, 11, 11, 4)
+                | unbits(offset, 10, 9, 8)
+                | unbits(offset, 8, 8, 10)
+                | unbits(offset, 7, 7, 6)
+                | unbits(offset, 6, 6, 7)
+                | unbits(offset, 5, 3, 1)
+                | unbits(offset, 2, 2, 5)
+                | 0b01)
+        }
+        ("c.li", &Instr::ADDI(rd, 0, imm)) => {
+            let imm = encode::signed(imm, 6, 1).with_context(|| context!())?;
+            Ok(0b010 << 13
+                | unbits(imm, 12, 12, 5)
+                | encode::reg(rd)? << 7
+                | unbits(imm, 6, 2, 0)
+                | 0b01)
+        }
+        ("c.addi16sp", &Instr::ADDI(2, 2, imm)) => {
+            let imm = encode::signed(imm, 10, 16).with_context(|| context!())?;
+            if imm == 0 {
+                return Err(anyhow!("c.addi16sp needs a non-zero immediate"))
+                    .with_context(|| context!());
+            }
+            Ok(0b011 << 13
+                | unbits(imm, 12, 12, 9)
+                | 2 << 7
+                | unbits(imm, 6, 6, 4)
+                | unbits(imm, 5, 5, 6)
+                | unbits(imm, 4, 3, 7)
+                | unbits(imm, 2, 2, 5)
+                | 0b01)
+        }
+        ("c.lui", &Instr::LUI(rd, imm)) => {
+            // 高 14 位是 imm[17] 的符号扩展
+            let nzimm = encode::signed(imm as i32, 18, 1 << 12).with_context(|| context!())?;
+            if nzimm == 0 || rd == 2 {
+                return Err(anyhow!("c.lui needs a non-zero immediate and rd != sp"))
+                    .with_context(|| context!());
+            }
+            Ok(0b011 << 13
+                | unbits(nzimm, 12, 12, 17)
+                | encode::reg(rd)? << 7
+                | unbits(nzimm, 6, 2, 12)
+                | 0b01)
+        }
+        ("c.srli", &Instr::SRLI(rd, rs1, imm))
+        | ("c.srai", &Instr::SRAI(rd, rs1, imm))
+        | ("c.andi", &Instr::ANDI(rd, rs1, imm))
+            if rd == rs1 =>
+        {
+            let (funct2, imm) = match mnemonic {
+                "c.srli" => (0b00, encode::unsigned(imm, 5, 1)),
+                "c.srai" => (0b01, encode::unsigned(imm, 5, 1)),
+                _ => (0b10, encode::signed(imm, 6, 1)),
+            };
+            let imm = imm.with_context(|| context!())?;
+            Ok(0b100 << 13
+                | unbits(imm, 12, 12, 5)
+                | funct2 << 10
+                | uncreg(rd)? << 7
+                | unbits(imm, 6, 2, 0)
+                | 0b01)
+        }
+        ("c.sub", &Instr::SUB(rd, rs1, rs2))
+        | ("c.xor", &Instr::XOR(rd, rs1, rs2))
+        | ("c.or", &Instr::OR(rd, rs1, rs2))
+        | ("c.and", &Instr::AND(rd, rs1, rs2))
+            if rd == rs1 =>
+        {
+            let funct2 = match mnemonic {
+                "c.sub" => 0b00,
+                "c.xor" => 0b01,
+                "c.or" => 0b10,
+                _ => 0b11,
+            };
+            Ok(0b100011 << 10 | uncreg(rd)? << 7 | funct2 << 5 | uncreg(rs2)? << 2 | 0b01)
+        }
+        ("c.beqz", &Instr::BEQ(rs1, 0, offset)) | ("c.bnez", &Instr::BNE(rs1, 0, offset)) => {
+            let funct3 = if mnemonic == "c.beqz" { 0b110 } else { 0b111 };
+            let offset = encode::signed(offset, 9, 2).with_context(|| context!())?;
+            Ok(funct3 << 13
+                | unbits(offset, 12, 12, 8)
+                | unbits(offset, 11, 10, 3)
+                | uncreg(rs1)? << 7
+                | unbits(offset, 6, 5, 6)
+                | unbits(offset, 4, 3, 1)
+                | unbits(offset, 2, 2, 5)
+                | 0b01)
+        }
+        // quadrant 2
+        ("c.slli", &Instr::SLLI(rd, rs1, shamt)) if rd == rs1 => {
+            let shamt = encode::unsigned(shamt, 5, 1).with_context(|| context!())?;
+            Ok(encode::reg(rd)? << 7 | unbits(shamt, 6, 2, 0) | 0b10)
+        }
+        ("c.lwsp", &Instr::LW(rd, offset, 2)) | ("c.flwsp", &Instr::FLW(rd, offset, 2)) => {
+            let (funct3, rd) = if mnemonic == "c.lwsp" {
+                (0b010, nonzero(rd)?)
+            } else {
+                (0b011, encode::reg(rd)?)
+            };
+            let offset = encode::unsigned(offset, 8, 4).with_context(|| context!())?;
+            Ok(funct3 << 13
+                | unbits(offset, 12, 12, 5)
+                | rd << 7
+                | unbits(offset, 6, 4, 2)
+                | unbits(offset, 3, 2, 6)
+                | 0b10)
+        }
+        ("c.jr", &Instr::JALR(0, 0, rs1)) => Ok(0b1000 << 12 | nonzero(rs1)? << 7 | 0b10),
+        ("c.mv", &Instr::ADD(rd, 0, rs2)) => {
+            Ok(0b1000 << 12 | encode::reg(rd)? << 7 | nonzero(rs2)? << 2 | 0b10)
+        }
+        ("c.ebreak", Instr::EBREAK) => Ok(0b1001 << 12 | 0b10),
+        ("c.jalr", &Instr::JALR(1, 0, rs1)) => Ok(0b1001 << 12 | nonzero(rs1)? << 7 | 0b10),
+        ("c.add", &Instr::ADD(rd, rs1, rs2)) if rd == rs1 => {
+            Ok(0b1001 << 12 | encode::reg(rd)? << 7 | nonzero(rs2)? << 2 | 0b10)
+        }
+        ("c.swsp", &Instr::SW(rs2, offset, 2)) | ("c.fswsp", &Instr::FSW(rs2, offset, 2)) => {
+            let funct3 = if mnemonic == "c.swsp" { 0b110 } else { 0b111 };
+            let offset = encode::unsigned(offset, 8, 4).with_context(|| context!())?;
+            Ok(funct3 << 13
+                | unbits(offset, 12, 9, 2)
+                | unbits(offset, 8, 7, 6)
+                | encode::reg(rs2)? << 2
+                | 0b10)
+        }
+        _ => Err(mismatch(mnemonic, expanded)).with_context(|| context!()),
+    }
+}
+
+/// 以压缩指令的形式打印
+pub fn fmt_compressed(
+    f: &mut std::fmt::Formatter<'_>,
+    mnemonic: &str,
+    expanded: &Instr,
+) -> std::fmt::Result {
+    match expanded {
+        Instr::ADDI(_, _, _) if mnemonic == "c.nop" => write!(f, "{}", mnemonic),
+        Instr::ADDI(rd, rs1, imm) if mnemonic == "c.addi4spn" => write!(
+            f,
+            "{} {}, {}, {:#x}",
+            mnemonic, ABI[*rd as usize], ABI[*rs1 as usize], imm
+        ),
+        Instr::ADDI(rd, _, imm)
+        | Instr::ANDI(rd, _, imm)
+        | Instr::SLLI(rd, _, imm)
+        | Instr::SRLI(rd, _, imm)
+        | Instr::SRAI(rd, _, imm) => write!(f, "{} {}, {:#x}", mnemonic, ABI[*rd as usize], imm),
+        Instr::LUI(rd, umm) => write!(f, "{} {}, {:#x}", mnemonic, ABI[*rd as usize], *umm >> 12),
+        Instr::LW(rd, offset, base) | Instr::SW(rd, offset, base) => write!(
+            f,
+            "{} {}, {:#x}({})",
+            mnemonic, ABI[*rd as usize], offset, ABI[*base as usize]
+        ),
+        Instr::FLW(rd, offset, base) | Instr::FSW(rd, offset, base) => write!(
+            f,
+            "{} {}, {:#x}({})",
+            mnemonic, FABI[*rd as usize], offset, ABI[*base as usize]
+        ),
+        Instr::ADD(rd, _, rs2)
+        | Instr::SUB(rd, _, rs2)
+        | Instr::XOR(rd, _, rs2)
+        | Instr::OR(rd, _, rs2)
+        | Instr::AND(rd, _, rs2) => write!(
+            f,
+            "{} {}, {}",
+            mnemonic, ABI[*rd as usize], ABI[*rs2 as usize]
+        ),
+        Instr::JAL(_, offset) => write!(f, "{} {:#x}", mnemonic, offset),
+        Instr::BEQ(rs1, _, offset) | Instr::BNE(rs1, _, offset) => {
+            write!(f, "{} {}, {:#x}", mnemonic, ABI[*rs1 as usize], offset)
+        }
+        Instr::JALR(_, _, rs1) => write!(f, "{} {}", mnemonic, ABI[*rs1 as usize]),
+        _ => write!(f, "{}", mnemonic),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// c.lui rd, nzimm
+    fn c_lui(rd: u32, nzimm: u32) -> u32 {
+        0b011 << 13 | unbits(nzimm, 12, 12, 5) | rd << 7 | unbits(nzimm, 6, 2, 0) | 0b01
+    }
+
+    #[test]
+    fn c_lui_x0_is_a_hint() {
+        let inst = decompress(c_lui(0, 1)).unwrap();
+        assert_eq!(inst, Instr::C("c.lui", Box::new(Instr::LUI(0, 0x1000))));
+        assert_eq!(inst.encode().unwrap(), c_lui(0, 1));
+        let inst = decompress(c_lui(0, 0x20)).unwrap();
+        assert_eq!(
+            inst,
+            Instr::C("c.lui", Box::new(Instr::LUI(0, 0xfffe_0000)))
+        );
+    }
+
+    #[test]
+    fn c_lui_zero_immediate_is_reserved() {
+        for rd in [0, 1, 5, 31] {
+            assert!(decompress(c_lui(rd, 0)).is_err());
+        }
+        assert!(decompress(c_lui(5, 1)).is_ok());
+    }
+}
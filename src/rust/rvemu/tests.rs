@@ -0,0 +1,1128 @@
+//! 整个 CPU 上运行小程序的测试
+
+use super::*;
+
+pub const KERNEL_BASE: u32 = 0x1c09_0000;
+pub const DRAM_SIZE: u32 = 0x1_0000;
+
+/// 用户程序从 0 开始, 异常处理程序在 KERNEL_BASE
+pub fn cpu_with(user: &str, kernel: &str, config: Config) -> CPU {
+    let user = assemble(user, 0).unwrap();
+    let kernel = assemble(kernel, KERNEL_BASE).unwrap();
+    let mut image = user.text;
+    image.extend(&user.data);
+    CPU::new(&image, 0, &kernel.text, KERNEL_BASE, 0, DRAM_SIZE, config)
+}
+
+pub fn cpu(user: &str) -> CPU {
+    cpu_with(user, "ebreak", Config::default())
+}
+
+/// 按 fetch, pc_step, execute 执行一步
+pub fn step(cpu: &mut CPU) -> Result<WBInfo> {
+    let inst = cpu.fetch()?;
+    cpu.pc_step();
+    cpu.execute(inst)
+}
+
+/// 一直执行到出错, 返回执行的步数
+pub fn run_to_error(cpu: &mut CPU, max_steps: u64) -> u64 {
+    (0..max_steps)
+        .find(|_| step(cpu).is_err())
+        .unwrap_or(max_steps)
+}
+
+#[test]
+fn counter_write_takes_effect_after_the_instruction() {
+    let mut cpu = cpu("
+        li t0, 100
+        csrw minstret, t0
+        csrr a0, minstret
+        csrw mcycle, t0
+        csrr a1, mcycle
+        csrw minstreth, zero
+        csrr a2, minstret
+        ebreak
+    ");
+    run_to_error(&mut cpu, 100);
+    // csrr 读到的是它之前已经完成的指令数
+    assert_eq!(cpu.reg(10), 100);
+    assert_eq!(cpu.reg(11), 100);
+    assert_eq!(cpu.reg(12), 103);
+}
+
+/// 打开 M 模式的定时器中断, mtimecmp = 20, 然后原地循环
+const TIMER_LOOP: &str = "
+    li t0, 0x02004000
+    li t1, 20
+    sw t1, 0(t0)
+    sw zero, 4(t0)
+    li t0, 0x80
+    csrw mie, t0
+    csrrsi zero, mstatus, 8
+loop:
+    addi a0, a0, 1
+    j loop
+";
+
+#[test]
+fn interrupt_is_not_reported_as_an_executed_instruction() {
+    let config = Config {
+        clint: true,
+        ..Config::default()
+    };
+    let mut cpu = cpu_with(TIMER_LOOP, "ebreak", config);
+    let info = (0..100)
+        .map(|_| step(&mut cpu).unwrap())
+        .find(|info| info.wb_trap != 0)
+        .unwrap();
+    assert_eq!(info.wb_have_inst, 0);
+    assert_eq!(info.inst_valid, 0);
+    assert_eq!(cpu.pc(), KERNEL_BASE);
+}
+
+#[test]
+fn ecall_reports_a_trap() {
+    let mut cpu = cpu("ecall");
+    let info = step(&mut cpu).unwrap();
+    assert_eq!((info.inst_valid, info.wb_trap), (1, 1));
+    assert_eq!(cpu.pc(), KERNEL_BASE);
+}
+
+#[test]
+fn halt_on_eret_stops_at_mret() {
+    let mut cpu = cpu_with("ecall\nebreak", "mret", Config::default());
+    step(&mut cpu).unwrap();
+    let e = step(&mut cpu).err().unwrap();
+    assert_eq!(e.downcast_ref::<Halt>(), Some(&Halt::Eret));
+}
+
+#[test]
+fn mret_returns_without_halt_on_eret() {
+    let config = Config {
+        halt_on_eret: false,
+        ..Config::default()
+    };
+    // 实验的约定: ecall 的 mepc 是下一条指令
+    let mut cpu = cpu_with("ecall\nli a0, 1\nebreak", "mret", config);
+    let e = (0..10).find_map(|_| step(&mut cpu).err()).unwrap();
+    assert_eq!(e.downcast_ref::<Halt>(), Some(&Halt::Ebreak));
+    assert_eq!(cpu.reg(10), 1);
+}
+
+#[test]
+fn ebreak_halts_without_trap_illegal() {
+    let mut cpu = cpu("nop\nebreak");
+    step(&mut cpu).unwrap();
+    let e = step(&mut cpu).err().unwrap();
+    assert_eq!(e.downcast_ref::<Halt>(), Some(&Halt::Ebreak));
+}
+
+#[test]
+fn ebreak_traps_with_trap_illegal() {
+    let config = Config {
+        trap_illegal: true,
+        ..Config::default()
+    };
+    let mut cpu = cpu_with(
+        "nop\nebreak",
+        "csrr a0, mcause\ncsrr a1, mtval\ncsrr a2, mepc\nmret",
+        config,
+    );
+    step(&mut cpu).unwrap();
+    let info = step(&mut cpu).unwrap();
+    assert_eq!(info.wb_trap, 1);
+    run_to_error(&mut cpu, 10);
+    assert_eq!((cpu.reg(10), cpu.reg(11), cpu.reg(12)), (3, 4, 4));
+}
+
+fn no_halt_on_eret() -> Config {
+    Config {
+        halt_on_eret: false,
+        ..Config::default()
+    }
+}
+
+#[test]
+fn uret_is_illegal_without_n_extension() {
+    let config = Config {
+        trap_illegal: true,
+        ..Config::default()
+    };
+    let mut cpu = cpu_with("uret", "csrr a0, mcause\ncsrr a1, mtval\nmret", config);
+    run_to_error(&mut cpu, 10);
+    assert_eq!(cpu.reg(10), 2);
+    assert_eq!(cpu.reg(11), 0x0020_0073);
+}
+
+#[test]
+fn uret_as_mret_returns_like_mret() {
+    let config = Config {
+        uret_as_mret: true,
+        ..no_halt_on_eret()
+    };
+    let mut cpu = cpu_with("ecall\nli a0, 1\nebreak", "uret", config);
+    let e = (0..10).find_map(|_| step(&mut cpu).err()).unwrap();
+    assert_eq!(e.downcast_ref::<Halt>(), Some(&Halt::Ebreak));
+    assert_eq!(cpu.reg(10), 1);
+}
+
+#[test]
+fn delegated_ecall_from_u_goes_to_stvec() {
+    let mut cpu = cpu_with(
+        "
+        la t0, handler
+        csrw stvec, t0
+        li t0, 0x100
+        csrw medeleg, t0
+        li t0, 0x1800
+        csrrc zero, mstatus, t0
+        la t0, user
+        csrw mepc, t0
+        mret
+    user:
+        ecall
+    after:
+        ebreak
+    handler:
+        csrr a0, scause
+        csrr a1, sepc
+        la a2, after
+        ebreak
+    ",
+        "ebreak",
+        no_halt_on_eret(),
+    );
+    run_to_error(&mut cpu, 100);
+    assert_eq!(cpu.mode(), Privilege::Supervisor);
+    assert_eq!(cpu.reg(10), 8);
+    assert_eq!(cpu.reg(11), cpu.reg(12));
+}
+
+#[test]
+fn sret_restores_spp_and_sie() {
+    let mut cpu = cpu_with(
+        "
+        li t0, 0x122
+        csrrc zero, mstatus, t0
+        li t0, 0x120
+        csrrs zero, mstatus, t0
+        la t0, supervisor
+        csrw sepc, t0
+        sret
+    supervisor:
+        csrr a0, sstatus
+        la t0, user
+        csrw sepc, t0
+        sret
+    user:
+        ebreak
+    ",
+        "ebreak",
+        no_halt_on_eret(),
+    );
+    run_to_error(&mut cpu, 100);
+    // sie = spie, spie = 1, spp = U
+    assert_eq!(cpu.reg(10) & 0x122, 0x22);
+    assert_eq!(cpu.mode(), Privilege::User);
+}
+
+#[test]
+fn mret_restores_mpp() {
+    for (mpp, mode) in [
+        (0, Privilege::User),
+        (1, Privilege::Supervisor),
+        (3, Privilege::Machine),
+    ] {
+        let mut cpu = cpu_with(
+            &format!(
+                "
+                li t0, 0x1800
+                csrrc zero, mstatus, t0
+                li t0, {}
+                csrrs zero, mstatus, t0
+                la t0, target
+                csrw mepc, t0
+                mret
+            target:
+                ebreak
+            ",
+                mpp << 11
+            ),
+            "ebreak",
+            no_halt_on_eret(),
+        );
+        run_to_error(&mut cpu, 100);
+        assert_eq!(cpu.mode(), mode);
+    }
+}
+
+#[test]
+fn m_only_csr_traps_in_u_mode() {
+    let config = Config {
+        user_mode: true,
+        trap_illegal: true,
+        ..Config::default()
+    };
+    let mut cpu = cpu_with(
+        "csrr a0, mstatus",
+        "csrr a0, mcause\ncsrr a1, mtval\nmret",
+        config,
+    );
+    run_to_error(&mut cpu, 10);
+    assert_eq!(cpu.reg(10), 2);
+    assert_eq!(cpu.reg(11), 0x3000_2573);
+}
+
+#[test]
+fn mprv_uses_mpp_for_loads_and_stores() {
+    let config = Config {
+        pmp_entries: 1,
+        trap_access_fault: true,
+        ..Config::default()
+    };
+    // 表项 0 覆盖整个地址空间, 只读可执行, 不锁定: 只约束 S/U 模式
+    let mut cpu = cpu_with(
+        "
+        li t0, -1
+        csrw pmpaddr0, t0
+        li t0, 0x1d
+        csrw pmpcfg0, t0
+        la s1, buf
+        sw t0, 0(s1)
+        li t0, 0x1800
+        csrrc zero, mstatus, t0
+        li t0, 0x20000
+        csrrs zero, mstatus, t0
+        lw a0, 0(s1)
+        sw zero, 0(s1)
+        ebreak
+    .data
+    buf:
+        .word 0
+    ",
+        "csrr a1, mcause\ncsrr a2, mtval\nebreak",
+        config,
+    );
+    run_to_error(&mut cpu, 100);
+    assert_eq!(cpu.reg(10), 0x1d);
+    assert_eq!(cpu.reg(11), 7);
+    assert_eq!(cpu.reg(12), cpu.reg(9));
+}
+
+/// 在 buf 处放一个字, 运行 body 后停下
+fn run_on_word(word: u32, body: &str) -> CPU {
+    let mut cpu = cpu_with(
+        &format!(
+            "
+            la s1, buf
+            li t0, {}
+            sw t0, 0(s1)
+            {}
+            ebreak
+        .data
+        buf:
+            .word 0
+        ",
+            word as i32, body
+        ),
+        "csrr a0, mcause\ncsrr a1, mtval\nmret",
+        Config::default(),
+    );
+    run_to_error(&mut cpu, 100);
+    cpu
+}
+
+#[test]
+fn sc_succeeds_after_lr() {
+    let cpu = run_on_word(
+        5,
+        "
+        lr.w a0, (s1)
+        li t1, 9
+        sc.w a1, t1, (s1)
+        lw a2, 0(s1)
+    ",
+    );
+    assert_eq!((cpu.reg(10), cpu.reg(11), cpu.reg(12)), (5, 0, 9));
+}
+
+#[test]
+fn sc_fails_after_a_store_to_the_reserved_word() {
+    let cpu = run_on_word(
+        5,
+        "
+        lr.w a0, (s1)
+        li t1, 7
+        sh t1, 2(s1)
+        li t1, 9
+        sc.w a1, t1, (s1)
+        lw a2, 0(s1)
+    ",
+    );
+    assert_eq!((cpu.reg(11), cpu.reg(12)), (1, 0x0007_0005));
+}
+
+#[test]
+fn sc_fails_without_a_reservation() {
+    let cpu = run_on_word(
+        5,
+        "
+        li t1, 9
+        sc.w a0, t1, (s1)
+        lr.w zero, (s1)
+        sc.w a1, t1, (s1)
+        li t1, 11
+        sc.w a2, t1, (s1)
+        lw a3, 0(s1)
+    ",
+    );
+    // 成功的 sc 也会清除保留
+    assert_eq!(
+        (cpu.reg(10), cpu.reg(11), cpu.reg(12), cpu.reg(13)),
+        (1, 0, 1, 9)
+    );
+}
+
+#[test]
+fn amo_results() {
+    let old = -5i32 as u32;
+    for (op, new) in [
+        ("amoswap.w", 3),
+        ("amoadd.w", -2i32 as u32),
+        ("amoxor.w", old ^ 3),
+        ("amoand.w", old & 3),
+        ("amoor.w", old | 3),
+        ("amomin.w", old),
+        ("amomax.w", 3),
+        ("amominu.w", 3),
+        ("amomaxu.w", old),
+    ] {
+        let cpu = run_on_word(old, &format!("li t1, 3\n{} a0, t1, (s1)\nlw a1, 0(s1)", op));
+        assert_eq!((cpu.reg(10), cpu.reg(11)), (old, new), "{}", op);
+    }
+}
+
+#[test]
+fn misaligned_amo_traps() {
+    let cpu = run_on_word(0, "addi s1, s1, 2\namoadd.w a2, zero, (s1)");
+    assert_eq!((cpu.reg(10), cpu.reg(11)), (6, cpu.reg(9)));
+}
+
+/// 差分测试的异常处理程序: ecall 直接返回, 其它异常跳过出错的指令,
+/// 定时器中断把 mtimecmp 设成 97 个周期以后. 只用 t5, t6
+const FUZZ_HANDLER: &str = "
+    csrr t6, mcause
+    blt t6, zero, irq
+    li t5, 11
+    beq t6, t5, done
+    csrr t6, mepc
+    addi t6, t6, 4
+    csrw mepc, t6
+done:
+    mret
+irq:
+    li t5, 0x0200bff8
+    lw t6, 0(t5)
+    addi t6, t6, 97
+    li t5, 0x02004000
+    sw t6, 0(t5)
+    sw zero, 4(t5)
+    mret
+";
+
+/// 随机生成的程序里可以读写的寄存器. s0 是循环计数, s1 指向 buf,
+/// s6/s7 是 mtime/mtimecmp 的地址, t5/t6 留给异常处理程序
+const FUZZ_REGS: [&str; 13] = [
+    "a0", "a1", "a2", "a3", "a4", "a5", "t0", "t1", "t2", "s2", "s3", "s4", "s5",
+];
+
+/// 随机的循环体: 运算, 访存, 读写 CLINT, 读计数器, ecall, 访问异常,
+/// 改写代码和 fence.i (smc 时), 以及向前的分支
+fn fuzz_program(rng: &mut impl rand::Rng, len: usize, smc: bool) -> String {
+    use rand::seq::SliceRandom;
+
+    let reg = |rng: &mut _| *FUZZ_REGS.choose(rng).unwrap();
+    let mut source = String::from(
+        "
+        li s0, 40
+        la s1, buf
+        li s6, 0x0200bff8
+        li s7, 0x02004000
+        li t0, 300
+        sw t0, 0(s7)
+        sw zero, 4(s7)
+        li t0, 0x80
+        csrw mie, t0
+        csrrsi zero, mstatus, 8
+    loop:
+    ",
+    );
+    let mut labels = vec![Vec::new(); len + 4];
+    for i in 0..len {
+        for label in &labels[i] {
+            source += &format!("{}:\n", label);
+        }
+        let (rd, rs1, rs2) = (reg(rng), reg(rng), reg(rng));
+        let kind = match rng.gen_range(0..16) {
+            12 | 13 if !smc => 0,
+            kind => kind,
+        };
+        let inst = match kind {
+            0..=3 => {
+                let op = [
+                    "add", "sub", "xor", "or", "and", "sll", "srl", "sra", "slt", "sltu",
+                ]
+                .choose(rng)
+                .unwrap();
+                format!("{} {}, {}, {}", op, rd, rs1, rs2)
+            }
+            4 | 5 => {
+                let op = ["addi", "xori", "ori", "andi", "slti", "sltiu"]
+                    .choose(rng)
+                    .unwrap();
+                format!("{} {}, {}, {}", op, rd, rs1, rng.gen_range(-2048..2048))
+            }
+            6 => format!("lui {}, {}", rd, rng.gen_range(0..1 << 20)),
+            7 => {
+                let op = ["lw", "lh", "lhu", "lb", "lbu"].choose(rng).unwrap();
+                format!("{} {}, {}(s1)", op, rd, rng.gen_range(0..60))
+            }
+            8 => {
+                let op = ["sw", "sh", "sb"].choose(rng).unwrap();
+                format!("{} {}, {}(s1)", op, rs2, rng.gen_range(0..60))
+            }
+            9 => match rng.gen_range(0..3) {
+                0 => format!("lw {}, 0(s6)", rd),
+                1 => format!("sw {}, 0(s7)", rs2),
+                _ => format!(
+                    "csrr {}, {}",
+                    rd,
+                    ["mcycle", "minstret", "mip"].choose(rng).unwrap()
+                ),
+            },
+            10 => "ecall".to_string(),
+            // 访问异常
+            11 => format!("lui t5, 0x40000\nlw {}, 0(t5)", rd),
+            // 改写循环里的代码, 取指仍然从 IROM 取
+            12 => format!("sw {}, {}(zero)", rs2, 40 + 4 * rng.gen_range(0..len)),
+            13 => "fence.i".to_string(),
+            _ => {
+                let skip = rng.gen_range(1..4);
+                let label = format!("skip_{}", i);
+                labels[i + skip].push(label.clone());
+                let op = ["beq", "bne", "blt", "bge", "bltu", "bgeu"]
+                    .choose(rng)
+                    .unwrap();
+                format!("{} {}, {}, {}", op, rs1, rs2, label)
+            }
+        };
+        source += &inst;
+        source += "\n";
+    }
+    for label in labels[len..].iter().flatten() {
+        source += &format!("{}:\n", label);
+    }
+    source += "
+        addi s0, s0, -1
+        bnez s0, loop
+        csrw mie, zero
+        csrr s8, mcycle
+        csrr s9, minstret
+        li s10, 0
+        li s11, 0
+    sum:
+        add t0, s1, s11
+        lw t0, 0(t0)
+        add s10, s10, t0
+        slli t1, s10, 7
+        xor s10, s10, t1
+        addi s11, s11, 4
+        li t0, 64
+        bne s11, t0, sum
+        ebreak
+        .data
+    buf:
+        .word 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16
+    ";
+    source
+}
+
+/// 分别用 fetch/pc_step/execute 和 CPU::run 运行到 ebreak, 比较结果. 返回 run 的 CPU
+fn run_matches_execute(source: &str, config: Config) -> CPU {
+    let mut expected = cpu_with(source, FUZZ_HANDLER, config);
+    let steps = run_to_error(&mut expected, 1_000_000);
+    let mut actual = cpu_with(source, FUZZ_HANDLER, config);
+    let (run_steps, result) = actual.run(1_000_000);
+
+    let e = result.err().unwrap();
+    assert_eq!(
+        e.downcast_ref::<Halt>(),
+        Some(&Halt::Ebreak),
+        "{:?} in program:\n{}",
+        e,
+        source
+    );
+    assert_eq!(run_steps, steps, "program:\n{}", source);
+    assert_eq!(actual.pc(), expected.pc(), "program:\n{}", source);
+    for r in 1..32 {
+        assert_eq!(
+            actual.reg(r),
+            expected.reg(r),
+            "x{} differs in program:\n{}",
+            r,
+            source
+        );
+    }
+    actual
+}
+
+/// 差分测试用的配置: 异常都进入 FUZZ_HANDLER
+fn fuzz_config() -> Config {
+    Config {
+        trap_misaligned: true,
+        trap_access_fault: true,
+        halt_on_eret: false,
+        clint: true,
+        ..Config::default()
+    }
+}
+
+#[test]
+fn run_matches_execute_on_random_programs() {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    let mut rng = StdRng::seed_from_u64(0x5eed);
+    for _ in 0..40 {
+        run_matches_execute(&fuzz_program(&mut rng, 120, true), fuzz_config());
+    }
+}
+
+#[cfg(feature = "jit")]
+#[test]
+fn jit_matches_execute_on_random_programs() {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    // 循环 40 次, 超过 JIT_THRESHOLD. 不改写代码时基本块会被编译
+    let mut rng = StdRng::seed_from_u64(0x5eed);
+    for jit_check in [false, true] {
+        let config = Config {
+            jit: true,
+            jit_check,
+            ..fuzz_config()
+        };
+        for smc in [false, true].repeat(5) {
+            let cpu = run_matches_execute(&fuzz_program(&mut rng, 120, smc), config);
+            assert!(smc || cpu.jit_code_size() > 0);
+        }
+    }
+}
+
+/// 过 after 个周期之后拉高中断线, 写任意值清除
+struct Alarm {
+    now: u64,
+    after: u64,
+    acked: bool,
+}
+
+impl Device for Alarm {
+    fn read(&mut self, _offset: u32, _size: u32) -> Result<u32> {
+        Ok(self.now as u32)
+    }
+
+    fn write(&mut self, _offset: u32, _value: u32, _size: u32) -> Result<()> {
+        self.acked = true;
+        Ok(())
+    }
+
+    fn tick(&mut self, cycles: u64) {
+        self.now += cycles;
+    }
+
+    fn irq(&self) -> bool {
+        self.now >= self.after && !self.acked
+    }
+}
+
+const ALARM_BASE: u32 = 0x1000_0000;
+
+/// 打开 PLIC 的 2 号中断源和 M 模式外部中断, 然后原地循环
+const ALARM_LOOP: &str = "
+    li t0, 0x0c000008
+    li t1, 1
+    sw t1, 0(t0)
+    li t0, 0x0c002000
+    li t1, 4
+    sw t1, 0(t0)
+    li t0, 0x800
+    csrw mie, t0
+    csrrsi zero, mstatus, 8
+loop:
+    addi a0, a0, 1
+    addi a1, a1, 2
+    xor a2, a0, a1
+    j loop
+";
+
+/// 记下进入时的 minstret, 清除中断, claim/complete 后返回
+const ALARM_HANDLER: &str = "
+    csrr s0, minstret
+    li t0, 0x10000000
+    sw zero, 0(t0)
+    li t0, 0x0c200004
+    lw t1, 0(t0)
+    sw t1, 0(t0)
+    addi s1, s1, 1
+    mret
+";
+
+fn alarm_cpu(config: Config) -> CPU {
+    let config = Config {
+        plic: true,
+        ..config
+    };
+    let mut cpu = cpu_with(ALARM_LOOP, ALARM_HANDLER, config);
+    let alarm = Alarm {
+        now: 0,
+        after: 1000,
+        acked: false,
+    };
+    cpu.add_device(ALARM_BASE, 4, Some(2), Box::new(alarm))
+        .unwrap();
+    cpu
+}
+
+#[test]
+fn device_interrupt_is_taken_inside_a_block_chain() {
+    let config = Config {
+        halt_on_eret: false,
+        ..Config::default()
+    };
+    let mut expected = alarm_cpu(config);
+    let entered = (0..5000)
+        .find(|_| {
+            step(&mut expected).unwrap();
+            expected.pc() == KERNEL_BASE
+        })
+        .unwrap();
+    // 第 1000 个周期结束时中断线变高, 下一步进入异常处理
+    assert_eq!(entered, 1000);
+    for _ in entered + 1..5000 {
+        step(&mut expected).unwrap();
+    }
+
+    let mut actual = alarm_cpu(config);
+    let (steps, result) = actual.run(5000);
+    result.unwrap();
+    assert_eq!(steps, 5000);
+    assert_eq!(actual.reg(9), 1);
+    assert_eq!(actual.pc(), expected.pc());
+    for r in 1..32 {
+        assert_eq!(actual.reg(r), expected.reg(r), "x{}", r);
+    }
+}
+
+#[cfg(feature = "jit")]
+#[test]
+fn jit_check_ticks_devices_once() {
+    // 热点循环会被编译, 最后读出设备走过的周期数
+    let source = "
+        li s0, 100
+    loop:
+        addi a0, a0, 1
+        addi a1, a1, 2
+        xor a2, a0, a1
+        addi s0, s0, -1
+        bnez s0, loop
+        li t0, 0x10000000
+        lw a3, 0(t0)
+        ebreak
+    ";
+    for jit_check in [false, true] {
+        let config = Config {
+            jit: true,
+            jit_check,
+            ..Config::default()
+        };
+        let counter = || Alarm {
+            now: 0,
+            after: u64::MAX,
+            acked: false,
+        };
+        let mut expected = cpu_with(source, "ebreak", config);
+        expected
+            .add_device(ALARM_BASE, 4, None, Box::new(counter()))
+            .unwrap();
+        run_to_error(&mut expected, 10_000);
+        let mut actual = cpu_with(source, "ebreak", config);
+        actual
+            .add_device(ALARM_BASE, 4, None, Box::new(counter()))
+            .unwrap();
+        let (_, result) = actual.run(10_000);
+        assert!(result.is_err());
+        assert!(actual.jit_code_size() > 0);
+        assert_eq!(actual.reg(13), expected.reg(13));
+    }
+}
+
+/// 用 execute 和 run 各执行一次 `op a0, a1, a2`, 返回 a0
+fn alu_with(op: &str, lhs: u32, rhs: u32, config: Config) -> u32 {
+    let source = format!(
+        "li a1, {}\nli a2, {}\n{} a0, a1, a2\nebreak",
+        lhs as i32, rhs as i32, op
+    );
+    run_matches_execute(&source, config).reg(10)
+}
+
+fn alu(op: &str, lhs: u32, rhs: u32) -> u32 {
+    alu_with(op, lhs, rhs, Config::default())
+}
+
+#[test]
+fn division_by_zero() {
+    assert_eq!(alu("div", 7, 0), u32::MAX);
+    assert_eq!(alu("div", -7i32 as u32, 0), u32::MAX);
+    assert_eq!(alu("rem", 7, 0), 7);
+    assert_eq!(alu("rem", -7i32 as u32, 0), -7i32 as u32);
+    assert_eq!(alu("divu", 7, 0), u32::MAX);
+    assert_eq!(alu("remu", -7i32 as u32, 0), -7i32 as u32);
+}
+
+#[test]
+fn signed_division_overflow() {
+    let min = i32::MIN as u32;
+    assert_eq!(alu("div", min, -1i32 as u32), min);
+    assert_eq!(alu("rem", min, -1i32 as u32), 0);
+    assert_eq!(alu("div", -7i32 as u32, 2), -3i32 as u32);
+    assert_eq!(alu("rem", -7i32 as u32, 2), -1i32 as u32);
+    assert_eq!(alu("divu", -7i32 as u32, 2), 0x7fff_fffc);
+}
+
+#[test]
+fn high_multiplication_of_negative_operands() {
+    assert_eq!(alu("mul", -3i32 as u32, 5), -15i32 as u32);
+    assert_eq!(alu("mulh", -2i32 as u32, 3), -1i32 as u32);
+    assert_eq!(alu("mulh", -2i32 as u32, -3i32 as u32), 0);
+    assert_eq!(alu("mulh", i32::MIN as u32, i32::MIN as u32), 0x4000_0000);
+    // mulhsu: rs1 有符号, rs2 无符号
+    assert_eq!(alu("mulhsu", -2i32 as u32, 3), -1i32 as u32);
+    assert_eq!(alu("mulhsu", 2, -1i32 as u32), 1);
+    assert_eq!(alu("mulhsu", -1i32 as u32, -1i32 as u32), -1i32 as u32);
+    assert_eq!(alu("mulhu", -2i32 as u32, 3), 2);
+    assert_eq!(alu("mulhu", -1i32 as u32, -1i32 as u32), -2i32 as u32);
+}
+
+fn zb() -> Config {
+    Config {
+        zba: true,
+        zbb: true,
+        zbs: true,
+        ..Config::default()
+    }
+}
+
+/// 用 execute 和 run 各执行一次 `op a0, a1`, 返回 a0
+fn unary(op: &str, x: u32) -> u32 {
+    let source = format!("li a1, {}\n{} a0, a1\nebreak", x as i32, op);
+    run_matches_execute(&source, zb()).reg(10)
+}
+
+#[test]
+fn count_bits() {
+    assert_eq!(unary("clz", 0), 32);
+    assert_eq!(unary("ctz", 0), 32);
+    assert_eq!(unary("cpop", 0), 0);
+    assert_eq!(unary("clz", 0x0001_0000), 15);
+    assert_eq!(unary("ctz", 0x0001_0000), 16);
+    assert_eq!(unary("cpop", 0xf0f0_0001), 9);
+    assert_eq!(unary("clz", u32::MAX), 0);
+}
+
+#[test]
+fn byte_operations() {
+    assert_eq!(unary("rev8", 0x1234_5678), 0x7856_3412);
+    assert_eq!(unary("orc.b", 0x0010_0200), 0x00ff_ff00);
+    assert_eq!(unary("orc.b", 0), 0);
+    assert_eq!(unary("sext.b", 0x80), 0xffff_ff80);
+    assert_eq!(unary("zext.h", 0xffff_8000), 0x8000);
+}
+
+#[test]
+fn shift_and_add() {
+    assert_eq!(alu_with("sh1add", 3, 10, zb()), 16);
+    assert_eq!(alu_with("sh2add", 3, 10, zb()), 22);
+    assert_eq!(alu_with("sh3add", 3, 10, zb()), 34);
+    assert_eq!(alu_with("sh3add", 0x2000_0000, 1, zb()), 1);
+}
+
+#[test]
+fn min_and_max() {
+    let minus_one = u32::MAX;
+    assert_eq!(alu_with("min", minus_one, 1, zb()), minus_one);
+    assert_eq!(alu_with("max", minus_one, 1, zb()), 1);
+    assert_eq!(alu_with("minu", minus_one, 1, zb()), 1);
+    assert_eq!(alu_with("maxu", minus_one, 1, zb()), minus_one);
+}
+
+#[test]
+fn single_bit_uses_the_low_five_bits_of_rs2() {
+    for index in [3, 35, 0xffff_ffe3] {
+        assert_eq!(alu_with("bset", 0, index, zb()), 0x8);
+        assert_eq!(alu_with("bclr", 0xff, index, zb()), 0xf7);
+        assert_eq!(alu_with("bext", 0x8, index, zb()), 1);
+        assert_eq!(alu_with("binv", 0x9, index, zb()), 0x1);
+    }
+}
+
+#[test]
+fn bit_manipulation_needs_its_extension() {
+    for (insts, off) in [
+        (
+            &[
+                "sh1add a0, a1, a2",
+                "sh2add a0, a1, a2",
+                "sh3add a0, a1, a2",
+            ][..],
+            Config { zba: false, ..zb() },
+        ),
+        (
+            &[
+                "andn a0, a1, a2",
+                "orn a0, a1, a2",
+                "xnor a0, a1, a2",
+                "min a0, a1, a2",
+                "max a0, a1, a2",
+                "minu a0, a1, a2",
+                "maxu a0, a1, a2",
+                "rol a0, a1, a2",
+                "ror a0, a1, a2",
+                "rori a0, a1, 3",
+                "clz a0, a1",
+                "ctz a0, a1",
+                "cpop a0, a1",
+                "sext.b a0, a1",
+                "sext.h a0, a1",
+                "zext.h a0, a1",
+                "orc.b a0, a1",
+                "rev8 a0, a1",
+            ][..],
+            Config { zbb: false, ..zb() },
+        ),
+        (
+            &[
+                "bset a0, a1, a2",
+                "bclr a0, a1, a2",
+                "bext a0, a1, a2",
+                "binv a0, a1, a2",
+                "bseti a0, a1, 3",
+                "bclri a0, a1, 3",
+                "bexti a0, a1, 3",
+                "binvi a0, a1, 3",
+            ][..],
+            Config { zbs: false, ..zb() },
+        ),
+    ] {
+        for inst in insts {
+            let mut cpu = cpu_with(inst, "ebreak", off);
+            let e = step(&mut cpu).err().unwrap();
+            assert!(
+                matches!(
+                    e.downcast_ref::<DecodeError>(),
+                    Some(DecodeError::Disabled(..))
+                ),
+                "{}",
+                inst
+            );
+            let mut cpu = cpu_with(inst, "ebreak", zb());
+            step(&mut cpu).unwrap();
+        }
+    }
+}
+
+/// mtvec 的向量表: 同步异常在 base, 定时器中断在 base + 4 * 7
+const VECTORED: &str = "
+    la t0, vec
+    ori t0, t0, 1
+    csrw mtvec, t0
+";
+const VECTOR_TABLE: &str = "
+vec:
+    li a0, 1
+    ebreak
+    .word 0, 0, 0, 0, 0
+    li a0, 7
+    ebreak
+";
+
+#[test]
+fn vectored_mtvec_offsets_only_interrupts() {
+    let mut cpu = cpu(&format!("{}\necall\n{}", VECTORED, VECTOR_TABLE));
+    run_to_error(&mut cpu, 100);
+    assert_eq!(cpu.reg(10), 1);
+
+    let config = Config {
+        clint: true,
+        ..Config::default()
+    };
+    let source = format!("{}\n{}\n{}", VECTORED, TIMER_LOOP, VECTOR_TABLE);
+    let mut cpu = cpu_with(&source, "ebreak", config);
+    run_to_error(&mut cpu, 1000);
+    assert_eq!(cpu.reg(10), 7);
+}
+
+#[test]
+fn misa_and_mhartid_are_read_only() {
+    let config = Config {
+        trap_illegal: true,
+        ..Config::default()
+    };
+    let mut cpu = cpu_with(
+        "
+        csrr s2, misa
+        csrw misa, zero
+        csrr s3, misa
+        csrr s4, mhartid
+        csrw mhartid, s2
+    ",
+        "csrr a0, mcause\ncsrr a1, mtval\nmret",
+        config,
+    );
+    run_to_error(&mut cpu, 100);
+    // misa 的写入被忽略, 写 mhartid 是非法指令, mtval 是指令本身
+    assert_eq!(cpu.reg(18) >> 30, 1);
+    assert_eq!(cpu.reg(19), cpu.reg(18));
+    assert_eq!(cpu.reg(20), 0);
+    assert_eq!(cpu.reg(10), 2);
+    assert_eq!(cpu.reg(11), 0xf149_1073);
+}
+
+#[test]
+fn mtval_holds_the_fault_address() {
+    let config = Config {
+        trap_misaligned: true,
+        ..Config::default()
+    };
+    let mut cpu = cpu_with(
+        "li t0, 0x123\nsw zero, 0(t0)",
+        "csrr a0, mcause\ncsrr a1, mtval\nmret",
+        config,
+    );
+    run_to_error(&mut cpu, 100);
+    assert_eq!((cpu.reg(10), cpu.reg(11)), (6, 0x123));
+}
+
+#[test]
+fn mret_sets_mie_from_mpie() {
+    for (mpie, mie) in [(0x80, 0x8), (0, 0)] {
+        let source = format!(
+            "
+            li t0, 0x1888
+            csrrs zero, mstatus, t0
+            li t0, {}
+            csrrc zero, mstatus, t0
+            la t0, after
+            csrw mepc, t0
+            mret
+        after:
+            csrr a0, mstatus
+            ebreak
+        ",
+            0x88 ^ mpie
+        );
+        let mut cpu = cpu_with(&source, "ebreak", no_halt_on_eret());
+        run_to_error(&mut cpu, 100);
+        // mie = mpie, mpie = 1
+        assert_eq!(cpu.reg(10) & 0x88, 0x80 | mie);
+    }
+}
+
+#[test]
+fn undecodable_instruction_traps_with_its_bits() {
+    let config = Config {
+        trap_illegal: true,
+        ..Config::default()
+    };
+    // 没有实现的 32 位编码, 以及保留的 c.jr x0
+    for (word, raw) in [(".word 0xffffffff", 0xffff_ffff), (".half 0x8002", 0x8002)] {
+        let mut cpu = cpu_with(
+            &format!("nop\n{}", word),
+            "csrr a0, mcause\ncsrr a1, mtval\ncsrr a2, mepc\nmret",
+            config,
+        );
+        run_to_error(&mut cpu, 100);
+        assert_eq!(
+            (cpu.reg(10), cpu.reg(11), cpu.reg(12)),
+            (2, raw, 4),
+            "{}",
+            word
+        );
+    }
+}
+
+/// 运行到异常处理程序的 mret, 返回 (mcause, mtval)
+fn first_trap(user: &str, config: Config) -> (u32, u32) {
+    let mut cpu = cpu_with(user, "csrr a0, mcause\ncsrr a1, mtval\nmret", config);
+    run_to_error(&mut cpu, 100);
+    (cpu.reg(10), cpu.reg(11))
+}
+
+#[test]
+fn misaligned_load_and_store() {
+    let load = "li t0, 0x102\nlh a2, 1(t0)\nebreak";
+    let store = "li t0, 0x101\nsw t0, 0(t0)\nebreak";
+    let config = Config {
+        trap_misaligned: true,
+        ..Config::default()
+    };
+    assert_eq!(first_trap(load, config), (4, 0x103));
+    assert_eq!(first_trap(store, config), (6, 0x101));
+
+    // 关掉时由硬件完成
+    let mut cpu =
+        cpu("li t0, 0x101\nli t1, 0x11223344\nsw t1, 0(t0)\nlw a2, 0(t0)\nlh a3, 1(t0)\nebreak");
+    let e = (0..100).find_map(|_| step(&mut cpu).err()).unwrap();
+    assert_eq!(e.downcast_ref::<Halt>(), Some(&Halt::Ebreak));
+    assert_eq!((cpu.reg(12), cpu.reg(13)), (0x1122_3344, 0x2233));
+}
+
+#[test]
+fn misaligned_fetch() {
+    let text = assemble("nop", 0).unwrap().text;
+    let kernel = assemble("csrr a0, mcause\ncsrr a1, mtval\nmret", KERNEL_BASE).unwrap();
+    for trap_misaligned in [false, true] {
+        let config = Config {
+            trap_misaligned,
+            trap_access_fault: true,
+            ..Config::default()
+        };
+        let mut cpu = CPU::new(
+            &text,
+            0x101,
+            &kernel.text,
+            KERNEL_BASE,
+            0,
+            DRAM_SIZE,
+            config,
+        );
+        run_to_error(&mut cpu, 100);
+        assert_eq!((cpu.reg(10), cpu.reg(11)), (0, 0x101));
+    }
+}
+
+#[test]
+fn out_of_range_access() {
+    let config = Config {
+        trap_access_fault: true,
+        ..Config::default()
+    };
+    let far = "li t0, 0x80000000";
+    for (inst, cause) in [("jr t0", 1), ("lw a2, 0(t0)", 5), ("sw a2, 0(t0)", 7)] {
+        let user = format!("{}\n{}\nebreak", far, inst);
+        assert_eq!(first_trap(&user, config), (cause, 0x8000_0000), "{}", inst);
+
+        // 关掉时作为错误返回
+        let mut cpu = cpu(&user);
+        let e = (0..100).find_map(|_| step(&mut cpu).err()).unwrap();
+        assert!(e.downcast_ref::<Halt>().is_none(), "{}", inst);
+    }
+}
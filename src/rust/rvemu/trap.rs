@@ -0,0 +1,193 @@
+use super::*;
+
+/// 特权级, 数值与 mstatus.MPP 的编码相同
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Privilege {
+    User = 0,
+    Supervisor = 1,
+    Machine = 3,
+}
+
+impl Privilege {
+    /// 从 mpp/spp 的编码得到特权级, 保留的 0b10 当作 U 模式
+    pub fn from_bits(bits: u32) -> Self {
+        match bits & 0b11 {
+            0b01 => Privilege::Supervisor,
+            0b11 => Privilege::Machine,
+            _ => Privilege::User,
+        }
+    }
+}
+
+/// 访存的类型
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Access {
+    Fetch,
+    Load,
+    Store,
+}
+
+impl Access {
+    /// 对应的访问异常
+    pub fn fault(&self, addr: u32) -> Exception {
+        match self {
+            Access::Fetch => Exception::InstructionAccessFault(addr),
+            Access::Load => Exception::LoadAccessFault(addr),
+            Access::Store => Exception::StoreAccessFault(addr),
+        }
+    }
+
+    /// 对应的缺页异常
+    pub fn page_fault(&self, addr: u32) -> Exception {
+        match self {
+            Access::Fetch => Exception::InstructionPageFault(addr),
+            Access::Load => Exception::LoadPageFault(addr),
+            Access::Store => Exception::StorePageFault(addr),
+        }
+    }
+}
+
+/// 同步异常, 参数为写入 mtval 的值
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Exception {
+    InstructionAddressMisaligned(u32),
+    InstructionAccessFault(u32),
+    IllegalInstruction(u32),
+    Breakpoint(u32),
+    LoadAddressMisaligned(u32),
+    LoadAccessFault(u32),
+    StoreAddressMisaligned(u32),
+    StoreAccessFault(u32),
+    EnvironmentCallFromU,
+    EnvironmentCallFromS,
+    EnvironmentCallFromM,
+    InstructionPageFault(u32),
+    LoadPageFault(u32),
+    StorePageFault(u32),
+}
+
+impl Exception {
+    /// 写入 mcause 的值
+    pub fn cause(&self) -> u32 {
+        match self {
+            Exception::InstructionAddressMisaligned(_) => 0,
+            Exception::InstructionAccessFault(_) => 1,
+            Exception::IllegalInstruction(_) => 2,
+            Exception::Breakpoint(_) => 3,
+            Exception::LoadAddressMisaligned(_) => 4,
+            Exception::LoadAccessFault(_) => 5,
+            Exception::StoreAddressMisaligned(_) => 6,
+            Exception::StoreAccessFault(_) => 7,
+            Exception::EnvironmentCallFromU => 8,
+            Exception::EnvironmentCallFromS => 9,
+            Exception::EnvironmentCallFromM => 11,
+            Exception::InstructionPageFault(_) => 12,
+            Exception::LoadPageFault(_) => 13,
+            Exception::StorePageFault(_) => 15,
+        }
+    }
+
+    /// 写入 mtval 的值
+    pub fn tval(&self) -> u32 {
+        match *self {
+            Exception::InstructionAddressMisaligned(tval)
+            | Exception::InstructionAccessFault(tval)
+            | Exception::IllegalInstruction(tval)
+            | Exception::Breakpoint(tval)
+            | Exception::LoadAddressMisaligned(tval)
+            | Exception::LoadAccessFault(tval)
+            | Exception::StoreAddressMisaligned(tval)
+            | Exception::StoreAccessFault(tval)
+            | Exception::InstructionPageFault(tval)
+            | Exception::LoadPageFault(tval)
+            | Exception::StorePageFault(tval) => tval,
+            Exception::EnvironmentCallFromU
+            | Exception::EnvironmentCallFromS
+            | Exception::EnvironmentCallFromM => 0,
+        }
+    }
+}
+
+impl Display for Exception {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let desc = match self {
+            Exception::InstructionAddressMisaligned(_) => "instruction address misaligned",
+            Exception::InstructionAccessFault(_) => "instruction access fault",
+            Exception::IllegalInstruction(_) => "illegal instruction",
+            Exception::Breakpoint(_) => "breakpoint",
+            Exception::LoadAddressMisaligned(_) => "load address misaligned",
+            Exception::LoadAccessFault(_) => "load access fault",
+            Exception::StoreAddressMisaligned(_) => "store/amo address misaligned",
+            Exception::StoreAccessFault(_) => "store/amo access fault",
+            Exception::EnvironmentCallFromU => "environment call from U-mode",
+            Exception::EnvironmentCallFromS => "environment call from S-mode",
+            Exception::EnvironmentCallFromM => "environment call from M-mode",
+            Exception::InstructionPageFault(_) => "instruction page fault",
+            Exception::LoadPageFault(_) => "load page fault",
+            Exception::StorePageFault(_) => "store/amo page fault",
+        };
+        write!(
+            f,
+            "{} (mcause={}, mtval={:#x})",
+            desc,
+            self.cause(),
+            self.tval()
+        )
+    }
+}
+
+impl std::error::Error for Exception {}
+
+/// 中断, mcause 的最高位为 1
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Interrupt {
+    SupervisorSoftware,
+    MachineSoftware,
+    SupervisorTimer,
+    MachineTimer,
+    SupervisorExternal,
+    MachineExternal,
+}
+
+impl Interrupt {
+    /// 按优先级从高到低排列
+    pub const ALL: [Interrupt; 6] = [
+        Interrupt::MachineExternal,
+        Interrupt::MachineSoftware,
+        Interrupt::MachineTimer,
+        Interrupt::SupervisorExternal,
+        Interrupt::SupervisorSoftware,
+        Interrupt::SupervisorTimer,
+    ];
+
+    /// 中断号, 也是在 mip/mie 中的位置
+    pub fn code(&self) -> u32 {
+        match self {
+            Interrupt::SupervisorSoftware => 1,
+            Interrupt::MachineSoftware => 3,
+            Interrupt::SupervisorTimer => 5,
+            Interrupt::MachineTimer => 7,
+            Interrupt::SupervisorExternal => 9,
+            Interrupt::MachineExternal => 11,
+        }
+    }
+
+    /// 写入 mcause 的值
+    pub fn cause(&self) -> u32 {
+        (1 << 31) | self.code()
+    }
+}
+
+impl Display for Interrupt {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let desc = match self {
+            Interrupt::SupervisorSoftware => "supervisor software interrupt",
+            Interrupt::MachineSoftware => "machine software interrupt",
+            Interrupt::SupervisorTimer => "supervisor timer interrupt",
+            Interrupt::MachineTimer => "machine timer interrupt",
+            Interrupt::SupervisorExternal => "supervisor external interrupt",
+            Interrupt::MachineExternal => "machine external interrupt",
+        };
+        write!(f, "{} (mcause={:#x})", desc, self.cause())
+    }
+}